@@ -1,25 +1,32 @@
 //! Parsing of WZ archives
 
-use crate::{archive::ImagePath, utils, Key};
-use crypto::{KeyStream, GMS_IV, KMS_IV, TRIMMED_KEY};
+use crate::{
+    archive::{Failures, Names},
+    utils, Key,
+};
+use crypto::{Encryptor, KeyStream, TRIMMED_KEY};
 use std::{
     fs,
     path::{Path, PathBuf},
 };
 use wz::{
-    archive,
-    error::{PackageError, Result},
+    archive::{self, writer::FileImage},
+    error::{Error, PackageError, Result},
     io::DummyEncryptor,
     types::WzHeader,
 };
 
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn do_create(
     path: &PathBuf,
     directory: &str,
     verbose: bool,
     key: Key,
     version: u16,
-) -> Result<()> {
+    resume: bool,
+    jobs: usize,
+    keep_going: bool,
+) -> Result<Vec<(String, Error)>> {
     // Remove the WZ archive if it exists
     utils::remove_file(path)?;
 
@@ -34,36 +41,110 @@ pub(crate) fn do_create(
     // Get the parent path of the directory (used to strip it from the WZ contents)
     let parent = utils::parent(&directory)?;
 
+    // Names escaped on extraction are restored from the list next to the directory
+    let names = Names::load(&parent.join(format!("{}.names", target)))?;
+
     // Create new WZ archive map
     let mut writer = archive::Writer::new(target);
-    recursive_do_create(&directory, parent, &mut writer, verbose)?;
+    let mut failures = Failures::new(keep_going);
+    recursive_do_create(
+        &directory,
+        parent,
+        Path::new(target),
+        &names,
+        &mut writer,
+        verbose,
+        &mut failures,
+    )?;
+
+    // Checksum the images concurrently
+    writer.prepare(jobs)?;
 
     // Create a new header
     let header = WzHeader::new(version);
 
-    // Save the WZ archive with the proper encryption
-    match key {
-        Key::Gms => writer.save(path, version, header, KeyStream::new(&TRIMMED_KEY, &GMS_IV)),
-        Key::Kms => writer.save(path, version, header, KeyStream::new(&TRIMMED_KEY, &KMS_IV)),
-        Key::None => writer.save(path, version, header, DummyEncryptor),
+    // Save the WZ archive with the proper encryption, leaving out the entries that failed
+    match key.iv() {
+        Some(iv) => save(
+            &mut writer,
+            path,
+            version,
+            header,
+            KeyStream::new(&TRIMMED_KEY, &iv),
+            resume,
+        ),
+        None => save(&mut writer, path, version, header, DummyEncryptor, resume),
+    }?;
+    Ok(failures.into_errors())
+}
+
+fn save<E>(
+    writer: &mut archive::Writer<FileImage>,
+    path: &PathBuf,
+    version: u16,
+    header: WzHeader,
+    encryptor: E,
+    resume: bool,
+) -> Result<()>
+where
+    E: Encryptor,
+{
+    if resume {
+        writer.save_resumable(path, version, header, encryptor)
+    } else {
+        writer.save(path, version, header, encryptor)
     }
 }
 
 fn recursive_do_create(
     current: &Path,
     parent: &Path,
-    writer: &mut archive::Writer<ImagePath>,
+    node: &Path,
+    names: &Names,
+    writer: &mut archive::Writer<FileImage>,
     verbose: bool,
+    failures: &mut Failures,
 ) -> Result<()> {
-    for file in fs::read_dir(current)? {
-        let path = file?.path();
+    let current_name = current.to_string_lossy();
+    let files = match failures.check(&current_name, fs::read_dir(current).map_err(Error::from))? {
+        Some(files) => files,
+        None => return Ok(()),
+    };
+    for file in files {
+        let file = match failures.check(&current_name, file.map_err(Error::from))? {
+            Some(file) => file,
+            None => continue,
+        };
+        let path = file.path();
         let stripped_path = path.strip_prefix(parent).expect("prefix should exist");
-        utils::verbose!(verbose, "{}", stripped_path.display());
+        let name = stripped_path.to_string_lossy();
+
+        // The path the entry has inside the archive
+        let escaped = stripped_path
+            .iter()
+            .map(|segment| segment.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let original = match failures.check(
+            &name,
+            names.original(&escaped, &file.file_name().to_string_lossy()),
+        )? {
+            Some(original) => node.join(original),
+            None => continue,
+        };
         if path.is_dir() {
-            writer.add_package(stripped_path)?;
-            recursive_do_create(&path, parent, writer, verbose)?;
+            if failures
+                .check(&name, writer.add_package(&original))?
+                .is_some()
+            {
+                utils::verbose!(verbose, "{}", original.to_string_lossy());
+                recursive_do_create(&path, parent, &original, names, writer, verbose, failures)?;
+            }
         } else if path.is_file() {
-            writer.add_image(stripped_path, ImagePath::new(&path)?)?;
+            let result = FileImage::new(&path).and_then(|image| writer.add_image(&original, image));
+            if failures.check(&name, result)?.is_some() {
+                utils::verbose!(verbose, "{}", original.to_string_lossy());
+            }
         }
     }
     Ok(())
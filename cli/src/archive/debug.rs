@@ -1,7 +1,9 @@
 //! Parsing of WZ archives
 
-use crate::{utils, Key};
-use crypto::{KeyStream, GMS_IV, KMS_IV, TRIMMED_KEY};
+use crate::archive::list::node_json;
+use crate::{utils, Key, Output};
+use crypto::{KeyStream, TRIMMED_KEY};
+use serde_json::json;
 use std::path::PathBuf;
 use wz::{
     archive,
@@ -14,54 +16,54 @@ pub(crate) fn do_debug(
     directory: &Option<String>,
     key: Key,
     version: Option<u16>,
+    output: Output,
 ) -> Result<()> {
     let name = utils::file_name(path)?;
-    match key {
-        Key::Gms => match version {
+    match key.iv() {
+        Some(iv) => match version {
             Some(v) => debug(
                 name,
-                archive::Reader::open_as_version(path, v, KeyStream::new(&TRIMMED_KEY, &GMS_IV))?,
+                archive::Reader::open_as_version(path, v, KeyStream::new(&TRIMMED_KEY, &iv))?,
                 directory,
+                output,
             ),
             None => debug(
                 name,
-                archive::Reader::open(path, KeyStream::new(&TRIMMED_KEY, &GMS_IV))?,
+                archive::Reader::open(path, KeyStream::new(&TRIMMED_KEY, &iv))?,
                 directory,
+                output,
             ),
         },
-        Key::Kms => match version {
-            Some(v) => debug(
-                name,
-                archive::Reader::open_as_version(path, v, KeyStream::new(&TRIMMED_KEY, &KMS_IV))?,
-                directory,
-            ),
-            None => debug(
-                name,
-                archive::Reader::open(path, KeyStream::new(&TRIMMED_KEY, &KMS_IV))?,
-                directory,
-            ),
-        },
-        Key::None => match version {
+        None => match version {
             Some(v) => debug(
                 name,
                 archive::Reader::open_as_version(path, v, DummyDecryptor)?,
                 directory,
+                output,
             ),
             None => debug(
                 name,
                 archive::Reader::open(path, DummyDecryptor)?,
                 directory,
+                output,
             ),
         },
     }
 }
 
-fn debug<R>(name: &str, mut archive: archive::Reader<R>, directory: &Option<String>) -> Result<()>
+fn debug<R>(
+    name: &str,
+    mut archive: archive::Reader<R>,
+    directory: &Option<String>,
+    output: Output,
+) -> Result<()>
 where
     R: WzRead,
 {
     // Print the archive header
-    println!("{:?}", archive.header());
+    if output == Output::Text {
+        println!("{:?}", archive.header());
+    }
     let map = archive.map(name)?;
     let mut cursor = match directory {
         // Find the optional directory
@@ -69,22 +71,69 @@ where
         // Get the root
         None => map.cursor(),
     };
+    if output == Output::Json {
+        let header = archive.header();
+        let mut node = node_json(cursor.get());
+        node["path"] = json!(cursor.pwd());
+        node["children"] = cursor
+            .children()
+            .zip(cursor.list())
+            .map(|(child, name)| {
+                let mut child = node_json(child);
+                child["name"] = json!(name);
+                child
+            })
+            .collect();
+        println!(
+            "{}",
+            json!({
+                "header": {
+                    "identifier": String::from_utf8_lossy(&header.identifier),
+                    "size": header.size,
+                    "absolute_position": header.absolute_position,
+                    "description": header.description,
+                    "version_hash": header.version_hash.map(u16::from),
+                },
+                "node": node,
+            })
+        );
+        return Ok(());
+    }
 
-    // Print the directory and its immediate children
-    println!("{:?} : {:?}", cursor.name(), cursor.get());
+    // Print the directory and its immediate children, with the nodes lined up
+    let mut lines = vec![(
+        String::new(),
+        cursor.name().to_string(),
+        cursor.get().to_string(),
+    )];
     let mut num_children = cursor.children().count();
     if num_children > 0 {
         cursor.first_child()?;
         loop {
+            let prefix = match num_children <= 1 {
+                true => "`-- ",
+                false => "|-- ",
+            };
+            lines.push((
+                String::from(prefix),
+                cursor.name().to_string(),
+                cursor.get().to_string(),
+            ));
             if num_children <= 1 {
-                println!("`-- {:?} : {:?}", cursor.name(), cursor.get());
                 break;
-            } else {
-                println!("|-- {:?} : {:?}", cursor.name(), cursor.get());
             }
             num_children -= 1;
             cursor.next_sibling()?;
         }
     }
+    let width = lines
+        .iter()
+        .map(|(prefix, name, _)| prefix.chars().count() + name.chars().count())
+        .max()
+        .unwrap_or_default();
+    for (prefix, name, node) in lines {
+        let pad = width - prefix.chars().count() - name.chars().count();
+        println!("{}{}{:pad$}  {}", prefix, name, "", node, pad = pad);
+    }
     Ok(())
 }
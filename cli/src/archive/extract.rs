@@ -1,76 +1,115 @@
 //! Parsing of WZ archives
 
-use crate::{utils, Key};
-use crypto::{KeyStream, GMS_IV, KMS_IV, TRIMMED_KEY};
-use std::{fs, path::PathBuf};
+use crate::{
+    archive::{links, Failures, Names},
+    utils, Key,
+};
+use crypto::{Decryptor, Encryptor, KeyStream, TRIMMED_KEY};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
 use wz::{
     archive::{self, reader},
     error::{Error, Result},
-    io::{DummyDecryptor, WzRead},
+    image,
+    io::{DummyDecryptor, DummyEncryptor, WzRead},
+    vfs::{self, Vfs},
 };
 
 pub(crate) fn do_extract(
     path: &PathBuf,
+    out: &Path,
     verbose: bool,
     key: Key,
     version: Option<u16>,
-) -> Result<()> {
+    keep_going: bool,
+    inline: bool,
+) -> Result<Vec<(String, Error)>> {
     let filename = utils::file_name(path)?;
-    match key {
-        Key::Gms => extract(
-            filename,
-            match version {
-                Some(v) => archive::Reader::open_as_version(
-                    path,
-                    v,
-                    KeyStream::new(&TRIMMED_KEY, &GMS_IV),
-                )?,
-                None => archive::Reader::open(path, KeyStream::new(&TRIMMED_KEY, &GMS_IV))?,
-            },
-            verbose,
-        ),
-        Key::Kms => extract(
-            filename,
-            match version {
-                Some(v) => archive::Reader::open_as_version(
-                    path,
-                    v,
-                    KeyStream::new(&TRIMMED_KEY, &KMS_IV),
-                )?,
-                None => archive::Reader::open(path, KeyStream::new(&TRIMMED_KEY, &KMS_IV))?,
-            },
-            verbose,
-        ),
-        Key::None => extract(
+    match key.iv() {
+        Some(iv) => {
+            let key = KeyStream::new(&TRIMMED_KEY, &iv);
+            extract(
+                filename,
+                match version {
+                    Some(v) => archive::Reader::open_as_version(path, v, key.clone())?,
+                    None => archive::Reader::open(path, key.clone())?,
+                },
+                match inline {
+                    true => Some((links::open_vfs(path, key.clone())?, key)),
+                    false => None,
+                },
+                out,
+                verbose,
+                keep_going,
+            )
+        }
+        None => extract(
             filename,
             match version {
                 Some(v) => archive::Reader::open_as_version(path, v, DummyDecryptor)?,
                 None => archive::Reader::open(path, DummyDecryptor)?,
             },
+            match inline {
+                true => Some((links::open_vfs(path, DummyDecryptor)?, DummyEncryptor)),
+                false => None,
+            },
+            out,
             verbose,
+            keep_going,
         ),
     }
 }
 
-fn extract<R>(name: &str, mut archive: archive::Reader<R>, verbose: bool) -> Result<()>
+/// Extracts the archive. With `inline`, images with links are saved with the link targets
+/// copied in, encrypted with the given encryptor.
+fn extract<R, D, E>(
+    name: &str,
+    mut archive: archive::Reader<R>,
+    mut inline: Option<(Vfs<D>, E)>,
+    out: &Path,
+    verbose: bool,
+    keep_going: bool,
+) -> Result<Vec<(String, Error)>>
 where
     R: WzRead,
+    D: Decryptor,
+    E: Encryptor + Clone,
 {
-    let map = archive.map(&name.replace(".wz", ""))?;
+    let root = name.replace(".wz", "");
+    let map = archive.map(&root)?;
     let mut reader = archive.into_inner();
+    let mut failures = Failures::new(keep_going);
+    let mut names = Names::new();
+    fs::create_dir_all(out)?;
     map.walk::<Error>(|cursor| {
         let path = cursor.pwd();
-        match cursor.get() {
-            reader::Node::Package => {
-                utils::create_dir(&path)?;
-            }
-            reader::Node::Image { offset, size } => {
-                utils::remove_file(&path)?;
-                let mut output = fs::File::create(&path)?;
-                reader.copy_to(&mut output, *offset, *size)?;
+        let output = names
+            .escape(&cursor.segments())
+            .map(|segments| out.join(segments.join("/")));
+        let result = output.and_then(|output| match cursor.get() {
+            reader::Node::Package => utils::create_dir(&output),
+            reader::Node::Image { offset, size, .. } => {
+                utils::remove_file(&output).and_then(|_| {
+                    if let Some((vfs, encryptor)) = &mut inline {
+                        let (image, inlined) = vfs::links::inline(vfs, &path)?;
+                        if !inlined.is_empty() {
+                            utils::verbose!(verbose, "{}: {} links inlined", path, inlined.len());
+                            let mut writer = image::Writer::from_map(image);
+                            return writer.save(&output, encryptor.clone());
+                        }
+                    }
+                    let mut output = fs::File::create(&output)?;
+                    reader.copy_to(&mut output, *offset, *size)
+                })
             }
+        });
+        if failures.check(&path, result)?.is_some() {
+            utils::verbose!(verbose, "{}", path);
         }
-        utils::verbose!(verbose, "{}", path);
         Ok(())
-    })
+    })?;
+    names.save(&out.join(format!("{}.names", root)))?;
+    Ok(failures.into_errors())
 }
@@ -0,0 +1,34 @@
+//! Entries skipped by batch operations
+
+use wz::error::{Error, Result};
+
+/// Errors of the entries skipped with `--keep-going`
+pub(crate) struct Failures {
+    keep_going: bool,
+    errors: Vec<(String, Error)>,
+}
+
+impl Failures {
+    pub(crate) fn new(keep_going: bool) -> Self {
+        Self {
+            keep_going,
+            errors: Vec::new(),
+        }
+    }
+
+    /// Records the error of the entry at `path` when keeping going, otherwise returns it
+    pub(crate) fn check<T>(&mut self, path: &str, result: Result<T>) -> Result<Option<T>> {
+        match result {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if self.keep_going => {
+                self.errors.push((String::from(path), e));
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    pub(crate) fn into_errors(self) -> Vec<(String, Error)> {
+        self.errors
+    }
+}
@@ -0,0 +1,94 @@
+//! Searching WZ archive strings
+
+use crate::{utils, Key};
+use crypto::{Decryptor, KeyStream, TRIMMED_KEY};
+use rayon::prelude::*;
+use regex::Regex;
+use std::{fs::File, io::BufReader, io::ErrorKind, path::PathBuf};
+use wz::{
+    archive::{self, reader::Node},
+    error::{Error, Result},
+    image::Scanner,
+    io::{DummyDecryptor, WzImageReader, WzRead, WzReader},
+    types::{Property, WzOffset},
+};
+
+pub(crate) fn do_grep(path: &PathBuf, pattern: &str, key: Key, version: Option<u16>) -> Result<()> {
+    let regex = match Regex::new(pattern) {
+        Ok(regex) => regex,
+        Err(e) => {
+            eprintln!("{}", e);
+            return Err(ErrorKind::InvalidInput.into());
+        }
+    };
+    match key.iv() {
+        Some(iv) => grep(path, &regex, version, KeyStream::new(&TRIMMED_KEY, &iv)),
+        None => grep(path, &regex, version, DummyDecryptor),
+    }
+}
+
+fn grep<D>(path: &PathBuf, regex: &Regex, version: Option<u16>, decryptor: D) -> Result<()>
+where
+    D: Decryptor + Clone + Send + Sync,
+{
+    let name = utils::file_name(path)?;
+    let mut archive = match version {
+        Some(v) => archive::Reader::open_as_version(path, v, decryptor.clone())?,
+        None => archive::Reader::open(path, decryptor.clone())?,
+    };
+    let map = archive.map(name)?;
+    let inner = archive.into_inner();
+    let (absolute_position, version_checksum) =
+        (inner.absolute_position(), inner.version_checksum());
+
+    // Collect the images to scan
+    let mut images: Vec<(String, WzOffset)> = Vec::new();
+    map.walk::<Error>(|cursor| {
+        // Empty images hold no strings
+        match cursor.get() {
+            node if node.is_empty_image() => {}
+            Node::Image { offset, .. } => images.push((cursor.pwd(), *offset)),
+            Node::Package => {}
+        }
+        Ok(())
+    })?;
+
+    // Scan each image on its own file handle
+    let matches = images
+        .par_iter()
+        .map_init(
+            || {
+                File::open(path).map(|file| {
+                    WzReader::new(
+                        absolute_position,
+                        version_checksum,
+                        BufReader::new(file),
+                        decryptor.clone(),
+                    )
+                })
+            },
+            |reader, (image_path, offset)| {
+                let reader = reader.as_mut().map_err(|e| Error::from(e.kind()))?;
+                let mut image_reader = WzImageReader::with_offset(reader, *offset);
+                image_reader.seek_to_start()?;
+                let mut found = Vec::new();
+                Scanner::new(image_reader).scan(image_path, |path, property| {
+                    let value = match property {
+                        Property::String(s) => s.as_ref(),
+                        Property::Uol(u) => u.as_ref(),
+                        _ => return Ok(()),
+                    };
+                    if regex.is_match(value) {
+                        found.push(format!("{}: {}", path, value));
+                    }
+                    Ok(())
+                })?;
+                Ok(found)
+            },
+        )
+        .collect::<Result<Vec<Vec<String>>>>()?;
+    for line in matches.iter().flatten() {
+        println!("{}", line);
+    }
+    Ok(())
+}
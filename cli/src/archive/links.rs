@@ -0,0 +1,54 @@
+//! References between the images of WZ archives
+
+use crate::{utils, Key};
+use crypto::{Decryptor, KeyStream, TRIMMED_KEY};
+use std::path::{Path, PathBuf};
+use wz::{
+    error::Result,
+    io::DummyDecryptor,
+    vfs::{links::Graph, Vfs},
+};
+
+pub(crate) fn do_links(path: &PathBuf, verbose: bool, key: Key) -> Result<()> {
+    match key.iv() {
+        Some(iv) => links(path, verbose, KeyStream::new(&TRIMMED_KEY, &iv)),
+        None => links(path, verbose, DummyDecryptor),
+    }
+}
+
+/// Opens every archive next to the one at `path`, so links into other archives can be followed
+pub(crate) fn open_vfs<D>(path: &Path, decryptor: D) -> Result<Vfs<D>>
+where
+    D: Decryptor + Clone,
+{
+    let dir = match utils::parent(&path)? {
+        dir if dir.as_os_str().is_empty() => Path::new("."),
+        dir => dir,
+    };
+    Vfs::open(dir, decryptor)
+}
+
+fn links<D>(path: &PathBuf, verbose: bool, decryptor: D) -> Result<()>
+where
+    D: Decryptor + Clone,
+{
+    let name = utils::file_name(path)?.replace(".wz", "");
+    let mut vfs = open_vfs(path, decryptor)?;
+    let graph = Graph::build(&mut vfs, &name)?;
+    for link in graph.links() {
+        utils::verbose!(
+            verbose,
+            "{} -> {} ({:?})",
+            link.source,
+            link.target,
+            link.kind
+        );
+    }
+    let mut dangling = 0;
+    for link in graph.dangling() {
+        println!("dangling: {} -> {}", link.source, link.target);
+        dangling += 1;
+    }
+    println!("{} links, {} dangling", graph.links().len(), dangling);
+    Ok(())
+}
@@ -1,56 +1,106 @@
 //! Parsing of WZ archives
 
-use crate::{utils, Key};
-use crypto::{KeyStream, GMS_IV, KMS_IV, TRIMMED_KEY};
-use std::path::PathBuf;
+use crate::{utils, Key, Output};
+use crypto::{Decryptor, KeyStream, TRIMMED_KEY};
+use serde_json::{json, Value};
+use std::{fs::File, io::BufReader, path::PathBuf};
 use wz::{
-    archive,
+    archive::{self, reader::Node},
     error::{Error, Result},
     io::DummyDecryptor,
     list,
 };
 
-pub(crate) fn do_list(path: &PathBuf, key: Key, version: Option<u16>) -> Result<()> {
+pub(crate) fn do_list(
+    path: &PathBuf,
+    key: Key,
+    version: Option<u16>,
+    index: bool,
+    output: Output,
+) -> Result<()> {
     let name = utils::file_name(path)?;
 
     // Map the WZ archive
-    let map = match key {
-        Key::Gms => match version {
-            Some(v) => {
-                archive::Reader::open_as_version(path, v, KeyStream::new(&TRIMMED_KEY, &GMS_IV))?
-                    .map(name)?
+    let map = if index {
+        let index_path = format!("{}.idx", path.display());
+        match key.iv() {
+            Some(iv) => {
+                let decryptor = KeyStream::new(&TRIMMED_KEY, &iv);
+                archive::Reader::open_indexed(path, index_path, name, decryptor)?.1
             }
-            None => {
-                archive::Reader::open(path, KeyStream::new(&TRIMMED_KEY, &GMS_IV))?.map(name)?
-            }
-        },
-        Key::Kms => match version {
-            Some(v) => {
-                archive::Reader::open_as_version(path, v, KeyStream::new(&TRIMMED_KEY, &KMS_IV))?
-                    .map(name)?
-            }
-            None => {
-                archive::Reader::open(path, KeyStream::new(&TRIMMED_KEY, &KMS_IV))?.map(name)?
-            }
-        },
-        Key::None => match version {
-            Some(v) => archive::Reader::open_as_version(path, v, DummyDecryptor)?.map(name)?,
-            None => archive::Reader::open(path, DummyDecryptor)?.map(name)?,
-        },
+            None => archive::Reader::open_indexed(path, index_path, name, DummyDecryptor)?.1,
+        }
+    } else {
+        match key.iv() {
+            Some(iv) => match version {
+                Some(v) => {
+                    archive::Reader::open_as_version(path, v, KeyStream::new(&TRIMMED_KEY, &iv))?
+                        .map(name)?
+                }
+                None => {
+                    archive::Reader::open(path, KeyStream::new(&TRIMMED_KEY, &iv))?.map(name)?
+                }
+            },
+            None => match version {
+                Some(v) => archive::Reader::open_as_version(path, v, DummyDecryptor)?.map(name)?,
+                None => archive::Reader::open(path, DummyDecryptor)?.map(name)?,
+            },
+        }
     };
 
     // Walk the map
-    map.walk::<Error>(|cursor| Ok(println!("{}", &cursor.pwd())))
+    match output {
+        Output::Text => map.walk::<Error>(|cursor| Ok(println!("{}", &cursor.pwd()))),
+        Output::Json => {
+            let mut nodes = Vec::new();
+            map.walk::<Error>(|cursor| {
+                let mut node = node_json(cursor.get());
+                node["path"] = json!(cursor.pwd());
+                node["index"] = json!(cursor.index());
+                nodes.push(node);
+                Ok(())
+            })?;
+            println!("{}", Value::Array(nodes));
+            Ok(())
+        }
+    }
+}
+
+/// Describes an archive node. Images include their offset, size and checksum.
+pub(crate) fn node_json(node: &Node) -> Value {
+    match node {
+        Node::Package => json!({ "type": "package" }),
+        Node::Image {
+            offset,
+            size,
+            checksum,
+        } => json!({
+            "type": "image",
+            "offset": **offset,
+            "size": **size,
+            "checksum": **checksum,
+        }),
+    }
 }
 
 pub(crate) fn do_list_file(path: &PathBuf, key: Key) -> Result<()> {
-    let reader = match key {
-        Key::Gms => list::Reader::parse(path, KeyStream::new(&TRIMMED_KEY, &GMS_IV))?,
-        Key::Kms => list::Reader::parse(path, KeyStream::new(&TRIMMED_KEY, &KMS_IV))?,
-        Key::None => list::Reader::parse(path, DummyDecryptor)?,
-    };
-    for string in reader.strings() {
-        println!("{}", string);
+    match key.iv() {
+        Some(iv) => list_file(path, KeyStream::new(&TRIMMED_KEY, &iv)),
+        None => list_file(path, DummyDecryptor),
+    }
+}
+
+fn list_file<D>(path: &PathBuf, decryptor: D) -> Result<()>
+where
+    D: Decryptor,
+{
+    // Entries are printed as they are read so large files are not held in memory
+    let mut entries = list::Reader::iter(BufReader::new(File::open(path)?), decryptor);
+    for entry in entries.by_ref() {
+        println!("{}", entry?.path);
+    }
+    for (offset, malformed) in entries.malformed() {
+        eprintln!("Malformed entry at {}: {:?}", offset, malformed);
     }
     Ok(())
 }
@@ -0,0 +1,97 @@
+//! WZ archive content manifests
+
+use crate::{utils, Key, Output};
+use crypto::{KeyStream, TRIMMED_KEY};
+use serde_json::json;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use wz::{
+    archive::{self, manifest::Mismatch, Manifest},
+    error::{PackageError, Result},
+    io::{DummyDecryptor, WzRead},
+};
+
+pub(crate) fn do_manifest(
+    path: &PathBuf,
+    output: &Path,
+    key: Key,
+    version: Option<u16>,
+) -> Result<()> {
+    match key.iv() {
+        Some(iv) => match version {
+            Some(v) => write_manifest(
+                archive::Reader::open_as_version(path, v, KeyStream::new(&TRIMMED_KEY, &iv))?,
+                path,
+                output,
+            ),
+            None => write_manifest(
+                archive::Reader::open(path, KeyStream::new(&TRIMMED_KEY, &iv))?,
+                path,
+                output,
+            ),
+        },
+        None => match version {
+            Some(v) => write_manifest(
+                archive::Reader::open_as_version(path, v, DummyDecryptor)?,
+                path,
+                output,
+            ),
+            None => write_manifest(archive::Reader::open(path, DummyDecryptor)?, path, output),
+        },
+    }
+}
+
+/// Checks the images extracted under `directory` against the manifest
+pub(crate) fn do_verify(
+    manifest: &Path,
+    directory: &Option<String>,
+    verbose: bool,
+    output: Output,
+) -> Result<()> {
+    let manifest = Manifest::from_text(&fs::read_to_string(manifest)?)?;
+    let directory = PathBuf::from(directory.as_deref().unwrap_or("."));
+    let mismatches = manifest.verify(directory)?;
+    let reasons = mismatches.iter().map(|(entry, mismatch)| {
+        let reason = match mismatch {
+            Mismatch::Missing => "missing",
+            Mismatch::Size => "size differs",
+            Mismatch::Hash => "hash differs",
+        };
+        (entry, reason)
+    });
+    match output {
+        Output::Text => {
+            for (entry, reason) in reasons {
+                println!("{}: {}", entry.path, reason);
+            }
+        }
+        Output::Json => {
+            let mismatches = reasons
+                .map(|(entry, reason)| json!({ "path": entry.path, "reason": reason }))
+                .collect::<Vec<_>>();
+            println!(
+                "{}",
+                json!({ "images": manifest.entries().len(), "mismatches": mismatches })
+            );
+        }
+    }
+    match mismatches.len() {
+        0 => {
+            utils::verbose!(verbose, "{} images match", manifest.entries().len());
+            Ok(())
+        }
+        n => Err(PackageError::Verify(n).into()),
+    }
+}
+
+fn write_manifest<R>(mut archive: archive::Reader<R>, path: &Path, output: &Path) -> Result<()>
+where
+    R: WzRead,
+{
+    let name = utils::file_name(&path)?.replace(".wz", "");
+    let map = archive.map(&name)?;
+    let manifest = archive::manifest(&mut archive.into_inner(), &map)?;
+    Ok(fs::write(output, manifest.to_text())?)
+}
@@ -3,13 +3,37 @@
 mod create;
 mod debug;
 mod extract;
-mod imagepath;
+mod failures;
+mod grep;
+mod links;
 mod list;
+mod manifest;
+mod names;
+mod optimize;
+mod pack;
+mod patch;
+mod preview;
 mod server;
+mod stats;
+mod status;
+mod sync;
+mod text;
 
 pub(crate) use create::do_create;
 pub(crate) use debug::do_debug;
 pub(crate) use extract::do_extract;
-pub(crate) use imagepath::ImagePath;
+pub(crate) use failures::Failures;
+pub(crate) use grep::do_grep;
+pub(crate) use links::do_links;
 pub(crate) use list::{do_list, do_list_file};
+pub(crate) use manifest::{do_manifest, do_verify};
+pub(crate) use names::Names;
+pub(crate) use optimize::do_optimize;
+pub(crate) use pack::{do_pack, do_unpack};
+pub(crate) use patch::{do_apply_patch, do_make_patch};
+pub(crate) use preview::do_preview;
 pub(crate) use server::do_server;
+pub(crate) use stats::do_stats;
+pub(crate) use status::do_status;
+pub(crate) use sync::do_sync;
+pub(crate) use text::{do_build_text_index, do_search};
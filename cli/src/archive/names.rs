@@ -0,0 +1,272 @@
+//! Escaping of node names that are not valid file names on every platform
+
+use crate::utils;
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::Write,
+    fs,
+    io::ErrorKind,
+    path::Path,
+};
+use wz::error::{PackageError, Result};
+
+/// Device names Windows reserves regardless of extension
+const RESERVED: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Paths of the extracted entries whose names were escaped, relative to the extraction directory.
+/// Written next to the extracted root as `<root>.names` so `create` can restore the original
+/// names.
+///
+/// Siblings whose names only differ by case would overwrite each other on case-insensitive file
+/// systems. The first one escaped keeps its name and the leading characters of the others are
+/// escaped until their file names differ regardless of case, so every platform extracts them to
+/// distinct files.
+#[derive(Default)]
+pub(crate) struct Names {
+    escaped: BTreeSet<String>,
+
+    /// Lowercased file paths, to the node path that was given each of them
+    folded: HashMap<String, String>,
+}
+
+impl Names {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads the escaped paths from `path`. A missing file means nothing was escaped.
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(text) => Ok(Self {
+                escaped: text.lines().map(String::from).collect(),
+                folded: HashMap::new(),
+            }),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Writes the escaped paths to `path`, or removes it if nothing was escaped
+    pub(crate) fn save(&self, path: &Path) -> Result<()> {
+        match self.escaped.is_empty() {
+            true => utils::remove_file(path),
+            false => {
+                let text = self.escaped.iter().fold(String::new(), |mut text, path| {
+                    let _ = writeln!(text, "{}", path);
+                    text
+                });
+                Ok(fs::write(path, text)?)
+            }
+        }
+    }
+
+    /// Returns the file names for `segments`, recording every escaped one
+    pub(crate) fn escape(&mut self, segments: &[&str]) -> Result<Vec<String>> {
+        let mut names: Vec<String> = Vec::with_capacity(segments.len());
+        for (i, segment) in segments.iter().enumerate() {
+            if segment.is_empty() {
+                return Err(PackageError::Path(segments.join("/")).into());
+            }
+            let node = segments[..=i].join("/");
+            let parent = names.join("/");
+            let path = |name: &str| match parent.is_empty() {
+                true => String::from(name),
+                false => format!("{}/{}", parent, name),
+            };
+            // Escapes more of the name while it clashes with a sibling
+            let mut forced = 0;
+            let (name, escaped) = loop {
+                let (name, escaped) = match escape(segment, forced) {
+                    Some(escaped) => (escaped, true),
+                    None => (String::from(*segment), false),
+                };
+                match self.folded.get(&path(&name).to_lowercase()) {
+                    Some(owner) if *owner != node && forced < segment.chars().count() => {
+                        forced += 1
+                    }
+                    Some(owner) if *owner != node => {
+                        return Err(PackageError::Path(segments.join("/")).into())
+                    }
+                    _ => break (name, escaped),
+                }
+            };
+            let file_path = path(&name);
+            self.folded.insert(file_path.to_lowercase(), node);
+            if escaped {
+                self.escaped.insert(file_path);
+            }
+            names.push(name);
+        }
+        Ok(names)
+    }
+
+    /// Returns the node name of the file at `path`, which is relative to the extraction directory
+    /// and separated by `/`
+    pub(crate) fn original(&self, path: &str, name: &str) -> Result<String> {
+        match self.escaped.contains(path) {
+            true => unescape(name).ok_or_else(|| PackageError::Path(String::from(path)).into()),
+            false => Ok(String::from(name)),
+        }
+    }
+}
+
+/// Returns `name` with the characters that would make it an invalid or unsafe file name, and its
+/// first `forced` characters, percent-encoded, or `None` if it can be used as is
+fn escape(name: &str, forced: usize) -> Option<String> {
+    let stem = name.split('.').next().unwrap_or_default();
+    let reserved = RESERVED.iter().any(|r| r.eq_ignore_ascii_case(stem));
+    let last = name.chars().count().checked_sub(1)?;
+    let unsafe_at = |i: usize, c: char| {
+        matches!(c, '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*')
+            || c.is_control()
+            || (i == 0 && reserved)
+            || (i == last && matches!(c, '.' | ' '))
+            || i < forced
+    };
+    if !name.chars().enumerate().any(|(i, c)| unsafe_at(i, c)) {
+        return None;
+    }
+    let mut escaped = String::with_capacity(name.len() * 3);
+    for (i, c) in name.chars().enumerate() {
+        match unsafe_at(i, c) || c == '%' {
+            true => {
+                let mut bytes = [0; 4];
+                for byte in c.encode_utf8(&mut bytes).bytes() {
+                    let _ = write!(escaped, "%{:02X}", byte);
+                }
+            }
+            false => escaped.push(c),
+        }
+    }
+    Some(escaped)
+}
+
+/// Reverses [`escape`]
+fn unescape(name: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(name.len());
+    let mut rest = name.as_bytes();
+    while let Some((&byte, remaining)) = rest.split_first() {
+        match byte {
+            b'%' => {
+                let hex = std::str::from_utf8(remaining.get(..2)?).ok()?;
+                bytes.push(u8::from_str_radix(hex, 16).ok()?);
+                rest = &remaining[2..];
+            }
+            _ => {
+                bytes.push(byte);
+                rest = remaining;
+            }
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{escape, unescape, Names};
+    use std::{collections::HashSet, env, fs};
+
+    /// Returns the name the file of `name` is restored to
+    fn round_trip(name: &str) -> Option<String> {
+        match escape(name, 0) {
+            Some(escaped) => unescape(&escaped),
+            None => Some(String::from(name)),
+        }
+    }
+
+    #[test]
+    fn escape_names() {
+        for name in [
+            "CON",
+            "con",
+            "aux.img",
+            "com1.txt",
+            "LPT9.tar.gz",
+            "trailing.",
+            "trailing ",
+            ".",
+            "..",
+            "100%",
+            "%41",
+            "a%b:c",
+            "tab\there",
+            "new\nline",
+            "\u{7f}",
+            "한글.img",
+            "日本*語",
+            "é?",
+        ] {
+            assert_eq!(round_trip(name).as_deref(), Some(name), "{:?}", name);
+        }
+        for name in [
+            "CON", "aux.img", "com1.txt", "a.", "a ", ".", "..", "a\tb", "한:글",
+        ] {
+            let escaped = escape(name, 0).unwrap_or_else(|| panic!("{:?} should be escaped", name));
+            assert!(
+                escaped
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "%._- ".contains(c) || !c.is_ascii()),
+                "{:?}",
+                escaped
+            );
+            assert!(!escaped.ends_with(['.', ' ']), "{:?}", escaped);
+        }
+        assert_eq!(escape("CON", 0).as_deref(), Some("%43ON"));
+        assert_eq!(escape("..", 0).as_deref(), Some(".%2E"));
+        assert_eq!(escape("a%b:c", 0).as_deref(), Some("a%25b%3Ac"));
+        assert_eq!(escape("Mob.img", 0), None);
+        assert_eq!(escape("100%", 0), None);
+    }
+
+    #[test]
+    fn restore_names() {
+        let paths = [
+            "Map/aux.img",
+            "Map/Back/new\nline.img",
+            "Map/Back/ok.img",
+            "Map/Obj/Foo.img",
+            "Map/Obj/foo.img",
+            "Map/Obj/FOO.img",
+            "Map/obj/x.img",
+            "Map/50%.img",
+        ];
+        let mut names = Names::new();
+        let mut files = Vec::new();
+        for path in paths.iter().filter(|path| !path.ends_with('/')) {
+            let segments = path.split('/').collect::<Vec<_>>();
+            let escaped = names.escape(&segments).expect("error escaping");
+            // Escaping the same node twice gives the same names
+            assert_eq!(names.escape(&segments).expect("error escaping"), escaped);
+            files.push((segments, escaped));
+        }
+        let folded = files
+            .iter()
+            .map(|(_, escaped)| escaped.join("/").to_lowercase())
+            .collect::<HashSet<_>>();
+        assert_eq!(folded.len(), files.len());
+        assert!(names.escape(&["Map", ""]).is_err());
+
+        let dir = env::temp_dir().join("wzarchive-names-test");
+        fs::create_dir_all(&dir).expect("error creating directory");
+        let path = dir.join("Map.names");
+        names.save(&path).expect("error saving names");
+        let loaded = Names::load(&path).expect("error loading names");
+        for (segments, escaped) in &files {
+            for i in 0..segments.len() {
+                let original = loaded
+                    .original(&escaped[..=i].join("/"), &escaped[i])
+                    .expect("error restoring name");
+                assert_eq!(original, segments[i]);
+            }
+        }
+
+        // Nothing escaped removes the file
+        Names::new().save(&path).expect("error saving names");
+        assert!(!path.exists());
+        fs::remove_dir_all(&dir).expect("error removing directory");
+    }
+}
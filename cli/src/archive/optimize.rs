@@ -0,0 +1,113 @@
+//! Optimization of WZ archives
+
+use crate::{utils, Key};
+use crypto::{Decryptor, Encryptor, KeyStream, TRIMMED_KEY};
+use std::path::{Path, PathBuf};
+use wz::{
+    archive::{
+        self,
+        optimize::{self, Policy, Report},
+    },
+    error::Result,
+    io::{DummyDecryptor, DummyEncryptor},
+};
+
+/// Rebuilds the archive at `path` into `output` according to `policy` and prints what changed
+pub(crate) fn do_optimize(
+    path: &PathBuf,
+    output: &Path,
+    policy: &Policy,
+    verbose: bool,
+    key: Key,
+    version: Option<u16>,
+    jobs: usize,
+) -> Result<()> {
+    let report = match key.iv() {
+        Some(iv) => optimize(
+            path,
+            output,
+            policy,
+            version,
+            || KeyStream::new(&TRIMMED_KEY, &iv),
+            KeyStream::new(&TRIMMED_KEY, &iv),
+            jobs,
+        )?,
+        None => optimize(
+            path,
+            output,
+            policy,
+            version,
+            || DummyDecryptor,
+            DummyEncryptor,
+            jobs,
+        )?,
+    };
+
+    for path in &report.stripped {
+        utils::verbose!(verbose, "{}: stripped", path);
+    }
+    for (path, before, after) in &report.canvases {
+        utils::verbose!(verbose, "{}: {} -> {} bytes", path, before, after);
+    }
+    for (path, original) in &report.shared {
+        utils::verbose!(verbose, "{}: shares the data of {}", path, original);
+    }
+    let saved = report
+        .canvases
+        .iter()
+        .map(|(_, before, after)| before - after)
+        .sum::<usize>();
+    println!("{} entries stripped", report.stripped.len());
+    println!(
+        "{} canvases re-encoded, {} bytes saved",
+        report.canvases.len(),
+        saved
+    );
+    println!("{} images share their data", report.shared.len());
+    let (before, after) = (path.metadata()?.len(), output.metadata()?.len());
+    println!(
+        "{} -> {} bytes, {} saved",
+        before,
+        after,
+        before.saturating_sub(after)
+    );
+    Ok(())
+}
+
+fn optimize<D, F, E>(
+    path: &PathBuf,
+    output: &Path,
+    policy: &Policy,
+    version: Option<u16>,
+    decryptor: F,
+    encryptor: E,
+    jobs: usize,
+) -> Result<Report>
+where
+    D: Decryptor,
+    F: Fn() -> D,
+    E: Encryptor + Clone + Send,
+{
+    let name = utils::file_name(path)?;
+    let mut reader = match version {
+        Some(v) => archive::Reader::open_as_version(path, v, decryptor())?,
+        None => archive::Reader::open(path, decryptor())?,
+    };
+    let version = match version {
+        Some(v) => v,
+        None => reader.resolve_version()?,
+    };
+    let header = reader.header().clone();
+    let map = reader.map(name)?;
+    let (mut writer, report) = optimize::optimize(
+        &mut reader,
+        &map,
+        path,
+        policy,
+        decryptor,
+        encryptor.clone(),
+        jobs,
+    )?;
+    writer.save(output, version, header, encryptor)?;
+    Ok(report)
+}
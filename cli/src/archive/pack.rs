@@ -0,0 +1,196 @@
+//! Compressed cold storage of WZ archives
+
+use crate::{utils, Key};
+use crypto::{KeyStream, TRIMMED_KEY};
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+use wz::{
+    archive::{self, reader::Node},
+    error::{Error, PackageError, Result},
+    io::DummyDecryptor,
+};
+
+const MAGIC: &[u8; 4] = b"WZPK";
+const FORMAT_VERSION: u16 = 1;
+const LEVEL: i32 = 19;
+
+/// Image stored in the pack
+struct Entry {
+    path: String,
+    offset: u64,
+    size: u64,
+}
+
+/// Packs the WZ archive at `path` into a zstd-compressed `output`.
+///
+/// The pack starts with a manifest of every image path, offset and size. It is followed by the
+/// archive bytes in order: each image is preceded by the header and package bytes in front of it.
+/// Images are stored as they are in the archive so unpacking restores it byte for byte.
+pub(crate) fn do_pack(path: &PathBuf, output: &Path, key: Key, version: Option<u16>) -> Result<()> {
+    let name = utils::file_name(path)?;
+    let map = match key.iv() {
+        Some(iv) => match version {
+            Some(v) => {
+                archive::Reader::open_as_version(path, v, KeyStream::new(&TRIMMED_KEY, &iv))?
+                    .map(name)?
+            }
+            None => archive::Reader::open(path, KeyStream::new(&TRIMMED_KEY, &iv))?.map(name)?,
+        },
+        None => match version {
+            Some(v) => archive::Reader::open_as_version(path, v, DummyDecryptor)?.map(name)?,
+            None => archive::Reader::open(path, DummyDecryptor)?.map(name)?,
+        },
+    };
+
+    // Collect the images in file order
+    let mut entries = Vec::new();
+    map.walk::<Error>(|cursor| {
+        if let Node::Image { offset, size, .. } = cursor.get() {
+            entries.push(Entry {
+                path: cursor.pwd(),
+                offset: **offset as u64,
+                size: **size as u64,
+            });
+        }
+        Ok(())
+    })?;
+    entries.sort_by_key(|entry| entry.offset);
+    let length = path.metadata()?.len();
+    let mut position = 0;
+    for entry in &entries {
+        if entry.offset < position || entry.offset + entry.size > length {
+            return Err(PackageError::Path(entry.path.clone()).into());
+        }
+        position = entry.offset + entry.size;
+    }
+
+    let mut src = BufReader::new(File::open(path)?);
+    let mut dest = zstd::Encoder::new(BufWriter::new(File::create(output)?), LEVEL)?;
+    dest.write_all(MAGIC)?;
+    dest.write_all(&FORMAT_VERSION.to_le_bytes())?;
+    dest.write_all(&length.to_le_bytes())?;
+    dest.write_all(&(entries.len() as u64).to_le_bytes())?;
+    for entry in &entries {
+        write_string(&mut dest, &entry.path)?;
+        dest.write_all(&entry.offset.to_le_bytes())?;
+        dest.write_all(&entry.size.to_le_bytes())?;
+    }
+
+    // Gaps between images are known from the manifest, so the bytes are simply copied in order
+    io::copy(&mut (&mut src).take(length), &mut dest)?;
+    dest.finish()?.flush()?;
+    Ok(())
+}
+
+/// Restores the WZ archive packed in `input` to `path`
+pub(crate) fn do_unpack(path: &PathBuf, input: &Path, verbose: bool) -> Result<()> {
+    let mut src = zstd::Decoder::new(File::open(input)?)?;
+    let mut magic = [0u8; 4];
+    src.read_exact(&mut magic)?;
+    if &magic != MAGIC || read_u16(&mut src)? != FORMAT_VERSION {
+        return Err(PackageError::Header.into());
+    }
+    let length = read_u64(&mut src)?;
+    for _ in 0..read_u64(&mut src)? {
+        let image = read_string(&mut src)?;
+        let (offset, size) = (read_u64(&mut src)?, read_u64(&mut src)?);
+        utils::verbose!(verbose, "{} ({} bytes at {})", image, size, offset);
+    }
+
+    // A truncated or corrupt pack must not leave part of an archive behind
+    let mut dest = BufWriter::new(File::create(path)?);
+    let copied = io::copy(&mut src.take(length), &mut dest).and_then(|copied| {
+        dest.flush()?;
+        Ok(copied)
+    });
+    drop(dest);
+    match copied {
+        Ok(copied) if copied == length => Ok(()),
+        copied => {
+            utils::remove_file(path)?;
+            match copied {
+                Err(e) => Err(e.into()),
+                Ok(_) => Err(PackageError::Header.into()),
+            }
+        }
+    }
+}
+
+fn write_string<W>(writer: &mut W, s: &str) -> Result<()>
+where
+    W: Write,
+{
+    writer.write_all(&(s.len() as u32).to_le_bytes())?;
+    Ok(writer.write_all(s.as_bytes())?)
+}
+
+fn read_string<R>(reader: &mut R) -> Result<String>
+where
+    R: Read,
+{
+    let mut len = [0u8; 4];
+    reader.read_exact(&mut len)?;
+    let mut data = vec![0u8; u32::from_le_bytes(len) as usize];
+    reader.read_exact(&mut data)?;
+    String::from_utf8(data).map_err(|_| PackageError::Header.into())
+}
+
+fn read_u16<R: Read>(reader: &mut R) -> Result<u16> {
+    let mut buf = [0u8; 2];
+    reader.read_exact(&mut buf)?;
+    Ok(u16::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{do_pack, do_unpack, MAGIC};
+    use crate::Key;
+    use std::{env, fs, io::Write, path::PathBuf};
+
+    #[test]
+    fn pack_round_trip() {
+        let dir = env::temp_dir().join("wzarchive-pack-test");
+        fs::create_dir_all(&dir).expect("error creating directory");
+        let archive = PathBuf::from("../wz/testdata/v83-base.wz");
+        let packed = dir.join("Base.wzpk");
+        let unpacked = dir.join("Base.wz");
+        do_pack(&archive, &packed, Key::Gms, None).expect("error packing");
+        do_unpack(&unpacked, &packed, false).expect("error unpacking");
+        assert_eq!(
+            fs::read(&unpacked).expect("error reading unpacked archive"),
+            fs::read(&archive).expect("error reading archive")
+        );
+
+        // A truncated pack is missing archive bytes and leaves nothing behind
+        let data = fs::read(&packed).expect("error reading pack");
+        let truncated = dir.join("truncated.wzpk");
+        fs::remove_file(&unpacked).expect("error removing archive");
+        for length in [data.len() / 2, data.len() - 8] {
+            fs::write(&truncated, &data[..length]).expect("error writing pack");
+            assert!(do_unpack(&unpacked, &truncated, false).is_err());
+            assert!(!unpacked.exists());
+        }
+
+        // So is one that is not a pack
+        let corrupt = dir.join("corrupt.wzpk");
+        let mut encoder = zstd::Encoder::new(Vec::new(), 0).expect("error compressing");
+        encoder.write_all(b"WZPX").expect("error compressing");
+        encoder.write_all(&[1, 0]).expect("error compressing");
+        fs::write(&corrupt, encoder.finish().expect("error compressing"))
+            .expect("error writing pack");
+        assert!(do_unpack(&unpacked, &corrupt, false).is_err());
+        fs::write(&corrupt, MAGIC).expect("error writing pack");
+        assert!(do_unpack(&unpacked, &corrupt, false).is_err());
+        fs::remove_dir_all(&dir).expect("error removing directory");
+    }
+}
@@ -0,0 +1,135 @@
+//! Differential patches of WZ archives
+
+use crate::{utils, Key};
+use crypto::{Decryptor, Encryptor, KeyStream, TRIMMED_KEY};
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::Path,
+};
+use wz::{
+    archive::{self, patch::Entry, Patch},
+    error::Result,
+    io::{DummyDecryptor, DummyEncryptor, WzReader},
+};
+
+const LEVEL: i32 = 19;
+
+/// Writes a zstd-compressed patch that turns the archive at `base` into the one at `path`
+pub(crate) fn do_make_patch(
+    path: &Path,
+    base: &Path,
+    output: &Path,
+    verbose: bool,
+    key: Key,
+    version: Option<u16>,
+) -> Result<()> {
+    let patch = match key.iv() {
+        Some(iv) => make_patch(
+            open(base, None, KeyStream::new(&TRIMMED_KEY, &iv))?,
+            open(path, version, KeyStream::new(&TRIMMED_KEY, &iv))?,
+            version,
+        )?,
+        None => make_patch(
+            open(base, None, DummyDecryptor)?,
+            open(path, version, DummyDecryptor)?,
+            version,
+        )?,
+    };
+    for entry in patch.entries() {
+        match entry {
+            Entry::Package(path) => utils::verbose!(verbose, "{}", path),
+            Entry::Copy { path, from, .. } if path == from => {}
+            Entry::Copy { path, from, .. } => utils::verbose!(verbose, "{} (from {})", path, from),
+            Entry::Data { path, data } => {
+                utils::verbose!(verbose, "{} ({} bytes)", path, data.len())
+            }
+        }
+    }
+    let mut dest = zstd::Encoder::new(BufWriter::new(File::create(output)?), LEVEL)?;
+    patch.write_to(&mut dest)?;
+    dest.finish()?.flush()?;
+    Ok(())
+}
+
+/// Applies the patch in `input` to the archive at `base` and saves the result to `path`
+pub(crate) fn do_apply_patch(
+    path: &Path,
+    base: &Path,
+    input: &Path,
+    verbose: bool,
+    key: Key,
+) -> Result<()> {
+    let patch = Patch::from_reader(&mut zstd::Decoder::new(BufReader::new(File::open(input)?))?)?;
+    utils::verbose!(
+        verbose,
+        "{} entries, version {}",
+        patch.entries().len(),
+        patch.version()
+    );
+    match key.iv() {
+        Some(iv) => apply_patch(
+            &patch,
+            path,
+            base,
+            KeyStream::new(&TRIMMED_KEY, &iv),
+            KeyStream::new(&TRIMMED_KEY, &iv),
+        ),
+        None => apply_patch(&patch, path, base, DummyDecryptor, DummyEncryptor),
+    }
+}
+
+type FileReader<D> = archive::Reader<WzReader<BufReader<File>, D>>;
+
+fn open<D>(path: &Path, version: Option<u16>, decryptor: D) -> Result<FileReader<D>>
+where
+    D: Decryptor,
+{
+    match version {
+        Some(v) => archive::Reader::open_as_version(path, v, decryptor),
+        None => archive::Reader::open(path, decryptor),
+    }
+}
+
+fn make_patch<D>(
+    mut base: FileReader<D>,
+    mut archive: FileReader<D>,
+    version: Option<u16>,
+) -> Result<Patch>
+where
+    D: Decryptor,
+{
+    let base_map = base.map("base")?;
+    let version = match version {
+        Some(v) => v,
+        None => archive.resolve_version()?,
+    };
+    let header = archive.header().clone();
+    let map = archive.map("archive")?;
+    archive::patch::diff(
+        &mut base.into_inner(),
+        &base_map,
+        &mut archive.into_inner(),
+        &map,
+        version,
+        &header,
+    )
+}
+
+fn apply_patch<D, E>(
+    patch: &Patch,
+    path: &Path,
+    base: &Path,
+    decryptor: D,
+    encryptor: E,
+) -> Result<()>
+where
+    D: Decryptor,
+    E: Encryptor,
+{
+    let base_map = archive::Reader::open(base, decryptor)?.map("base")?;
+    utils::remove_file(path)?;
+    patch
+        .apply(base, &base_map)?
+        .save(path, patch.version(), patch.header(), encryptor)
+}
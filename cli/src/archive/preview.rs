@@ -0,0 +1,202 @@
+//! Previewing WZ archive canvases in the terminal
+
+use crate::{utils, Key, Preview};
+use ::image::{imageops, imageops::FilterType, ImageFormat, Rgba, RgbaImage};
+use crypto::{KeyStream, TRIMMED_KEY};
+use std::{collections::BTreeSet, env, fmt::Write, path::PathBuf};
+use wz::{
+    archive::{self, reader::Node},
+    error::{ImageError, Result},
+    image,
+    io::{DummyDecryptor, WzImageReader, WzRead},
+    types::Property,
+};
+
+/// Width used when the terminal width is unknown
+const DEFAULT_COLUMNS: u32 = 80;
+
+pub(crate) fn do_preview(
+    path: &PathBuf,
+    canvas: &str,
+    key: Key,
+    version: Option<u16>,
+    preview: Preview,
+) -> Result<()> {
+    let name = utils::file_name(path)?;
+    match key.iv() {
+        Some(iv) => self::preview(
+            name,
+            match version {
+                Some(v) => {
+                    archive::Reader::open_as_version(path, v, KeyStream::new(&TRIMMED_KEY, &iv))?
+                }
+                None => archive::Reader::open(path, KeyStream::new(&TRIMMED_KEY, &iv))?,
+            },
+            canvas,
+            preview,
+        ),
+        None => self::preview(
+            name,
+            match version {
+                Some(v) => archive::Reader::open_as_version(path, v, DummyDecryptor)?,
+                None => archive::Reader::open(path, DummyDecryptor)?,
+            },
+            canvas,
+            preview,
+        ),
+    }
+}
+
+fn preview<R>(
+    name: &str,
+    mut archive: archive::Reader<R>,
+    canvas: &str,
+    preview: Preview,
+) -> Result<()>
+where
+    R: WzRead,
+{
+    // Split `Weapon/01472030.img/info/icon` into the image and the canvas within it
+    let invalid = || ImageError::Path(String::from(canvas));
+    let index = canvas.find(".img/").ok_or_else(invalid)? + ".img".len();
+    let (image_path, property_path) = (&canvas[..index], &canvas[index + 1..]);
+
+    let root = name.replace(".wz", "");
+    let map = archive.map(&root)?;
+    let offset = match map.get(format!("{}/{}", root, image_path))? {
+        Node::Image { offset, .. } => *offset,
+        Node::Package => return Err(invalid().into()),
+    };
+    let image_name = utils::file_name(&image_path)?;
+    let mut inner = archive.into_inner();
+    let mut image_reader = WzImageReader::with_offset(&mut inner, offset);
+    image_reader.seek_to_start()?;
+    let image = image::Reader::new(image_reader).map(image_name)?;
+    let buffer = match image.get(format!("{}/{}", image_name, property_path))? {
+        Property::Canvas(canvas) => canvas.image_buffer()?,
+        _ => return Err(invalid().into()),
+    };
+
+    match preview {
+        Preview::Ansi => print!("{}", half_blocks(&fit(buffer))),
+        Preview::Sixel => print!("{}", sixel(&buffer)),
+        Preview::Png => {
+            let output = env::temp_dir().join(format!("{}.png", canvas.replace('/', "_")));
+            buffer.save_with_format(&output, ImageFormat::Png)?;
+            println!("{}", output.display());
+        }
+    }
+    Ok(())
+}
+
+/// Shrinks `image` to the width of the terminal
+fn fit(image: RgbaImage) -> RgbaImage {
+    let columns = env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse().ok())
+        .unwrap_or(DEFAULT_COLUMNS);
+    match image.width() > columns {
+        true => {
+            let height = (image.height() * columns / image.width()).max(1);
+            imageops::resize(&image, columns, height, FilterType::Nearest)
+        }
+        false => image,
+    }
+}
+
+/// Returns the color of `pixel` unless it is mostly transparent
+fn opaque(pixel: &Rgba<u8>) -> Option<[u8; 3]> {
+    match pixel[3] >= 128 {
+        true => Some([pixel[0], pixel[1], pixel[2]]),
+        false => None,
+    }
+}
+
+/// Draws two rows of pixels per line with upper and lower half blocks in 24-bit color
+fn half_blocks(image: &RgbaImage) -> String {
+    let mut text = String::new();
+    for y in (0..image.height()).step_by(2) {
+        for x in 0..image.width() {
+            let top = opaque(image.get_pixel(x, y));
+            let bottom = match y + 1 < image.height() {
+                true => opaque(image.get_pixel(x, y + 1)),
+                false => None,
+            };
+            let _ = match (top, bottom) {
+                (None, None) => write!(text, "\x1b[0m "),
+                (Some([r, g, b]), None) => write!(text, "\x1b[0;38;2;{};{};{}m▀", r, g, b),
+                (None, Some([r, g, b])) => write!(text, "\x1b[0;38;2;{};{};{}m▄", r, g, b),
+                (Some([r, g, b]), Some([br, bg, bb])) => write!(
+                    text,
+                    "\x1b[0;38;2;{};{};{};48;2;{};{};{}m▀",
+                    r, g, b, br, bg, bb
+                ),
+            };
+        }
+        text.push_str("\x1b[0m\n");
+    }
+    text
+}
+
+/// Encodes `image` as sixels with the 216 colors of a 6x6x6 color cube. Transparent pixels are
+/// left undrawn.
+fn sixel(image: &RgbaImage) -> String {
+    let color = |x: u32, y: u32| {
+        opaque(image.get_pixel(x, y)).map(|[r, g, b]| {
+            let level = |c: u8| (c as usize + 25) / 51;
+            level(r) * 36 + level(g) * 6 + level(b)
+        })
+    };
+    let mut text = format!("\x1bPq\"1;1;{};{}", image.width(), image.height());
+    for i in 0..216 {
+        let _ = write!(
+            text,
+            "#{};2;{};{};{}",
+            i,
+            i / 36 * 20,
+            i / 6 % 6 * 20,
+            i % 6 * 20
+        );
+    }
+    for top in (0..image.height()).step_by(6) {
+        let rows = top..(top + 6).min(image.height());
+        let colors = rows
+            .clone()
+            .flat_map(|y| (0..image.width()).filter_map(move |x| color(x, y)))
+            .collect::<BTreeSet<usize>>();
+        for c in colors {
+            let _ = write!(text, "#{}", c);
+            let mut run = (0, '?');
+            for x in 0..image.width() {
+                let bits = rows
+                    .clone()
+                    .filter(|y| color(x, *y) == Some(c))
+                    .fold(0, |bits, y| bits | 1 << (y - top));
+                let sixel = (63 + bits) as u8 as char;
+                match run {
+                    (count, s) if count > 0 && s == sixel => run.0 += 1,
+                    (count, s) => {
+                        push_run(&mut text, count, s);
+                        run = (1, sixel);
+                    }
+                }
+            }
+            push_run(&mut text, run.0, run.1);
+            text.push('$');
+        }
+        text.push('-');
+    }
+    text.push_str("\x1b\\");
+    text
+}
+
+/// Writes `count` repetitions of `sixel`, using the repeat introducer for long runs
+fn push_run(text: &mut String, count: usize, sixel: char) {
+    match count {
+        0 => {}
+        1..=3 => text.push_str(&sixel.to_string().repeat(count)),
+        _ => {
+            let _ = write!(text, "!{}{}", count, sixel);
+        }
+    }
+}
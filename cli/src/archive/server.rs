@@ -1,13 +1,22 @@
 //! Parsing of WZ archives
 
-use crate::{utils, Key};
-use crypto::{KeyStream, GMS_IV, KMS_IV, TRIMMED_KEY};
-use std::{fs, path::PathBuf};
+use crate::{utils, Data, Dialect, Key};
+use ::image::ImageFormat;
+use crypto::{KeyStream, TRIMMED_KEY};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
 use wz::{
     archive::{self, reader},
     error::{Error, Result},
     image,
-    io::{xml::writer::XmlWriter, DummyDecryptor, WzImageReader, WzRead},
+    io::{
+        xml::writer::{self, DataMode, ToXml, XmlOptions, XmlWriter},
+        DummyDecryptor, WzImageReader, WzRead,
+    },
+    map::Map,
+    types::Property,
 };
 
 pub(crate) fn do_server(
@@ -15,45 +24,57 @@ pub(crate) fn do_server(
     verbose: bool,
     key: Key,
     version: Option<u16>,
+    dialect: Dialect,
+    indent: usize,
+    data: Data,
 ) -> Result<()> {
     let filename = utils::file_name(path)?;
-    match key {
-        Key::Gms => server(
+    let options = XmlOptions {
+        dialect: match dialect {
+            Dialect::Native => writer::Dialect::Native,
+            Dialect::Harepacker => writer::Dialect::HaRepacker,
+            Dialect::Wzdumper => writer::Dialect::WzDumper,
+        },
+        indent: match indent {
+            0 => None,
+            n => Some(" ".repeat(n)),
+        },
+        data: match data {
+            Data::Omit => DataMode::Omit,
+            Data::Inline => DataMode::Inline,
+            Data::Files => DataMode::Files,
+        },
+    };
+    match key.iv() {
+        Some(iv) => server(
             filename,
             match version {
-                Some(v) => archive::Reader::open_as_version(
-                    path,
-                    v,
-                    KeyStream::new(&TRIMMED_KEY, &GMS_IV),
-                )?,
-                None => archive::Reader::open(path, KeyStream::new(&TRIMMED_KEY, &GMS_IV))?,
+                Some(v) => {
+                    archive::Reader::open_as_version(path, v, KeyStream::new(&TRIMMED_KEY, &iv))?
+                }
+                None => archive::Reader::open(path, KeyStream::new(&TRIMMED_KEY, &iv))?,
             },
             verbose,
+            &options,
         ),
-        Key::Kms => server(
-            filename,
-            match version {
-                Some(v) => archive::Reader::open_as_version(
-                    path,
-                    v,
-                    KeyStream::new(&TRIMMED_KEY, &KMS_IV),
-                )?,
-                None => archive::Reader::open(path, KeyStream::new(&TRIMMED_KEY, &KMS_IV))?,
-            },
-            verbose,
-        ),
-        Key::None => server(
+        None => server(
             filename,
             match version {
                 Some(v) => archive::Reader::open_as_version(path, v, DummyDecryptor)?,
                 None => archive::Reader::open(path, DummyDecryptor)?,
             },
             verbose,
+            &options,
         ),
     }
 }
 
-fn server<R>(name: &str, mut archive: archive::Reader<R>, verbose: bool) -> Result<()>
+fn server<R>(
+    name: &str,
+    mut archive: archive::Reader<R>,
+    verbose: bool,
+    options: &XmlOptions,
+) -> Result<()>
 where
     R: WzRead,
 {
@@ -68,13 +89,39 @@ where
             reader::Node::Image { offset, .. } => {
                 let path = format!("{}.xml", cursor.pwd());
                 utils::remove_file(&path)?;
-                let mut image_reader = WzImageReader::with_offset(&mut reader, *offset);
-                image_reader.seek_to_start()?;
-                let mut image = image::Reader::new(image_reader);
-                let map = image.map(cursor.name())?;
+                let map = match cursor.get().is_empty_image() {
+                    true => Map::new(String::from(cursor.name()), Property::ImgDir),
+                    false => {
+                        let mut image_reader = WzImageReader::with_offset(&mut reader, *offset);
+                        image_reader.seek_to_start()?;
+                        image::Reader::new(image_reader).map(cursor.name())?
+                    }
+                };
                 utils::verbose!(verbose, "{}", path);
-                let mut writer = XmlWriter::new(fs::File::create(&path)?);
+                let mut writer = XmlWriter::with_options(fs::File::create(&path)?, options.clone());
                 writer.write(&mut map.cursor())?;
+                if options.data == DataMode::Files {
+                    save_files(utils::parent(&path)?, &map, verbose)?;
+                }
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Saves the canvases and sounds of the image where the `src` attributes of [`DataMode::Files`]
+/// point to
+fn save_files(directory: &Path, map: &Map<Property>, verbose: bool) -> Result<()> {
+    map.walk::<Error>(|cursor| {
+        let property = cursor.get();
+        if let Some(extension) = property.extension() {
+            let path = directory.join(format!("{}.{}", cursor.pwd(), extension));
+            fs::create_dir_all(utils::parent(&path)?)?;
+            utils::verbose!(verbose, "{}", path.display());
+            match property {
+                Property::Canvas(canvas) => canvas.save_to_file(&path, ImageFormat::Png)?,
+                Property::Sound(sound) => sound.save_to_file(&path)?,
+                _ => {}
             }
         }
         Ok(())
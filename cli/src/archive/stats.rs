@@ -0,0 +1,137 @@
+//! WZ archive statistics
+
+use crate::{utils, Key, Output};
+use crypto::{KeyStream, TRIMMED_KEY};
+use serde_json::json;
+use std::path::PathBuf;
+use wz::{
+    archive::{self, stats::ImageStat, Stats},
+    error::Result,
+    io::DummyDecryptor,
+};
+
+pub(crate) fn do_stats(
+    path: &PathBuf,
+    key: Key,
+    version: Option<u16>,
+    top: usize,
+    output: Output,
+) -> Result<()> {
+    let name = utils::file_name(path)?;
+
+    // Map the WZ archive
+    let map = match key.iv() {
+        Some(iv) => match version {
+            Some(v) => {
+                archive::Reader::open_as_version(path, v, KeyStream::new(&TRIMMED_KEY, &iv))?
+                    .map(name)?
+            }
+            None => archive::Reader::open(path, KeyStream::new(&TRIMMED_KEY, &iv))?.map(name)?,
+        },
+        None => match version {
+            Some(v) => archive::Reader::open_as_version(path, v, DummyDecryptor)?.map(name)?,
+            None => archive::Reader::open(path, DummyDecryptor)?.map(name)?,
+        },
+    };
+    let stats = archive::Stats::compute(&map, top);
+    if output == Output::Json {
+        print_json(&stats);
+        return Ok(());
+    }
+
+    println!("Images:     {}", stats.images);
+    println!("Packages:   {}", stats.packages);
+    println!("Total size: {}", stats.total_size);
+
+    println!("\nSize distribution:");
+    let mut min = 0;
+    for bucket in &stats.distribution {
+        let range = match bucket.max {
+            Some(max) => format!("{} - {}", min, max - 1),
+            None => format!("{}+", min),
+        };
+        println!(
+            "  {:>20} : {:>8} images {:>12} bytes",
+            range, bucket.count, bucket.total
+        );
+        min = bucket.max.unwrap_or(min);
+    }
+
+    println!("\nLargest images:");
+    for image in &stats.largest {
+        println!("  {:>12} {}", image.size, image.path);
+    }
+
+    println!("\nTop-level packages:");
+    for package in &stats.top_level {
+        println!(
+            "  {:>12} {:>8} images {}",
+            package.size, package.images, package.name
+        );
+    }
+
+    println!("\nDuplicate images:");
+    for group in &stats.duplicates {
+        println!(
+            "  {} x {} bytes (checksum {})",
+            group.len(),
+            group[0].size,
+            group[0].checksum
+        );
+        for image in group {
+            println!("    {}", image.path);
+        }
+    }
+
+    if !stats.invalid.is_empty() {
+        println!("\nImages with an invalid size:");
+        for path in &stats.invalid {
+            println!("  {}", path);
+        }
+    }
+    Ok(())
+}
+
+fn print_json(stats: &Stats) {
+    let image = |image: &ImageStat| json!({ "path": image.path, "size": image.size, "checksum": image.checksum });
+    let mut min = 0;
+    let distribution = stats
+        .distribution
+        .iter()
+        .map(|bucket| {
+            let bucket_json = json!({
+                "min": min,
+                "max": bucket.max,
+                "count": bucket.count,
+                "total": bucket.total,
+            });
+            min = bucket.max.unwrap_or(min);
+            bucket_json
+        })
+        .collect::<Vec<_>>();
+    let top_level = stats
+        .top_level
+        .iter()
+        .map(|package| {
+            json!({ "name": package.name, "images": package.images, "size": package.size })
+        })
+        .collect::<Vec<_>>();
+    let duplicates = stats
+        .duplicates
+        .iter()
+        .map(|group| group.iter().map(image).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    println!(
+        "{}",
+        json!({
+            "images": stats.images,
+            "packages": stats.packages,
+            "total_size": stats.total_size,
+            "distribution": distribution,
+            "largest": stats.largest.iter().map(image).collect::<Vec<_>>(),
+            "top_level": top_level,
+            "duplicates": duplicates,
+            "invalid": stats.invalid,
+        })
+    );
+}
@@ -0,0 +1,190 @@
+//! Comparison of extracted WZ archives with the archive
+
+use crate::{archive::Names, utils, Key, Output};
+use crypto::{KeyStream, TRIMMED_KEY};
+use serde_json::json;
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+use wz::{
+    archive::{self, reader},
+    error::{Error, Result},
+    io::{checksum_reader, DummyDecryptor},
+    map::Map,
+    types::WzInt,
+};
+
+/// How an extracted image compares to the archive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Change {
+    /// The file matches the image by size and checksum
+    Unchanged,
+
+    /// The file differs from the image
+    Modified,
+
+    /// The file has no image in the archive
+    New,
+
+    /// The image has no file
+    Missing,
+}
+
+impl Change {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Self::Unchanged => "unchanged",
+            Self::Modified => "modified",
+            Self::New => "new",
+            Self::Missing => "missing",
+        }
+    }
+}
+
+/// Image of an archive or file of the directory it was extracted to
+#[derive(Debug, Clone)]
+pub(crate) struct Status {
+    /// Path of the image in the archive, starting with the archive name
+    pub(crate) path: String,
+
+    /// File the image is extracted to
+    pub(crate) file: PathBuf,
+
+    pub(crate) change: Change,
+}
+
+/// Prints the images extracted under `directory` that differ from the archive at `path`
+pub(crate) fn do_status(
+    path: &PathBuf,
+    directory: &Option<String>,
+    verbose: bool,
+    key: Key,
+    version: Option<u16>,
+    output: Output,
+) -> Result<()> {
+    let out = PathBuf::from(directory.as_deref().unwrap_or("."));
+    let name = utils::file_name(path)?.replace(".wz", "");
+    let map = match key.iv() {
+        Some(iv) => match version {
+            Some(v) => {
+                archive::Reader::open_as_version(path, v, KeyStream::new(&TRIMMED_KEY, &iv))?
+                    .map(&name)
+            }
+            None => archive::Reader::open(path, KeyStream::new(&TRIMMED_KEY, &iv))?.map(&name),
+        },
+        None => match version {
+            Some(v) => archive::Reader::open_as_version(path, v, DummyDecryptor)?.map(&name),
+            None => archive::Reader::open(path, DummyDecryptor)?.map(&name),
+        },
+    }?;
+    let statuses = status(&map, &out)?;
+    let unchanged = statuses
+        .iter()
+        .filter(|status| status.change == Change::Unchanged)
+        .count();
+    let changes = statuses
+        .iter()
+        .filter(|status| status.change != Change::Unchanged);
+    match output {
+        Output::Text => {
+            for status in changes {
+                println!(
+                    "{:<9} {}",
+                    format!("{}:", status.change.as_str()),
+                    status.path
+                );
+            }
+            utils::verbose!(verbose, "{} images unchanged", unchanged);
+        }
+        Output::Json => {
+            let changes = changes
+                .map(|status| {
+                    json!({
+                        "path": status.path,
+                        "file": status.file.to_string_lossy(),
+                        "change": status.change.as_str(),
+                    })
+                })
+                .collect::<Vec<_>>();
+            println!("{}", json!({ "unchanged": unchanged, "changes": changes }));
+        }
+    }
+    Ok(())
+}
+
+/// Compares every image of the archive mapped in `map` with the file it was extracted to under
+/// `out`, then lists the files that are not in the archive. Images and files are matched through
+/// the escaped names written by extract, and compared by size and checksum.
+pub(crate) fn status(map: &Map<reader::Node>, out: &Path) -> Result<Vec<Status>> {
+    let root = map.name();
+
+    // Every extracted file, by its path relative to `out`
+    let names = Names::load(&out.join(format!("{}.names", root)))?;
+    let mut files = BTreeMap::new();
+    list_files(&out.join(root), root, root, &names, &mut files)?;
+
+    let mut statuses = Vec::new();
+    let mut escaper = Names::new();
+    map.walk::<Error>(|cursor| {
+        if let reader::Node::Image { size, checksum, .. } = cursor.get() {
+            let escaped = escaper.escape(&cursor.segments())?.join("/");
+            let change = match files.remove(&escaped) {
+                Some(_) if matches(&out.join(&escaped), *size, *checksum)? => Change::Unchanged,
+                Some(_) => Change::Modified,
+                None => Change::Missing,
+            };
+            statuses.push(Status {
+                path: cursor.pwd(),
+                file: out.join(&escaped),
+                change,
+            });
+        }
+        Ok(())
+    })?;
+    statuses.extend(files.into_iter().map(|(escaped, node)| Status {
+        path: node,
+        file: out.join(escaped),
+        change: Change::New,
+    }));
+    Ok(statuses)
+}
+
+/// Adds the files under `dir` to `files`, keyed by their path relative to the extraction
+/// directory, `escaped`, with the path of the image they would be in the archive, `node`
+fn list_files(
+    dir: &Path,
+    escaped: &str,
+    node: &str,
+    names: &Names,
+    files: &mut BTreeMap<String, String>,
+) -> Result<()> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let file_name = entry.file_name().to_string_lossy().into_owned();
+        let escaped = format!("{}/{}", escaped, file_name);
+        let node = format!("{}/{}", node, names.original(&escaped, &file_name)?);
+        let path = entry.path();
+        if path.is_dir() {
+            list_files(&path, &escaped, &node, names, files)?;
+        } else if path.is_file() {
+            files.insert(escaped, node);
+        }
+    }
+    Ok(())
+}
+
+/// Returns `true` when the file at `path` has the given size and checksum
+fn matches(path: &Path, size: WzInt, checksum: WzInt) -> Result<bool> {
+    if fs::metadata(path)?.len() != *size as u64 {
+        return Ok(false);
+    }
+    Ok(checksum_reader(fs::File::open(path)?)?.1 == checksum)
+}
@@ -0,0 +1,170 @@
+//! Synchronization of WZ archives with the directories they are extracted to
+
+use crate::{
+    archive::{
+        status::{self, Change},
+        Names,
+    },
+    utils, Key, SyncTo,
+};
+use crypto::{Decryptor, Encryptor, KeyStream, TRIMMED_KEY};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+use wz::{
+    archive::{
+        self,
+        patch::PatchImage,
+        reader,
+        writer::{AnyImage, FileImage},
+    },
+    error::{Error, Result},
+    io::{DummyDecryptor, DummyEncryptor, WzRead},
+};
+
+/// Brings the archive at `path` and the images extracted under `directory` in line, touching only
+/// the images that differ
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn do_sync(
+    path: &PathBuf,
+    directory: &Option<String>,
+    to: SyncTo,
+    verbose: bool,
+    key: Key,
+    version: Option<u16>,
+    jobs: usize,
+) -> Result<()> {
+    let out = PathBuf::from(directory.as_deref().unwrap_or("."));
+    match key.iv() {
+        Some(iv) => sync(
+            path,
+            &out,
+            to,
+            verbose,
+            version,
+            KeyStream::new(&TRIMMED_KEY, &iv),
+            KeyStream::new(&TRIMMED_KEY, &iv),
+            jobs,
+        ),
+        None => sync(
+            path,
+            &out,
+            to,
+            verbose,
+            version,
+            DummyDecryptor,
+            DummyEncryptor,
+            jobs,
+        ),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn sync<D, E>(
+    path: &PathBuf,
+    out: &Path,
+    to: SyncTo,
+    verbose: bool,
+    version: Option<u16>,
+    decryptor: D,
+    encryptor: E,
+    jobs: usize,
+) -> Result<()>
+where
+    D: Decryptor,
+    E: Encryptor + Clone + Send,
+{
+    let root = utils::file_name(path)?.replace(".wz", "");
+    let mut reader = match version {
+        Some(v) => archive::Reader::open_as_version(path, v, decryptor)?,
+        None => archive::Reader::open(path, decryptor)?,
+    };
+    let version = match version {
+        Some(v) => v,
+        None => reader.resolve_version()?,
+    };
+    let header = reader.header().clone();
+    let map = reader.map(&root)?;
+    let statuses = status::status(&map, out)?;
+    let changed = statuses
+        .iter()
+        .filter(|status| status.change != Change::Unchanged)
+        .collect::<Vec<_>>();
+    if changed.is_empty() {
+        println!("{} is up to date", root);
+        return Ok(());
+    }
+    for status in &changed {
+        utils::verbose!(verbose, "{}: {}", status.path, status.change.as_str());
+    }
+
+    match to {
+        SyncTo::Archive => {
+            // Unchanged images are copied from the archive and the others read from their files.
+            // Everything keeps its place and new images go last.
+            let by_path = statuses
+                .iter()
+                .map(|status| (status.path.as_str(), status))
+                .collect::<HashMap<_, _>>();
+            let mut writer = archive::Writer::<AnyImage<E>>::new(&root);
+            map.walk::<Error>(|cursor| {
+                let image_path = cursor.pwd();
+                match (cursor.get(), by_path.get(image_path.as_str())) {
+                    (reader::Node::Package, _) => writer.add_package(&image_path),
+                    (
+                        reader::Node::Image {
+                            offset,
+                            size,
+                            checksum,
+                        },
+                        Some(status),
+                    ) => match status.change {
+                        Change::Unchanged => writer.add_image(
+                            &image_path,
+                            AnyImage::from(PatchImage::Copy {
+                                path: path.clone(),
+                                offset: *offset,
+                                size: *size,
+                                checksum: *checksum,
+                            }),
+                        ),
+                        Change::Modified => {
+                            writer.add_image(&image_path, FileImage::new(&status.file)?.into())
+                        }
+                        Change::New | Change::Missing => Ok(()),
+                    },
+                    (reader::Node::Image { .. }, None) => Ok(()),
+                }
+            })?;
+            for status in changed.iter().filter(|status| status.change == Change::New) {
+                writer.add_image(&status.path, FileImage::new(&status.file)?.into())?;
+            }
+            writer.prepare(jobs)?;
+            writer.save(path, version, header, encryptor)?;
+        }
+        SyncTo::Directory => {
+            let names_path = out.join(format!("{}.names", root));
+            let mut names = Names::load(&names_path)?;
+            let mut inner = reader.into_inner();
+            for status in &changed {
+                match status.change {
+                    Change::Modified | Change::Missing => {
+                        if let reader::Node::Image { offset, size, .. } = map.get(&status.path)? {
+                            names.escape(&status.path.split('/').collect::<Vec<_>>())?;
+                            fs::create_dir_all(utils::parent(&status.file)?)?;
+                            let mut file = fs::File::create(&status.file)?;
+                            inner.copy_to(&mut file, *offset, *size)?;
+                        }
+                    }
+                    Change::New => fs::remove_file(&status.file)?,
+                    Change::Unchanged => {}
+                }
+            }
+            names.save(&names_path)?;
+        }
+    }
+    println!("{} images synced", changed.len());
+    Ok(())
+}
@@ -0,0 +1,31 @@
+//! Full-text index of the strings of WZ archives
+
+use crate::{utils, Key};
+use crypto::{KeyStream, TRIMMED_KEY};
+use std::path::{Path, PathBuf};
+use wz::{
+    error::Result,
+    io::DummyDecryptor,
+    vfs::{text::TextIndex, Vfs},
+};
+
+pub(crate) fn do_build_text_index(path: &PathBuf, dir: &Path, key: Key) -> Result<()> {
+    let index = match key.iv() {
+        Some(iv) => TextIndex::build(&mut Vfs::open(dir, KeyStream::new(&TRIMMED_KEY, &iv))?)?,
+        None => TextIndex::build(&mut Vfs::open(dir, DummyDecryptor)?)?,
+    };
+    index.save(path)?;
+    println!(
+        "{}: {} strings indexed",
+        utils::file_name(path)?,
+        index.len()
+    );
+    Ok(())
+}
+
+pub(crate) fn do_search(path: &PathBuf, query: &str) -> Result<()> {
+    for (archive, path) in TextIndex::load(path)?.search(query) {
+        println!("{}/{}", archive, path);
+    }
+    Ok(())
+}
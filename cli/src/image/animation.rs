@@ -0,0 +1,36 @@
+//! Animation exporter
+
+use crate::{utils, Key};
+use crypto::{KeyStream, TRIMMED_KEY};
+use std::path::PathBuf;
+use wz::{
+    error::Result,
+    image::{animation, Reader},
+    io::DummyDecryptor,
+};
+
+pub(crate) fn do_animation(
+    path: &PathBuf,
+    animation: &str,
+    apng: bool,
+    verbose: bool,
+    key: Key,
+) -> Result<()> {
+    let name = utils::file_name(path)?;
+    let map = match key.iv() {
+        Some(iv) => Reader::open(path, KeyStream::new(&TRIMMED_KEY, &iv))?.map(name)?,
+        None => Reader::open(path, DummyDecryptor)?.map(name)?,
+    };
+    let animation = animation.trim_matches('/');
+    let exported = animation::export(&map, &format!("{}/{}", name, animation))?;
+
+    // Name the output after the animation path
+    let out = format!(
+        "{}-{}.{}",
+        name.replace(".img", ""),
+        animation.replace('/', "-"),
+        if apng { "png" } else { "gif" }
+    );
+    utils::verbose!(verbose, "{}", &out);
+    exported.save(&out)
+}
@@ -1,41 +1,161 @@
 //! Image builder
 
-use crate::{utils, Key};
-use crypto::{KeyStream, GMS_IV, KMS_IV, TRIMMED_KEY};
+use crate::{utils, Dialect, Key, Quality};
+use crypto::{KeyStream, TRIMMED_KEY};
 use std::{
+    collections::HashMap,
     fs,
     io::BufReader,
     path::{Path, PathBuf},
     str::FromStr,
+    thread,
+    time::{Duration, SystemTime},
 };
 use wz::{
-    error::{ImageError, Result},
+    error::{Error, ImageError, Result},
     image::Writer,
     io::{
         xml::{
             attribute::OwnedAttribute,
-            reader::{EventReader, XmlEvent},
+            reader::{EventReader, XmlEvent, XmlReader},
+            writer,
         },
         DummyEncryptor,
     },
     map::Map,
-    types::{Canvas, CanvasFormat, Property, Sound, UolObject, UolString, Vector, WzInt, WzLong},
+    types::{
+        Bc3Quality, Canvas, CanvasFormat, Property, Sound, UolObject, UolString, Vector, WzInt,
+        WzLong,
+    },
 };
 
-pub(crate) fn do_create(path: &PathBuf, directory: &str, verbose: bool, key: Key) -> Result<()> {
+pub(crate) fn do_create(
+    path: &PathBuf,
+    directory: &str,
+    verbose: bool,
+    key: Key,
+    quality: Quality,
+    watch: bool,
+    server: Option<Dialect>,
+) -> Result<()> {
+    let quality = match quality {
+        Quality::Fast => Bc3Quality::Fast,
+        Quality::Normal => Bc3Quality::Normal,
+        Quality::Best => Bc3Quality::Best,
+    };
+    let server = server.map(|dialect| match dialect {
+        Dialect::Native => writer::Dialect::Native,
+        Dialect::Harepacker => writer::Dialect::HaRepacker,
+        Dialect::Wzdumper => writer::Dialect::WzDumper,
+    });
+    let mut cache = CanvasCache::default();
+    if !watch {
+        return create(path, directory, verbose, key, quality, server, &mut cache);
+    }
+
+    // Rebuild whenever a file next to the XML changes. Unchanged canvases are not encoded again.
+    let root = match utils::parent(&directory)? {
+        root if root.as_os_str().is_empty() => Path::new("."),
+        root => root,
+    };
+    let mut output = None;
+    let mut last = None;
+    loop {
+        let current = snapshot(root, output.as_deref())?;
+        if last.as_ref() != Some(&current) {
+            match create(path, directory, verbose, key, quality, server, &mut cache) {
+                Ok(()) => println!("Built {}", path.display()),
+                Err(e) => eprintln!("Error: {:?}", e),
+            }
+            output = fs::canonicalize(path).ok();
+            last = Some(current);
+        }
+        thread::sleep(WATCH_INTERVAL);
+    }
+}
+
+/// How often the source directory is checked for changes in watch mode
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+fn create(
+    path: &PathBuf,
+    directory: &str,
+    verbose: bool,
+    key: Key,
+    quality: Bc3Quality,
+    server: Option<writer::Dialect>,
+    cache: &mut CanvasCache,
+) -> Result<()> {
     // Remove the WZ archive if it exists
     utils::remove_file(path)?;
     let target = utils::file_name(path)?;
     utils::verbose!(verbose, "{}", target);
-    let mut writer = Writer::from_map(map_image_from_xml(target, directory, verbose)?);
-    match key {
-        Key::Gms => writer.save(path, KeyStream::new(&TRIMMED_KEY, &GMS_IV)),
-        Key::Kms => writer.save(path, KeyStream::new(&TRIMMED_KEY, &KMS_IV)),
-        Key::None => writer.save(path, DummyEncryptor),
+    let map = match server {
+        Some(dialect) => map_image_from_server_xml(target, directory, dialect, verbose)?,
+        None => map_image_from_xml(target, directory, verbose, quality, cache)?,
+    };
+    let mut writer = Writer::from_map(map);
+    match key.iv() {
+        Some(iv) => writer.save(path, KeyStream::new(&TRIMMED_KEY, &iv)),
+        None => writer.save(path, DummyEncryptor),
     }
 }
 
-fn map_image_from_xml<S>(img_name: &str, xml_path: S, verbose: bool) -> Result<Map<Property>>
+/// Returns the modification time of every file under `dir` except `output`, sorted by path
+fn snapshot(dir: &Path, output: Option<&Path>) -> Result<Vec<(PathBuf, SystemTime)>> {
+    let mut files = Vec::new();
+    let mut pending = vec![fs::canonicalize(dir)?];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let metadata = fs::metadata(&path)?;
+            if metadata.is_dir() {
+                pending.push(path);
+            } else if Some(path.as_path()) != output {
+                files.push((path, metadata.modified()?));
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Canvases encoded by earlier builds, keyed by source file and requested format
+#[derive(Default)]
+struct CanvasCache {
+    canvases: HashMap<(PathBuf, Option<i32>), (SystemTime, Canvas)>,
+}
+
+impl CanvasCache {
+    /// Encodes the image at `path`, reusing the last encode if the file has not been modified
+    fn load(&mut self, path: &Path, format: Option<i32>, quality: Bc3Quality) -> Result<Canvas> {
+        let modified = fs::metadata(path)?.modified()?;
+        let key = (path.to_path_buf(), format);
+        if let Some((time, canvas)) = self.canvases.get(&key) {
+            if *time == modified {
+                return Ok(canvas.clone());
+            }
+        }
+        let canvas = match format {
+            Some(format) => Canvas::from_image_with_quality(
+                path,
+                CanvasFormat::from_int(WzInt::from(format))?,
+                quality,
+            )?,
+            None => Canvas::from_image_auto(path)?,
+        };
+        self.canvases.insert(key, (modified, canvas.clone()));
+        Ok(canvas)
+    }
+}
+
+fn map_image_from_xml<S>(
+    img_name: &str,
+    xml_path: S,
+    verbose: bool,
+    quality: Bc3Quality,
+    cache: &mut CanvasCache,
+) -> Result<Map<Property>>
 where
     S: AsRef<Path>,
 {
@@ -50,7 +170,8 @@ where
             XmlEvent::StartElement {
                 name, attributes, ..
             } => {
-                let (name, prop) = read_start_element(&name.local_name, &attributes, &parent)?;
+                let (name, prop) =
+                    read_start_element(&name.local_name, &attributes, &parent, quality, cache)?;
                 if name != img_name {
                     return Err(ImageError::Name(img_name.into(), name).into());
                 }
@@ -74,10 +195,18 @@ where
             XmlEvent::StartElement {
                 name, attributes, ..
             } => {
-                let (name, property) = read_start_element(&name.local_name, &attributes, &parent)?;
+                let (name, property) =
+                    read_start_element(&name.local_name, &attributes, &parent, quality, cache)?;
+                let format = match &property {
+                    Property::Canvas(canvas) => Some(canvas.format()),
+                    _ => None,
+                };
                 cursor.create(name.clone(), property)?;
                 cursor.move_to(&name)?;
-                utils::verbose!(verbose, "{}", cursor.pwd());
+                match format {
+                    Some(format) => utils::verbose!(verbose, "{} ({:?})", cursor.pwd(), format),
+                    None => utils::verbose!(verbose, "{}", cursor.pwd()),
+                }
             }
             XmlEvent::EndElement { .. } => {
                 let _ = cursor.parent();
@@ -89,6 +218,39 @@ where
     Ok(map)
 }
 
+/// Reads the server XML written by `wzarchive -S`, HaRepacker or WzDumper. Canvas and sound data
+/// comes from the embedded `basedata`, see `wzarchive --data inline`.
+fn map_image_from_server_xml<S>(
+    img_name: &str,
+    xml_path: S,
+    dialect: writer::Dialect,
+    verbose: bool,
+) -> Result<Map<Property>>
+where
+    S: AsRef<Path>,
+{
+    let file = BufReader::new(fs::File::open(xml_path)?);
+    let map: Map<Property> = XmlReader::with_dialect(file, dialect).read()?;
+    if map.name() != img_name {
+        return Err(ImageError::Name(img_name.into(), map.name().into()).into());
+    }
+    match map.cursor().get() {
+        Property::ImgDir => {}
+        _ => return Err(ImageError::ImageRoot.into()),
+    }
+    map.walk::<Error>(|cursor| {
+        match cursor.get() {
+            _ if cursor.pwd() == img_name => {}
+            Property::Canvas(canvas) => {
+                utils::verbose!(verbose, "{} ({:?})", cursor.pwd(), canvas.format())
+            }
+            _ => utils::verbose!(verbose, "{}", cursor.pwd()),
+        }
+        Ok(())
+    })?;
+    Ok(map)
+}
+
 macro_rules! map_attributes {
     ( $attrs:ident, $( $name:expr, $container:ident ),* ) => {
         $(
@@ -111,6 +273,8 @@ fn read_start_element<S>(
     name: &str,
     attributes: &[OwnedAttribute],
     directory: S,
+    quality: Bc3Quality,
+    cache: &mut CanvasCache,
 ) -> Result<(String, Property)>
 where
     S: AsRef<Path>,
@@ -159,13 +323,18 @@ where
             Ok((name.into(), Property::ImgDir))
         }
         "canvas" => {
-            map_attributes!(attributes, "name", name, "format", format, "src", src);
-            let format = CanvasFormat::from_int(WzInt::from(
-                i32::from_str(format).map_err(|_| ImageError::Value(format.into()))?,
-            ))?;
+            map_attributes!(attributes, "name", name, "src", src);
             let mut path = directory.as_ref().to_path_buf();
             path.push(src);
-            let canvas = Canvas::from_image(&path, format)?;
+            // Pick the format from the image when it is not given
+            let format = match attributes.iter().find(|a| a.name.local_name == "format") {
+                Some(attr) => {
+                    let format = &attr.value;
+                    Some(i32::from_str(format).map_err(|_| ImageError::Value(format.into()))?)
+                }
+                None => None,
+            };
+            let canvas = cache.load(&path, format, quality)?;
             Ok((name.into(), Property::Canvas(canvas)))
         }
         "extended" => {
@@ -1,7 +1,7 @@
 //! Parsing of WZ images
 
 use crate::{utils, Key};
-use crypto::{KeyStream, GMS_IV, KMS_IV, TRIMMED_KEY};
+use crypto::{KeyStream, TRIMMED_KEY};
 use std::{io, io::Write, path::PathBuf};
 use wz::{
     error::Result,
@@ -18,20 +18,14 @@ pub(crate) fn do_debug(
     key: Key,
 ) -> Result<()> {
     let name = utils::file_name(path)?;
-    let result = match key {
-        Key::Gms => debug(
+    let result = match key.iv() {
+        Some(iv) => debug(
             name,
-            Reader::open(path, KeyStream::new(&TRIMMED_KEY, &GMS_IV))?,
+            Reader::open(path, KeyStream::new(&TRIMMED_KEY, &iv))?,
             directory,
             verbose,
         ),
-        Key::Kms => debug(
-            name,
-            Reader::open(path, KeyStream::new(&TRIMMED_KEY, &KMS_IV))?,
-            directory,
-            verbose,
-        ),
-        Key::None => debug(
+        None => debug(
             name,
             Reader::open(path, DummyDecryptor)?,
             directory,
@@ -47,34 +41,33 @@ pub(crate) fn do_debug(
     }
 }
 
-fn debug_print<'a>(
-    f: &mut dyn Write,
-    cursor: &Cursor<'a, Property>,
-    verbose: bool,
-) -> io::Result<()> {
-    if verbose {
-        VerboseDebug::debug(&cursor.name(), f)?;
-    } else {
-        write!(f, "{:?}", cursor.name())?;
-    }
-    write!(f, " : ")?;
-    if verbose {
-        VerboseDebug::debug(cursor.get(), f)?;
-    } else {
-        write!(f, "{:?}", cursor.get())?;
+/// Renders the name and the value of the property under `cursor`
+fn debug_print<'a>(cursor: &Cursor<'a, Property>, verbose: bool) -> io::Result<(String, String)> {
+    match verbose {
+        true => {
+            let mut name = Vec::new();
+            VerboseDebug::debug(&cursor.name(), &mut name)?;
+            let mut value = Vec::new();
+            VerboseDebug::debug(cursor.get(), &mut value)?;
+            Ok((
+                String::from_utf8_lossy(&name).into_owned(),
+                String::from_utf8_lossy(&value).into_owned(),
+            ))
+        }
+        false => Ok((cursor.name().to_string(), cursor.get().to_string())),
     }
-    writeln!(f, "")
 }
 
+/// Collects the tree under `cursor` as (prefix and name, value) lines
 fn debug_recursive<'a>(
     prelude: &str,
     space: &str,
     cursor: &mut Cursor<'a, Property>,
     verbose: bool,
+    lines: &mut Vec<(String, String)>,
 ) -> Result<()> {
-    let mut lock = io::stdout().lock();
-    write!(lock, "{}", prelude)?;
-    debug_print(&mut lock, &cursor, verbose)?;
+    let (name, value) = debug_print(cursor, verbose)?;
+    lines.push((format!("{}{}", prelude, name), value));
     let mut num_children = cursor.children().count();
     if num_children > 0 {
         cursor.first_child()?;
@@ -85,6 +78,7 @@ fn debug_recursive<'a>(
                     &format!("{}    ", space),
                     cursor,
                     verbose,
+                    lines,
                 )?;
                 break;
             } else {
@@ -93,6 +87,7 @@ fn debug_recursive<'a>(
                     &format!("{}|   ", space),
                     cursor,
                     verbose,
+                    lines,
                 )?;
             }
             num_children -= 1;
@@ -120,10 +115,24 @@ where
         None => map.cursor(),
     };
 
+    let mut lines = Vec::new();
     let num_children = cursor.children().count();
     if num_children > 0 {
-        Ok(debug_recursive("|-- ", "|   ", &mut cursor, verbose)?)
+        debug_recursive("|-- ", "|   ", &mut cursor, verbose, &mut lines)?;
     } else {
-        Ok(debug_recursive("`-- ", "", &mut cursor, verbose)?)
+        debug_recursive("`-- ", "", &mut cursor, verbose, &mut lines)?;
     }
+
+    // Line up the values after the longest name
+    let width = lines
+        .iter()
+        .map(|(name, _)| name.chars().count())
+        .max()
+        .unwrap_or_default();
+    let mut lock = io::stdout().lock();
+    for (name, value) in lines {
+        let pad = width - name.chars().count();
+        writeln!(lock, "{}{:pad$}  {}", name, "", value, pad = pad)?;
+    }
+    Ok(())
 }
@@ -1,7 +1,7 @@
 //! Image extractor
 
 use crate::{utils, Key};
-use crypto::{KeyStream, GMS_IV, KMS_IV, TRIMMED_KEY};
+use crypto::{KeyStream, TRIMMED_KEY};
 use image::ImageFormat;
 use std::{borrow::Cow, fs, io::Write, path::PathBuf};
 use wz::{
@@ -21,18 +21,13 @@ use wz::{
 
 pub(crate) fn do_extract(path: &PathBuf, verbose: bool, key: Key) -> Result<()> {
     let name = utils::file_name(path)?;
-    let result = match key {
-        Key::Gms => extract(
+    let result = match key.iv() {
+        Some(iv) => extract(
             name,
-            Reader::open(path, KeyStream::new(&TRIMMED_KEY, &GMS_IV))?,
+            Reader::open(path, KeyStream::new(&TRIMMED_KEY, &iv))?,
             verbose,
         ),
-        Key::Kms => extract(
-            name,
-            Reader::open(path, KeyStream::new(&TRIMMED_KEY, &KMS_IV))?,
-            verbose,
-        ),
-        Key::None => extract(name, Reader::open(path, DummyDecryptor)?, verbose),
+        None => extract(name, Reader::open(path, DummyDecryptor)?, verbose),
     };
     match result {
         Ok(_) => Ok(()),
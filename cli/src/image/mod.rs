@@ -1,11 +1,23 @@
 //! Image modules
 
+mod animation;
 mod create;
 mod debug;
 mod extract;
 mod list;
+mod optimize;
+mod sizes;
+mod sprites;
+mod strings;
+mod validate;
 
+pub(crate) use animation::do_animation;
 pub(crate) use create::do_create;
 pub(crate) use debug::do_debug;
 pub(crate) use extract::do_extract;
 pub(crate) use list::do_list;
+pub(crate) use optimize::do_optimize;
+pub(crate) use sizes::do_sizes;
+pub(crate) use sprites::do_sprites;
+pub(crate) use strings::{do_export_strings, do_import_strings};
+pub(crate) use validate::do_validate;
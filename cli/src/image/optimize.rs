@@ -0,0 +1,80 @@
+//! Shrinking the canvases of WZ images
+
+use crate::{utils, Key, Target};
+use crypto::{KeyStream, TRIMMED_KEY};
+use std::path::PathBuf;
+use wz::{
+    error::{Error, Result},
+    image::{Reader, Writer},
+    io::{DummyDecryptor, DummyEncryptor},
+    types::{Canvas, CanvasFormat, Property, ReencodeOptions},
+};
+
+pub(crate) fn do_optimize(
+    path: &PathBuf,
+    target: Target,
+    dither: bool,
+    verbose: bool,
+    key: Key,
+) -> Result<()> {
+    let name = utils::file_name(path)?;
+    let format = match target {
+        Target::Bgra4444 => CanvasFormat::Bgra4444,
+        Target::Rgb565 => CanvasFormat::Rgb565,
+    };
+    let options = ReencodeOptions {
+        dither,
+        ..ReencodeOptions::default()
+    };
+
+    // The image is read fully before it is overwritten
+    let mut map = match key.iv() {
+        Some(iv) => Reader::open(path, KeyStream::new(&TRIMMED_KEY, &iv))?.map(name)?,
+        None => Reader::open(path, DummyDecryptor)?.map(name)?,
+    };
+    let mut canvases = Vec::new();
+    map.walk::<Error>(|cursor| {
+        if let Property::Canvas(canvas) = cursor.get() {
+            if canvas.format() == CanvasFormat::Bgra8888 {
+                canvases.push((cursor.bookmark(), cursor.pwd(), canvas.clone()));
+            }
+        }
+        Ok(())
+    })?;
+
+    let (mut before, mut after) = (0, 0);
+    for (bookmark, pwd, canvas) in canvases {
+        if format == CanvasFormat::Rgb565 && !is_opaque(&canvas)? {
+            utils::verbose!(verbose, "{}: skipped, not opaque", pwd);
+            continue;
+        }
+        let reencoded = canvas.reencode(format, &options)?;
+        let (old, new) = (canvas.data().len(), reencoded.data().len());
+        match new < old {
+            true => {
+                println!("{}: {} -> {} bytes, {} saved", pwd, old, new, old - new);
+                *map.cursor_mut_at_bookmark(bookmark)?.get_mut() = Property::Canvas(reencoded);
+                before += old;
+                after += new;
+            }
+            false => utils::verbose!(verbose, "{}: skipped, {} -> {} bytes", pwd, old, new),
+        }
+    }
+    println!(
+        "{}: {} -> {} bytes, {} saved",
+        name,
+        before,
+        after,
+        before - after
+    );
+
+    let mut writer = Writer::from_map(map);
+    match key.iv() {
+        Some(iv) => writer.save(path, KeyStream::new(&TRIMMED_KEY, &iv)),
+        None => writer.save(path, DummyEncryptor),
+    }
+}
+
+fn is_opaque(canvas: &Canvas) -> Result<bool> {
+    Ok(canvas.image_buffer()?.pixels().all(|p| p[3] == u8::MAX))
+}
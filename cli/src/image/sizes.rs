@@ -0,0 +1,53 @@
+//! Size accounting of WZ images
+
+use crate::{utils, Key};
+use crypto::{KeyStream, TRIMMED_KEY};
+use std::path::PathBuf;
+use wz::{
+    error::{ImageError, Result},
+    image::{Reader, SizeReport},
+    io::DummyDecryptor,
+};
+
+pub(crate) fn do_sizes(
+    path: &PathBuf,
+    directory: &Option<String>,
+    budget: Option<u64>,
+    verbose: bool,
+    key: Key,
+) -> Result<()> {
+    let name = utils::file_name(path)?;
+    let map = match key.iv() {
+        Some(iv) => Reader::open(path, KeyStream::new(&TRIMMED_KEY, &iv))?.map(name)?,
+        None => Reader::open(path, DummyDecryptor)?.map(name)?,
+    };
+    let report = map.size_report()?;
+    let directory = directory.as_deref().unwrap_or_default();
+    let subtree = report
+        .get(directory)
+        .ok_or_else(|| ImageError::Path(String::from(directory)))?;
+
+    let mut children = subtree.children.iter().collect::<Vec<&SizeReport>>();
+    children.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.name.cmp(&b.name)));
+    println!(
+        "{:>12} {:>12} {:>12} {:>12} {:>12}  name",
+        "size", "strings", "canvases", "sounds", "other"
+    );
+    for child in children {
+        print_row(child, &child.name);
+    }
+    print_row(subtree, "total");
+    utils::verbose!(verbose, "{} is {} bytes", name, report.size);
+
+    match budget {
+        Some(budget) if report.size > budget => Err(ImageError::Budget(report.size, budget).into()),
+        _ => Ok(()),
+    }
+}
+
+fn print_row(report: &SizeReport, name: &str) {
+    println!(
+        "{:>12} {:>12} {:>12} {:>12} {:>12}  {}",
+        report.size, report.strings, report.canvases, report.sounds, report.other, name
+    );
+}
@@ -0,0 +1,44 @@
+//! Sprite sheet exporter
+
+use crate::{utils, Key};
+use crypto::{KeyStream, TRIMMED_KEY};
+use std::path::PathBuf;
+use wz::{
+    error::Result,
+    image::{Reader, SpriteSheet},
+    io::DummyDecryptor,
+};
+
+pub(crate) fn do_sprites(
+    path: &PathBuf,
+    animation: &str,
+    scale: u32,
+    trim: bool,
+    verbose: bool,
+    key: Key,
+) -> Result<()> {
+    let name = utils::file_name(path)?;
+    let map = match key.iv() {
+        Some(iv) => Reader::open(path, KeyStream::new(&TRIMMED_KEY, &iv))?.map(name)?,
+        None => Reader::open(path, DummyDecryptor)?.map(name)?,
+    };
+    let animation = animation.trim_matches('/');
+    let path = format!("{}/{}", name, animation);
+    let sheet = if trim {
+        SpriteSheet::from_map_trimmed(&map, &path, scale)?
+    } else {
+        SpriteSheet::from_map(&map, &path, scale)?
+    };
+
+    // Name the outputs after the animation path
+    let out = format!(
+        "{}-{}",
+        name.replace(".img", ""),
+        animation.replace('/', "-")
+    );
+    let png_out = format!("{}.png", out);
+    let json_out = format!("{}.json", out);
+    utils::verbose!(verbose, "{}", &png_out);
+    utils::verbose!(verbose, "{}", &json_out);
+    sheet.save(&png_out, &json_out)
+}
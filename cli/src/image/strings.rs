@@ -0,0 +1,53 @@
+//! String table export and import
+
+use crate::{utils, Key};
+use crypto::{KeyStream, TRIMMED_KEY};
+use std::{fs, path::PathBuf};
+use wz::{
+    error::Result,
+    image::{Reader, StringTable, Writer},
+    io::{DummyDecryptor, DummyEncryptor},
+};
+
+pub(crate) fn do_export_strings(
+    path: &PathBuf,
+    output: &PathBuf,
+    verbose: bool,
+    key: Key,
+) -> Result<()> {
+    let name = utils::file_name(path)?;
+    let map = match key.iv() {
+        Some(iv) => Reader::open(path, KeyStream::new(&TRIMMED_KEY, &iv))?.map_lazy(name)?,
+        None => Reader::open(path, DummyDecryptor)?.map_lazy(name)?,
+    };
+    let table = StringTable::from_map(&map);
+    let contents = match output.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => table.to_json(),
+        _ => table.to_csv(),
+    };
+    utils::verbose!(verbose, "{}", output.display());
+    Ok(fs::write(output, contents)?)
+}
+
+pub(crate) fn do_import_strings(
+    path: &PathBuf,
+    csv: &PathBuf,
+    verbose: bool,
+    key: Key,
+) -> Result<()> {
+    let name = utils::file_name(path)?;
+    let table = StringTable::from_csv(&fs::read_to_string(csv)?)?;
+
+    // The image is read fully before it is overwritten
+    let mut map = match key.iv() {
+        Some(iv) => Reader::open(path, KeyStream::new(&TRIMMED_KEY, &iv))?.map(name)?,
+        None => Reader::open(path, DummyDecryptor)?.map(name)?,
+    };
+    let changed = table.apply(&mut map)?;
+    utils::verbose!(verbose, "{}: {} strings changed", name, changed);
+    let mut writer = Writer::from_map(map);
+    match key.iv() {
+        Some(iv) => writer.save(path, KeyStream::new(&TRIMMED_KEY, &iv)),
+        None => writer.save(path, DummyEncryptor),
+    }
+}
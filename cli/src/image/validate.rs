@@ -0,0 +1,65 @@
+//! Checking WZ images against a schema
+
+use crate::{utils, Key};
+use crypto::{KeyStream, TRIMMED_KEY};
+use serde_json::Value;
+use std::{fs, path::PathBuf};
+use wz::{
+    error::{ImageError, Result},
+    image::{self, Reader, Schema},
+    io::DummyDecryptor,
+};
+
+pub(crate) fn do_validate(path: &PathBuf, schema: &PathBuf, verbose: bool, key: Key) -> Result<()> {
+    let text = fs::read_to_string(schema)?;
+    let schema = match schema.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => schema_from_json(&text)?,
+        _ => Schema::from_toml(&text)?,
+    };
+    let name = utils::file_name(path)?;
+    let map = match key.iv() {
+        Some(iv) => Reader::open(path, KeyStream::new(&TRIMMED_KEY, &iv))?.map_lazy(name)?,
+        None => Reader::open(path, DummyDecryptor)?.map_lazy(name)?,
+    };
+    let violations = image::validate_schema(&map, &schema);
+    for violation in &violations {
+        println!("{}", violation);
+    }
+    match violations.len() {
+        0 => {
+            utils::verbose!(verbose, "{} properties match", schema.fields().len());
+            Ok(())
+        }
+        n => Err(ImageError::Schema(n).into()),
+    }
+}
+
+/// Reads a JSON object mapping paths to kinds. Nested objects prefix the paths within them, like
+/// tables do in TOML schemas.
+fn schema_from_json(text: &str) -> Result<Schema> {
+    let value =
+        serde_json::from_str::<Value>(text).map_err(|e| ImageError::Value(e.to_string()))?;
+    let mut schema = Schema::new();
+    add_fields(&mut schema, "", &value)?;
+    Ok(schema)
+}
+
+fn add_fields(schema: &mut Schema, prefix: &str, value: &Value) -> Result<()> {
+    let object = match value {
+        Value::Object(object) => object,
+        v => return Err(ImageError::Value(v.to_string()).into()),
+    };
+    for (key, value) in object {
+        let path = match prefix {
+            "" => key.clone(),
+            _ => format!("{}/{}", prefix, key),
+        };
+        match value {
+            Value::String(kind) => {
+                schema.require(&path, kind.parse()?);
+            }
+            v => add_fields(schema, &path, v)?,
+        }
+    }
+    Ok(())
+}
@@ -2,8 +2,15 @@
 #![doc = include_str!("../README.md")]
 
 use clap::{Args, Parser, ValueEnum};
-use std::path::PathBuf;
-use wz::error::Result;
+use crypto::{CMS_IV, GMS_IV, JMS_IV, KMS_IV, MSEA_IV, TMS_IV};
+use serde_json::json;
+use std::{path::PathBuf, process};
+use wz::{
+    archive::optimize::Policy,
+    error::{Error, Result},
+    types::ReencodeOptions,
+    versions::{Client, Region},
+};
 
 pub(crate) mod archive;
 pub(crate) mod utils;
@@ -31,15 +38,90 @@ struct Cli {
     key: Key,
 
     /// The version of WZ archive. Required if create. Overrides the WZ version otherwise.
-    #[arg(short = 'm', long)]
+    #[arg(short = 'm', long, group = "release")]
     version: Option<u16>,
+
+    /// Client the WZ archive belongs to, e.g. `gms83`. Sets both the key and the version.
+    #[arg(long, group = "release", conflicts_with = "key", value_parser = parse_client)]
+    client: Option<Client>,
+
+    /// Directory to extract into
+    #[arg(long, value_name = "DIR", default_value = ".", requires = "extract")]
+    out: PathBuf,
+
+    /// Number of largest images to show in stats
+    #[arg(long, default_value_t = 10)]
+    top: usize,
+
+    /// Keep the archive contents in a `<FILE>.idx` sidecar to speed up listing
+    #[arg(long, default_value_t = false, conflicts_with = "release")]
+    index: bool,
+
+    /// Continue an interrupted create from `<FILE>.part`
+    #[arg(long, default_value_t = false, requires = "create")]
+    resume: bool,
+
+    /// Number of threads used to prepare images when creating. Defaults to the number of CPUs.
+    #[arg(short, long)]
+    jobs: Option<usize>,
+
+    /// Skip the entries that fail to extract or create instead of stopping. The failures are
+    /// summarized at the end and their count is the exit code (capped at 255).
+    #[arg(long, default_value_t = false)]
+    keep_going: bool,
+
+    /// Shape of the server XML
+    #[arg(long, value_enum, default_value_t = Dialect::Native, requires = "server")]
+    dialect: Dialect,
+
+    /// Number of spaces nested server XML elements are indented with. 0 writes each image on one
+    /// line.
+    #[arg(long, default_value_t = 2, requires = "server")]
+    indent: usize,
+
+    /// Where canvas and sound data goes in the server XML. `inline` embeds it as base64, so
+    /// `wzimage -c --server` can build the images again. `files` saves PNG and WAV files next to
+    /// the XML.
+    #[arg(long, value_enum, default_value_t = Data::Omit, requires = "server")]
+    data: Data,
+
+    /// Original archive that patches are made from and applied to
+    #[arg(long, value_name = "ARCHIVE")]
+    base: Option<PathBuf>,
+
+    /// How --preview shows the canvas. `png` saves it to the temporary directory and prints the
+    /// path.
+    #[arg(long, value_enum, default_value_t = Preview::Ansi, requires = "preview")]
+    preview_as: Preview,
+
+    /// Output format of list, stats, debug, verify and status. Errors are printed to stderr as JSON
+    /// objects in json mode.
+    #[arg(long, value_enum, default_value_t = Output::Text)]
+    output: Output,
+
+    /// Convert BGRA8888 canvases with more compressed data than this to BGRA4444 with --optimize
+    #[arg(long, value_name = "BYTES", requires = "optimize")]
+    canvas_limit: Option<usize>,
+
+    /// Dither the canvases converted by --optimize
+    #[arg(long, default_value_t = false, requires = "canvas_limit")]
+    dither: bool,
+
+    /// Keep empty images and packages with --optimize
+    #[arg(long, default_value_t = false, requires = "optimize")]
+    keep_empty: bool,
+
+    /// Copy the targets of UOLs, `_outlink` and `_inlink` into the extracted images. Links into
+    /// other archives are followed through the archives next to the one extracted.
+    #[arg(long, default_value_t = false, requires = "extract")]
+    inline_links: bool,
 }
 
 #[derive(Args)]
 #[group(required = true, multiple = false)]
 struct Action {
     /// Create a new WZ archive
-    #[arg(short = 'c', requires = "version", requires = "directory")]
+    #[arg(short = 'c', requires = "release", requires = "directory")]
     create: bool,
 
     /// List the WZ archive contents
@@ -61,36 +143,321 @@ struct Action {
     /// Generate server XML files based on the wz archive
     #[arg(short = 'S')]
     server: bool,
+
+    /// Print WZ archive statistics
+    #[arg(short = 's', long)]
+    stats: bool,
+
+    /// Print string and UOL properties matching the regex
+    #[arg(short = 'g', long, value_name = "PATTERN")]
+    grep: Option<String>,
+
+    /// Pack the WZ archive into a zstd-compressed file
+    #[arg(long, value_name = "PACKFILE")]
+    pack: Option<PathBuf>,
+
+    /// Restore the WZ archive from a file written by --pack
+    #[arg(long, value_name = "PACKFILE")]
+    unpack: Option<PathBuf>,
+
+    /// Write the size and SHA-256 of every image
+    #[arg(long, value_name = "OUTPUT")]
+    manifest: Option<PathBuf>,
+
+    /// Write a patch that turns the archive given with --base into the one given with -f
+    #[arg(long, value_name = "PATCHFILE", requires = "base")]
+    make_patch: Option<PathBuf>,
+
+    /// Apply a patch written by --make-patch to the archive given with --base, saving to -f
+    #[arg(long, value_name = "PATCHFILE", requires = "base")]
+    apply_patch: Option<PathBuf>,
+
+    /// Check the images extracted under DIR (default: current directory) against the manifest
+    /// given with -f
+    #[arg(long)]
+    verify: bool,
+
+    /// Compare the images extracted under DIR (default: current directory) with the archive given
+    /// with -f, listing the new, modified and missing ones. Verbose counts the unchanged ones.
+    #[arg(long)]
+    status: bool,
+
+    /// Bring the archive given with -f and the images extracted under DIR (default: current
+    /// directory) in line, touching only the images --status lists. `archive` rebuilds the
+    /// archive from the changed files and `directory` extracts the changed images again, removing
+    /// the files that are not in the archive.
+    #[arg(long, value_enum, value_name = "TO")]
+    sync: Option<SyncTo>,
+
+    /// Show the canvas at the path, e.g. `Weapon/01472030.img/info/icon`, in the terminal
+    #[arg(long, value_name = "CANVAS")]
+    preview: Option<String>,
+
+    /// Rebuild the WZ archive into OUTPUT, storing identical images once and removing empty ones
+    #[arg(long, value_name = "OUTPUT")]
+    optimize: Option<PathBuf>,
+
+    /// List the UOL, `_outlink` and `_inlink` references whose target does not exist. Verbose
+    /// lists every reference.
+    #[arg(long)]
+    links: bool,
+
+    /// Index the strings of every WZ archive in DIR into the file given with -f
+    #[arg(long, value_name = "DIR")]
+    build_text_index: Option<PathBuf>,
+
+    /// Print the paths of the strings holding every word of QUERY, using the index given with -f
+    #[arg(long, value_name = "QUERY")]
+    search: Option<String>,
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
 enum Key {
     Gms,
     Kms,
+    Tms,
+    Jms,
+    Cms,
+    Msea,
     None,
 }
 
+impl Key {
+    /// Returns the IV strings are encrypted with, or `None` if they are not encrypted
+    fn iv(&self) -> Option<[u8; 4]> {
+        let iv = match self {
+            Self::Gms => GMS_IV,
+            Self::Kms => KMS_IV,
+            Self::Tms => TMS_IV,
+            Self::Jms => JMS_IV,
+            Self::Cms => CMS_IV,
+            Self::Msea => MSEA_IV,
+            Self::None => return None,
+        };
+        crypto::is_encrypted(&iv).then_some(iv)
+    }
+}
+
+impl From<Region> for Key {
+    fn from(region: Region) -> Self {
+        match region {
+            Region::Gms => Self::Gms,
+            Region::Kms => Self::Kms,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum Dialect {
+    Native,
+    Harepacker,
+    Wzdumper,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum Data {
+    Omit,
+    Inline,
+    Files,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum Preview {
+    Ansi,
+    Sixel,
+    Png,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum SyncTo {
+    Archive,
+    Directory,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum Output {
+    Text,
+    Json,
+}
+
+fn parse_client(name: &str) -> std::result::Result<Client, String> {
+    name.parse().map_err(|e: Error| e.to_string())
+}
+
 fn main() -> Result<()> {
     let args = Cli::parse();
+    let output = args.output;
+    match run(args) {
+        Err(e) if output == Output::Json => {
+            eprintln!("{}", json!({ "error": error_json(&e) }));
+            process::exit(1);
+        }
+        Ok(failures) if !failures.is_empty() => {
+            print_failures(&failures, output);
+            process::exit(failures.len().min(255) as i32);
+        }
+        result => result.map(|_| ()),
+    }
+}
+
+fn print_failures(failures: &[(String, Error)], output: Output) {
+    match output {
+        Output::Text => {
+            eprintln!("{} entries failed:", failures.len());
+            for (path, e) in failures {
+                eprintln!("  {}: {}", path, e);
+            }
+        }
+        Output::Json => {
+            let errors = failures
+                .iter()
+                .map(|(path, e)| {
+                    let mut error = error_json(e);
+                    error["path"] = json!(path);
+                    error
+                })
+                .collect::<Vec<_>>();
+            eprintln!("{}", json!({ "errors": errors }));
+        }
+    }
+}
+
+fn error_json(e: &Error) -> serde_json::Value {
+    let kind = match e {
+        Error::Canvas(_) => "canvas",
+        Error::Decode(_) => "decode",
+        Error::Image(_) => "image",
+        Error::Io(_) => "io",
+        Error::Map(_) => "map",
+        Error::Package(_) => "package",
+        Error::Sound(_) => "sound",
+        Error::Xml(_) => "xml",
+    };
+    json!({ "kind": kind, "message": e.to_string() })
+}
+
+/// Runs the action and returns the entries skipped with `--keep-going`
+fn run(args: Cli) -> Result<Vec<(String, Error)>> {
     let action = &args.action;
+    let (key, version) = match args.client {
+        Some(client) => (Key::from(client.region), Some(client.version)),
+        None => (args.key, args.version),
+    };
     if action.create {
-        archive::do_create(
+        return archive::do_create(
             &args.file,
             &args.directory.unwrap(),
             args.verbose,
-            args.key,
-            args.version.unwrap(),
-        )?;
+            key,
+            version.unwrap(),
+            args.resume,
+            args.jobs
+                .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get())),
+            args.keep_going,
+        );
     } else if action.list {
-        archive::do_list(&args.file, args.key, args.version)?;
+        archive::do_list(&args.file, key, version, args.index, args.output)?;
     } else if action.extract {
-        archive::do_extract(&args.file, args.verbose, args.key, args.version)?;
+        return archive::do_extract(
+            &args.file,
+            &args.out,
+            args.verbose,
+            key,
+            version,
+            args.keep_going,
+            args.inline_links,
+        );
     } else if action.debug {
-        archive::do_debug(&args.file, &args.directory, args.key, args.version)?;
+        archive::do_debug(&args.file, &args.directory, key, version, args.output)?;
     } else if action.list_file {
-        archive::do_list_file(&args.file, args.key)?;
+        archive::do_list_file(&args.file, key)?;
     } else if action.server {
-        archive::do_server(&args.file, args.verbose, args.key, args.version)?;
+        archive::do_server(
+            &args.file,
+            args.verbose,
+            key,
+            version,
+            args.dialect,
+            args.indent,
+            args.data,
+        )?;
+    } else if action.stats {
+        archive::do_stats(&args.file, key, version, args.top, args.output)?;
+    } else if let Some(pattern) = &action.grep {
+        archive::do_grep(&args.file, pattern, key, version)?;
+    } else if let Some(output) = &action.pack {
+        archive::do_pack(&args.file, output, key, version)?;
+    } else if let Some(input) = &action.unpack {
+        archive::do_unpack(&args.file, input, args.verbose)?;
+    } else if let Some(output) = &action.manifest {
+        archive::do_manifest(&args.file, output, key, version)?;
+    } else if let Some(output) = &action.make_patch {
+        archive::do_make_patch(
+            &args.file,
+            args.base.as_deref().unwrap(),
+            output,
+            args.verbose,
+            key,
+            version,
+        )?;
+    } else if let Some(input) = &action.apply_patch {
+        archive::do_apply_patch(
+            &args.file,
+            args.base.as_deref().unwrap(),
+            input,
+            args.verbose,
+            key,
+        )?;
+    } else if action.verify {
+        archive::do_verify(&args.file, &args.directory, args.verbose, args.output)?;
+    } else if action.status {
+        archive::do_status(
+            &args.file,
+            &args.directory,
+            args.verbose,
+            key,
+            version,
+            args.output,
+        )?;
+    } else if let Some(to) = action.sync {
+        archive::do_sync(
+            &args.file,
+            &args.directory,
+            to,
+            args.verbose,
+            key,
+            version,
+            args.jobs
+                .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get())),
+        )?;
+    } else if let Some(canvas) = &action.preview {
+        archive::do_preview(&args.file, canvas, key, version, args.preview_as)?;
+    } else if let Some(output) = &action.optimize {
+        let policy = Policy {
+            canvas_limit: args.canvas_limit,
+            options: ReencodeOptions {
+                dither: args.dither,
+                ..ReencodeOptions::default()
+            },
+            strip: !args.keep_empty,
+            ..Policy::default()
+        };
+        archive::do_optimize(
+            &args.file,
+            output,
+            &policy,
+            args.verbose,
+            key,
+            version,
+            args.jobs
+                .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get())),
+        )?;
+    } else if action.links {
+        archive::do_links(&args.file, args.verbose, key)?;
+    } else if let Some(dir) = &action.build_text_index {
+        archive::do_build_text_index(&args.file, dir, key)?;
+    } else if let Some(query) = &action.search {
+        archive::do_search(&args.file, query)?;
     }
-    Ok(())
+    Ok(Vec::new())
 }
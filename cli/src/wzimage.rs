@@ -2,6 +2,7 @@
 #![doc = include_str!("../README.md")]
 
 use clap::{Args, Parser, ValueEnum};
+use crypto::{CMS_IV, GMS_IV, JMS_IV, KMS_IV, MSEA_IV, TMS_IV};
 use std::path::PathBuf;
 use wz::error::Result;
 
@@ -29,6 +30,46 @@ struct Cli {
     /// Expect encrypted strings
     #[arg(short, long, value_enum, default_value_t = Key::None)]
     key: Key,
+
+    /// Sprite sheet scale
+    #[arg(long, default_value_t = 1)]
+    scale: u32,
+
+    /// Crop the transparent border of every sprite sheet frame
+    #[arg(long, default_value_t = false)]
+    trim_transparent: bool,
+
+    /// Export animations as APNG instead of GIF
+    #[arg(long, default_value_t = false)]
+    apng: bool,
+
+    /// BC3 encoding quality when creating
+    #[arg(long, value_enum, default_value_t = Quality::Normal)]
+    quality: Quality,
+
+    /// Keep running after creating and rebuild whenever the XML or its assets change
+    #[arg(long, default_value_t = false, requires = "create")]
+    watch: bool,
+
+    /// Create from the server XML written by `wzarchive -S` instead of the extracted XML
+    #[arg(long, default_value_t = false, requires = "create")]
+    server: bool,
+
+    /// Tool that wrote the server XML read with `--server`
+    #[arg(long, value_enum, default_value_t = Dialect::Native, requires = "server")]
+    dialect: Dialect,
+
+    /// Fail when the image encodes to more bytes than this with `--sizes`
+    #[arg(long, value_name = "BYTES", requires = "sizes")]
+    budget: Option<u64>,
+
+    /// Format the canvases are converted to with `--optimize`
+    #[arg(long, value_enum, default_value_t = Target::Bgra4444, requires = "optimize")]
+    target: Target,
+
+    /// Dither the canvases converted with `--optimize`
+    #[arg(long, default_value_t = false, requires = "optimize")]
+    dither: bool,
 }
 
 #[derive(Args)]
@@ -49,27 +90,125 @@ struct Action {
     /// Debug the WZ image
     #[arg(short = 'd')]
     debug: bool,
+
+    /// Export the animation at the path as a sprite sheet PNG and JSON metadata
+    #[arg(long, value_name = "ANIMATION")]
+    sprites: Option<String>,
+
+    /// Export the animation at the path as a GIF, or an APNG with `--apng`
+    #[arg(long, value_name = "ANIMATION")]
+    anim: Option<String>,
+
+    /// Export the string properties as CSV, or JSON when the output ends in `.json`
+    #[arg(long, value_name = "OUTPUT")]
+    export_strings: Option<PathBuf>,
+
+    /// Write the strings of an edited CSV back into the WZ image
+    #[arg(long, value_name = "CSV")]
+    import_strings: Option<PathBuf>,
+
+    /// Check the WZ image against a TOML or JSON schema of expected properties
+    #[arg(long, value_name = "SCHEMA")]
+    validate: Option<PathBuf>,
+
+    /// Report the encoded size of every child of the path, split into strings, canvases, sounds
+    /// and everything else
+    #[arg(long)]
+    sizes: bool,
+
+    /// Convert the BGRA8888 canvases to a smaller format, keeping the ones that shrink
+    #[arg(long)]
+    optimize: bool,
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
 enum Key {
     Gms,
     Kms,
+    Tms,
+    Jms,
+    Cms,
+    Msea,
     None,
 }
 
+impl Key {
+    /// Returns the IV strings are encrypted with, or `None` if they are not encrypted
+    fn iv(&self) -> Option<[u8; 4]> {
+        let iv = match self {
+            Self::Gms => GMS_IV,
+            Self::Kms => KMS_IV,
+            Self::Tms => TMS_IV,
+            Self::Jms => JMS_IV,
+            Self::Cms => CMS_IV,
+            Self::Msea => MSEA_IV,
+            Self::None => return None,
+        };
+        crypto::is_encrypted(&iv).then_some(iv)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum Dialect {
+    Native,
+    Harepacker,
+    Wzdumper,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum Target {
+    Bgra4444,
+    Rgb565,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum Quality {
+    Fast,
+    Normal,
+    Best,
+}
+
 fn main() -> Result<()> {
     let args = Cli::parse();
 
     let action = &args.action;
     if action.create {
-        image::do_create(&args.file, &args.path.unwrap(), args.verbose, args.key)?;
+        image::do_create(
+            &args.file,
+            &args.path.unwrap(),
+            args.verbose,
+            args.key,
+            args.quality,
+            args.watch,
+            args.server.then_some(args.dialect),
+        )?;
     } else if action.list {
         image::do_list(&args.file, args.key)?;
     } else if action.extract {
         image::do_extract(&args.file, args.verbose, args.key)?;
     } else if action.debug {
         image::do_debug(&args.file, &args.path, args.verbose, args.key)?;
+    } else if let Some(animation) = &action.sprites {
+        image::do_sprites(
+            &args.file,
+            animation,
+            args.scale,
+            args.trim_transparent,
+            args.verbose,
+            args.key,
+        )?;
+    } else if let Some(animation) = &action.anim {
+        image::do_animation(&args.file, animation, args.apng, args.verbose, args.key)?;
+    } else if let Some(output) = &action.export_strings {
+        image::do_export_strings(&args.file, output, args.verbose, args.key)?;
+    } else if let Some(csv) = &action.import_strings {
+        image::do_import_strings(&args.file, csv, args.verbose, args.key)?;
+    } else if let Some(schema) = &action.validate {
+        image::do_validate(&args.file, schema, args.verbose, args.key)?;
+    } else if action.sizes {
+        image::do_sizes(&args.file, &args.path, args.budget, args.verbose, args.key)?;
+    } else if action.optimize {
+        image::do_optimize(&args.file, args.target, args.dither, args.verbose, args.key)?;
     }
     Ok(())
 }
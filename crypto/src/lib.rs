@@ -32,6 +32,25 @@ pub const GMS_IV: [u8; 4] = [0x4d, 0x23, 0xc7, 0x2b];
 /// The AES-256 IV used in KMS
 pub const KMS_IV: [u8; 4] = [0xb9, 0x7d, 0x63, 0xe9];
 
+/// The AES-256 IV used in MSEA. Same as [`KMS_IV`].
+pub const MSEA_IV: [u8; 4] = KMS_IV;
+
+/// The IV used in TMS. Zeroed since TMS strings are not encrypted, only masked. Read them without
+/// a [`KeyStream`].
+pub const TMS_IV: [u8; 4] = [0x00; 4];
+
+/// The IV used in JMS. Zeroed like [`TMS_IV`].
+pub const JMS_IV: [u8; 4] = [0x00; 4];
+
+/// The IV used in CMS. Zeroed like [`TMS_IV`].
+pub const CMS_IV: [u8; 4] = [0x00; 4];
+
+/// Returns `true` if strings are encrypted with a [`KeyStream`] built from `iv`. A zeroed IV
+/// means they are not.
+pub fn is_encrypted(iv: &[u8; 4]) -> bool {
+    iv != &[0x00; 4]
+}
+
 /// Trait representing Encryptors
 pub trait Encryptor {
     /// Encrypts an array of bytes
@@ -43,3 +62,13 @@ pub trait Decryptor {
     /// Decrypts an array of bytes
     fn decrypt(&mut self, bytes: &mut Vec<u8>);
 }
+
+impl<D> Decryptor for Box<D>
+where
+    D: Decryptor + ?Sized,
+{
+    #[inline]
+    fn decrypt(&mut self, bytes: &mut Vec<u8>) {
+        (**self).decrypt(bytes)
+    }
+}
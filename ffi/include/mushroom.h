@@ -0,0 +1,46 @@
+/* C interface to the mushroom WZ library */
+
+#ifndef MUSHROOM_H
+#define MUSHROOM_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define MUSHROOM_KEY_NONE 0
+#define MUSHROOM_KEY_GMS 1
+#define MUSHROOM_KEY_KMS 2
+
+typedef struct MushroomArchive MushroomArchive;
+
+/* Opens a WZ archive. Returns NULL on failure. */
+MushroomArchive *mushroom_archive_open(const char *path, int key);
+
+/* Closes an archive. NULL is ignored. */
+void mushroom_archive_free(MushroomArchive *archive);
+
+/* Newline separated child names. Free with mushroom_string_free. */
+char *mushroom_list(MushroomArchive *archive, const char *path);
+
+/* Property value as text. Free with mushroom_string_free. */
+char *mushroom_get_property(MushroomArchive *archive, const char *path);
+
+/* Canvas as RGBA pixels. The size pointers must not be NULL. Free with mushroom_buffer_free. */
+uint8_t *mushroom_get_canvas(MushroomArchive *archive, const char *path, uint32_t *width,
+                             uint32_t *height, size_t *len);
+
+void mushroom_string_free(char *s);
+
+void mushroom_buffer_free(uint8_t *buffer, size_t len);
+
+/* Message of the last failure on this thread or NULL */
+const char *mushroom_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* MUSHROOM_H */
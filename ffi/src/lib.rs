@@ -0,0 +1,393 @@
+//! C ABI over the WZ library.
+//!
+//! Archives are opened into opaque handles and queried with `/` separated paths that start with
+//! the archive name without its extension, e.g. `String/Mob.img/100100/name`. Strings and buffers
+//! handed out by this library must be released with the matching free function. When a function
+//! fails it returns null and [`mushroom_last_error`] describes the failure. Panics are caught and
+//! reported the same way instead of unwinding into the caller.
+//!
+//! The matching header is `include/mushroom.h`.
+
+use crypto::{KeyStream, GMS_IV, KMS_IV, TRIMMED_KEY};
+use std::{
+    cell::RefCell,
+    ffi::{c_char, c_int, CStr, CString},
+    fmt::Write,
+    panic::{self, AssertUnwindSafe},
+    ptr,
+};
+use wz::{
+    error::{Error, ImageError, Result},
+    io::DummyDecryptor,
+    types::Property,
+    vfs::{Node, Vfs},
+};
+
+/// Strings are not encrypted
+pub const MUSHROOM_KEY_NONE: c_int = 0;
+
+/// Strings are encrypted with the GMS key
+pub const MUSHROOM_KEY_GMS: c_int = 1;
+
+/// Strings are encrypted with the KMS key
+pub const MUSHROOM_KEY_KMS: c_int = 2;
+
+/// Opened archives
+pub enum MushroomArchive {
+    Encrypted(Vfs<KeyStream>),
+    Plain(Vfs<DummyDecryptor>),
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+macro_rules! with_vfs {
+    ($archive:expr, $vfs:ident => $body:expr) => {
+        match $archive {
+            MushroomArchive::Encrypted($vfs) => $body,
+            MushroomArchive::Plain($vfs) => $body,
+        }
+    };
+}
+
+/// Opens a WZ archive. `key` is one of the `MUSHROOM_KEY_*` constants. Returns null on failure.
+///
+/// # Safety
+///
+/// `path` must be a valid nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn mushroom_archive_open(
+    path: *const c_char,
+    key: c_int,
+) -> *mut MushroomArchive {
+    guard(ptr::null_mut(), || archive_open(path, key))
+}
+
+unsafe fn archive_open(path: *const c_char, key: c_int) -> *mut MushroomArchive {
+    let result = str_arg(path).and_then(|path| match key {
+        MUSHROOM_KEY_GMS => Ok(MushroomArchive::Encrypted(Vfs::from_archives(
+            [path],
+            KeyStream::new(&TRIMMED_KEY, &GMS_IV),
+        )?)),
+        MUSHROOM_KEY_KMS => Ok(MushroomArchive::Encrypted(Vfs::from_archives(
+            [path],
+            KeyStream::new(&TRIMMED_KEY, &KMS_IV),
+        )?)),
+        MUSHROOM_KEY_NONE => Ok(MushroomArchive::Plain(Vfs::from_archives(
+            [path],
+            DummyDecryptor,
+        )?)),
+        k => Err(ImageError::Value(format!("unknown key {}", k)).into()),
+    });
+    match result {
+        Ok(archive) => Box::into_raw(Box::new(archive)),
+        Err(e) => fail(e, ptr::null_mut()),
+    }
+}
+
+/// Closes an archive opened with [`mushroom_archive_open`]. Null is ignored.
+///
+/// # Safety
+///
+/// `archive` must come from [`mushroom_archive_open`] and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn mushroom_archive_free(archive: *mut MushroomArchive) {
+    if !archive.is_null() {
+        guard((), || drop(Box::from_raw(archive)));
+    }
+}
+
+/// Lists the children of a package, image or property as newline separated names. Returns null
+/// on failure. Free the result with [`mushroom_string_free`].
+///
+/// # Safety
+///
+/// `archive` must be a live handle and `path` a valid nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn mushroom_list(
+    archive: *mut MushroomArchive,
+    path: *const c_char,
+) -> *mut c_char {
+    guard(ptr::null_mut(), || list(archive, path))
+}
+
+unsafe fn list(archive: *mut MushroomArchive, path: *const c_char) -> *mut c_char {
+    let result = handle(archive).and_then(|archive| {
+        let path = str_arg(path)?;
+        let names = with_vfs!(archive, vfs => match vfs.read(path)? {
+            Node::Directory(names) => names,
+            Node::Image(image) => child_names(&image, image.name())?,
+            Node::Property(_) => {
+                let image_path = image_of(path)?;
+                let image = vfs.open_image(image_path)?;
+                let inner = &path[image_path.len()..];
+                child_names(&image, &format!("{}{}", image.name(), inner))?
+            }
+        });
+        Ok(names.join("\n"))
+    });
+    string_result(result)
+}
+
+/// Returns the value of a property as text. Vectors are written as `x,y` and canvases as
+/// `width,height`. Returns null on failure. Free the result with [`mushroom_string_free`].
+///
+/// # Safety
+///
+/// `archive` must be a live handle and `path` a valid nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn mushroom_get_property(
+    archive: *mut MushroomArchive,
+    path: *const c_char,
+) -> *mut c_char {
+    guard(ptr::null_mut(), || get_property(archive, path))
+}
+
+unsafe fn get_property(archive: *mut MushroomArchive, path: *const c_char) -> *mut c_char {
+    let result = handle(archive).and_then(|archive| {
+        let path = str_arg(path)?;
+        match with_vfs!(archive, vfs => vfs.read(path)?) {
+            Node::Property(property) => Ok(property_text(&property)),
+            _ => Err(ImageError::Path(String::from(path)).into()),
+        }
+    });
+    string_result(result)
+}
+
+/// Decodes a canvas into an RGBA buffer of `width * height * 4` bytes. The size is written to
+/// `width`, `height` and `len`, which must not be null. Returns null on failure. Free the result
+/// with [`mushroom_buffer_free`].
+///
+/// # Safety
+///
+/// `archive` must be a live handle, `path` a valid nul-terminated string and `width`, `height`
+/// and `len` valid pointers or null.
+#[no_mangle]
+pub unsafe extern "C" fn mushroom_get_canvas(
+    archive: *mut MushroomArchive,
+    path: *const c_char,
+    width: *mut u32,
+    height: *mut u32,
+    len: *mut usize,
+) -> *mut u8 {
+    guard(ptr::null_mut(), || {
+        get_canvas(archive, path, width, height, len)
+    })
+}
+
+unsafe fn get_canvas(
+    archive: *mut MushroomArchive,
+    path: *const c_char,
+    width: *mut u32,
+    height: *mut u32,
+    len: *mut usize,
+) -> *mut u8 {
+    if width.is_null() || height.is_null() || len.is_null() {
+        return fail(
+            ImageError::Value(String::from("null size pointer")).into(),
+            ptr::null_mut(),
+        );
+    }
+    let result = handle(archive).and_then(|archive| {
+        let path = str_arg(path)?;
+        match with_vfs!(archive, vfs => vfs.read(path)?) {
+            Node::Property(Property::Canvas(canvas)) => Ok(canvas.image_buffer()?),
+            _ => Err(ImageError::Path(String::from(path)).into()),
+        }
+    });
+    match result {
+        Ok(image) => {
+            *width = image.width();
+            *height = image.height();
+            let buffer = image.into_raw().into_boxed_slice();
+            *len = buffer.len();
+            Box::into_raw(buffer) as *mut u8
+        }
+        Err(e) => fail(e, ptr::null_mut()),
+    }
+}
+
+/// Releases a string returned by this library. Null is ignored.
+///
+/// # Safety
+///
+/// `s` must come from this library and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn mushroom_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// Releases a buffer returned by [`mushroom_get_canvas`]. Null is ignored.
+///
+/// # Safety
+///
+/// `buffer` and `len` must come from [`mushroom_get_canvas`] and the buffer must not be used
+/// afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn mushroom_buffer_free(buffer: *mut u8, len: usize) {
+    if !buffer.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(buffer, len)));
+    }
+}
+
+/// Returns the message of the last failure on this thread or null. The message stays valid until
+/// the next call into this library on the same thread.
+#[no_mangle]
+pub extern "C" fn mushroom_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| {
+        e.borrow()
+            .as_ref()
+            .map_or(ptr::null(), |message| message.as_ptr())
+    })
+}
+
+// *** PRIVATES *** //
+
+fn fail<T>(error: Error, value: T) -> T {
+    fail_with(&error.to_string(), value)
+}
+
+fn fail_with<T>(message: &str, value: T) -> T {
+    let message = CString::new(message.replace('\0', " ")).expect("nul bytes should be replaced");
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(message));
+    value
+}
+
+/// Runs `body`, returning `value` and recording the message if it panics. Unwinding into the
+/// caller would abort the host process.
+fn guard<T>(value: T, body: impl FnOnce() -> T) -> T {
+    match panic::catch_unwind(AssertUnwindSafe(body)) {
+        Ok(result) => result,
+        Err(payload) => {
+            let message = match (
+                payload.downcast_ref::<&str>(),
+                payload.downcast_ref::<String>(),
+            ) {
+                (Some(message), _) => message,
+                (_, Some(message)) => message.as_str(),
+                _ => "unknown panic",
+            };
+            fail_with(&format!("panic: {}", message), value)
+        }
+    }
+}
+
+unsafe fn handle<'a>(archive: *mut MushroomArchive) -> Result<&'a mut MushroomArchive> {
+    archive
+        .as_mut()
+        .ok_or_else(|| ImageError::Value(String::from("null archive")).into())
+}
+
+unsafe fn str_arg<'a>(s: *const c_char) -> Result<&'a str> {
+    if s.is_null() {
+        return Err(ImageError::Value(String::from("null string")).into());
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map_err(|_| ImageError::Value(String::from("string is not UTF-8")).into())
+}
+
+fn string_result(result: Result<String>) -> *mut c_char {
+    match result.and_then(|s| {
+        CString::new(s).map_err(|_| ImageError::Value(String::from("nul in value")).into())
+    }) {
+        Ok(s) => s.into_raw(),
+        Err(e) => fail(e, ptr::null_mut()),
+    }
+}
+
+/// Returns the part of `path` up to and including the image
+fn image_of(path: &str) -> Result<&str> {
+    match path.find(".img") {
+        Some(i) => Ok(&path[..i + 4]),
+        None => Err(ImageError::Path(String::from(path)).into()),
+    }
+}
+
+fn child_names(image: &wz::map::Map<Property>, path: &str) -> Result<Vec<String>> {
+    let cursor = image.cursor_at(path)?;
+    let names = cursor.list().map(String::from).collect();
+    Ok(names)
+}
+
+fn property_text(property: &Property) -> String {
+    let mut text = String::new();
+    let _ = match property {
+        Property::Null => write!(text, "null"),
+        Property::Short(v) => write!(text, "{}", v),
+        Property::Int(v) => write!(text, "{}", **v),
+        Property::Long(v) => write!(text, "{}", **v),
+        Property::Float(v) => write!(text, "{}", v),
+        Property::Double(v) => write!(text, "{}", v),
+        Property::String(v) => write!(text, "{}", v.as_ref()),
+        Property::ImgDir => write!(text, "imgdir"),
+        Property::Canvas(v) => write!(text, "{},{}", *v.width(), *v.height()),
+        Property::Convex => write!(text, "extended"),
+        Property::Vector(v) => write!(text, "{},{}", *v.x, *v.y),
+        Property::Uol(v) => write!(text, "{}", v.as_ref()),
+        Property::Sound(v) => write!(text, "{}", *v.duration()),
+        Property::Unknown(t) => write!(text, "unknown {}", t),
+        Property::Error(e) => write!(text, "error {}", e),
+    };
+    text
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::*;
+    use std::ffi::{CStr, CString};
+
+    #[test]
+    fn v83_string() {
+        let path = CString::new("../wz/testdata/v83-string.wz").unwrap();
+        unsafe {
+            let archive = mushroom_archive_open(path.as_ptr(), MUSHROOM_KEY_GMS);
+            assert!(!archive.is_null());
+
+            let query = CString::new("v83-string/Mob.img/100100/name").unwrap();
+            let name = mushroom_get_property(archive, query.as_ptr());
+            assert!(!name.is_null());
+            assert!(!CStr::from_ptr(name).to_bytes().is_empty());
+            mushroom_string_free(name);
+
+            let query = CString::new("v83-string").unwrap();
+            let list = mushroom_list(archive, query.as_ptr());
+            assert!(CStr::from_ptr(list).to_str().unwrap().contains("Mob.img"));
+            mushroom_string_free(list);
+
+            let query = CString::new("v83-string/Missing.img").unwrap();
+            assert!(mushroom_get_property(archive, query.as_ptr()).is_null());
+            assert!(!mushroom_last_error().is_null());
+
+            // Size pointers are checked before anything is written through them
+            let (mut width, mut len) = (0u32, 0usize);
+            let query = CString::new("v83-string/Mob.img/100100/name").unwrap();
+            let buffer = mushroom_get_canvas(
+                archive,
+                query.as_ptr(),
+                &mut width,
+                ptr::null_mut(),
+                &mut len,
+            );
+            assert!(buffer.is_null());
+            let error = CStr::from_ptr(mushroom_last_error()).to_str().unwrap();
+            assert!(error.contains("null size pointer"), "{}", error);
+            mushroom_archive_free(archive);
+        }
+    }
+
+    #[test]
+    fn catch_panics() {
+        assert_eq!(guard(1, || 2), 2);
+        assert!(guard(ptr::null_mut::<u8>(), || panic!("boom")).is_null());
+        let error = unsafe { CStr::from_ptr(mushroom_last_error()) };
+        assert_eq!(error.to_str().unwrap(), "panic: boom");
+        guard((), || panic!("{}", String::from("formatted")));
+        let error = unsafe { CStr::from_ptr(mushroom_last_error()) };
+        assert_eq!(error.to_str().unwrap(), "panic: formatted");
+    }
+}
@@ -0,0 +1,180 @@
+//! mapledev.ini parsing
+//!
+//! The config is a plain INI file shared by the launcher and the DLL:
+//!
+//! ```ini
+//! ; Comment
+//! [section]
+//! key = value
+//! ```
+//!
+//! Keys may be repeated within a section. `Section::get` returns the last value while
+//! `Section::get_all` returns every value in order.
+
+use crate::error::Error;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+/// Config location, relative to the working directory
+pub const CONFIG_FILE: &str = "mapledev.ini";
+
+/// A named group of key/value pairs
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Section {
+    name: String,
+    entries: Vec<(String, String)>,
+}
+
+impl Section {
+    /// Name of the section
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the last value of `key`
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Returns every value of `key`
+    pub fn get_all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries
+            .iter()
+            .filter(move |(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Returns every key/value pair in order
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// Returns the last value of `key` as a bool
+    pub fn get_bool(&self, key: &str) -> Result<Option<bool>, Error> {
+        match self.get(key) {
+            Some(v) => match v.to_ascii_lowercase().as_str() {
+                "1" | "true" | "yes" | "on" => Ok(Some(true)),
+                "0" | "false" | "no" | "off" => Ok(Some(false)),
+                _ => Err(self.invalid(key, v)),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the last value of `key` as an unsigned integer. Values prefixed with `0x` are
+    /// parsed as hex.
+    pub fn get_u32(&self, key: &str) -> Result<Option<u32>, Error> {
+        match self.get(key) {
+            Some(v) => parse_u32(v).map(Some).ok_or_else(|| self.invalid(key, v)),
+            None => Ok(None),
+        }
+    }
+
+    /// Error for an unparsable value
+    pub fn invalid(&self, key: &str, value: &str) -> Error {
+        Error::Config(format!(
+            "[{}] invalid value for `{}`: `{}`",
+            self.name, key, value
+        ))
+    }
+}
+
+/// Parsed mapledev.ini
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Config {
+    sections: Vec<Section>,
+}
+
+impl Config {
+    /// Loads `CONFIG_FILE`. A missing file results in an empty config.
+    pub fn load() -> Result<Self, Error> {
+        Self::from_file(CONFIG_FILE)
+    }
+
+    /// Loads a config file. A missing file results in an empty config.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        match fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(Error::Config(format!("{}: {}", path.display(), e))),
+        }
+    }
+
+    /// Parses the text of a config file
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut sections = vec![Section::default()];
+        for (num, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[') {
+                let name = name
+                    .strip_suffix(']')
+                    .ok_or_else(|| Error::Config(format!("line {}: expected `]`", num + 1)))?;
+                sections.push(Section {
+                    name: name.trim().into(),
+                    entries: Vec::new(),
+                });
+            } else {
+                let (key, value) = line
+                    .split_once('=')
+                    .ok_or_else(|| Error::Config(format!("line {}: expected `=`", num + 1)))?;
+                sections
+                    .last_mut()
+                    .expect("always at least one section")
+                    .entries
+                    .push((key.trim().into(), value.trim().into()));
+            }
+        }
+        Ok(Self { sections })
+    }
+
+    /// Returns the first section named `name`. Keys before any section header belong to the
+    /// section named "".
+    pub fn section(&self, name: &str) -> Option<&Section> {
+        self.sections.iter().find(|s| s.name == name)
+    }
+
+    /// Returns every section whose name starts with `prefix`, along with the rest of its name
+    pub fn sections_with_prefix<'a>(
+        &'a self,
+        prefix: &'a str,
+    ) -> impl Iterator<Item = (&'a str, &'a Section)> + 'a {
+        self.sections
+            .iter()
+            .filter_map(move |s| s.name.strip_prefix(prefix).map(|rest| (rest, s)))
+    }
+}
+
+/// Parses a decimal or `0x` prefixed hex integer
+pub fn parse_u32(value: &str) -> Option<u32> {
+    match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+/// Parses a string of hex bytes. Whitespace between bytes is ignored.
+pub fn parse_hex(value: &str) -> Option<Vec<u8>> {
+    let digits = value
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>();
+    if digits.is_empty() || !digits.is_ascii() || digits.len() % 2 != 0 {
+        return None;
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).ok())
+        .collect()
+}
@@ -19,6 +19,11 @@ pub enum Error {
     HookInitializeFailed(String),
     HookEnableFailed(String),
     AddressFormat,
+    Config(String),
+    ProfileNotFound(u32),
+    PatchMismatch(String, usize),
+    MemoryRead(usize),
+    MemoryProtect(usize),
     Unknown(String),
 }
 
@@ -39,6 +44,17 @@ impl fmt::Display for Error {
             Self::HookInitializeFailed(func) => write!(f, "Could not hook `{}`", func),
             Self::HookEnableFailed(func) => write!(f, "Hook initialization failed `{}`", func),
             Self::AddressFormat => write!(f, "Address could not be formatted"),
+            Self::Config(s) => write!(f, "Config error: {}", s),
+            Self::ProfileNotFound(crc) => {
+                write!(f, "No patch profile matches the client (crc32 {:08X})", crc)
+            }
+            Self::PatchMismatch(p, addr) => write!(
+                f,
+                "Original bytes at {:08X} do not match patch profile `{}`",
+                addr, p
+            ),
+            Self::MemoryRead(addr) => write!(f, "Could not read memory at {:08X}", addr),
+            Self::MemoryProtect(addr) => write!(f, "Could not unprotect memory at {:08X}", addr),
             Self::Unknown(s) => write!(f, "Unknown: {}", s),
         }
     }
@@ -0,0 +1,178 @@
+//! kernel32.dll file hooks
+//!
+//! Redirects reads of .wz archives to patched copies so mods never touch the originals:
+//!
+//! ```ini
+//! [wz]
+//! ; Directory searched for replacement archives
+//! mod_dir = mods
+//! ; <archive> = <replacement>
+//! Character.wz = mods/Character_v2.wz
+//! ```
+//!
+//! Explicit mappings take priority over `mod_dir`. Archives are matched by file name,
+//! ignoring case. Only `CreateFileA` needs hooking: the returned handle belongs to the
+//! replacement so every following `ReadFile` reads from it.
+
+use crate::config::Config;
+use crate::error::Error;
+use crate::utils;
+use retour::static_detour;
+use std::ffi::{CStr, CString};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use winapi::shared::minwindef::DWORD;
+use winapi::um::minwinbase::LPSECURITY_ATTRIBUTES;
+use winapi::um::processthreadsapi::ExitProcess;
+use winapi::um::winnt::{GENERIC_WRITE, HANDLE, LPCSTR};
+
+/// Reserved `[wz]` key
+const MOD_DIR: &str = "mod_dir";
+
+static_detour! {
+    /// CreateFileA hook structure
+    static CreateFileAHook: unsafe extern "system" fn(LPCSTR, DWORD, DWORD, LPSECURITY_ATTRIBUTES, DWORD, DWORD, HANDLE) -> HANDLE;
+}
+
+/// CreateFileA function definition
+type CreateFileAFn = unsafe extern "system" fn(
+    LPCSTR,
+    DWORD,
+    DWORD,
+    LPSECURITY_ATTRIBUTES,
+    DWORD,
+    DWORD,
+    HANDLE,
+) -> HANDLE;
+
+/// Archive replacements
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WzOverrides {
+    mod_dir: Option<PathBuf>,
+    files: Vec<(String, PathBuf)>,
+}
+
+impl WzOverrides {
+    /// Reads the `[wz]` section. Every explicit replacement must exist.
+    pub fn from_config(config: &Config) -> Result<Self, Error> {
+        let section = match config.section("wz") {
+            Some(section) => section,
+            None => return Ok(Self::default()),
+        };
+        let mod_dir = section.get(MOD_DIR).map(PathBuf::from);
+        if let Some(dir) = &mod_dir {
+            if !dir.is_dir() {
+                return Err(section.invalid(MOD_DIR, &dir.to_string_lossy()));
+            }
+        }
+        let files = section
+            .entries()
+            .filter(|(name, _)| *name != MOD_DIR)
+            .map(|(name, path)| {
+                if Path::new(path).is_file() {
+                    Ok((name.to_ascii_lowercase(), PathBuf::from(path)))
+                } else {
+                    Err(section.invalid(name, path))
+                }
+            })
+            .collect::<Result<Vec<(String, PathBuf)>, Error>>()?;
+        Ok(Self { mod_dir, files })
+    }
+
+    /// Returns true if nothing is overridden
+    pub fn is_empty(&self) -> bool {
+        self.mod_dir.is_none() && self.files.is_empty()
+    }
+
+    /// Returns the replacement for `path`, if any
+    pub fn resolve(&self, path: &str) -> Option<PathBuf> {
+        let name = Path::new(path).file_name()?.to_str()?;
+        if !name.to_ascii_lowercase().ends_with(".wz") {
+            return None;
+        }
+        let lower = name.to_ascii_lowercase();
+        if let Some((_, replacement)) = self.files.iter().rev().find(|(n, _)| *n == lower) {
+            return Some(replacement.clone());
+        }
+        self.mod_dir
+            .as_ref()
+            .map(|dir| dir.join(name))
+            .filter(|replacement| replacement.is_file())
+    }
+}
+
+lazy_static! {
+    /// Archive replacements
+    static ref OVERRIDES: Mutex<WzOverrides> = Mutex::new(WzOverrides::default());
+}
+
+/// CreateFileA Detour
+#[allow(non_snake_case)]
+fn CreateFileA_detour(
+    lpFileName: LPCSTR,
+    dwDesiredAccess: DWORD,
+    dwShareMode: DWORD,
+    lpSecurityAttributes: LPSECURITY_ATTRIBUTES,
+    dwCreationDisposition: DWORD,
+    dwFlagsAndAttributes: DWORD,
+    hTemplateFile: HANDLE,
+) -> HANDLE {
+    // Only reads are redirected
+    let replacement = if !lpFileName.is_null() && dwDesiredAccess & GENERIC_WRITE == 0 {
+        let path = unsafe { CStr::from_ptr(lpFileName) }
+            .to_string_lossy()
+            .to_string();
+        OVERRIDES
+            .lock()
+            .unwrap_or_else(|e| {
+                winlog!("[CreateFileA] ERROR: {:?}", e);
+                unsafe { ExitProcess(3424) };
+                panic!();
+            })
+            .resolve(&path)
+            .map(|replacement| {
+                winlog!(
+                    "[CreateFileA] Replaced: {} -> {}",
+                    path,
+                    replacement.display()
+                );
+                utils::to_cstring(&replacement.to_string_lossy())
+            })
+    } else {
+        None
+    };
+    let file_name = replacement
+        .as_ref()
+        .map(|r: &CString| r.as_ptr())
+        .unwrap_or(lpFileName);
+    unsafe {
+        CreateFileAHook.call(
+            file_name,
+            dwDesiredAccess,
+            dwShareMode,
+            lpSecurityAttributes,
+            dwCreationDisposition,
+            dwFlagsAndAttributes,
+            hTemplateFile,
+        )
+    }
+}
+
+/// Sets up kernel32.dll hooks
+pub(crate) unsafe fn main(config: &Config) -> Result<(), Error> {
+    let overrides = WzOverrides::from_config(config)?;
+    if overrides.is_empty() {
+        return Ok(());
+    }
+    *OVERRIDES
+        .lock()
+        .map_err(|e| Error::Unknown(format!("{:?}", e)))? = overrides;
+    let address = utils::load_module_symbol("kernel32.dll", "CreateFileA")?;
+    let target: CreateFileAFn = ::std::mem::transmute(address);
+    CreateFileAHook
+        .initialize(target, CreateFileA_detour)
+        .map_err(|_| Error::HookInitializeFailed("CreateFileA".into()))?
+        .enable()
+        .map_err(|_| Error::HookEnableFailed("CreateFileA".into()))?;
+    Ok(())
+}
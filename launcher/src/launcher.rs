@@ -2,6 +2,7 @@
 //! Launches MapleStory and injects mapledev.dll
 
 use std::ffi::CString;
+use std::fs;
 use std::path::Path;
 use sysinfo::{Pid, PidExt, ProcessExt, System, SystemExt};
 use winapi::shared::minwindef::{DWORD, FALSE, LPVOID};
@@ -12,11 +13,17 @@ use winapi::um::processthreadsapi::{
     STARTUPINFOA,
 };
 
+#[allow(dead_code)]
+mod config;
 mod error;
 
+#[macro_use]
 #[allow(dead_code)]
 mod utils;
 
+#[allow(dead_code)]
+mod patch;
+
 pub use error::Error;
 
 // MapleStory EXE
@@ -102,6 +109,11 @@ unsafe fn inject_dll(pid: DWORD, dll: CString) -> Result<(), Error> {
 }
 
 pub fn main() -> Result<(), Error> {
+    // Refuse to launch a client without a matching patch profile
+    let config = config::Config::load()?;
+    let client = fs::read(MAPLESTORY).map_err(|_| Error::Path(MAPLESTORY.into()))?;
+    patch::Profile::select(&config, &client)?;
+
     let ms_exe = CString::new(MAPLESTORY).unwrap();
     let mut si: STARTUPINFOA = unsafe { ::std::mem::zeroed() };
     let mut pi: PROCESS_INFORMATION = unsafe { ::std::mem::zeroed() };
@@ -8,13 +8,27 @@ use winapi::shared::minwindef::{BOOL, DWORD, FALSE, HINSTANCE, LPVOID, TRUE};
 use winapi::um::libloaderapi::DisableThreadLibraryCalls;
 use winapi::um::winnt::DLL_PROCESS_ATTACH;
 
+pub mod config;
 pub mod error;
 
 #[macro_use]
 #[allow(dead_code)]
 pub(crate) mod utils;
 
+mod filehook;
+mod patch;
+mod redirect;
 mod sockhook;
+mod window;
+
+/// Loads the config, patches the client and installs hooks
+unsafe fn init() -> Result<(), error::Error> {
+    let config = config::Config::load()?;
+    patch::main(&config)?;
+    sockhook::main(&config)?;
+    window::main(&config)?;
+    filehook::main(&config)
+}
 
 #[no_mangle]
 #[allow(non_snake_case)]
@@ -26,7 +40,7 @@ pub unsafe extern "system" fn DllMain(
     if fdwReason == DLL_PROCESS_ATTACH {
         DisableThreadLibraryCalls(hinstDLL);
         winlog!("[DllMain] Injected mapledev.dll");
-        match sockhook::main() {
+        match init() {
             Ok(_) => TRUE,
             Err(e) => {
                 winlog!("[DllMain] {:?}", e);
@@ -0,0 +1,148 @@
+//! Client memory patch profiles
+//!
+//! Patches are grouped into profiles keyed by the CRC32 of the client executable:
+//!
+//! ```ini
+//! [general]
+//! ; Optional. Forces a profile instead of matching by checksum
+//! profile = gmsv83
+//!
+//! [profile.gmsv83]
+//! ; Optional. When omitted, the profile matches any client
+//! crc32 = 0x12345678
+//! ; patch = <address> <original bytes> <replacement bytes>
+//! patch = 0x9F1C04 E8C0FCFFFF 9090909090
+//! ```
+//!
+//! The original bytes of every patch are verified before any patch is applied so a
+//! mismatched client is left untouched.
+
+use crate::config::{self, Config, Section};
+use crate::error::Error;
+use crate::utils;
+use std::{env, fs};
+
+/// Section prefix of patch profiles
+const PROFILE_PREFIX: &str = "profile.";
+
+/// A single memory patch
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Patch {
+    pub address: usize,
+    pub original: Vec<u8>,
+    pub replacement: Vec<u8>,
+}
+
+impl Patch {
+    /// Parses `<address> <original bytes> <replacement bytes>`
+    fn parse(section: &Section, value: &str) -> Result<Self, Error> {
+        let mut it = value.split_whitespace();
+        let (address, original, replacement) = match (it.next(), it.next(), it.next(), it.next()) {
+            (Some(a), Some(o), Some(r), None) => (a, o, r),
+            _ => return Err(section.invalid("patch", value)),
+        };
+        let address = config::parse_u32(address).ok_or_else(|| section.invalid("patch", value))?;
+        let original =
+            config::parse_hex(original).ok_or_else(|| section.invalid("patch", value))?;
+        let replacement =
+            config::parse_hex(replacement).ok_or_else(|| section.invalid("patch", value))?;
+        if original.len() != replacement.len() {
+            return Err(section.invalid("patch", value));
+        }
+        Ok(Self {
+            address: address as usize,
+            original,
+            replacement,
+        })
+    }
+}
+
+/// A set of patches for one client build
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Profile {
+    pub name: String,
+    pub crc32: Option<u32>,
+    pub patches: Vec<Patch>,
+}
+
+impl Profile {
+    /// Parses a `[profile.<name>]` section
+    pub fn from_section(name: &str, section: &Section) -> Result<Self, Error> {
+        Ok(Self {
+            name: name.into(),
+            crc32: section.get_u32("crc32")?,
+            patches: section
+                .get_all("patch")
+                .map(|value| Patch::parse(section, value))
+                .collect::<Result<Vec<Patch>, Error>>()?,
+        })
+    }
+
+    /// Returns every profile in the config
+    pub fn all(config: &Config) -> Result<Vec<Self>, Error> {
+        config
+            .sections_with_prefix(PROFILE_PREFIX)
+            .map(|(name, section)| Self::from_section(name, section))
+            .collect()
+    }
+
+    /// Selects the profile matching the client executable. Returns `Ok(None)` when the config
+    /// defines no profiles and `Error::ProfileNotFound` when none match.
+    pub fn select(config: &Config, client: &[u8]) -> Result<Option<Self>, Error> {
+        let profiles = Self::all(config)?;
+        if profiles.is_empty() {
+            return Ok(None);
+        }
+        let checksum = crc32(client);
+        let forced = config.section("general").and_then(|s| s.get("profile"));
+        profiles
+            .into_iter()
+            .filter(|p| forced.map(|name| p.name == name).unwrap_or(true))
+            .find(|p| p.crc32.map(|crc| crc == checksum).unwrap_or(true))
+            .map(Some)
+            .ok_or(Error::ProfileNotFound(checksum))
+    }
+
+    /// Verifies the original bytes of every patch
+    pub unsafe fn verify(&self) -> Result<(), Error> {
+        for patch in &self.patches {
+            if utils::read_memory(patch.address, patch.original.len())? != patch.original {
+                return Err(Error::PatchMismatch(self.name.clone(), patch.address));
+            }
+        }
+        Ok(())
+    }
+
+    /// Verifies then applies every patch
+    pub unsafe fn apply(&self) -> Result<(), Error> {
+        self.verify()?;
+        for patch in &self.patches {
+            utils::patch(patch.address, &patch.replacement)?;
+        }
+        Ok(())
+    }
+}
+
+/// CRC32 (IEEE) of `data`
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| {
+            (crc >> 1) ^ (0xEDB88320 & (crc & 1).wrapping_neg())
+        })
+    })
+}
+
+/// Applies the patch profile matching the running client
+pub(crate) unsafe fn main(config: &Config) -> Result<(), Error> {
+    let exe = env::current_exe().map_err(|e| Error::Path(e.to_string()))?;
+    let client = fs::read(&exe).map_err(|_| Error::Path(exe.display().to_string()))?;
+    if let Some(profile) = Profile::select(config, &client)? {
+        profile.apply()?;
+        winlog!(
+            "[patch::main] Applied {} patches from `{}`",
+            profile.patches.len(),
+            profile.name
+        );
+    }
+    Ok(())
+}
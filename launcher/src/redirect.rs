@@ -0,0 +1,185 @@
+//! Connection redirect table
+//!
+//! Maps the address the client connects to onto a new address. Rules are checked in order
+//! and the first match wins:
+//!
+//! ```ini
+//! [redirect]
+//! ; <ip|*>:<port|low-high|*> = <ip|*>:<port|*>
+//! 8.31.99.141:8484 = 127.0.0.1:8484
+//! *:8585-8600 = 10.0.0.2:*
+//! *:* = 127.0.0.1:*
+//! ```
+//!
+//! A `*` on the right keeps the original ip or port. Without a `[redirect]` section, ports
+//! 8000-8999 are redirected to `DEFAULT_IP`.
+
+use crate::config::{Config, Section};
+use crate::error::Error;
+use std::net::Ipv4Addr;
+
+/// The IP to redirect INET traffic to when no redirect table is configured
+const DEFAULT_IP: Ipv4Addr = Ipv4Addr::new(172, 17, 112, 1);
+
+/// Addresses a rule applies to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pattern {
+    pub ip: Option<Ipv4Addr>,
+    pub ports: Option<(u16, u16)>,
+}
+
+impl Pattern {
+    /// Returns true if the address matches
+    pub fn matches(&self, ip: Ipv4Addr, port: u16) -> bool {
+        self.ip.map(|i| i == ip).unwrap_or(true)
+            && self
+                .ports
+                .map(|(low, high)| low <= port && port <= high)
+                .unwrap_or(true)
+    }
+}
+
+/// Address a rule redirects to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Target {
+    pub ip: Option<Ipv4Addr>,
+    pub port: Option<u16>,
+}
+
+/// A single redirect
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rule {
+    pub from: Pattern,
+    pub to: Target,
+}
+
+impl Rule {
+    /// Parses `<ip|*>:<port|low-high|*>` and `<ip|*>:<port|*>`
+    pub fn parse(from: &str, to: &str) -> Option<Self> {
+        let (from_ip, from_port) = from.rsplit_once(':')?;
+        let (to_ip, to_port) = to.rsplit_once(':')?;
+        let ports = match from_port.trim() {
+            "*" => None,
+            p => match p.split_once('-') {
+                Some((low, high)) => {
+                    let (low, high) = (low.trim().parse().ok()?, high.trim().parse().ok()?);
+                    // A reversed range would never match
+                    if low > high {
+                        return None;
+                    }
+                    Some((low, high))
+                }
+                None => {
+                    let port = p.parse().ok()?;
+                    Some((port, port))
+                }
+            },
+        };
+        Some(Self {
+            from: Pattern {
+                ip: parse_ip(from_ip)?,
+                ports,
+            },
+            to: Target {
+                ip: parse_ip(to_ip)?,
+                port: match to_port.trim() {
+                    "*" => None,
+                    p => Some(p.parse().ok()?),
+                },
+            },
+        })
+    }
+}
+
+/// Parses an ip or `*`
+fn parse_ip(ip: &str) -> Option<Option<Ipv4Addr>> {
+    match ip.trim() {
+        "*" => Some(None),
+        ip => ip.parse().ok().map(Some),
+    }
+}
+
+/// Ordered list of redirect rules
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RedirectTable {
+    rules: Vec<Rule>,
+}
+
+impl RedirectTable {
+    /// Builds the table from the `[redirect]` section
+    pub fn from_config(config: &Config) -> Result<Self, Error> {
+        match config.section("redirect") {
+            Some(section) => Self::from_section(section),
+            None => Ok(Self {
+                rules: vec![Rule {
+                    from: Pattern {
+                        ip: None,
+                        ports: Some((8000, 8999)),
+                    },
+                    to: Target {
+                        ip: Some(DEFAULT_IP),
+                        port: None,
+                    },
+                }],
+            }),
+        }
+    }
+
+    /// Parses every rule of a section
+    pub fn from_section(section: &Section) -> Result<Self, Error> {
+        Ok(Self {
+            rules: section
+                .entries()
+                .map(|(from, to)| Rule::parse(from, to).ok_or_else(|| section.invalid(from, to)))
+                .collect::<Result<Vec<Rule>, Error>>()?,
+        })
+    }
+
+    /// Returns the rules in order
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// Returns the new address of a connection or `None` if it isn't redirected
+    pub fn resolve(&self, ip: Ipv4Addr, port: u16) -> Option<(Ipv4Addr, u16)> {
+        self.rules
+            .iter()
+            .find(|rule| rule.from.matches(ip, port))
+            .map(|rule| (rule.to.ip.unwrap_or(ip), rule.to.port.unwrap_or(port)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::config::Config;
+    use crate::redirect::{Pattern, RedirectTable, Rule};
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn parse_rules() {
+        let rule = Rule::parse("*:8585-8600", "10.0.0.2:*").expect("error parsing rule");
+        assert_eq!(
+            rule.from,
+            Pattern {
+                ip: None,
+                ports: Some((8585, 8600)),
+            }
+        );
+        assert!(Rule::parse("*:8484-8484", "*:*").is_some());
+        assert!(Rule::parse("*:9000-8000", "*:*").is_none());
+        assert!(Rule::parse("*:8000", "*:x").is_none());
+
+        let config =
+            Config::parse("[redirect]\n*:9000-8000 = 127.0.0.1:*\n").expect("error parsing");
+        assert!(RedirectTable::from_config(&config).is_err());
+        let config =
+            Config::parse("[redirect]\n*:8000-9000 = 127.0.0.1:*\n").expect("error parsing");
+        let table = RedirectTable::from_config(&config).expect("error building table");
+        assert_eq!(
+            table.resolve(Ipv4Addr::new(1, 2, 3, 4), 8484),
+            Some((Ipv4Addr::new(127, 0, 0, 1), 8484))
+        );
+        assert_eq!(table.resolve(Ipv4Addr::new(1, 2, 3, 4), 9001), None);
+    }
+}
@@ -1,21 +1,31 @@
 //! mswsock.dll hooks
 
+use crate::config::Config;
 use crate::error::Error;
+use crate::redirect::RedirectTable;
 use crate::utils;
 use retour::static_detour;
-use std::ffi::{CStr, CString};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::net::Ipv4Addr;
 use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
 use winapi::ctypes::c_int;
-use winapi::shared::minwindef::{LPINT, ULONG, WORD};
-use winapi::shared::ws2def::{LPSOCKADDR, LPWSABUF, SOCKADDR_IN};
+use winapi::shared::minwindef::{DWORD, LPDWORD, LPINT, WORD};
+use winapi::shared::ws2def::{LPSOCKADDR, LPWSABUF};
 use winapi::um::processthreadsapi::ExitProcess;
 use winapi::um::winsock2::{
-    inet_addr, inet_ntoa, ntohs, LPQOS, LPSOCKADDR_IN, LPWSAPROTOCOL_INFOW, SOCKET,
+    htons, ntohs, LPQOS, LPSOCKADDR_IN, LPWSAOVERLAPPED, LPWSAOVERLAPPED_COMPLETION_ROUTINE,
+    LPWSAPROTOCOL_INFOW, SOCKET, SOCKET_ERROR, WSA_IO_PENDING,
 };
-use winapi::um::ws2spi::{LPWSPDATA, LPWSPPROC_TABLE, WSPUPCALLTABLE};
+use winapi::um::ws2spi::{LPWSATHREADID, LPWSPDATA, LPWSPPROC_TABLE, WSPUPCALLTABLE};
 
-/// The IP to redirect INET traffic to
-const IP: &str = "172.17.112.1";
+/// Default packet log location
+const PACKET_LOG: &str = "packets.log";
+
+/// Named pipe path prefix
+const PIPE_PREFIX: &str = r"\\.\pipe\";
 
 static_detour! {
     /// WSPStartup hook structure
@@ -46,6 +56,158 @@ type WSPConnectFn = unsafe extern "system" fn(
     LPINT,
 ) -> c_int;
 
+/// WSPSend function definition
+type WSPSendFn = unsafe extern "system" fn(
+    SOCKET,
+    LPWSABUF,
+    DWORD,
+    LPDWORD,
+    DWORD,
+    LPWSAOVERLAPPED,
+    LPWSAOVERLAPPED_COMPLETION_ROUTINE,
+    LPWSATHREADID,
+    LPINT,
+) -> c_int;
+
+/// WSPRecv function definition
+type WSPRecvFn = unsafe extern "system" fn(
+    SOCKET,
+    LPWSABUF,
+    DWORD,
+    LPDWORD,
+    LPDWORD,
+    LPWSAOVERLAPPED,
+    LPWSAOVERLAPPED_COMPLETION_ROUTINE,
+    LPWSATHREADID,
+    LPINT,
+) -> c_int;
+
+/// Packet direction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Send,
+    Recv,
+}
+
+/// Packet log record format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LogFormat {
+    /// `<unix ms> <SEND|RECV> <socket> <length> <hex>` lines
+    Text,
+    /// `<unix ms: u64> <direction: u8> <socket: u32> <length: u32> <data>`, little-endian
+    Binary,
+}
+
+/// Writes raw packets to a file or named pipe
+///
+/// ```ini
+/// [packets]
+/// enabled = true
+/// ; File or named pipe (\\.\pipe\<name>)
+/// output = packets.log
+/// ; text or binary
+/// format = text
+/// ```
+#[derive(Debug)]
+struct PacketLog {
+    out: File,
+    format: LogFormat,
+}
+
+impl PacketLog {
+    /// Opens the packet log if enabled in the config
+    fn from_config(config: &Config) -> Result<Option<Self>, Error> {
+        let section = match config.section("packets") {
+            Some(section) => section,
+            None => return Ok(None),
+        };
+        if !section.get_bool("enabled")?.unwrap_or(false) {
+            return Ok(None);
+        }
+        let format = match section.get("format").unwrap_or("text") {
+            "text" => LogFormat::Text,
+            "binary" => LogFormat::Binary,
+            f => return Err(section.invalid("format", f)),
+        };
+        let output = section.get("output").unwrap_or(PACKET_LOG);
+        let out = if output.starts_with(PIPE_PREFIX) {
+            OpenOptions::new().write(true).open(output)
+        } else {
+            OpenOptions::new().create(true).append(true).open(output)
+        }
+        .map_err(|e| Error::Config(format!("{}: {}", output, e)))?;
+        Ok(Some(Self { out, format }))
+    }
+
+    /// Writes a single packet
+    fn write(&mut self, direction: Direction, sock: SOCKET, data: &[u8]) -> io::Result<()> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        match self.format {
+            LogFormat::Text => {
+                let hex = data
+                    .iter()
+                    .map(|b| format!("{:02X}", b))
+                    .collect::<String>();
+                let direction = match direction {
+                    Direction::Send => "SEND",
+                    Direction::Recv => "RECV",
+                };
+                writeln!(
+                    self.out,
+                    "{} {} {} {} {}",
+                    timestamp,
+                    direction,
+                    sock,
+                    data.len(),
+                    hex
+                )
+            }
+            LogFormat::Binary => {
+                let mut record = Vec::with_capacity(17 + data.len());
+                record.extend_from_slice(&timestamp.to_le_bytes());
+                record.push(direction as u8);
+                record.extend_from_slice(&(sock as u32).to_le_bytes());
+                record.extend_from_slice(&(data.len() as u32).to_le_bytes());
+                record.extend_from_slice(data);
+                self.out.write_all(&record)
+            }
+        }?;
+        self.out.flush()
+    }
+}
+
+/// Copies the first `len` bytes out of a WSABUF array
+unsafe fn gather(buffers: LPWSABUF, count: DWORD, len: usize) -> Vec<u8> {
+    let mut data = Vec::with_capacity(len);
+    for i in 0..count as usize {
+        if data.len() == len {
+            break;
+        }
+        let buffer = &*buffers.add(i);
+        let take = (buffer.len as usize).min(len - data.len());
+        data.extend_from_slice(::std::slice::from_raw_parts(buffer.buf as *const u8, take));
+    }
+    data
+}
+
+lazy_static! {
+    /// Original WSPSend function
+    static ref WSPSEND: Mutex<Option<WSPSendFn>> = Mutex::new(None);
+}
+
+lazy_static! {
+    /// Original WSPRecv function
+    static ref WSPRECV: Mutex<Option<WSPRecvFn>> = Mutex::new(None);
+}
+
+lazy_static! {
+    /// Packet log, when enabled
+    static ref PACKETS: Mutex<Option<PacketLog>> = Mutex::new(None);
+}
+
 lazy_static! {
     /// Original WSPGetPeerName function
     static ref WSPGETPEERNAME: Mutex<Option<WSPGetPeerNameFn>> = Mutex::new(None);
@@ -57,30 +219,43 @@ lazy_static! {
 }
 
 lazy_static! {
-    /// The encoded address to redirect INET traffic to
-    static ref REROUTED_ADDR: Mutex<ULONG> = Mutex::new(unsafe { ::std::mem::zeroed() });
+    /// Where to redirect INET traffic to
+    static ref REDIRECTS: Mutex<RedirectTable> = Mutex::new(RedirectTable::default());
 }
 
 lazy_static! {
-    /// The original address the client was trying to reach
-    static ref LAST_CONNECT: Mutex<ULONG> = Mutex::new(unsafe { ::std::mem::zeroed() });
+    /// The original address each redirected socket was trying to reach
+    static ref CONNECTIONS: Mutex<HashMap<SOCKET, (Ipv4Addr, u16)>> = Mutex::new(HashMap::new());
+}
+
+/// Reads the address of a SOCKADDR_IN
+unsafe fn get_addr(addr: LPSOCKADDR_IN) -> (Ipv4Addr, u16) {
+    (
+        Ipv4Addr::from((*(*addr).sin_addr.S_un.S_addr()).to_ne_bytes()),
+        ntohs((*addr).sin_port),
+    )
+}
+
+/// Overwrites the address of a SOCKADDR_IN
+unsafe fn set_addr(addr: LPSOCKADDR_IN, ip: Ipv4Addr, port: u16) {
+    *(*addr).sin_addr.S_un.S_addr_mut() = u32::from_ne_bytes(ip.octets());
+    (*addr).sin_port = htons(port);
 }
 
 /// Wrapped static function
 #[allow(non_snake_case)]
 unsafe fn WSPGetPeerName(sock: SOCKET, name: LPSOCKADDR, namelen: LPINT, lpErrno: LPINT) -> c_int {
-    WSPGETPEERNAME
-        .lock()
-        .unwrap_or_else(|e| {
-            winlog!("[WSPGetPeerName] ERROR: {:?}", e);
-            ExitProcess(3424);
-            panic!();
-        })
-        .unwrap_or_else(|| {
-            winlog!("[WSPGetPeerName] ERROR: WSPGetPeerName null");
-            ExitProcess(3424);
-            panic!();
-        })(sock, name, namelen, lpErrno)
+    // Copied out so the lock is not held during the call, which may block
+    let original = *WSPGETPEERNAME.lock().unwrap_or_else(|e| {
+        winlog!("[WSPGetPeerName] ERROR: {:?}", e);
+        ExitProcess(3424);
+        panic!();
+    });
+    original.unwrap_or_else(|| {
+        winlog!("[WSPGetPeerName] ERROR: WSPGetPeerName null");
+        ExitProcess(3424);
+        panic!();
+    })(sock, name, namelen, lpErrno)
 }
 
 /// Wrapped static function
@@ -95,18 +270,17 @@ unsafe fn WSPConnect(
     lpGQOS: LPQOS,
     lpErrno: LPINT,
 ) -> c_int {
-    WSPCONNECT
-        .lock()
-        .unwrap_or_else(|e| {
-            winlog!("[WSPConnect] ERROR: {:?}", e);
-            ExitProcess(3424);
-            panic!();
-        })
-        .unwrap_or_else(|| {
-            winlog!("[WSPConnect] ERROR: WSPConnect null");
-            ExitProcess(3424);
-            panic!();
-        })(
+    // Copied out so the lock is not held during the call, which may block
+    let original = *WSPCONNECT.lock().unwrap_or_else(|e| {
+        winlog!("[WSPConnect] ERROR: {:?}", e);
+        ExitProcess(3424);
+        panic!();
+    });
+    original.unwrap_or_else(|| {
+        winlog!("[WSPConnect] ERROR: WSPConnect null");
+        ExitProcess(3424);
+        panic!();
+    })(
         sock,
         name,
         namelen,
@@ -118,6 +292,190 @@ unsafe fn WSPConnect(
     )
 }
 
+/// Wrapped static function
+#[allow(non_snake_case)]
+unsafe fn WSPSend(
+    sock: SOCKET,
+    lpBuffers: LPWSABUF,
+    dwBufferCount: DWORD,
+    lpNumberOfBytesSent: LPDWORD,
+    dwFlags: DWORD,
+    lpOverlapped: LPWSAOVERLAPPED,
+    lpCompletionRoutine: LPWSAOVERLAPPED_COMPLETION_ROUTINE,
+    lpThreadId: LPWSATHREADID,
+    lpErrno: LPINT,
+) -> c_int {
+    // Copied out so the lock is not held during the call, which may block
+    let original = *WSPSEND.lock().unwrap_or_else(|e| {
+        winlog!("[WSPSend] ERROR: {:?}", e);
+        ExitProcess(3424);
+        panic!();
+    });
+    original.unwrap_or_else(|| {
+        winlog!("[WSPSend] ERROR: WSPSend null");
+        ExitProcess(3424);
+        panic!();
+    })(
+        sock,
+        lpBuffers,
+        dwBufferCount,
+        lpNumberOfBytesSent,
+        dwFlags,
+        lpOverlapped,
+        lpCompletionRoutine,
+        lpThreadId,
+        lpErrno,
+    )
+}
+
+/// Wrapped static function
+#[allow(non_snake_case)]
+unsafe fn WSPRecv(
+    sock: SOCKET,
+    lpBuffers: LPWSABUF,
+    dwBufferCount: DWORD,
+    lpNumberOfBytesRecvd: LPDWORD,
+    lpFlags: LPDWORD,
+    lpOverlapped: LPWSAOVERLAPPED,
+    lpCompletionRoutine: LPWSAOVERLAPPED_COMPLETION_ROUTINE,
+    lpThreadId: LPWSATHREADID,
+    lpErrno: LPINT,
+) -> c_int {
+    // Copied out so the lock is not held during the call, which may block
+    let original = *WSPRECV.lock().unwrap_or_else(|e| {
+        winlog!("[WSPRecv] ERROR: {:?}", e);
+        ExitProcess(3424);
+        panic!();
+    });
+    original.unwrap_or_else(|| {
+        winlog!("[WSPRecv] ERROR: WSPRecv null");
+        ExitProcess(3424);
+        panic!();
+    })(
+        sock,
+        lpBuffers,
+        dwBufferCount,
+        lpNumberOfBytesRecvd,
+        lpFlags,
+        lpOverlapped,
+        lpCompletionRoutine,
+        lpThreadId,
+        lpErrno,
+    )
+}
+
+/// Writes a packet to the packet log
+unsafe fn log_packet(
+    direction: Direction,
+    sock: SOCKET,
+    buffers: LPWSABUF,
+    count: DWORD,
+    len: usize,
+) {
+    let data = gather(buffers, count, len);
+    if let Some(log) = PACKETS
+        .lock()
+        .unwrap_or_else(|e| {
+            winlog!("[log_packet] ERROR: {:?}", e);
+            ExitProcess(3424);
+            panic!();
+        })
+        .as_mut()
+    {
+        if let Err(e) = log.write(direction, sock, &data) {
+            winlog!("[log_packet] ERROR: {:?}", e);
+        }
+    }
+}
+
+/// WSPSend Detour
+#[allow(non_snake_case)]
+unsafe extern "system" fn WSPSend_detour(
+    sock: SOCKET,
+    lpBuffers: LPWSABUF,
+    dwBufferCount: DWORD,
+    lpNumberOfBytesSent: LPDWORD,
+    dwFlags: DWORD,
+    lpOverlapped: LPWSAOVERLAPPED,
+    lpCompletionRoutine: LPWSAOVERLAPPED_COMPLETION_ROUTINE,
+    lpThreadId: LPWSATHREADID,
+    lpErrno: LPINT,
+) -> c_int {
+    let ret = WSPSend(
+        sock,
+        lpBuffers,
+        dwBufferCount,
+        lpNumberOfBytesSent,
+        dwFlags,
+        lpOverlapped,
+        lpCompletionRoutine,
+        lpThreadId,
+        lpErrno,
+    );
+
+    // Overlapped sends may complete later but every buffer is queued, so they are logged whole
+    // once they are accepted
+    if !lpOverlapped.is_null() {
+        let pending = ret == SOCKET_ERROR && *lpErrno == WSA_IO_PENDING as c_int;
+        if ret == 0 || pending {
+            let len = (0..dwBufferCount as usize)
+                .map(|i| (*lpBuffers.add(i)).len as usize)
+                .sum();
+            log_packet(Direction::Send, sock, lpBuffers, dwBufferCount, len);
+        }
+    } else if ret == 0 && !lpNumberOfBytesSent.is_null() {
+        log_packet(
+            Direction::Send,
+            sock,
+            lpBuffers,
+            dwBufferCount,
+            *lpNumberOfBytesSent as usize,
+        );
+    }
+
+    ret
+}
+
+/// WSPRecv Detour
+#[allow(non_snake_case)]
+unsafe extern "system" fn WSPRecv_detour(
+    sock: SOCKET,
+    lpBuffers: LPWSABUF,
+    dwBufferCount: DWORD,
+    lpNumberOfBytesRecvd: LPDWORD,
+    lpFlags: LPDWORD,
+    lpOverlapped: LPWSAOVERLAPPED,
+    lpCompletionRoutine: LPWSAOVERLAPPED_COMPLETION_ROUTINE,
+    lpThreadId: LPWSATHREADID,
+    lpErrno: LPINT,
+) -> c_int {
+    let ret = WSPRecv(
+        sock,
+        lpBuffers,
+        dwBufferCount,
+        lpNumberOfBytesRecvd,
+        lpFlags,
+        lpOverlapped,
+        lpCompletionRoutine,
+        lpThreadId,
+        lpErrno,
+    );
+
+    // Overlapped receives only hold their data once they complete, which the hook does not see,
+    // so they are not logged
+    if ret == 0 && lpOverlapped.is_null() && !lpNumberOfBytesRecvd.is_null() {
+        log_packet(
+            Direction::Recv,
+            sock,
+            lpBuffers,
+            dwBufferCount,
+            *lpNumberOfBytesRecvd as usize,
+        );
+    }
+
+    ret
+}
+
 /// WSPGetPeerName Detour
 #[allow(non_snake_case)]
 unsafe extern "system" fn WSPGetPeerName_detour(
@@ -128,37 +486,31 @@ unsafe extern "system" fn WSPGetPeerName_detour(
 ) -> c_int {
     let ret = WSPGetPeerName(sock, name, namelen, lpErrno);
 
-    let from_addr: LPSOCKADDR_IN = ::std::mem::transmute(name);
-
-    let port = ntohs((*from_addr).sin_port);
-
-    // Only if this is the login portal
-    if port >= 8000 && port < 9000 {
-        let mut to_addr: SOCKADDR_IN = ::std::mem::zeroed();
-        *to_addr.sin_addr.S_un.S_addr_mut() = *LAST_CONNECT.lock().unwrap_or_else(|e| {
+    // Only if this socket was redirected
+    let original = CONNECTIONS
+        .lock()
+        .unwrap_or_else(|e| {
             winlog!("[WSPGetPeerName] ERROR: {:?}", e);
             ExitProcess(3424);
             panic!();
-        });
+        })
+        .get(&sock)
+        .copied();
+    if let (0, Some((to_ip, to_port))) = (ret, original) {
+        let from_addr: LPSOCKADDR_IN = ::std::mem::transmute(name);
+        let (from_ip, from_port) = get_addr(from_addr);
 
         // Debug
-        let from_ip: String = CStr::from_ptr(inet_ntoa((*from_addr).sin_addr))
-            .to_string_lossy()
-            .into();
-        let port = ntohs((*from_addr).sin_port);
-        let to_ip: String = CStr::from_ptr(inet_ntoa(to_addr.sin_addr))
-            .to_string_lossy()
-            .into();
         winlog!(
             "[WSPGetPeerName] Replaced: {}:{} -> {}:{}",
             from_ip,
-            port,
+            from_port,
             to_ip,
-            port,
+            to_port,
         );
 
         // Overwrite response
-        (*from_addr).sin_addr = to_addr.sin_addr;
+        set_addr(from_addr, to_ip, to_port);
     }
 
     ret
@@ -177,37 +529,44 @@ unsafe extern "system" fn WSPConnect_detour(
     lpErrno: LPINT,
 ) -> c_int {
     let from_addr: LPSOCKADDR_IN = ::std::mem::transmute(name);
+    let (from_ip, from_port) = get_addr(from_addr);
 
-    let port = ntohs((*from_addr).sin_port);
-
-    // Only if this is the login portal
-    if port >= 8000 && port < 9000 {
-        // Debug
-        let from_ip: String = CStr::from_ptr(inet_ntoa((*from_addr).sin_addr))
-            .to_string_lossy()
-            .into();
-        winlog!(
-            "[WSPConnect] Replaced: {}:{} -> {}:{}",
-            from_ip,
-            port,
-            IP,
-            port,
-        );
-
-        // Save original routing information
-        *LAST_CONNECT.lock().unwrap_or_else(|e| {
+    let redirect = REDIRECTS
+        .lock()
+        .unwrap_or_else(|e| {
             winlog!("[WSPConnect] ERROR: {:?}", e);
             ExitProcess(3424);
             panic!();
-        }) = *(*from_addr).sin_addr.S_un.S_addr();
+        })
+        .resolve(from_ip, from_port);
+    let mut connections = CONNECTIONS.lock().unwrap_or_else(|e| {
+        winlog!("[WSPConnect] ERROR: {:?}", e);
+        ExitProcess(3424);
+        panic!();
+    });
+    match redirect {
+        Some((to_ip, to_port)) => {
+            // Debug
+            winlog!(
+                "[WSPConnect] Replaced: {}:{} -> {}:{}",
+                from_ip,
+                from_port,
+                to_ip,
+                to_port,
+            );
 
-        // Overwrite destination
-        *(*from_addr).sin_addr.S_un.S_addr_mut() = *REROUTED_ADDR.lock().unwrap_or_else(|e| {
-            winlog!("[WSPConnect] ERROR: {:?}", e);
-            ExitProcess(3424);
-            panic!();
-        });
+            // Save original routing information
+            connections.insert(sock, (from_ip, from_port));
+
+            // Overwrite destination
+            set_addr(from_addr, to_ip, to_port);
+        }
+        None => {
+            // Socket handles are reused
+            connections.remove(&sock);
+        }
     }
+    drop(connections);
 
     WSPConnect(
         sock,
@@ -255,16 +614,43 @@ fn WSPStartup_detour(
             panic!();
         }) = unsafe { (*lpProcTable).lpWSPConnect };
         unsafe { (*lpProcTable).lpWSPConnect = Some(WSPConnect_detour) };
+
+        // Hook WSPSend and WSPRecv only when logging packets
+        let logging = PACKETS
+            .lock()
+            .unwrap_or_else(|e| {
+                winlog!("[WSPStartup] ERROR: {:?}", e);
+                unsafe { ExitProcess(3424) };
+                panic!();
+            })
+            .is_some();
+        if logging {
+            *WSPSEND.lock().unwrap_or_else(|e| {
+                winlog!("[WSPStartup] ERROR: {:?}", e);
+                unsafe { ExitProcess(3424) };
+                panic!();
+            }) = unsafe { (*lpProcTable).lpWSPSend };
+            unsafe { (*lpProcTable).lpWSPSend = Some(WSPSend_detour) };
+
+            *WSPRECV.lock().unwrap_or_else(|e| {
+                winlog!("[WSPStartup] ERROR: {:?}", e);
+                unsafe { ExitProcess(3424) };
+                panic!();
+            }) = unsafe { (*lpProcTable).lpWSPRecv };
+            unsafe { (*lpProcTable).lpWSPRecv = Some(WSPRecv_detour) };
+        }
     }
     ret
 }
 
 /// Sets up mswsock.dll hooks
-pub(crate) unsafe fn main() -> Result<(), Error> {
-    let ip = CString::new(IP).map_err(|_| Error::CStringFailed(IP.into()))?;
-    *REROUTED_ADDR
+pub(crate) unsafe fn main(config: &Config) -> Result<(), Error> {
+    *PACKETS
+        .lock()
+        .map_err(|e| Error::Unknown(format!("{:?}", e)))? = PacketLog::from_config(config)?;
+    *REDIRECTS
         .lock()
-        .map_err(|e| Error::Unknown(format!("{:?}", e)))? = inet_addr(ip.as_ptr());
+        .map_err(|e| Error::Unknown(format!("{:?}", e)))? = RedirectTable::from_config(config)?;
     let address = utils::load_module_symbol("mswsock.dll", "WSPStartup")?;
     let target: WSPStartupFn = ::std::mem::transmute(address);
     WSPStartupHook
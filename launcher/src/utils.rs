@@ -3,10 +3,12 @@
 use crate::error::Error;
 use std::ffi::CString;
 use std::fmt;
-use winapi::shared::minwindef::HINSTANCE;
+use winapi::shared::minwindef::{DWORD, FALSE, HINSTANCE, LPCVOID, LPVOID};
 use winapi::um::debugapi::OutputDebugStringA;
 use winapi::um::libloaderapi::{GetModuleHandleA, GetProcAddress, LoadLibraryA};
-use winapi::um::processthreadsapi::ExitProcess;
+use winapi::um::memoryapi::{ReadProcessMemory, VirtualProtect};
+use winapi::um::processthreadsapi::{ExitProcess, FlushInstructionCache, GetCurrentProcess};
+use winapi::um::winnt::PAGE_EXECUTE_READWRITE;
 
 pub unsafe fn windows_log(args: fmt::Arguments) {
     let formatted = format!("[MapleDev] {}", args);
@@ -69,3 +71,39 @@ pub unsafe fn load_module_symbol(module: &str, symbol: &str) -> Result<usize, Er
     }
     Ok(address as usize)
 }
+
+/// Reads `len` bytes of the current process' memory
+pub unsafe fn read_memory(address: usize, len: usize) -> Result<Vec<u8>, Error> {
+    let mut buf = vec![0u8; len];
+    let mut read = 0;
+    if ReadProcessMemory(
+        GetCurrentProcess(),
+        address as LPCVOID,
+        buf.as_mut_ptr() as LPVOID,
+        len,
+        &mut read,
+    ) == FALSE
+        || read != len
+    {
+        return Err(Error::MemoryRead(address));
+    }
+    Ok(buf)
+}
+
+/// Overwrites the current process' memory, restoring the page protection afterwards
+pub unsafe fn patch(address: usize, bytes: &[u8]) -> Result<(), Error> {
+    let mut old: DWORD = 0;
+    if VirtualProtect(
+        address as LPVOID,
+        bytes.len(),
+        PAGE_EXECUTE_READWRITE,
+        &mut old,
+    ) == FALSE
+    {
+        return Err(Error::MemoryProtect(address));
+    }
+    ::std::ptr::copy_nonoverlapping(bytes.as_ptr(), address as *mut u8, bytes.len());
+    VirtualProtect(address as LPVOID, bytes.len(), old, &mut old);
+    FlushInstructionCache(GetCurrentProcess(), address as LPCVOID, bytes.len());
+    Ok(())
+}
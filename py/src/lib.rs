@@ -0,0 +1,263 @@
+//! Python bindings for the WZ library.
+//!
+//! Build with `maturin develop` from this directory.
+//!
+//! ```python
+//! import mushroom
+//!
+//! archive = mushroom.Archive("Mob.wz", key="gms")
+//! canvas = archive.get("Mob/0100100.img/move/0")
+//! pixels = canvas.to_numpy()  # height x width x 4
+//! canvas.to_pil().save("move0.png")
+//! ```
+
+use crypto::{KeyStream, GMS_IV, KMS_IV, TRIMMED_KEY};
+use numpy::{ndarray::Array3, IntoPyArray, PyArray3};
+use pyo3::{create_exception, exceptions::PyException, prelude::*, types::PyBytes};
+use std::sync::Arc;
+use wz::{
+    io::DummyDecryptor,
+    map::Map,
+    types::Property,
+    vfs::{Node, Vfs},
+};
+
+create_exception!(mushroom, WzError, PyException);
+
+fn wz_err(e: wz::error::Error) -> PyErr {
+    WzError::new_err(e.to_string())
+}
+
+enum Handle {
+    Encrypted(Vfs<KeyStream>),
+    Plain(Vfs<DummyDecryptor>),
+}
+
+macro_rules! with_vfs {
+    ($handle:expr, $vfs:ident => $body:expr) => {
+        match $handle {
+            Handle::Encrypted($vfs) => $body,
+            Handle::Plain($vfs) => $body,
+        }
+    };
+}
+
+/// WZ archive. Paths start with the archive name without its extension, e.g.
+/// `String/Mob.img/100100/name`.
+#[pyclass(unsendable)]
+struct Archive {
+    handle: Handle,
+}
+
+#[pymethods]
+impl Archive {
+    #[new]
+    #[pyo3(signature = (path, key = "none"))]
+    fn new(path: &str, key: &str) -> PyResult<Self> {
+        let handle = match key {
+            "gms" => Handle::Encrypted(
+                Vfs::from_archives([path], KeyStream::new(&TRIMMED_KEY, &GMS_IV))
+                    .map_err(wz_err)?,
+            ),
+            "kms" => Handle::Encrypted(
+                Vfs::from_archives([path], KeyStream::new(&TRIMMED_KEY, &KMS_IV))
+                    .map_err(wz_err)?,
+            ),
+            "none" => Handle::Plain(Vfs::from_archives([path], DummyDecryptor).map_err(wz_err)?),
+            k => return Err(WzError::new_err(format!("unknown key {}", k))),
+        };
+        Ok(Self { handle })
+    }
+
+    /// Returns the names of the children at `path`
+    fn list(&mut self, path: &str) -> PyResult<Vec<String>> {
+        match with_vfs!(&mut self.handle, vfs => vfs.read(path)).map_err(wz_err)? {
+            Node::Directory(names) => Ok(names),
+            Node::Image(image) => Image { map: image }.list(""),
+            Node::Property(_) => {
+                let (image, inner) = self.split(path)?;
+                image.list(inner)
+            }
+        }
+    }
+
+    /// Returns the image at `path`
+    fn image(&mut self, path: &str) -> PyResult<Image> {
+        let map = with_vfs!(&mut self.handle, vfs => vfs.open_image(path)).map_err(wz_err)?;
+        Ok(Image { map })
+    }
+
+    /// Returns the value at `path`. Packages and property directories are returned as lists of
+    /// child names.
+    fn get(&mut self, py: Python<'_>, path: &str) -> PyResult<PyObject> {
+        match with_vfs!(&mut self.handle, vfs => vfs.read(path)).map_err(wz_err)? {
+            Node::Directory(names) => Ok(names.into_py(py)),
+            Node::Image(map) => Ok(Image { map }.into_py(py)),
+            Node::Property(Property::ImgDir | Property::Convex) => Ok(self.list(path)?.into_py(py)),
+            Node::Property(property) => property_to_py(py, property),
+        }
+    }
+}
+
+impl Archive {
+    /// Splits a property path into its image and the path within the image
+    fn split<'a>(&mut self, path: &'a str) -> PyResult<(Image, &'a str)> {
+        let end = path
+            .find(".img")
+            .map(|i| i + 4)
+            .ok_or_else(|| WzError::new_err(format!("not an image path: {}", path)))?;
+        let image = self.image(&path[..end])?;
+        Ok((image, path[end..].trim_start_matches('/')))
+    }
+}
+
+/// Parsed WZ image. Paths are relative to the image root.
+#[pyclass]
+struct Image {
+    map: Arc<Map<Property>>,
+}
+
+#[pymethods]
+impl Image {
+    /// Name of the image
+    #[getter]
+    fn name(&self) -> &str {
+        self.map.name()
+    }
+
+    /// Returns the names of the children at `path`
+    #[pyo3(signature = (path = ""))]
+    fn list(&self, path: &str) -> PyResult<Vec<String>> {
+        let cursor = self
+            .map
+            .cursor_at(self.full_path(path))
+            .map_err(|e| wz_err(e.into()))?;
+        let names = cursor.list().map(String::from).collect();
+        Ok(names)
+    }
+
+    /// Returns the value at `path`. Property directories are returned as lists of child names.
+    fn get(&self, py: Python<'_>, path: &str) -> PyResult<PyObject> {
+        let property = self
+            .map
+            .get(self.full_path(path))
+            .map_err(|e| wz_err(e.into()))?;
+        match property {
+            Property::ImgDir | Property::Convex => Ok(self.list(path)?.into_py(py)),
+            property => property_to_py(py, property.clone()),
+        }
+    }
+}
+
+impl Image {
+    fn full_path(&self, path: &str) -> String {
+        match path.trim_matches('/') {
+            "" => String::from(self.map.name()),
+            path => format!("{}/{}", self.map.name(), path),
+        }
+    }
+}
+
+/// Image stored in a WZ image
+#[pyclass]
+struct Canvas {
+    canvas: wz::types::Canvas,
+}
+
+#[pymethods]
+impl Canvas {
+    #[getter]
+    fn width(&self) -> i32 {
+        *self.canvas.width()
+    }
+
+    #[getter]
+    fn height(&self) -> i32 {
+        *self.canvas.height()
+    }
+
+    /// Name of the stored pixel format
+    #[getter]
+    fn format(&self) -> String {
+        format!("{:?}", self.canvas.format())
+    }
+
+    /// Returns the pixels as RGBA bytes
+    fn rgba<'py>(&self, py: Python<'py>) -> PyResult<&'py PyBytes> {
+        let image = self.canvas.image_buffer().map_err(wz_err)?;
+        Ok(PyBytes::new(py, image.as_raw()))
+    }
+
+    /// Returns the pixels as a `height x width x 4` numpy array
+    fn to_numpy<'py>(&self, py: Python<'py>) -> PyResult<&'py PyArray3<u8>> {
+        let image = self.canvas.image_buffer().map_err(wz_err)?;
+        let shape = (image.height() as usize, image.width() as usize, 4);
+        let array = Array3::from_shape_vec(shape, image.into_raw())
+            .map_err(|e| WzError::new_err(e.to_string()))?;
+        Ok(array.into_pyarray(py))
+    }
+
+    /// Returns the pixels as a PIL image. Requires Pillow.
+    fn to_pil(&self, py: Python<'_>) -> PyResult<PyObject> {
+        let image = self.canvas.image_buffer().map_err(wz_err)?;
+        let pil = py.import("PIL.Image")?;
+        let size = (image.width(), image.height());
+        let bytes = PyBytes::new(py, image.as_raw());
+        Ok(pil
+            .call_method1("frombytes", ("RGBA", size, bytes))?
+            .into_py(py))
+    }
+}
+
+/// WAV sound stored in a WZ image
+#[pyclass]
+struct Sound {
+    sound: wz::types::Sound,
+}
+
+#[pymethods]
+impl Sound {
+    /// Duration in milliseconds
+    #[getter]
+    fn duration(&self) -> i32 {
+        *self.sound.duration()
+    }
+
+    /// Raw sound data
+    #[getter]
+    fn data<'py>(&self, py: Python<'py>) -> &'py PyBytes {
+        PyBytes::new(py, self.sound.data())
+    }
+
+    /// Saves the sound as a WAV file
+    fn save(&self, path: &str) -> PyResult<()> {
+        self.sound.save_to_file(path).map_err(wz_err)
+    }
+}
+
+fn property_to_py(py: Python<'_>, property: Property) -> PyResult<PyObject> {
+    Ok(match property {
+        Property::Null | Property::ImgDir | Property::Convex | Property::Unknown(_) => py.None(),
+        Property::Error(e) => return Err(WzError::new_err(e)),
+        Property::Short(v) => v.into_py(py),
+        Property::Int(v) => (*v).into_py(py),
+        Property::Long(v) => (*v).into_py(py),
+        Property::Float(v) => v.into_py(py),
+        Property::Double(v) => v.into_py(py),
+        Property::String(v) => v.as_ref().into_py(py),
+        Property::Vector(v) => (*v.x, *v.y).into_py(py),
+        Property::Uol(v) => v.as_ref().into_py(py),
+        Property::Canvas(canvas) => Canvas { canvas }.into_py(py),
+        Property::Sound(sound) => Sound { sound }.into_py(py),
+    })
+}
+
+#[pymodule]
+fn mushroom(py: Python<'_>, m: &PyModule) -> PyResult<()> {
+    m.add_class::<Archive>()?;
+    m.add_class::<Image>()?;
+    m.add_class::<Canvas>()?;
+    m.add_class::<Sound>()?;
+    m.add("WzError", py.get_type::<WzError>())?;
+    Ok(())
+}
@@ -0,0 +1,58 @@
+//! Compares the size and speed of the canvas compression levels.
+//!
+//! Run with `cargo bench -p wz --bench compression`.
+
+use crypto::{KeyStream, GMS_IV, TRIMMED_KEY};
+use std::time::Instant;
+use wz::{
+    error::{Error, Result},
+    image::Reader,
+    types::{Compressor, Property, Zlib},
+};
+
+const IMAGES: &[(&str, &str)] = &[
+    ("testdata/v83-weapon.img", "weapon.img"),
+    ("testdata/v83-tamingmob.img", "tamingmob.img"),
+];
+
+fn main() -> Result<()> {
+    let mut canvases = Vec::new();
+    let mut stored = 0;
+    for (path, name) in IMAGES {
+        let mut reader = Reader::open(path, KeyStream::new(&TRIMMED_KEY, &GMS_IV))?;
+        reader.map(name)?.walk::<Error>(|cursor| {
+            if let Property::Canvas(canvas) = cursor.get() {
+                stored += canvas.data().len();
+                canvases.push(canvas.decompressed_data()?);
+            }
+            Ok(())
+        })?;
+    }
+    let raw: usize = canvases.iter().map(Vec::len).sum();
+    println!(
+        "{} canvases, {} bytes raw, {} bytes stored",
+        canvases.len(),
+        raw,
+        stored
+    );
+    println!(
+        "{:>5} {:>10} {:>7} {:>10}",
+        "level", "bytes", "ratio", "time"
+    );
+    for level in 0..=9 {
+        let zlib = Zlib::new(level);
+        let start = Instant::now();
+        let mut size = 0;
+        for data in &canvases {
+            size += zlib.compress(data)?.len();
+        }
+        println!(
+            "{:>5} {:>10} {:>7.3} {:>10.2?}",
+            level,
+            size,
+            size as f64 / raw as f64,
+            start.elapsed()
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,66 @@
+//! Compares decoding an unencrypted image with `DummyDecryptor` against a decryptor that is a
+//! plain no-op, and against decoding the encrypted original with a `KeyStream`. The first two
+//! should take the same time.
+//!
+//! Run with `cargo bench -p wz --bench decryption`.
+
+use crypto::{Decryptor, KeyStream, GMS_IV, TRIMMED_KEY};
+use std::{
+    io::Cursor,
+    time::{Duration, Instant},
+};
+use wz::{
+    error::Result,
+    image::{Reader, Writer},
+    io::{DummyDecryptor, WzImageWriter, WzReader, WzWriter},
+};
+
+const ROUNDS: usize = 500;
+const BATCHES: usize = 5;
+
+/// Decryptor the compiler can see through without any help
+struct NoOp;
+
+impl Decryptor for NoOp {
+    fn decrypt(&mut self, _: &mut Vec<u8>) {}
+}
+
+/// Returns the fastest of [`BATCHES`] runs lazily mapping the image in `data` [`ROUNDS`] times
+fn time<D, F>(data: &[u8], decryptor: F) -> Result<Duration>
+where
+    D: Decryptor,
+    F: Fn() -> D,
+{
+    let mut fastest = Duration::MAX;
+    for _ in 0..BATCHES {
+        let start = Instant::now();
+        for _ in 0..ROUNDS {
+            let inner = WzReader::new(0, 0, Cursor::new(data), decryptor());
+            Reader::new(inner).map_lazy("tamingmob.img")?;
+        }
+        fastest = fastest.min(start.elapsed());
+    }
+    Ok(fastest)
+}
+
+fn main() -> Result<()> {
+    let key = || KeyStream::new(&TRIMMED_KEY, &GMS_IV);
+    let encrypted = std::fs::read("testdata/v83-tamingmob.img")?;
+    let map =
+        Reader::new(WzReader::new(0, 0, Cursor::new(&encrypted), key())).map("tamingmob.img")?;
+
+    // Write the same image without encryption
+    let mut inner = WzWriter::unencrypted(0, 0, Cursor::new(Vec::new()));
+    Writer::from_map(map).write_to(&mut WzImageWriter::new(&mut inner))?;
+    let unencrypted = inner.into_inner().into_inner();
+
+    println!("{:>10} {:>10}", "decryptor", "time");
+    for (name, elapsed) in [
+        ("noop", time(&unencrypted, || NoOp)?),
+        ("dummy", time(&unencrypted, || DummyDecryptor)?),
+        ("keystream", time(&encrypted, key)?),
+    ] {
+        println!("{:>10} {:>10.2?}", name, elapsed);
+    }
+    Ok(())
+}
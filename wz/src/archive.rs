@@ -1,7 +1,20 @@
 //! WZ Archive
 
+pub mod index;
+pub mod manifest;
+#[cfg(feature = "canvas-codecs")]
+pub mod optimize;
+pub mod patch;
 pub mod reader;
+pub mod search;
+pub mod signature;
+pub mod stats;
 pub mod writer;
 
+pub use index::Index;
+pub use manifest::{manifest, Manifest};
+pub use patch::Patch;
 pub use reader::Reader;
+pub use search::find_image_containing;
+pub use stats::Stats;
 pub use writer::Writer;
@@ -0,0 +1,309 @@
+//! WZ Archive index sidecar
+
+use crate::archive::reader::{Node, MAX_DEPTH};
+use crate::error::{PackageError, Result};
+use crate::map::{Cursor, CursorMut, Map};
+use crate::types::{WzHeader, WzInt, WzOffset};
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+    time::UNIX_EPOCH,
+};
+
+const MAGIC: &[u8; 4] = b"WZIX";
+const FORMAT_VERSION: u16 = 1;
+const PACKAGE: u8 = 0;
+const IMAGE: u8 = 1;
+
+/// Identifies the archive an index was built from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Stamp {
+    size: u64,
+    modified: u64,
+    version_hash: u16,
+}
+
+impl Stamp {
+    fn of<S>(archive_path: S) -> Result<Self>
+    where
+        S: AsRef<Path>,
+    {
+        let metadata = fs::metadata(&archive_path)?;
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        let header = WzHeader::from_reader(&mut BufReader::new(File::open(&archive_path)?))?;
+        Ok(Self {
+            size: metadata.len(),
+            modified,
+            version_hash: header.version_hash.map_or(u16::MAX, u16::from),
+        })
+    }
+}
+
+/// Content map of a WZ archive saved next to it, typically as `<archive>.idx`.
+///
+/// Mapping large archives such as `Map.wz` means decoding every package. An index holds the
+/// decoded map along with the version checksum so later opens skip both the package decoding and
+/// the version brute force. An index is stale once the archive size, modification time or
+/// version hash changes. See [`Reader::open_indexed`](crate::archive::Reader::open_indexed).
+#[derive(Debug)]
+pub struct Index {
+    stamp: Stamp,
+    version_checksum: u32,
+    map: Map<Node>,
+}
+
+impl Index {
+    /// Creates an index of the archive at `archive_path` from its map
+    pub fn new<S>(archive_path: S, version_checksum: u32, map: Map<Node>) -> Result<Self>
+    where
+        S: AsRef<Path>,
+    {
+        Ok(Self {
+            stamp: Stamp::of(archive_path)?,
+            version_checksum,
+            map,
+        })
+    }
+
+    /// Loads the index at `index_path`. Returns `None` when the index is missing, unreadable or
+    /// stale for the archive at `archive_path`.
+    pub fn load<S, T>(index_path: S, archive_path: T) -> Result<Option<Self>>
+    where
+        S: AsRef<Path>,
+        T: AsRef<Path>,
+    {
+        let stamp = Stamp::of(archive_path)?;
+        let file = match File::open(index_path) {
+            Ok(file) => file,
+            Err(_) => return Ok(None),
+        };
+        match decode(&mut BufReader::new(file)) {
+            Ok(index) if index.stamp == stamp => Ok(Some(index)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Saves the index to `index_path`
+    pub fn save<S>(&self, index_path: S) -> Result<()>
+    where
+        S: AsRef<Path>,
+    {
+        let mut writer = BufWriter::new(File::create(index_path)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        writer.write_all(&self.stamp.size.to_le_bytes())?;
+        writer.write_all(&self.stamp.modified.to_le_bytes())?;
+        writer.write_all(&self.stamp.version_hash.to_le_bytes())?;
+        writer.write_all(&self.version_checksum.to_le_bytes())?;
+        write_string(&mut writer, self.map.name())?;
+        write_children(&mut writer, &mut self.map.cursor())?;
+        Ok(writer.flush()?)
+    }
+
+    /// Returns the version checksum of the archive
+    pub fn version_checksum(&self) -> u32 {
+        self.version_checksum
+    }
+
+    /// Returns the archive map
+    pub fn map(&self) -> &Map<Node> {
+        &self.map
+    }
+
+    /// Consumes the index and returns the archive map
+    pub fn into_map(self) -> Map<Node> {
+        self.map
+    }
+}
+
+fn decode<R>(reader: &mut R) -> Result<Index>
+where
+    R: Read,
+{
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC || read_u16(reader)? != FORMAT_VERSION {
+        return Err(PackageError::Header.into());
+    }
+    let stamp = Stamp {
+        size: read_u64(reader)?,
+        modified: read_u64(reader)?,
+        version_hash: read_u16(reader)?,
+    };
+    let version_checksum = read_u32(reader)?;
+    let mut map = Map::new(read_string(reader)?, Node::Package);
+    read_children(reader, &mut map.cursor_mut())?;
+    Ok(Index {
+        stamp,
+        version_checksum,
+        map,
+    })
+}
+
+fn write_children<W>(writer: &mut W, cursor: &mut Cursor<Node>) -> Result<()>
+where
+    W: Write,
+{
+    let count = cursor.list().count();
+    writer.write_all(&(count as u32).to_le_bytes())?;
+    for i in 0..count {
+        match i {
+            0 => cursor.first_child()?,
+            _ => cursor.next_sibling()?,
+        };
+        write_string(writer, cursor.name())?;
+        match cursor.get() {
+            Node::Package => writer.write_all(&[PACKAGE])?,
+            Node::Image {
+                offset,
+                size,
+                checksum,
+            } => {
+                writer.write_all(&[IMAGE])?;
+                writer.write_all(&offset.to_le_bytes())?;
+                writer.write_all(&size.to_le_bytes())?;
+                writer.write_all(&checksum.to_le_bytes())?;
+            }
+        }
+        write_children(writer, cursor)?;
+    }
+    if count > 0 {
+        cursor.parent()?;
+    }
+    Ok(())
+}
+
+fn read_children<R>(reader: &mut R, cursor: &mut CursorMut<Node>) -> Result<()>
+where
+    R: Read,
+{
+    // Children left to read at each level below the cursor's node, innermost last
+    let mut remaining = vec![read_u32(reader)?];
+    while let Some(count) = remaining.last_mut() {
+        if *count == 0 {
+            remaining.pop();
+            if !remaining.is_empty() {
+                cursor.parent()?;
+            }
+            continue;
+        }
+        *count -= 1;
+        let name = read_string(reader)?;
+        let mut kind = [0u8; 1];
+        reader.read_exact(&mut kind)?;
+        let node = match kind[0] {
+            PACKAGE => Node::Package,
+            IMAGE => Node::Image {
+                offset: WzOffset::from(read_u32(reader)?),
+                size: WzInt::from(read_u32(reader)? as i32),
+                checksum: WzInt::from(read_u32(reader)? as i32),
+            },
+            t => return Err(PackageError::ContentType(t).into()),
+        };
+        cursor.create(name, node)?.last_child()?;
+        if remaining.len() > MAX_DEPTH {
+            return Err(PackageError::Depth.into());
+        }
+        remaining.push(read_u32(reader)?);
+    }
+    Ok(())
+}
+
+pub(crate) fn write_string<W>(writer: &mut W, s: &str) -> Result<()>
+where
+    W: Write,
+{
+    writer.write_all(&(s.len() as u32).to_le_bytes())?;
+    Ok(writer.write_all(s.as_bytes())?)
+}
+
+pub(crate) fn read_string<R>(reader: &mut R) -> Result<String>
+where
+    R: Read,
+{
+    let mut data = vec![0u8; read_u32(reader)? as usize];
+    reader.read_exact(&mut data)?;
+    String::from_utf8(data).map_err(|_| PackageError::Header.into())
+}
+
+fn read_u16<R: Read>(reader: &mut R) -> Result<u16> {
+    let mut buf = [0u8; 2];
+    reader.read_exact(&mut buf)?;
+    Ok(u16::from_le_bytes(buf))
+}
+
+pub(crate) fn read_u32<R: Read>(reader: &mut R) -> Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::archive::{reader::Node, Index, Reader};
+    use crate::error::Error;
+    use crypto::{KeyStream, GMS_IV, TRIMMED_KEY};
+    use std::{env, fs};
+
+    #[test]
+    fn v83_index() {
+        let dir = env::temp_dir().join("wz-index-test");
+        fs::create_dir_all(&dir).expect("error creating directory");
+        let archive_path = dir.join("Base.wz");
+        let index_path = dir.join("Base.wz.idx");
+        fs::copy("testdata/v83-base.wz", &archive_path).expect("error copying");
+        let _ = fs::remove_file(&index_path);
+
+        let key = KeyStream::new(&TRIMMED_KEY, &GMS_IV);
+        let (_, map) = Reader::open_indexed(&archive_path, &index_path, "Base.wz", key.clone())
+            .expect("error opening archive");
+        let index = Index::load(&index_path, &archive_path)
+            .expect("error loading index")
+            .expect("index should be fresh");
+        let (mut reader, indexed) =
+            Reader::open_indexed(&archive_path, &index_path, "Base.wz", key)
+                .expect("error opening archive");
+        let mut expected = Vec::new();
+        map.walk::<Error>(|cursor| {
+            expected.push((cursor.pwd(), *cursor.get()));
+            Ok(())
+        })
+        .expect("walk should not fail");
+        for map in [index.map(), &indexed] {
+            let mut found = Vec::new();
+            map.walk::<Error>(|cursor| {
+                found.push((cursor.pwd(), *cursor.get()));
+                Ok(())
+            })
+            .expect("walk should not fail");
+            assert_eq!(found, expected);
+        }
+        assert_eq!(indexed.name(), "Base.wz");
+        assert!(matches!(
+            reader
+                .map("Base.wz")
+                .expect("error mapping")
+                .get("Base.wz/smap.img"),
+            Ok(Node::Image { .. })
+        ));
+
+        // Touching the archive makes the index stale
+        let data = fs::read(&archive_path).expect("error reading archive");
+        fs::write(&archive_path, [&data[..], &[0u8]].concat()).expect("error writing archive");
+        assert!(Index::load(&index_path, &archive_path)
+            .expect("error loading index")
+            .is_none());
+        fs::remove_dir_all(&dir).expect("error removing directory");
+    }
+}
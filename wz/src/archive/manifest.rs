@@ -0,0 +1,214 @@
+//! WZ Archive content manifest
+
+use crate::archive::reader::Node;
+use crate::error::{Error, PackageError, Result};
+use crate::io::WzRead;
+use crate::map::Map;
+use sha2::{Digest, Sha256};
+use std::{
+    fmt::Write,
+    fs::File,
+    io::{self, BufReader},
+    path::Path,
+};
+
+/// Image listed in a [`Manifest`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// Path of the image, starting with the archive name
+    pub path: String,
+
+    /// Size of the image in bytes
+    pub size: u64,
+
+    /// SHA-256 of the image data
+    pub sha256: [u8; 32],
+}
+
+/// Way an extracted image differs from its [`Entry`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mismatch {
+    /// The file does not exist
+    Missing,
+
+    /// The file size differs
+    Size,
+
+    /// The file contents differ
+    Hash,
+}
+
+/// Size and SHA-256 of every image in an archive.
+///
+/// Patch servers compare the manifests of two archives to find the images that need to be
+/// downloaded. The text form has one `<sha256> <size> <path>` line per image.
+///
+/// Example:
+///
+/// ```no_run
+/// use crypto::{KeyStream, GMS_IV, TRIMMED_KEY};
+/// use std::fs;
+/// use wz::archive::{self, Reader};
+///
+/// let mut reader = Reader::open("Character.wz", KeyStream::new(&TRIMMED_KEY, &GMS_IV)).unwrap();
+/// let map = reader.map("Character").unwrap();
+/// let manifest = archive::manifest(&mut reader.into_inner(), &map).unwrap();
+/// fs::write("Character.manifest", manifest.to_text()).unwrap();
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Manifest {
+    entries: Vec<Entry>,
+}
+
+/// Hashes every image of the archive mapped in `map`
+pub fn manifest<R>(reader: &mut R, map: &Map<Node>) -> Result<Manifest>
+where
+    R: WzRead,
+{
+    let mut entries = Vec::new();
+    map.walk::<Error>(|cursor| {
+        if let Node::Image { offset, size, .. } = cursor.get() {
+            let mut hasher = Sha256::new();
+            reader.copy_to(&mut hasher, *offset, *size)?;
+            entries.push(Entry {
+                path: cursor.pwd(),
+                size: **size as u64,
+                sha256: hasher.finalize().into(),
+            });
+        }
+        Ok(())
+    })?;
+    Ok(Manifest { entries })
+}
+
+impl Manifest {
+    /// Parses a manifest written by [`Manifest::to_text`]
+    pub fn from_text(text: &str) -> Result<Self> {
+        let entries = text
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| parse_line(line).ok_or_else(|| PackageError::Manifest(line.into()).into()))
+            .collect::<Result<Vec<Entry>>>()?;
+        Ok(Self { entries })
+    }
+
+    pub(crate) fn from_entries(entries: Vec<Entry>) -> Self {
+        Self { entries }
+    }
+
+    /// Returns the entries
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Writes one `<sha256> <size> <path>` line per image
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for entry in &self.entries {
+            for b in entry.sha256 {
+                let _ = write!(text, "{:02x}", b);
+            }
+            let _ = writeln!(text, " {} {}", entry.size, entry.path);
+        }
+        text
+    }
+
+    /// Checks the images extracted under `dir` and returns the ones that differ
+    pub fn verify<S>(&self, dir: S) -> Result<Vec<(&Entry, Mismatch)>>
+    where
+        S: AsRef<Path>,
+    {
+        let mut mismatches = Vec::new();
+        for entry in &self.entries {
+            let path = dir.as_ref().join(&entry.path);
+            let file = match File::open(&path) {
+                Ok(file) => file,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    mismatches.push((entry, Mismatch::Missing));
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            if file.metadata()?.len() != entry.size {
+                mismatches.push((entry, Mismatch::Size));
+                continue;
+            }
+            let mut hasher = Sha256::new();
+            io::copy(&mut BufReader::new(file), &mut hasher)?;
+            if <[u8; 32]>::from(hasher.finalize()) != entry.sha256 {
+                mismatches.push((entry, Mismatch::Hash));
+            }
+        }
+        Ok(mismatches)
+    }
+}
+
+fn parse_line(line: &str) -> Option<Entry> {
+    let (hash, rest) = line.split_once(' ')?;
+    let (size, path) = rest.split_once(' ')?;
+    if hash.len() != 64 || !hash.is_ascii() {
+        return None;
+    }
+    let mut sha256 = [0u8; 32];
+    for (i, b) in sha256.iter_mut().enumerate() {
+        *b = u8::from_str_radix(&hash[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(Entry {
+        path: String::from(path),
+        size: size.parse().ok()?,
+        sha256,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::archive::{self, manifest::Mismatch, reader::Node, Manifest, Reader};
+    use crate::io::WzRead;
+    use crypto::{KeyStream, GMS_IV, TRIMMED_KEY};
+    use std::{env, fs};
+
+    #[test]
+    fn v83_manifest() {
+        let mut reader = Reader::open(
+            "testdata/v83-base.wz",
+            KeyStream::new(&TRIMMED_KEY, &GMS_IV),
+        )
+        .expect("error opening archive");
+        let map = reader.map("Base").expect("error mapping archive");
+        let mut inner = reader.into_inner();
+        let manifest = archive::manifest(&mut inner, &map).expect("error hashing");
+        assert!(!manifest.entries().is_empty());
+        let parsed = Manifest::from_text(&manifest.to_text()).expect("error parsing");
+        assert_eq!(parsed, manifest);
+        assert!(Manifest::from_text("not a manifest").is_err());
+
+        // Extract the first two images, one of them modified
+        let dir = env::temp_dir().join("wz-manifest-test");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("Base")).expect("error creating directory");
+        let entries = manifest.entries();
+        for (i, entry) in entries.iter().take(2).enumerate() {
+            let image = map.get(&entry.path).expect("missing image");
+            let (offset, size) = match image {
+                Node::Image { offset, size, .. } => (*offset, *size),
+                _ => panic!("expected an image"),
+            };
+            let mut data = Vec::new();
+            inner
+                .copy_to(&mut data, offset, size)
+                .expect("error copying");
+            if i == 1 {
+                data[0] ^= 0xff;
+            }
+            fs::write(dir.join(&entry.path), data).expect("error writing");
+        }
+        let mismatches = manifest.verify(&dir).expect("error verifying");
+        assert_eq!(mismatches.len(), entries.len() - 1);
+        assert_eq!(mismatches[0], (&entries[1], Mismatch::Hash));
+        assert!(mismatches[1..]
+            .iter()
+            .all(|(_, mismatch)| *mismatch == Mismatch::Missing));
+        fs::remove_dir_all(&dir).expect("error removing directory");
+    }
+}
@@ -0,0 +1,352 @@
+//! WZ Archive optimization
+
+use crate::archive::{
+    patch::PatchImage,
+    reader::Node,
+    writer::{EncodedImage, ImageRef},
+    Reader, Writer,
+};
+use crate::error::{Error, Result};
+use crate::io::{WzRead, WzWriter};
+use crate::map::Map;
+use crate::types::{CanvasFormat, Property, ReencodeOptions, WzInt};
+use crypto::{Decryptor, Encryptor};
+use std::collections::HashSet;
+use std::io::{Seek, Write};
+use std::path::Path;
+
+/// What [`optimize`] is allowed to change
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Policy {
+    /// Re-encode the BGRA8888 canvases whose compressed data is larger than this many bytes.
+    /// `None` leaves every canvas alone.
+    pub canvas_limit: Option<usize>,
+
+    /// Format oversized canvases are re-encoded as. Canvases that do not shrink are kept.
+    pub format: CanvasFormat,
+
+    /// Options oversized canvases are re-encoded with
+    pub options: ReencodeOptions,
+
+    /// Remove images without any data and packages without any images
+    pub strip: bool,
+
+    /// Store identical images once, see [`Writer::deduplicate`]
+    pub deduplicate: bool,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            canvas_limit: None,
+            format: CanvasFormat::Bgra4444,
+            options: ReencodeOptions::default(),
+            strip: true,
+            deduplicate: true,
+        }
+    }
+}
+
+/// What [`optimize`] changed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Report {
+    /// Paths of the removed images and packages
+    pub stripped: Vec<String>,
+
+    /// Paths of the re-encoded canvases with the size of their data before and after
+    pub canvases: Vec<(String, usize, usize)>,
+
+    /// Paths of the images sharing their data, with the path of the image they share it with
+    pub shared: Vec<(String, String)>,
+}
+
+/// [`ImageRef`] of an optimized archive
+#[derive(Debug)]
+pub enum OptimizedImage<E>
+where
+    E: Encryptor + Clone,
+{
+    /// Image copied as it is stored in the original archive
+    Copy(PatchImage<'static>),
+
+    /// Image with re-encoded canvases
+    Encoded(EncodedImage<E>),
+}
+
+impl<E> ImageRef for OptimizedImage<E>
+where
+    E: Encryptor + Clone,
+{
+    fn size(&self) -> Result<WzInt> {
+        match self {
+            Self::Copy(image) => image.size(),
+            Self::Encoded(image) => image.size(),
+        }
+    }
+
+    fn checksum(&self) -> Result<WzInt> {
+        match self {
+            Self::Copy(image) => image.checksum(),
+            Self::Encoded(image) => image.checksum(),
+        }
+    }
+
+    fn write<W, F>(&self, writer: &mut WzWriter<W, F>) -> Result<()>
+    where
+        W: Write + Seek,
+        F: Encryptor,
+    {
+        match self {
+            Self::Copy(image) => image.write(writer),
+            Self::Encoded(image) => image.write(writer),
+        }
+    }
+
+    fn prepare(&mut self) -> Result<()> {
+        match self {
+            Self::Copy(image) => image.prepare(),
+            Self::Encoded(image) => image.prepare(),
+        }
+    }
+}
+
+/// Rebuilds the archive at `source`, read by `reader` and mapped in `map`, according to `policy`.
+/// Images are decrypted with `decryptor` and re-encoded ones are encrypted with `encryptor`, which
+/// must match the key of the saved archive. The images are prepared on up to `threads` threads.
+///
+/// Save the returned writer with the version and header of the original archive.
+///
+/// Example:
+///
+/// ```no_run
+/// use crypto::{KeyStream, GMS_IV, TRIMMED_KEY};
+/// use std::path::Path;
+/// use wz::archive::{optimize, Reader};
+///
+/// let key = KeyStream::new(&TRIMMED_KEY, &GMS_IV);
+/// let mut reader = Reader::open("Mob.wz", key.clone()).unwrap();
+/// let version = reader.resolve_version().unwrap();
+/// let header = reader.header().clone();
+/// let map = reader.map("Mob").unwrap();
+/// let policy = optimize::Policy {
+///     canvas_limit: Some(64 << 10),
+///     ..optimize::Policy::default()
+/// };
+/// let (mut writer, report) = optimize::optimize(
+///     &mut reader,
+///     &map,
+///     Path::new("Mob.wz"),
+///     &policy,
+///     || key.clone(),
+///     key.clone(),
+///     4,
+/// )
+/// .unwrap();
+/// writer.save("Mob.optimized.wz", version, header, key).unwrap();
+/// println!("{} images share their data", report.shared.len());
+/// ```
+pub fn optimize<R, F, D, E>(
+    reader: &mut Reader<R>,
+    map: &Map<Node>,
+    source: &Path,
+    policy: &Policy,
+    decryptor: F,
+    encryptor: E,
+    threads: usize,
+) -> Result<(Writer<OptimizedImage<E>>, Report)>
+where
+    R: WzRead,
+    F: Fn() -> D,
+    D: Decryptor,
+    E: Encryptor + Clone + Send,
+{
+    let mut entries = Vec::new();
+    map.walk::<Error>(|cursor| {
+        entries.push((cursor.pwd(), *cursor.get()));
+        Ok(())
+    })?;
+
+    // Packages are kept when an image with data lies somewhere beneath them
+    let mut occupied = HashSet::new();
+    for (path, node) in &entries {
+        if let Node::Image { .. } = node {
+            if !node.is_empty_image() {
+                let mut parent = path.as_str();
+                while let Some((package, _)) = parent.rsplit_once('/') {
+                    occupied.insert(package);
+                    parent = package;
+                }
+            }
+        }
+    }
+
+    let mut writer = Writer::new(map.cursor().name());
+    let mut report = Report::default();
+    for (path, node) in entries.iter().skip(1) {
+        match node {
+            Node::Package if policy.strip && !occupied.contains(path.as_str()) => {
+                report.stripped.push(path.clone())
+            }
+            Node::Package => writer.add_package(path)?,
+            Node::Image { .. } if policy.strip && node.is_empty_image() => {
+                report.stripped.push(path.clone())
+            }
+            Node::Image {
+                offset,
+                size,
+                checksum,
+            } => {
+                let copy = PatchImage::Copy {
+                    path: source.to_path_buf(),
+                    offset: *offset,
+                    size: *size,
+                    checksum: *checksum,
+                };
+                let image = match policy.canvas_limit {
+                    Some(limit) => {
+                        let mut image = reader.map_image(map, path, |_| decryptor())?;
+                        match reencode_canvases(&mut image, limit, policy)? {
+                            canvases if canvases.is_empty() => OptimizedImage::Copy(copy),
+                            canvases => {
+                                // Canvas paths start with the image name, which is already in
+                                // the image path
+                                report.canvases.extend(canvases.into_iter().map(
+                                    |(canvas, before, after)| {
+                                        let (_, canvas) =
+                                            canvas.split_once('/').unwrap_or_default();
+                                        (format!("{}/{}", path, canvas), before, after)
+                                    },
+                                ));
+                                OptimizedImage::Encoded(EncodedImage::new(image, encryptor.clone()))
+                            }
+                        }
+                    }
+                    None => OptimizedImage::Copy(copy),
+                };
+                writer.add_image(path, image)?;
+            }
+        }
+    }
+
+    writer.prepare(threads)?;
+    if policy.deduplicate {
+        report.shared = writer.deduplicate()?;
+    }
+    Ok((writer, report))
+}
+
+/// Re-encodes the BGRA8888 canvases of `image` with data larger than `limit` and returns the ones
+/// that shrank
+fn reencode_canvases(
+    image: &mut Map<Property>,
+    limit: usize,
+    policy: &Policy,
+) -> Result<Vec<(String, usize, usize)>> {
+    let mut reencoded = Vec::new();
+    image.walk_mut::<Error>(|path, property| {
+        if let Property::Canvas(canvas) = property {
+            if canvas.format() == CanvasFormat::Bgra8888 && canvas.data().len() > limit {
+                let smaller = canvas.reencode(policy.format, &policy.options)?;
+                let (before, after) = (canvas.data().len(), smaller.data().len());
+                if after < before {
+                    *canvas = smaller;
+                    reencoded.push((String::from(path), before, after));
+                }
+            }
+        }
+        Ok(())
+    })?;
+    Ok(reencoded)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::archive::{optimize, reader::Node, Reader};
+    use crate::error::Result;
+    use crate::image::{self, writer::write_map};
+    use crate::io::{WzImageWriter, WzWriter};
+    use crate::map::Map;
+    use crate::types::{CanvasFormat, Property};
+    use crypto::{KeyStream, GMS_IV, TRIMMED_KEY};
+    use std::{io::Cursor, path::Path};
+
+    fn key() -> KeyStream {
+        KeyStream::new(&TRIMMED_KEY, &GMS_IV)
+    }
+
+    fn encode(image: &Map<Property>) -> Result<Vec<u8>> {
+        let mut inner = WzWriter::unencrypted(0, 0, Cursor::new(Vec::new()));
+        write_map(image, &mut WzImageWriter::new(&mut inner))?;
+        Ok(inner.into_inner().into_inner())
+    }
+
+    #[test]
+    fn optimize_v83() {
+        let path = Path::new("testdata/v83-base.wz");
+        let mut reader = Reader::open(path, key()).expect("error opening archive");
+        let version = reader.resolve_version().expect("error resolving version");
+        let header = reader.header().clone();
+        let map = reader.map("Base").expect("error mapping archive");
+
+        let policy = optimize::Policy {
+            canvas_limit: Some(0),
+            ..optimize::Policy::default()
+        };
+        let (mut writer, report) =
+            optimize::optimize(&mut reader, &map, path, &policy, key, key(), 2)
+                .expect("error optimizing");
+        let bytes = writer
+            .save_to_vec(version, header, key())
+            .expect("error saving");
+        let original = std::fs::metadata(path)
+            .expect("error reading archive")
+            .len();
+        assert!(bytes.len() as u64 <= original);
+
+        // Everything but the stripped entries is still there with the same contents
+        let mut rebuilt = Reader::from_bytes(bytes, key()).expect("error opening archive");
+        let rebuilt_map = rebuilt.map("Base").expect("error mapping archive");
+        map.walk::<()>(|cursor| {
+            let path = cursor.pwd();
+            let stripped = report.stripped.contains(&path);
+            assert_eq!(rebuilt_map.get(&path).is_err(), stripped, "{}", path);
+            if let (Node::Image { .. }, false) = (cursor.get(), stripped) {
+                let expected = reader.map_image(&map, &path, |_| key()).unwrap();
+                let image = rebuilt.map_image(&rebuilt_map, &path, |_| key()).unwrap();
+                assert_eq!(encode(&image).unwrap(), encode(&expected).unwrap());
+            }
+            Ok(())
+        })
+        .unwrap();
+        for (copy, original) in &report.shared {
+            assert_eq!(rebuilt_map.get(copy).ok(), rebuilt_map.get(original).ok());
+        }
+    }
+
+    #[test]
+    fn oversized_canvases() {
+        let mut map = image::Reader::open("testdata/v83-tamingmob.img", key())
+            .expect("error opening image")
+            .map("tamingmob.img")
+            .expect("error mapping image");
+        let policy = optimize::Policy::default();
+        assert!(optimize::reencode_canvases(&mut map, usize::MAX, &policy)
+            .expect("error re-encoding")
+            .is_empty());
+
+        let reencoded =
+            optimize::reencode_canvases(&mut map, 0, &policy).expect("error re-encoding");
+        assert!(!reencoded.is_empty());
+        for (path, before, after) in reencoded {
+            assert!(after < before);
+            match map.get(&path) {
+                Ok(Property::Canvas(canvas)) => {
+                    assert_eq!(canvas.format(), CanvasFormat::Bgra4444);
+                    assert_eq!(canvas.data().len(), after);
+                }
+                p => panic!("expected a canvas at {}, found {:?}", path, p),
+            }
+        }
+    }
+}
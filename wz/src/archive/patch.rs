@@ -0,0 +1,582 @@
+//! WZ Archive differential patches
+
+use crate::archive::reader::Node;
+use crate::archive::writer::{ImageRef, Writer};
+use crate::error::{Error, PackageError, Result};
+use crate::io::{checksum_reader, WzRead, WzWrite, WzWriter};
+use crate::map::Map;
+use crate::types::{WzHeader, WzInt, WzOffset};
+use crypto::Encryptor;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8; 4] = b"WZPT";
+const FORMAT_VERSION: u16 = 2;
+
+const PACKAGE: u8 = 0;
+const COPY: u8 = 1;
+const DATA: u8 = 2;
+
+/// Content of the patched archive, in archive order. Paths do not include the root package.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Entry {
+    /// Package, which may be empty
+    Package(String),
+
+    /// Image copied from the image at `from` in the old archive, which must have `size` and
+    /// `checksum`
+    Copy {
+        path: String,
+        from: String,
+        size: WzInt,
+        checksum: WzInt,
+    },
+
+    /// Image that is not in the old archive
+    Data { path: String, data: Vec<u8> },
+}
+
+/// Differences between two versions of an archive.
+///
+/// A patch lists every package and image of the new archive. Images found in the old archive,
+/// even under a different path, are copied from it. Only new or modified images are stored, so
+/// mods can be distributed without shipping the full archive.
+///
+/// Example:
+///
+/// ```no_run
+/// use crypto::{KeyStream, GMS_IV, TRIMMED_KEY};
+/// use std::fs::File;
+/// use wz::archive::{self, Reader};
+///
+/// let decryptor = || KeyStream::new(&TRIMMED_KEY, &GMS_IV);
+/// let mut old = Reader::open("Character.wz", decryptor()).unwrap();
+/// let old_map = old.map("Character").unwrap();
+/// let mut new = Reader::open("Character-new.wz", decryptor()).unwrap();
+/// let version = new.resolve_version().unwrap();
+/// let header = new.header().clone();
+/// let new_map = new.map("Character").unwrap();
+/// let patch = archive::patch::diff(
+///     &mut old.into_inner(),
+///     &old_map,
+///     &mut new.into_inner(),
+///     &new_map,
+///     version,
+///     &header,
+/// )
+/// .unwrap();
+/// patch.write_to(&mut File::create("Character.wzpt").unwrap()).unwrap();
+///
+/// // Rebuild Character-new.wz from Character.wz
+/// let mut writer = patch.apply("Character.wz", &old_map).unwrap();
+/// writer
+///     .save("Character-patched.wz", patch.version(), patch.header(), decryptor())
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Patch {
+    version: u16,
+    description: String,
+    entries: Vec<Entry>,
+}
+
+/// Compares the archive mapped in `new_map` against the one in `old_map`. `version` and `header`
+/// are the new archive's.
+pub fn diff<R, S>(
+    old: &mut R,
+    old_map: &Map<Node>,
+    new: &mut S,
+    new_map: &Map<Node>,
+    version: u16,
+    header: &WzHeader,
+) -> Result<Patch>
+where
+    R: WzRead,
+    S: WzRead,
+{
+    // Index the old images by size and checksum to find moved images
+    let mut candidates = HashMap::<(i32, i32), Vec<String>>::new();
+    old_map.walk::<Error>(|cursor| {
+        if let Node::Image { size, checksum, .. } = cursor.get() {
+            candidates
+                .entry((**size, **checksum))
+                .or_default()
+                .push(relative(&cursor.pwd()).into());
+        }
+        Ok(())
+    })?;
+
+    let mut entries = Vec::new();
+    new_map.walk::<Error>(|cursor| {
+        let path = String::from(relative(&cursor.pwd()));
+        match cursor.get() {
+            Node::Package if path.is_empty() => {}
+            Node::Package => entries.push(Entry::Package(path)),
+            Node::Image {
+                offset,
+                size,
+                checksum,
+            } => {
+                let mut data = Vec::new();
+                new.copy_to(&mut data, *offset, *size)?;
+                let mut from = None;
+                if let Some(paths) = candidates.get_mut(&(**size, **checksum)) {
+                    // Prefer the image at the same path
+                    if let Some(i) = paths.iter().position(|p| *p == path) {
+                        paths.swap(0, i);
+                    }
+                    for candidate in paths.iter() {
+                        if read_image(old, old_map, candidate)? == data {
+                            from = Some(candidate.clone());
+                            break;
+                        }
+                    }
+                }
+                entries.push(match from {
+                    Some(from) => Entry::Copy {
+                        path,
+                        from,
+                        size: *size,
+                        checksum: *checksum,
+                    },
+                    None => Entry::Data { path, data },
+                });
+            }
+        }
+        Ok(())
+    })?;
+    Ok(Patch {
+        version,
+        description: header.description.clone(),
+        entries,
+    })
+}
+
+impl Patch {
+    /// Reads a patch written by [`Patch::write_to`]
+    pub fn from_reader<R>(reader: &mut R) -> Result<Self>
+    where
+        R: Read,
+    {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC || read_u16(reader)? != FORMAT_VERSION {
+            return Err(PackageError::Patch.into());
+        }
+        let version = read_u16(reader)?;
+        let description = read_string(reader)?;
+        let mut entries = Vec::new();
+        for _ in 0..read_u64(reader)? {
+            let mut tag = [0u8];
+            reader.read_exact(&mut tag)?;
+            let path = read_string(reader)?;
+            entries.push(match tag[0] {
+                PACKAGE => Entry::Package(path),
+                COPY => Entry::Copy {
+                    path,
+                    from: read_string(reader)?,
+                    size: WzInt::from(read_i32(reader)?),
+                    checksum: WzInt::from(read_i32(reader)?),
+                },
+                DATA => {
+                    let len = read_u64(reader)?;
+                    Entry::Data {
+                        path,
+                        data: read_bytes(reader, len)?,
+                    }
+                }
+                _ => return Err(PackageError::Patch.into()),
+            });
+        }
+        Ok(Self {
+            version,
+            description,
+            entries,
+        })
+    }
+
+    /// Writes the patch
+    pub fn write_to<W>(&self, writer: &mut W) -> Result<()>
+    where
+        W: Write,
+    {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        writer.write_all(&self.version.to_le_bytes())?;
+        write_string(writer, &self.description)?;
+        writer.write_all(&(self.entries.len() as u64).to_le_bytes())?;
+        for entry in &self.entries {
+            match entry {
+                Entry::Package(path) => {
+                    writer.write_all(&[PACKAGE])?;
+                    write_string(writer, path)?;
+                }
+                Entry::Copy {
+                    path,
+                    from,
+                    size,
+                    checksum,
+                } => {
+                    writer.write_all(&[COPY])?;
+                    write_string(writer, path)?;
+                    write_string(writer, from)?;
+                    writer.write_all(&size.to_le_bytes())?;
+                    writer.write_all(&checksum.to_le_bytes())?;
+                }
+                Entry::Data { path, data } => {
+                    writer.write_all(&[DATA])?;
+                    write_string(writer, path)?;
+                    writer.write_all(&(data.len() as u64).to_le_bytes())?;
+                    writer.write_all(data)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the version of the patched archive
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    /// Returns a header for the patched archive
+    pub fn header(&self) -> WzHeader {
+        WzHeader::with_description(self.version, &self.description)
+    }
+
+    /// Returns the entries
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Builds the patched archive from the old archive at `path`, mapped in `map`. Save it with
+    /// [`Patch::version`], [`Patch::header`] and the old archive's key.
+    ///
+    /// Errors when an image copied from the old archive does not exist or does not have the size
+    /// and checksum recorded in the patch, which means the patch was made for another archive.
+    pub fn apply<S>(&self, path: S, map: &Map<Node>) -> Result<Writer<PatchImage<'_>>>
+    where
+        S: AsRef<Path>,
+    {
+        let root = map.cursor().name().to_string();
+        let mut writer = Writer::new(&root);
+        for entry in &self.entries {
+            match entry {
+                Entry::Package(package) => writer.add_package(Path::new(&root).join(package))?,
+                Entry::Copy {
+                    path: image,
+                    from,
+                    size: expected_size,
+                    checksum: expected_checksum,
+                } => {
+                    let (offset, size, checksum) = match map.get(format!("{}/{}", root, from)) {
+                        Ok(Node::Image {
+                            offset,
+                            size,
+                            checksum,
+                        }) => (*offset, *size, *checksum),
+                        _ => return Err(PackageError::Path(from.clone()).into()),
+                    };
+                    if (size, checksum) != (*expected_size, *expected_checksum) {
+                        return Err(PackageError::PatchBase(from.clone()).into());
+                    }
+                    writer.add_image(
+                        Path::new(&root).join(image),
+                        PatchImage::Copy {
+                            path: path.as_ref().to_path_buf(),
+                            offset,
+                            size,
+                            checksum,
+                        },
+                    )?;
+                }
+                Entry::Data { path: image, data } => writer.add_image(
+                    Path::new(&root).join(image),
+                    PatchImage::Data(data.as_slice()),
+                )?,
+            }
+        }
+        Ok(writer)
+    }
+}
+
+/// [`ImageRef`] of a patched archive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PatchImage<'a> {
+    /// Image in the old archive at `path`
+    Copy {
+        path: PathBuf,
+        offset: WzOffset,
+        size: WzInt,
+        checksum: WzInt,
+    },
+
+    /// Image stored in the patch
+    Data(&'a [u8]),
+}
+
+impl ImageRef for PatchImage<'_> {
+    fn size(&self) -> Result<WzInt> {
+        match self {
+            Self::Copy { size, .. } => Ok(*size),
+            Self::Data(data) => i32::try_from(data.len())
+                .map(WzInt::from)
+                .map_err(|_| PackageError::ArchiveTooLarge.into()),
+        }
+    }
+
+    fn checksum(&self) -> Result<WzInt> {
+        match self {
+            Self::Copy { checksum, .. } => Ok(*checksum),
+            Self::Data(data) => Ok(checksum_reader(*data)?.1),
+        }
+    }
+
+    fn write<W, E>(&self, writer: &mut WzWriter<W, E>) -> Result<()>
+    where
+        W: Write + Seek,
+        E: Encryptor,
+    {
+        match self {
+            Self::Copy {
+                path, offset, size, ..
+            } => {
+                let mut src = BufReader::new(File::open(path)?);
+                src.seek(SeekFrom::Start(**offset as u64))?;
+                writer.copy_from(&mut src, *size)
+            }
+            Self::Data(data) => writer.write_all(data),
+        }
+    }
+}
+
+/// Strips the root package from `path`
+fn relative(path: &str) -> &str {
+    path.split_once('/').map_or("", |(_, rest)| rest)
+}
+
+fn read_image<R>(reader: &mut R, map: &Map<Node>, path: &str) -> Result<Vec<u8>>
+where
+    R: WzRead,
+{
+    let root = map.cursor().name().to_string();
+    let mut data = Vec::new();
+    if let Node::Image { offset, size, .. } = map.get(format!("{}/{}", root, path))? {
+        reader.copy_to(&mut data, *offset, *size)?;
+    }
+    Ok(data)
+}
+
+fn write_string<W>(writer: &mut W, s: &str) -> Result<()>
+where
+    W: Write,
+{
+    writer.write_all(&(s.len() as u32).to_le_bytes())?;
+    Ok(writer.write_all(s.as_bytes())?)
+}
+
+fn read_string<R>(reader: &mut R) -> Result<String>
+where
+    R: Read,
+{
+    let mut len = [0u8; 4];
+    reader.read_exact(&mut len)?;
+    let data = read_bytes(reader, u32::from_le_bytes(len) as u64)?;
+    String::from_utf8(data).map_err(|_| PackageError::Patch.into())
+}
+
+/// Reads `len` bytes. Only the bytes actually read are allocated, so a corrupt length fails
+/// instead of allocating whatever it claims.
+fn read_bytes<R>(reader: &mut R, len: u64) -> Result<Vec<u8>>
+where
+    R: Read,
+{
+    let mut data = Vec::new();
+    reader.take(len).read_to_end(&mut data)?;
+    match data.len() as u64 == len {
+        true => Ok(data),
+        false => Err(PackageError::Patch.into()),
+    }
+}
+
+fn read_u16<R: Read>(reader: &mut R) -> Result<u16> {
+    let mut buf = [0u8; 2];
+    reader.read_exact(&mut buf)?;
+    Ok(u16::from_le_bytes(buf))
+}
+
+fn read_i32<R: Read>(reader: &mut R) -> Result<i32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(i32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::archive::patch::{self, Entry, PatchImage};
+    use crate::archive::{reader::Node, Patch, Reader, Writer};
+    use crate::error::{Error, PackageError};
+    use crate::io::WzRead;
+    use crate::types::{WzHeader, WzInt};
+    use crypto::{KeyStream, GMS_IV, TRIMMED_KEY};
+    use std::{env, fs, io::Cursor};
+
+    #[test]
+    fn v83_patch() {
+        let key = || KeyStream::new(&TRIMMED_KEY, &GMS_IV);
+        let mut old = Reader::open("testdata/v83-base.wz", key()).expect("error opening archive");
+        let old_header = old.header().clone();
+        let old_map = old.map("Base").expect("error mapping archive");
+        let mut old_inner = old.into_inner();
+
+        // Modify smap.img, move zmap.img into a new package and add an empty package
+        let mut images = Vec::new();
+        old_map
+            .walk::<Error>(|cursor| {
+                if let Node::Image { offset, size, .. } = cursor.get() {
+                    let mut data = Vec::new();
+                    old_inner.copy_to(&mut data, *offset, *size)?;
+                    images.push((cursor.pwd(), data));
+                }
+                Ok(())
+            })
+            .expect("error reading images");
+        let mut writer = Writer::new("Base");
+        for (path, data) in &mut images {
+            match path.as_str() {
+                "Base/smap.img" => data[10] ^= 0xff,
+                "Base/zmap.img" => *path = String::from("Base/Extra/moved.img"),
+                _ => {}
+            }
+        }
+        for (path, data) in &images {
+            writer
+                .add_image(path, PatchImage::Data(data))
+                .expect("error adding image");
+        }
+        writer
+            .add_package("Base/Empty")
+            .expect("error adding package");
+        let dir = env::temp_dir().join("wz-patch-test");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("error creating directory");
+        let new_path = dir.join("new.wz");
+        let header = WzHeader::with_description(83, &old_header.description);
+        writer
+            .save(&new_path, 83, header.clone(), key())
+            .expect("error saving archive");
+
+        let mut new = Reader::open(&new_path, key()).expect("error opening archive");
+        let new_map = new.map("Base").expect("error mapping archive");
+        let patch = patch::diff(
+            &mut old_inner,
+            &old_map,
+            &mut new.into_inner(),
+            &new_map,
+            83,
+            &header,
+        )
+        .expect("error diffing");
+        let data = patch
+            .entries()
+            .iter()
+            .filter(|entry| matches!(entry, Entry::Data { .. }))
+            .collect::<Vec<&Entry>>();
+        assert_eq!(data.len(), 1);
+        assert!(matches!(data[0], Entry::Data { path, .. } if path == "smap.img"));
+        let zmap = match old_map.get("Base/zmap.img") {
+            Ok(Node::Image { size, checksum, .. }) => (*size, *checksum),
+            n => panic!("expected an image, found {:?}", n),
+        };
+        assert!(patch.entries().contains(&Entry::Copy {
+            path: String::from("Extra/moved.img"),
+            from: String::from("zmap.img"),
+            size: zmap.0,
+            checksum: zmap.1,
+        }));
+        assert!(patch
+            .entries()
+            .contains(&Entry::Package(String::from("Empty"))));
+
+        let mut encoded = Vec::new();
+        patch.write_to(&mut encoded).expect("error writing patch");
+        let decoded = Patch::from_reader(&mut Cursor::new(&encoded)).expect("error reading patch");
+        assert_eq!(decoded, patch);
+        assert!(Patch::from_reader(&mut Cursor::new(&encoded[1..])).is_err());
+
+        // Applying the patch to the old archive rebuilds the new one
+        let patched_path = dir.join("patched.wz");
+        decoded
+            .apply("testdata/v83-base.wz", &old_map)
+            .expect("error applying patch")
+            .save(&patched_path, decoded.version(), decoded.header(), key())
+            .expect("error saving archive");
+        assert_eq!(
+            fs::read(&patched_path).expect("error reading archive"),
+            fs::read(&new_path).expect("error reading archive")
+        );
+
+        // Copied images must match the ones the patch was made from
+        let mut other_map = Reader::open("testdata/v83-base.wz", key())
+            .expect("error opening archive")
+            .map("Base")
+            .expect("error mapping archive");
+        let mut cursor = other_map
+            .cursor_mut_at("Base/zmap.img")
+            .expect("missing image");
+        if let Node::Image { checksum, .. } = cursor.get_mut() {
+            *checksum = WzInt::from(**checksum ^ 1);
+        }
+        assert!(matches!(
+            decoded.apply("testdata/v83-base.wz", &other_map),
+            Err(Error::Package(PackageError::PatchBase(p))) if p == "zmap.img"
+        ));
+        fs::remove_dir_all(&dir).expect("error removing directory");
+    }
+
+    #[test]
+    fn corrupt_lengths() {
+        let header = |description_len: u32| {
+            let mut data = b"WZPT".to_vec();
+            data.extend_from_slice(&2u16.to_le_bytes());
+            data.extend_from_slice(&83u16.to_le_bytes());
+            data.extend_from_slice(&description_len.to_le_bytes());
+            data
+        };
+
+        // Lengths far past the end of the patch fail without being allocated
+        let data = header(u32::MAX);
+        assert!(matches!(
+            Patch::from_reader(&mut Cursor::new(&data)),
+            Err(Error::Package(PackageError::Patch))
+        ));
+        let mut data = header(0);
+        data.extend_from_slice(&1u64.to_le_bytes());
+        data.push(2);
+        data.extend_from_slice(&1u32.to_le_bytes());
+        data.push(b'a');
+        data.extend_from_slice(&u64::MAX.to_le_bytes());
+        data.extend_from_slice(&[0u8; 16]);
+        assert!(matches!(
+            Patch::from_reader(&mut Cursor::new(&data)),
+            Err(Error::Package(PackageError::Patch))
+        ));
+
+        // Patches written by an older version do not record the copied images
+        let mut data = header(0);
+        data[4..6].copy_from_slice(&1u16.to_le_bytes());
+        data.extend_from_slice(&0u64.to_le_bytes());
+        assert!(Patch::from_reader(&mut Cursor::new(&data)).is_err());
+        data[4..6].copy_from_slice(&2u16.to_le_bytes());
+        assert!(Patch::from_reader(&mut Cursor::new(&data)).is_ok());
+    }
+}
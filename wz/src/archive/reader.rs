@@ -1,18 +1,61 @@
 //! WZ Archive Reader
 
-use crate::error::{PackageError, Result};
-use crate::io::{Decode, DummyDecryptor, WzRead, WzReader};
-use crate::map::{CursorMut, Map};
+use crate::archive::{manifest, signature, signature::VerifyingKey, Index};
+use crate::error::{DecodeError, Error, ImageError, PackageError, Result};
+use crate::image;
+use crate::io::{Decode, DummyDecryptor, WzDecryptingReader, WzImageReader, WzRead, WzReader};
+use crate::map::Map;
 use crate::types::raw::{package::ContentRef, Package};
-use crate::types::{WzHeader, WzInt, WzOffset};
-use crypto::{checksum, Decryptor};
-use std::{fs::File, io::BufReader, path::Path};
+use crate::types::{HeaderWarning, Property, StringDecoding, WzHeader, WzInt, WzOffset};
+use crypto::Decryptor;
+use std::{
+    collections::BTreeSet,
+    fmt,
+    fs::File,
+    io::{self, BufReader, Read, Seek},
+    path::Path,
+};
+
+/// Deepest packages are nested when mapping an archive. Real archives are only a few levels deep
+/// but a package pointing back at itself would otherwise never end.
+pub(crate) const MAX_DEPTH: usize = 64;
 
 /// Map node pointing to WZ archive contents
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Node {
     Package,
-    Image { offset: WzOffset, size: WzInt },
+    Image {
+        offset: WzOffset,
+        size: WzInt,
+        checksum: WzInt,
+    },
+}
+
+/// Shows the type of the node followed by the size, offset and checksum of images, e.g.
+/// `image    size     1234  offset 0x0000003C  checksum 0x00001F2E`
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Node::Package => write!(f, "package"),
+            Node::Image {
+                offset,
+                size,
+                checksum,
+            } => write!(
+                f,
+                "image    size {:>8}  offset {}  checksum 0x{:08X}",
+                **size, offset, **checksum as u32
+            ),
+        }
+    }
+}
+
+impl Node {
+    /// Returns `true` for images without any data, which some broken archives contain. They are
+    /// mapped as an image holding nothing but its root.
+    pub fn is_empty_image(&self) -> bool {
+        matches!(self, Node::Image { size, .. } if **size == 0)
+    }
 }
 
 /// Reads a WZ archive
@@ -37,6 +80,17 @@ where
 {
     header: WzHeader,
     inner: R,
+    warnings: Vec<HeaderWarning>,
+}
+
+/// Stored bytes of a single image, returned by [`Reader::raw_image`]
+#[derive(Debug)]
+pub struct RawImage<'a, R>
+where
+    R: WzRead,
+{
+    inner: &'a mut R,
+    remaining: u64,
 }
 
 impl Reader<WzReader<BufReader<File>, DummyDecryptor>> {
@@ -57,10 +111,52 @@ where
     where
         S: AsRef<Path>,
     {
-        let mut buf = BufReader::new(File::open(path)?);
-        let header = WzHeader::from_reader(&mut buf)?;
-        let inner = bruteforce_version(&header, buf, decryptor)?;
-        Ok(Reader::new(header, inner))
+        Self::open_with(path, decryptor, true)
+    }
+
+    /// Opens a WZ archive like [`Reader::open`]. Unless `strict` is set, a nonstandard header is
+    /// accepted and the problems are available through [`Reader::warnings`].
+    pub fn open_with<S>(
+        path: S,
+        decryptor: D,
+        strict: bool,
+    ) -> Result<Reader<WzReader<BufReader<File>, D>>>
+    where
+        S: AsRef<Path>,
+    {
+        Reader::from_reader_with(BufReader::new(File::open(path)?), decryptor, strict)
+    }
+
+    /// Opens a WZ archive and maps it as `name` using the index at `index_path`. A missing or
+    /// stale index is rebuilt from the archive and saved.
+    pub fn open_indexed<S, T>(
+        path: S,
+        index_path: T,
+        name: &str,
+        decryptor: D,
+    ) -> Result<(Self, Map<Node>)>
+    where
+        S: AsRef<Path>,
+        T: AsRef<Path>,
+    {
+        if let Some(index) = Index::load(&index_path, &path)? {
+            let mut buf = BufReader::new(File::open(&path)?);
+            let header = WzHeader::from_reader(&mut buf)?;
+            let inner = WzReader::new(
+                header.absolute_position,
+                index.version_checksum(),
+                buf,
+                decryptor,
+            );
+            let mut map = index.into_map();
+            map.rename(String::from(name))?;
+            return Ok((Reader::new(header, inner), map));
+        }
+        let mut reader = Self::open(&path, decryptor)?;
+        let map = reader.map(name)?;
+        let index = Index::new(&path, reader.inner.version_checksum(), map)?;
+        index.save(index_path)?;
+        Ok((reader, index.into_map()))
     }
 
     /// Opens a WZ archive and reads the header data.
@@ -75,25 +171,61 @@ where
         let mut buf = BufReader::new(File::open(path)?);
         let header = WzHeader::from_reader(&mut buf)?;
         let absolute_position = header.absolute_position;
-        let (version_hash, version_checksum) = checksum(&version.to_string());
-        if version_hash != header.version_hash {
-            Err(PackageError::Checksum.into())
-        } else {
-            Ok(Reader::new(
+        let version_hash = header.version_hash.ok_or(PackageError::Unversioned)?;
+        match version_hash.checksum(version) {
+            Some(version_checksum) => Ok(Reader::new(
                 header,
                 WzReader::new(absolute_position, version_checksum, buf, decryptor),
-            ))
+            )),
+            None => Err(PackageError::Checksum.into()),
         }
     }
 }
 
+impl<T, D> Reader<WzReader<T, D>>
+where
+    T: Read + Seek,
+    D: Decryptor,
+{
+    /// Reads a WZ archive from any seekable reader positioned at the start of the archive, e.g. a
+    /// download held in memory. Attempts to brute force the version like [`Reader::open`].
+    pub fn from_reader(reader: T, decryptor: D) -> Result<Self> {
+        Self::from_reader_with(reader, decryptor, true)
+    }
+
+    /// Reads a WZ archive like [`Reader::from_reader`]. Unless `strict` is set, a nonstandard
+    /// header is accepted and the problems are available through [`Reader::warnings`].
+    pub fn from_reader_with(mut reader: T, decryptor: D, strict: bool) -> Result<Self> {
+        let (header, warnings) = WzHeader::from_reader_with(&mut reader, strict)?;
+        let inner = WzReader::new(header.absolute_position, 0u32, reader, decryptor);
+        let mut reader = Reader::new(header, inner);
+        reader.warnings = warnings;
+        reader.resolve_version()?;
+        Ok(reader)
+    }
+}
+
+impl<D> Reader<WzReader<io::Cursor<Vec<u8>>, D>>
+where
+    D: Decryptor,
+{
+    /// Reads a WZ archive held in memory
+    pub fn from_bytes(bytes: Vec<u8>, decryptor: D) -> Result<Self> {
+        Self::from_reader(io::Cursor::new(bytes), decryptor)
+    }
+}
+
 impl<R> Reader<R>
 where
     R: WzRead,
 {
     /// Creates a new archive reader from a WzRead
     pub fn new(header: WzHeader, inner: R) -> Self {
-        Self { header, inner }
+        Self {
+            header,
+            inner,
+            warnings: Vec::new(),
+        }
     }
 
     /// Returns a reference to the header
@@ -101,76 +233,568 @@ where
         &self.header
     }
 
+    /// Returns the header problems accepted by [`Reader::open_with`]
+    pub fn warnings(&self) -> &[HeaderWarning] {
+        &self.warnings
+    }
+
+    /// Sets how strings that are not valid UTF-8 or UTF-16 are decoded, in both the archive and
+    /// its images. By default they are decoded lossily, see [`StringDecoding`].
+    pub fn set_string_decoding(&mut self, decoding: StringDecoding) -> &mut Self {
+        self.inner.set_string_decoding(decoding);
+        self
+    }
+
     /// Maps the archive contents. The root will be named `name`
     pub fn map(&mut self, name: &str) -> Result<Map<Node>> {
         let name = String::from(name);
         let mut map = Map::new(name, Node::Package);
         self.inner.seek_to_start()?;
-        map_package_to(&mut self.inner, &mut map.cursor_mut())?;
+        map_package_to(&mut self.inner, &mut map)?;
         Ok(map)
     }
 
+    /// Returns the exact bytes stored for the image at `path`, without decrypting or parsing them.
+    /// The path is relative to the archive root, e.g. `Weapon/01302000.img`.
+    ///
+    /// Stored images can be copied into another archive verbatim as long as both archives use the
+    /// same key, since images are encrypted independently of their location.
+    pub fn raw_image(&mut self, path: &str) -> Result<RawImage<'_, R>> {
+        let mut components = path.split('/').filter(|name| !name.is_empty()).peekable();
+        self.inner.seek_to_start()?;
+        while let Some(name) = components.next() {
+            let package = Package::decode(&mut self.inner)?;
+            let content = package
+                .contents
+                .into_iter()
+                .find(|content| match content {
+                    ContentRef::Package(data) | ContentRef::Image(data) => {
+                        data.name.as_str() == name
+                    }
+                })
+                .ok_or_else(|| PackageError::Path(path.into()))?;
+            match (content, components.peek()) {
+                (ContentRef::Package(data), Some(_)) => {
+                    self.inner.seek(data.offset)?;
+                }
+                (ContentRef::Image(data), None) => {
+                    let remaining =
+                        u64::try_from(*data.size).map_err(|_| DecodeError::Length(*data.size))?;
+                    self.inner.seek(data.offset)?;
+                    return Ok(RawImage {
+                        inner: &mut self.inner,
+                        remaining,
+                    });
+                }
+                _ => break,
+            }
+        }
+        Err(PackageError::Path(path.into()).into())
+    }
+
+    /// Maps the image at `path` of `map`, decrypting its strings with the decryptor `select`
+    /// returns. `select` is given the path so the choice can be made per image.
+    ///
+    /// GMS v65 to v83 only encrypt the images listed in List.wz:
+    ///
+    /// ```no_run
+    /// use crypto::{Decryptor, KeyStream, GMS_IV, TRIMMED_KEY};
+    /// use wz::{archive::Reader, io::DummyDecryptor, list};
+    ///
+    /// let key = KeyStream::new(&TRIMMED_KEY, &GMS_IV);
+    /// let listed = list::Reader::parse("List.wz", key.clone()).unwrap();
+    /// let mut reader = Reader::open("Mob.wz", key.clone()).unwrap();
+    /// let map = reader.map("Mob").unwrap();
+    /// let image = reader
+    ///     .map_image(&map, "Mob/0100100.img", |path| -> Box<dyn Decryptor> {
+    ///         match listed.contains(path) {
+    ///             true => Box::new(key.clone()),
+    ///             false => Box::new(DummyDecryptor),
+    ///         }
+    ///     })
+    ///     .unwrap();
+    /// ```
+    pub fn map_image<F, D>(
+        &mut self,
+        map: &Map<Node>,
+        path: &str,
+        select: F,
+    ) -> Result<Map<Property>>
+    where
+        F: FnOnce(&str) -> D,
+        D: Decryptor,
+    {
+        let cursor = map.cursor_at(path)?;
+        let offset = match cursor.get() {
+            node if node.is_empty_image() => {
+                return Ok(Map::new(String::from(cursor.name()), Property::ImgDir))
+            }
+            Node::Image { offset, .. } => *offset,
+            Node::Package => return Err(ImageError::Path(String::from(path)).into()),
+        };
+        let mut decrypting = WzDecryptingReader::new(&mut self.inner, select(path));
+        let mut image_reader = WzImageReader::with_offset(&mut decrypting, offset);
+        image_reader.seek_to_start()?;
+        image::Reader::new(image_reader).map(cursor.name())
+    }
+
+    /// Finds the version of the archive and sets its checksum on the inner reader.
+    ///
+    /// Several versions share the same version hash. Each candidate checksum is used to decode the
+    /// top-level package and the first one whose contents all lie within the archive is kept.
+    /// Returns the matching version.
+    pub fn resolve_version(&mut self) -> Result<u16> {
+        let lower_bound = WzOffset::from(self.header.absolute_position as u32);
+        let upper_bound = self.header.absolute_position as u64 + self.header.size;
+        let version_hash = self.header.version_hash.ok_or(PackageError::Unversioned)?;
+        for &(version, version_checksum) in version_hash.possible_versions() {
+            self.inner.set_version_checksum(version_checksum);
+            self.inner.seek_to_start()?;
+
+            // A wrong checksum scrambles the offsets, which usually point outside the archive or
+            // fail to decode at all
+            let package = match Package::decode(&mut self.inner) {
+                Ok(package) => package,
+                Err(_) => continue,
+            };
+            // Zero-length images may start right at the end
+            if package.contents.iter().all(|content| {
+                let offset = content.offset();
+                offset >= lower_bound
+                    && (offset < upper_bound || (offset == upper_bound && *content.size() == 0))
+            }) {
+                return Ok(version);
+            }
+        }
+        Err(PackageError::BruteForceChecksum.into())
+    }
+
+    /// Checks the signature appended by [`Writer::save_signed`](crate::archive::Writer::save_signed)
+    /// against the images of the archive.
+    ///
+    /// Errors when the archive is not signed, when it was signed by another key or when any image
+    /// was modified, added or removed after signing.
+    pub fn verify_signature(&mut self, key: &VerifyingKey) -> Result<()> {
+        let end = u32::try_from(self.header.absolute_position as u64 + self.header.size)
+            .map_err(|_| PackageError::Signature)?;
+        let mut trailer = [0u8; signature::TRAILER_LENGTH];
+        self.inner.seek(WzOffset::from(end))?;
+        if self.inner.read_exact(&mut trailer).is_err() {
+            return Err(PackageError::Signature.into());
+        }
+        let map = self.map(signature::ROOT)?;
+        let manifest = manifest(&mut self.inner, &map)?;
+        match signature::verify(&trailer, manifest.entries().to_vec(), key) {
+            true => Ok(()),
+            false => Err(PackageError::Signature.into()),
+        }
+    }
+
     /// Consumes the archive and returns the inner reader
     pub fn into_inner(self) -> R {
         self.inner
     }
 }
 
-fn bruteforce_version<D>(
-    header: &WzHeader,
-    buf: BufReader<File>,
-    decryptor: D,
-) -> Result<WzReader<BufReader<File>, D>>
+impl<R> RawImage<'_, R>
 where
-    D: Decryptor,
+    R: WzRead,
 {
-    let lower_bound = WzOffset::from(header.absolute_position as u32);
-    let upper_bound = WzOffset::from(header.absolute_position as u32 + header.size as u32);
-    let mut inner = WzReader::new(header.absolute_position, 0u32, buf, decryptor);
-    for (_, version_checksum) in WzHeader::possible_versions(header.version_hash) {
-        inner.set_version_checksum(version_checksum);
-        inner.seek_to_start()?;
-
-        // Decodes the top-level directory contents. If all contents lie within the lower and
-        // upper bounds, we can assume the version checksum is good.
-        let package = Package::decode(&mut inner)?;
-        let filtered_len = package
-            .contents
-            .iter()
-            .map(|content| content.offset())
-            .filter(|off| *off >= lower_bound && *off < upper_bound)
-            .count();
-        if package.contents.len() == filtered_len {
-            return Ok(inner);
-        }
-    }
-    Err(PackageError::BruteForceChecksum.into())
+    /// Returns the number of bytes left to read
+    pub fn remaining(&self) -> u64 {
+        self.remaining
+    }
 }
 
-fn map_package_to<R>(reader: &mut R, cursor: &mut CursorMut<Node>) -> Result<()>
+impl<R> Read for RawImage<'_, R>
 where
     R: WzRead,
 {
-    let package = Package::decode(reader)?;
-    for content in package.contents {
-        match &content {
-            ContentRef::Package(ref data) => {
-                cursor.create(String::from(data.name.as_str()), Node::Package)?;
-                cursor.move_to(data.name.as_ref())?;
-                reader.seek(data.offset)?;
-                map_package_to(reader, cursor)?;
-                cursor.parent()?;
-            }
-            ContentRef::Image(ref data) => {
-                cursor.create(
-                    String::from(data.name.as_str()),
-                    Node::Image {
-                        offset: data.offset,
-                        size: data.size,
-                    },
-                )?;
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = usize::try_from(self.remaining).map_or(buf.len(), |r| buf.len().min(r));
+        if len == 0 {
+            return Ok(0);
+        }
+        let read = self.inner.read(&mut buf[..len]).map_err(|e| match e {
+            Error::Io(kind) => io::Error::from(kind),
+            e => io::Error::other(e.to_string()),
+        })?;
+        self.remaining -= read as u64;
+        Ok(read)
+    }
+}
+
+fn map_package_to<R>(reader: &mut R, map: &mut Map<Node>) -> Result<()>
+where
+    R: WzRead,
+{
+    // Packages left to decode with their offset and depth. The root is where the reader is.
+    let mut pending = vec![(map.cursor().bookmark(), None, 0)];
+    // Offsets of the packages decoded so far. Packages sharing contents would be mapped again for
+    // every package pointing at them, which grows exponentially with the depth.
+    let mut visited = BTreeSet::new();
+    while let Some((bookmark, offset, depth)) = pending.pop() {
+        if depth > MAX_DEPTH {
+            return Err(PackageError::Depth.into());
+        }
+        if let Some(offset) = offset {
+            reader.seek(offset)?;
+        }
+        let position = reader.position()?;
+        if !visited.insert(position) {
+            return Err(PackageError::SharedPackage(position).into());
+        }
+        let package = Package::decode(reader)?;
+        let mut cursor = map.cursor_mut_at_bookmark(bookmark)?;
+        let mut packages = Vec::new();
+        for content in package.contents {
+            match content {
+                ContentRef::Package(data) => {
+                    cursor
+                        .create(data.name.clone(), Node::Package)?
+                        .move_to(&data.name)?;
+                    packages.push((cursor.bookmark(), Some(data.offset), depth + 1));
+                    cursor.parent()?;
+                }
+                ContentRef::Image(data) => {
+                    cursor.create(
+                        data.name,
+                        Node::Image {
+                            offset: data.offset,
+                            size: data.size,
+                            checksum: data.checksum,
+                        },
+                    )?;
+                }
             }
         }
+        // Decode the packages in the order of the contents
+        pending.extend(packages.into_iter().rev());
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+
+    use crate::archive::{
+        reader::{Node, MAX_DEPTH},
+        signature::SigningKey,
+        Reader,
+    };
+    use crate::error::{DecodeError, Error, PackageError};
+    use crate::io::{Decode, DummyDecryptor, Encode, SizeHint, WzRead, WzReader, WzWriter};
+    use crate::map::Map;
+    use crate::types::raw::{
+        package::{ContentRef, Metadata},
+        Package,
+    };
+    use crate::types::{Property, WzHeader, WzInt, WzOffset};
+    use crypto::{checksum, Decryptor, KeyStream, GMS_IV, TRIMMED_KEY};
+    use std::{
+        fs::{self, File},
+        io::{BufReader, Cursor, Read},
+    };
+
+    #[test]
+    fn content_order() {
+        let mut reader = Reader::open(
+            "testdata/v83-base.wz",
+            KeyStream::new(&TRIMMED_KEY, &GMS_IV),
+        )
+        .expect("error opening archive");
+        let map = reader.map("Base").expect("error mapping archive");
+
+        // Children are mapped in the order of the package contents
+        let mut inner = reader.into_inner();
+        inner.seek_to_start().expect("error seeking");
+        let package = Package::decode(&mut inner).expect("error decoding package");
+        let cursor = map.cursor();
+        assert_eq!(cursor.child_count(), package.contents.len());
+        for (i, content) in package.contents.iter().enumerate() {
+            let name = match content {
+                ContentRef::Package(data) | ContentRef::Image(data) => data.name.as_str(),
+            };
+            let mut child = map.cursor();
+            child.move_to(name).expect("missing content");
+            assert_eq!(child.index(), i);
+        }
+    }
+
+    #[test]
+    fn display_nodes() {
+        assert_eq!(Node::Package.to_string(), "package");
+        let image = Node::Image {
+            offset: WzOffset::from(0x3Cu32),
+            size: WzInt::from(1234),
+            checksum: WzInt::from(-1),
+        };
+        assert_eq!(
+            image.to_string(),
+            "image    size     1234  offset 0x0000003C  checksum 0xFFFFFFFF"
+        );
+    }
+
+    #[test]
+    fn resolve_versions() {
+        let mut buf = BufReader::new(File::open("testdata/v83-base.wz").expect("error opening"));
+        let header = WzHeader::from_reader(&mut buf).expect("error reading header");
+        let key = KeyStream::new(&TRIMMED_KEY, &GMS_IV);
+        let inner = WzReader::new(header.absolute_position, 0u32, buf, key);
+        let mut reader = Reader::new(header, inner);
+        assert_eq!(reader.resolve_version().expect("error resolving"), 83);
+        reader.map("Base.wz").expect("error mapping");
+    }
+
+    #[test]
+    fn in_memory_archive() {
+        let key = || KeyStream::new(&TRIMMED_KEY, &GMS_IV);
+        let data = fs::read("testdata/v83-base.wz").expect("error reading archive");
+        let expected = Reader::open("testdata/v83-base.wz", key())
+            .expect("error opening archive")
+            .map("Base")
+            .expect("error mapping archive");
+        let mut reader = Reader::from_bytes(data.clone(), key()).expect("error reading archive");
+        let map = reader.map("Base").expect("error mapping archive");
+        assert_eq!(
+            format!("{:?}", map.debug_pretty_print()),
+            format!("{:?}", expected.debug_pretty_print())
+        );
+        assert_eq!(reader.inner.version_checksum(), checksum("83").1);
+
+        // Anything seekable works, not only files and vectors
+        let mut reader =
+            Reader::from_reader(Cursor::new(&data[..]), key()).expect("error reading archive");
+        assert!(reader.map("Base").is_ok());
+        assert!(Reader::from_bytes(data[..8].to_vec(), key()).is_err());
+    }
+
+    #[test]
+    fn raw_images() {
+        let mut reader = Reader::open(
+            "testdata/v83-base.wz",
+            KeyStream::new(&TRIMMED_KEY, &GMS_IV),
+        )
+        .expect("error opening archive");
+        let map = reader.map("Base.wz").expect("error mapping archive");
+        let mut images = Vec::new();
+        map.walk::<Error>(|cursor| {
+            if let Node::Image { offset, size, .. } = cursor.get() {
+                let pwd = cursor.pwd();
+                let path = pwd.split_once('/').expect("image should have a parent").1;
+                images.push((String::from(path), *offset, *size));
+            }
+            Ok(())
+        })
+        .expect("error walking");
+        assert!(!images.is_empty());
+
+        let mut inner = Reader::open(
+            "testdata/v83-base.wz",
+            KeyStream::new(&TRIMMED_KEY, &GMS_IV),
+        )
+        .expect("error opening archive")
+        .into_inner();
+        for (path, offset, size) in &images {
+            let mut raw = Vec::new();
+            let mut image = reader.raw_image(path).expect("error finding image");
+            assert_eq!(image.remaining(), **size as u64);
+            image.read_to_end(&mut raw).expect("error reading image");
+            assert_eq!(image.remaining(), 0);
+            let mut copied = Vec::new();
+            inner
+                .copy_to(&mut copied, *offset, *size)
+                .expect("error copying");
+            assert_eq!(raw, copied);
+        }
+        assert!(reader.raw_image("missing.img").is_err());
+    }
+
+    #[test]
+    fn per_image_decryptors() {
+        let mut reader = Reader::open(
+            "testdata/v83-base.wz",
+            KeyStream::new(&TRIMMED_KEY, &GMS_IV),
+        )
+        .expect("error opening archive");
+        let map = reader.map("Base").expect("error mapping archive");
+        let mut images = Vec::new();
+        map.walk::<Error>(|cursor| {
+            if let Node::Image { .. } = cursor.get() {
+                images.push(cursor.pwd());
+            }
+            Ok(())
+        })
+        .expect("error walking");
+        let names = |image: &Map<Property>| {
+            let mut names = Vec::new();
+            image
+                .walk::<Error>(|cursor| {
+                    names.push(cursor.pwd());
+                    Ok(())
+                })
+                .expect("error walking");
+            names
+        };
+
+        // Only the first image is listed as encrypted
+        let listed = &images[0];
+        let select = |path: &str| -> Box<dyn Decryptor> {
+            match path == listed {
+                true => Box::new(KeyStream::new(&TRIMMED_KEY, &GMS_IV)),
+                false => Box::new(DummyDecryptor),
+            }
+        };
+        let encrypted = reader
+            .map_image(&map, listed, select)
+            .expect("error mapping image");
+        assert!(names(&encrypted).len() > 1);
+        for path in &images[1..] {
+            let expected = reader
+                .map_image(&map, path, |_| KeyStream::new(&TRIMMED_KEY, &GMS_IV))
+                .expect("error mapping image");
+            // Encrypted strings read without the key either fail to decode or come out scrambled
+            if let Ok(image) = reader.map_image(&map, path, select) {
+                assert_ne!(names(&image), names(&expected));
+            }
+        }
+        assert!(reader.map_image(&map, "Base", select).is_err());
+        assert!(reader.map_image(&map, "Base/missing.img", select).is_err());
+    }
+
+    /// Opens an archive holding `packages` one after the other, the first being the root.
+    /// `packages` is given the offset of every package.
+    fn archive<F>(count: usize, packages: F) -> Reader<WzReader<Cursor<Vec<u8>>, DummyDecryptor>>
+    where
+        F: Fn(&[WzOffset]) -> Vec<Package>,
+    {
+        let header = WzHeader::new(83);
+        let start = header.absolute_position as u32 + 2;
+        let (_, version_checksum) = checksum("83");
+
+        // Package sizes do not depend on the offsets they hold
+        let mut offsets = vec![WzOffset::from(start); count];
+        for (i, package) in packages(&offsets).iter().enumerate().take(count - 1) {
+            offsets[i + 1] = WzOffset::from(*offsets[i] + package.size_hint());
+        }
+
+        let mut writer = WzWriter::unencrypted(
+            header.absolute_position,
+            version_checksum,
+            Cursor::new(Vec::new()),
+        );
+        header.encode(&mut writer).expect("error encoding header");
+        for package in packages(&offsets) {
+            package.encode(&mut writer).expect("error encoding package");
+        }
+        let data = writer.into_inner().into_inner();
+        let inner = WzReader::unencrypted(
+            header.absolute_position,
+            version_checksum,
+            Cursor::new(data),
+        );
+        Reader::new(header, inner)
+    }
+
+    /// Package holding a single package called `name` at `offset`
+    fn parent(name: &str, offset: WzOffset) -> Package {
+        Package {
+            contents: vec![ContentRef::Package(Metadata::new(
+                String::from(name),
+                WzInt::from(0),
+                WzInt::from(0),
+                offset,
+            ))],
+        }
+    }
+
+    #[test]
+    fn negative_raw_image() {
+        let mut reader = archive(1, |offsets| {
+            vec![Package {
+                contents: vec![ContentRef::Image(Metadata::new(
+                    String::from("bad.img"),
+                    WzInt::from(-1),
+                    WzInt::from(0),
+                    offsets[0],
+                ))],
+            }]
+        });
+        assert!(matches!(
+            reader.raw_image("bad.img"),
+            Err(Error::Decode(DecodeError::Length(-1)))
+        ));
+    }
+
+    #[test]
+    fn signature_past_offsets() {
+        let mut reader = archive(1, |_| vec![Package { contents: vec![] }]);
+        reader.header.size = u32::MAX as u64;
+        let key = SigningKey::from_bytes(&[7; 32]).verifying_key();
+        assert!(matches!(
+            reader.verify_signature(&key),
+            Err(Error::Package(PackageError::Signature))
+        ));
+    }
+
+    #[test]
+    fn self_referencing_package() {
+        // The only package of the root points back at the root
+        let mut reader = archive(1, |offsets| vec![parent("Loop", offsets[0])]);
+        let root = WzOffset::from(WzHeader::new(83).absolute_position as u32 + 2);
+        assert!(matches!(
+            reader.map("Loop"),
+            Err(Error::Package(PackageError::SharedPackage(o))) if o == root
+        ));
+    }
+
+    #[test]
+    fn shared_packages() {
+        // Both packages of the root point at the same contents
+        let mut reader = archive(2, |offsets| {
+            vec![
+                Package {
+                    contents: ["A", "B"]
+                        .iter()
+                        .map(|name| {
+                            ContentRef::Package(Metadata::new(
+                                String::from(*name),
+                                WzInt::from(0),
+                                WzInt::from(0),
+                                offsets[1],
+                            ))
+                        })
+                        .collect(),
+                },
+                Package { contents: vec![] },
+            ]
+        });
+        assert!(matches!(
+            reader.map("Shared"),
+            Err(Error::Package(PackageError::SharedPackage(_)))
+        ));
+    }
+
+    #[test]
+    fn deep_packages() {
+        // Chains of packages, each holding the next one
+        let chain = |count: usize| {
+            archive(count, move |offsets| {
+                let mut packages = offsets[1..]
+                    .iter()
+                    .map(|offset| parent("P", *offset))
+                    .collect::<Vec<Package>>();
+                packages.push(Package { contents: vec![] });
+                packages
+            })
+        };
+        let map = chain(MAX_DEPTH + 1)
+            .map("Deep")
+            .expect("error mapping archive");
+        assert!(map.get(format!("Deep{}", "/P".repeat(MAX_DEPTH))).is_ok());
+        assert!(matches!(
+            chain(MAX_DEPTH + 2).map("Deep"),
+            Err(Error::Package(PackageError::Depth))
+        ));
+    }
+}
@@ -0,0 +1,146 @@
+//! Searching WZ archive values
+
+use crate::archive::reader::Node;
+use crate::error::{Error, Result};
+use crate::image;
+use crate::io::{WzImageReader, WzRead};
+use crate::map::Map;
+use crate::types::{Property, WzOffset};
+use rayon::prelude::*;
+
+/// Returns every image in `map` holding a property `predicate` matches, along with the paths of
+/// the matching properties. Paths start at the archive root. Images are scanned in parallel and
+/// mapped without their canvas data.
+///
+/// `open` is called once per worker thread and must return a reader over the archive `map` was
+/// mapped from, with the same absolute position and version checksum.
+///
+/// Example:
+///
+/// ```no_run
+/// use crypto::{KeyStream, GMS_IV, TRIMMED_KEY};
+/// use std::{fs::File, io::BufReader};
+/// use wz::{archive, io::{WzRead, WzReader}, types::Property};
+///
+/// let key = KeyStream::new(&TRIMMED_KEY, &GMS_IV);
+/// let mut reader = archive::Reader::open("String.wz", key.clone()).unwrap();
+/// let map = reader.map("String").unwrap();
+/// let inner = reader.into_inner();
+/// let (absolute_position, version_checksum) = (inner.absolute_position(), inner.version_checksum());
+/// let found = archive::find_image_containing(
+///     &map,
+///     || {
+///         let file = BufReader::new(File::open("String.wz")?);
+///         Ok(WzReader::new(absolute_position, version_checksum, file, key.clone()))
+///     },
+///     |property| match property {
+///         Property::String(s) => s.as_ref().contains("Henesys"),
+///         _ => false,
+///     },
+/// )
+/// .unwrap();
+/// ```
+pub fn find_image_containing<O, R, P>(
+    map: &Map<Node>,
+    open: O,
+    predicate: P,
+) -> Result<Vec<(String, Vec<String>)>>
+where
+    O: Fn() -> Result<R> + Sync,
+    R: WzRead,
+    P: Fn(&Property) -> bool + Sync,
+{
+    let mut images: Vec<(String, WzOffset, bool)> = Vec::new();
+    map.walk::<Error>(|cursor| {
+        if let Node::Image { offset, .. } = cursor.get() {
+            images.push((cursor.pwd(), *offset, cursor.get().is_empty_image()));
+        }
+        Ok(())
+    })?;
+
+    // Scan each image on its own reader
+    let found = images
+        .par_iter()
+        .map_init(
+            || None,
+            |reader, (path, offset, empty)| {
+                let image = match empty {
+                    true => Map::new(path.clone(), Property::ImgDir),
+                    false => {
+                        let reader = match reader {
+                            Some(reader) => reader,
+                            None => reader.insert(open()?),
+                        };
+                        let mut image_reader = WzImageReader::with_offset(reader, *offset);
+                        image_reader.seek_to_start()?;
+                        image::Reader::new(image_reader).map_lazy(path)?
+                    }
+                };
+                let paths = image.find_value(&predicate);
+                Ok((!paths.is_empty()).then(|| (path.clone(), paths)))
+            },
+        )
+        .collect::<Result<Vec<Option<(String, Vec<String>)>>>>()?;
+    Ok(found.into_iter().flatten().collect())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::archive::{self, reader::Node};
+    use crate::error::Error;
+    use crate::io::{WzRead, WzReader};
+    use crate::types::Property;
+    use crypto::{KeyStream, GMS_IV, TRIMMED_KEY};
+    use std::{fs::File, io::BufReader};
+
+    #[test]
+    fn find_images() {
+        let key = || KeyStream::new(&TRIMMED_KEY, &GMS_IV);
+        let mut reader =
+            archive::Reader::open("testdata/v83-base.wz", key()).expect("error opening archive");
+        let map = reader.map("Base").expect("error mapping archive");
+        let mut expected = Vec::new();
+        let mut images = Vec::new();
+        map.walk::<Error>(|cursor| {
+            if let Node::Image { .. } = cursor.get() {
+                images.push(cursor.pwd());
+            }
+            Ok(())
+        })
+        .expect("error walking");
+        for path in &images {
+            let image = reader
+                .map_image(&map, path, |_| key())
+                .expect("error mapping image");
+            let paths = image.find_value(|property| matches!(property, Property::String(_)));
+            if !paths.is_empty() {
+                let prefix = &path[..path.len() - image.name().len()];
+                let paths = paths.iter().map(|p| format!("{}{}", prefix, p)).collect();
+                expected.push((path.clone(), paths));
+            }
+        }
+        assert!(!expected.is_empty());
+
+        let inner = reader.into_inner();
+        let (absolute_position, version_checksum) =
+            (inner.absolute_position(), inner.version_checksum());
+        let open = || {
+            let file = BufReader::new(File::open("testdata/v83-base.wz")?);
+            Ok(WzReader::new(
+                absolute_position,
+                version_checksum,
+                file,
+                key(),
+            ))
+        };
+        let found = archive::find_image_containing(&map, open, |property| {
+            matches!(property, Property::String(_))
+        })
+        .expect("error searching archive");
+        assert_eq!(found, expected);
+        assert!(archive::find_image_containing(&map, open, |_| false)
+            .expect("error searching archive")
+            .is_empty());
+    }
+}
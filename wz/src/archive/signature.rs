@@ -0,0 +1,107 @@
+//! WZ Archive signatures
+
+use crate::archive::manifest::{Entry, Manifest};
+use ed25519_dalek::{Signature, Signer};
+
+pub use ed25519_dalek::{SigningKey, VerifyingKey};
+
+/// Marks the signature appended after the end of a signed archive
+pub(crate) const MAGIC: &[u8; 4] = b"WZSG";
+
+/// Length of the signature trailer
+pub(crate) const TRAILER_LENGTH: usize = 4 + Signature::BYTE_SIZE;
+
+/// Name given to the root package in the signed manifest, so renaming the archive keeps the
+/// signature valid
+pub(crate) const ROOT: &str = "archive";
+
+/// Returns the signed text: the manifest lines of every image, sorted by path
+pub(crate) fn message(mut entries: Vec<Entry>) -> Vec<u8> {
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    Manifest::from_entries(entries).to_text().into_bytes()
+}
+
+/// Signs the image `entries` and returns the trailer to append to the archive
+pub(crate) fn trailer(entries: Vec<Entry>, key: &SigningKey) -> Vec<u8> {
+    let signature = key.sign(&message(entries));
+    [&MAGIC[..], &signature.to_bytes()].concat()
+}
+
+/// Checks the signature in `trailer` against the image `entries`
+pub(crate) fn verify(trailer: &[u8], entries: Vec<Entry>, key: &VerifyingKey) -> bool {
+    if trailer.len() != TRAILER_LENGTH || !trailer.starts_with(MAGIC) {
+        return false;
+    }
+    let signature = Signature::from_slice(&trailer[MAGIC.len()..]).expect("length was checked");
+    key.verify_strict(&message(entries), &signature).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::archive::patch::PatchImage;
+    use crate::archive::signature::SigningKey;
+    use crate::archive::{reader::Node, Reader, Writer};
+    use crate::error::Error;
+    use crate::io::WzRead;
+    use crate::types::WzHeader;
+    use crypto::{KeyStream, GMS_IV, TRIMMED_KEY};
+    use std::{env, fs};
+
+    #[test]
+    fn signed_archive() {
+        let key = || KeyStream::new(&TRIMMED_KEY, &GMS_IV);
+        let mut reader =
+            Reader::open("testdata/v83-base.wz", key()).expect("error opening archive");
+        let map = reader.map("Base").expect("error mapping archive");
+        let mut inner = reader.into_inner();
+        let mut images = Vec::new();
+        map.walk::<Error>(|cursor| {
+            if let Node::Image { offset, size, .. } = cursor.get() {
+                let mut data = Vec::new();
+                inner.copy_to(&mut data, *offset, *size)?;
+                images.push((cursor.pwd(), data));
+            }
+            Ok(())
+        })
+        .expect("error reading images");
+        let mut writer = Writer::new("Base");
+        for (path, data) in &images {
+            writer
+                .add_image(path, PatchImage::Data(data))
+                .expect("error adding image");
+        }
+
+        let dir = env::temp_dir().join("wz-signature-test");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("error creating directory");
+        let path = dir.join("Base.wz");
+        let signing_key = SigningKey::from_bytes(&[7; 32]);
+        writer
+            .save_signed(&path, 83, WzHeader::new(83), key(), &signing_key)
+            .expect("error saving archive");
+        assert!(!dir.join("Base.wz.part").exists());
+
+        let mut reader = Reader::open(&path, key()).expect("error opening signed archive");
+        reader
+            .verify_signature(&signing_key.verifying_key())
+            .expect("error verifying signature");
+        let other_key = SigningKey::from_bytes(&[8; 32]).verifying_key();
+        assert!(reader.verify_signature(&other_key).is_err());
+        assert!(Reader::open("testdata/v83-base.wz", key())
+            .expect("error opening archive")
+            .verify_signature(&signing_key.verifying_key())
+            .is_err());
+
+        // Modify a byte of the last image
+        let mut data = fs::read(&path).expect("error reading archive");
+        let last = data.len() - 69;
+        data[last] ^= 0xff;
+        fs::write(&path, data).expect("error writing archive");
+        let mut reader = Reader::open(&path, key()).expect("error opening modified archive");
+        assert!(reader
+            .verify_signature(&signing_key.verifying_key())
+            .is_err());
+        fs::remove_dir_all(&dir).expect("error removing directory");
+    }
+}
@@ -0,0 +1,271 @@
+//! WZ Archive statistics
+
+use crate::archive::reader::Node;
+use crate::map::Map;
+use std::collections::HashMap;
+use std::convert::Infallible;
+
+/// Upper bounds of the size distribution buckets: 1 KiB, 10 KiB, 100 KiB, 1 MiB, 10 MiB
+const BUCKETS: [u64; 5] = [1 << 10, 10 << 10, 100 << 10, 1 << 20, 10 << 20];
+
+/// Number of images within a size range
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SizeBucket {
+    /// Exclusive upper bound of the bucket. `None` for the last bucket.
+    pub max: Option<u64>,
+
+    /// Number of images in the bucket
+    pub count: usize,
+
+    /// Combined size of the images in the bucket
+    pub total: u64,
+}
+
+/// Image entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageStat {
+    /// Full path of the image
+    pub path: String,
+
+    /// Size of the image
+    pub size: u64,
+
+    /// Checksum of the image
+    pub checksum: i32,
+}
+
+/// Top-level package entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageStat {
+    /// Name of the package. Images at the root are counted on their own.
+    pub name: String,
+
+    /// Number of images within the package
+    pub images: usize,
+
+    /// Combined size of the images within the package
+    pub size: u64,
+}
+
+/// Archive statistics
+///
+/// Example:
+///
+/// ```no_run
+/// use crypto::{KeyStream, GMS_IV, TRIMMED_KEY};
+/// use wz::archive::{Reader, Stats};
+///
+/// let mut archive = Reader::open("Character.wz", KeyStream::new(&TRIMMED_KEY, &GMS_IV)).unwrap();
+/// let map = archive.map("Character.wz").unwrap();
+/// let stats = Stats::compute(&map, 10);
+/// println!("{} images", stats.images);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stats {
+    /// Number of images, not counting the invalid ones
+    pub images: usize,
+
+    /// Number of packages, not counting the root
+    pub packages: usize,
+
+    /// Combined size of all images
+    pub total_size: u64,
+
+    /// Image size distribution, smallest bucket first
+    pub distribution: Vec<SizeBucket>,
+
+    /// Largest images, largest first
+    pub largest: Vec<ImageStat>,
+
+    /// Groups of images sharing the same checksum and size, most wasted space first
+    pub duplicates: Vec<Vec<ImageStat>>,
+
+    /// Sizes of the root's children, largest first
+    pub top_level: Vec<PackageStat>,
+
+    /// Paths of the images with a negative size. They are not counted in any
+    /// other statistic.
+    pub invalid: Vec<String>,
+}
+
+impl Stats {
+    /// Computes the statistics of a mapped archive, keeping the `largest` biggest images
+    pub fn compute(map: &Map<Node>, largest: usize) -> Self {
+        let mut packages = 0;
+        let mut images = Vec::new();
+        let mut invalid = Vec::new();
+        let _ = map.walk::<Infallible>(|cursor| {
+            match cursor.get() {
+                Node::Package => packages += 1,
+                Node::Image { size, checksum, .. } => match u64::try_from(**size) {
+                    Ok(size) => images.push(ImageStat {
+                        path: cursor.pwd(),
+                        size,
+                        checksum: **checksum,
+                    }),
+                    Err(_) => invalid.push(cursor.pwd()),
+                },
+            }
+            Ok(())
+        });
+
+        // Size distribution
+        let mut distribution = BUCKETS
+            .iter()
+            .map(|max| Some(*max))
+            .chain([None])
+            .map(|max| SizeBucket {
+                max,
+                count: 0,
+                total: 0,
+            })
+            .collect::<Vec<SizeBucket>>();
+        for image in &images {
+            let bucket = distribution
+                .iter_mut()
+                .find(|b| b.max.map(|max| image.size < max).unwrap_or(true))
+                .expect("last bucket is unbounded");
+            bucket.count += 1;
+            bucket.total = bucket.total.saturating_add(image.size);
+        }
+
+        // Top-level packages
+        let root = map.cursor();
+        let mut top_level = root
+            .list()
+            .map(|name| {
+                (
+                    name,
+                    PackageStat {
+                        name: String::from(name),
+                        images: 0,
+                        size: 0,
+                    },
+                )
+            })
+            .collect::<HashMap<&str, PackageStat>>();
+        for image in &images {
+            let name = image.path.split('/').nth(1).unwrap_or(&image.path);
+            let entry = top_level.entry(name).or_insert_with(|| PackageStat {
+                name: String::from(name),
+                images: 0,
+                size: 0,
+            });
+            entry.images += 1;
+            entry.size = entry.size.saturating_add(image.size);
+        }
+        let mut top_level = top_level.into_values().collect::<Vec<PackageStat>>();
+        top_level.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.name.cmp(&b.name)));
+
+        // Duplicates
+        let mut groups = HashMap::<(i32, u64), Vec<ImageStat>>::new();
+        for image in &images {
+            groups
+                .entry((image.checksum, image.size))
+                .or_default()
+                .push(image.clone());
+        }
+        let mut duplicates = groups
+            .into_values()
+            .filter(|group| group.len() > 1)
+            .collect::<Vec<Vec<ImageStat>>>();
+        duplicates.sort_by(|a, b| {
+            let wasted = |g: &Vec<ImageStat>| g[0].size.saturating_mul(g.len() as u64 - 1);
+            wasted(b)
+                .cmp(&wasted(a))
+                .then_with(|| a[0].path.cmp(&b[0].path))
+        });
+
+        // Largest images
+        let total_size = images
+            .iter()
+            .fold(0u64, |total, image| total.saturating_add(image.size));
+        let num_images = images.len();
+        images.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.path.cmp(&b.path)));
+        images.truncate(largest);
+
+        Self {
+            images: num_images,
+            packages: packages - 1,
+            total_size,
+            distribution,
+            largest: images,
+            duplicates,
+            top_level,
+            invalid,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::archive::{reader::Node, Reader, Stats};
+    use crate::map::Map;
+    use crate::types::{WzInt, WzOffset};
+    use crypto::{KeyStream, GMS_IV, TRIMMED_KEY};
+
+    #[test]
+    fn v83_stats() {
+        let mut archive = Reader::open(
+            "testdata/v83-base.wz",
+            KeyStream::new(&TRIMMED_KEY, &GMS_IV),
+        )
+        .expect("error opening archive");
+        let map = archive.map("Base.wz").expect("error mapping archive");
+        let stats = Stats::compute(&map, 3);
+        let mut images = 0;
+        map.walk::<()>(|cursor| {
+            if let Node::Image { .. } = cursor.get() {
+                images += 1;
+            }
+            Ok(())
+        })
+        .expect("walk should not fail");
+        assert_eq!(stats.images, images);
+        assert!(stats.largest.len() <= 3);
+        assert!(stats
+            .largest
+            .windows(2)
+            .all(|pair| pair[0].size >= pair[1].size));
+        assert_eq!(
+            stats.distribution.iter().map(|b| b.count).sum::<usize>(),
+            stats.images
+        );
+        assert_eq!(
+            stats.top_level.iter().map(|p| p.size).sum::<u64>(),
+            stats.total_size
+        );
+    }
+
+    #[test]
+    fn invalid_sizes() {
+        let image = |size: i32| Node::Image {
+            offset: WzOffset::from(0u32),
+            size: WzInt::from(size),
+            checksum: WzInt::from(1),
+        };
+        let mut map = Map::new(String::from("Test.wz"), Node::Package);
+        map.cursor_mut()
+            .create(String::from("a.img"), image(i32::MAX))
+            .expect("error creating image")
+            .create(String::from("b.img"), image(i32::MAX))
+            .expect("error creating image")
+            .create(String::from("c.img"), image(i32::MAX))
+            .expect("error creating image")
+            .create(String::from("bad.img"), image(-1))
+            .expect("error creating image");
+        let stats = Stats::compute(&map, 10);
+        assert_eq!(stats.images, 3);
+        assert_eq!(stats.invalid, vec![String::from("Test.wz/bad.img")]);
+        assert_eq!(stats.total_size, 3 * i32::MAX as u64);
+        assert!(stats
+            .largest
+            .iter()
+            .all(|image| image.size == i32::MAX as u64));
+        assert_eq!(stats.duplicates.len(), 1);
+        assert_eq!(stats.duplicates[0].len(), 3);
+        let bad = stats.top_level.iter().find(|p| p.name == "bad.img");
+        assert_eq!(bad.map(|p| p.images), Some(0));
+    }
+}
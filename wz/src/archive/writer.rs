@@ -1,15 +1,26 @@
 //! WZ Archive Writer
 
-use crate::error::{PackageError, Result};
-use crate::io::{DummyEncryptor, Encode, SizeHint, WzWriter};
-use crate::map::{Cursor, CursorMut, Map};
+use crate::archive::{
+    manifest, patch::PatchImage, reader::MAX_DEPTH, signature, signature::SigningKey,
+};
+use crate::error::{DecodeError, Error, PackageError, Result};
+use crate::image;
+use crate::io::{
+    checksum_reader, DummyEncryptor, Encode, SizeHint, WzImageWriter, WzWrite, WzWriter,
+};
+use crate::map::{Bookmark, Cursor, CursorMut, Map};
 use crate::types::raw::package::{ContentRef, Metadata};
-use crate::types::{WzHeader, WzInt, WzOffset};
-use crypto::{checksum, Encryptor};
-use std::fs::File;
-use std::io::{self, BufWriter, Seek, Write};
+use crate::types::{Property, WzHeader, WzInt, WzOffset};
+use crypto::Encryptor;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
 use std::num::Wrapping;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
 
 /// Trait for representing Images
 pub trait ImageRef {
@@ -24,6 +35,273 @@ pub trait ImageRef {
     where
         W: Write + Seek,
         E: Encryptor;
+
+    /// Does the expensive part of serializing the Image ahead of time. Called from worker threads
+    /// by [`Writer::prepare`]. Does nothing by default.
+    fn prepare(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// [`ImageRef`] over a parsed image.
+///
+/// The image is serialized into memory by [`ImageRef::prepare`]. Unprepared images are serialized
+/// every time they are needed, so call [`Writer::prepare`] before saving.
+#[derive(Debug)]
+pub struct EncodedImage<E>
+where
+    E: Encryptor + Clone,
+{
+    image: image::Writer,
+    encryptor: E,
+    encoded: Option<(Vec<u8>, WzInt)>,
+}
+
+impl<E> EncodedImage<E>
+where
+    E: Encryptor + Clone,
+{
+    /// Creates an image encrypted with `encryptor`. The encryptor must match the archive's.
+    pub fn new(map: Map<Property>, encryptor: E) -> Self {
+        Self {
+            image: image::Writer::from_map(map),
+            encryptor,
+            encoded: None,
+        }
+    }
+
+    /// Serializes the image and returns the data and its checksum
+    fn encode(&self) -> Result<(Vec<u8>, WzInt)> {
+        let mut inner = WzWriter::new(0, 0, io::Cursor::new(Vec::new()), self.encryptor.clone());
+        self.image.write_to(&mut WzImageWriter::new(&mut inner))?;
+        let data = inner.into_inner().into_inner();
+        let (_, checksum) = checksum_reader(data.as_slice())?;
+        Ok((data, checksum))
+    }
+
+    fn encoded(&self) -> Result<Cow<'_, (Vec<u8>, WzInt)>> {
+        match &self.encoded {
+            Some(encoded) => Ok(Cow::Borrowed(encoded)),
+            None => Ok(Cow::Owned(self.encode()?)),
+        }
+    }
+}
+
+impl<E> ImageRef for EncodedImage<E>
+where
+    E: Encryptor + Clone,
+{
+    fn size(&self) -> Result<WzInt> {
+        Ok(WzInt::from(self.encoded()?.0.len() as i32))
+    }
+
+    fn checksum(&self) -> Result<WzInt> {
+        Ok(self.encoded()?.1)
+    }
+
+    fn write<W, F>(&self, writer: &mut WzWriter<W, F>) -> Result<()>
+    where
+        W: Write + Seek,
+        F: Encryptor,
+    {
+        writer.write_all(&self.encoded()?.0)
+    }
+
+    fn prepare(&mut self) -> Result<()> {
+        if self.encoded.is_none() {
+            self.encoded = Some(self.encode()?);
+        }
+        Ok(())
+    }
+}
+
+/// [`ImageRef`] over an image file, such as the ones extracted from an archive.
+///
+/// The size is read when the image is created, which also catches unreadable files before the
+/// archive is written. The checksum is computed by [`ImageRef::prepare`] or the first time it is
+/// needed, then kept. Computing the checksum or writing the image fails with
+/// [`PackageError::ImageChanged`] if the file no longer has the size it was created with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileImage {
+    path: PathBuf,
+    size: WzInt,
+    checksum: OnceLock<WzInt>,
+}
+
+impl FileImage {
+    /// Creates an image backed by the file at `path`
+    pub fn new<S>(path: S) -> Result<Self>
+    where
+        S: AsRef<Path>,
+    {
+        let path = path.as_ref().to_path_buf();
+        let size = i32::try_from(File::open(&path)?.metadata()?.len())
+            .map_err(|_| PackageError::ArchiveTooLarge)?;
+        Ok(Self {
+            path,
+            size: WzInt::from(size),
+            checksum: OnceLock::new(),
+        })
+    }
+
+    /// Returns the path of the image file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Opens the file, making sure it still has the size the image was created with
+    fn open(&self) -> Result<File> {
+        let file = File::open(&self.path)?;
+        match file.metadata()?.len() == *self.size as u64 {
+            true => Ok(file),
+            false => {
+                Err(PackageError::ImageChanged(self.path.to_string_lossy().into_owned()).into())
+            }
+        }
+    }
+}
+
+impl ImageRef for FileImage {
+    fn size(&self) -> Result<WzInt> {
+        Ok(self.size)
+    }
+
+    fn checksum(&self) -> Result<WzInt> {
+        match self.checksum.get() {
+            Some(checksum) => Ok(*checksum),
+            None => {
+                let checksum = checksum_reader(self.open()?)?.1;
+                Ok(*self.checksum.get_or_init(|| checksum))
+            }
+        }
+    }
+
+    fn write<W, E>(&self, writer: &mut WzWriter<W, E>) -> Result<()>
+    where
+        W: Write + Seek,
+        E: Encryptor,
+    {
+        let mut src = BufReader::new(self.open()?);
+        writer.copy_from(&mut src, self.size)
+    }
+
+    fn prepare(&mut self) -> Result<()> {
+        self.checksum().map(|_| ())
+    }
+}
+
+/// [`ImageRef`] over images of different types, so they can be mixed in a single [`Writer`].
+///
+/// [`ImageRef`] cannot be made into a trait object, so every kind of image is a variant instead.
+///
+/// Example:
+///
+/// ```no_run
+/// use crypto::{KeyStream, GMS_IV, TRIMMED_KEY};
+/// use wz::archive::{patch::PatchImage, writer::{AnyImage, EncodedImage}, Writer};
+/// use wz::image::Reader;
+/// use wz::types::{WzHeader, WzInt, WzOffset};
+///
+/// let key = KeyStream::new(&TRIMMED_KEY, &GMS_IV);
+/// let map = Reader::open("0100100.img", key.clone()).unwrap().map("0100100.img").unwrap();
+/// let mut writer = Writer::new("Mob");
+/// writer
+///     .add_image("Mob/0100100.img", EncodedImage::new(map, key.clone()).into())
+///     .unwrap();
+/// writer
+///     .add_image(
+///         "Mob/0100101.img",
+///         AnyImage::from(PatchImage::Copy {
+///             path: "Mob.wz".into(),
+///             offset: WzOffset::from(0x3Cu32),
+///             size: WzInt::from(1234),
+///             checksum: WzInt::from(5678),
+///         }),
+///     )
+///     .unwrap();
+/// writer.save("Mob.new.wz", 83, WzHeader::new(83), key).unwrap();
+/// ```
+#[derive(Debug)]
+pub enum AnyImage<'a, E>
+where
+    E: Encryptor + Clone,
+{
+    /// Parsed image
+    Encoded(EncodedImage<E>),
+
+    /// Image copied from another archive or held in memory as it is stored
+    Patch(PatchImage<'a>),
+
+    /// Image file
+    File(FileImage),
+}
+
+impl<E> From<EncodedImage<E>> for AnyImage<'_, E>
+where
+    E: Encryptor + Clone,
+{
+    fn from(other: EncodedImage<E>) -> Self {
+        Self::Encoded(other)
+    }
+}
+
+impl<'a, E> From<PatchImage<'a>> for AnyImage<'a, E>
+where
+    E: Encryptor + Clone,
+{
+    fn from(other: PatchImage<'a>) -> Self {
+        Self::Patch(other)
+    }
+}
+
+impl<E> From<FileImage> for AnyImage<'_, E>
+where
+    E: Encryptor + Clone,
+{
+    fn from(other: FileImage) -> Self {
+        Self::File(other)
+    }
+}
+
+impl<E> ImageRef for AnyImage<'_, E>
+where
+    E: Encryptor + Clone,
+{
+    fn size(&self) -> Result<WzInt> {
+        match self {
+            Self::Encoded(image) => image.size(),
+            Self::Patch(image) => image.size(),
+            Self::File(image) => image.size(),
+        }
+    }
+
+    fn checksum(&self) -> Result<WzInt> {
+        match self {
+            Self::Encoded(image) => image.checksum(),
+            Self::Patch(image) => image.checksum(),
+            Self::File(image) => image.checksum(),
+        }
+    }
+
+    fn write<W, F>(&self, writer: &mut WzWriter<W, F>) -> Result<()>
+    where
+        W: Write + Seek,
+        F: Encryptor,
+    {
+        match self {
+            Self::Encoded(image) => image.write(writer),
+            Self::Patch(image) => image.write(writer),
+            Self::File(image) => image.write(writer),
+        }
+    }
+
+    fn prepare(&mut self) -> Result<()> {
+        match self {
+            Self::Encoded(image) => image.prepare(),
+            Self::Patch(image) => image.prepare(),
+            Self::File(image) => image.prepare(),
+        }
+    }
 }
 
 /// Map node representing the contents of the WZ archive
@@ -54,6 +332,9 @@ where
     I: ImageRef,
 {
     map: Map<Node<I>>,
+
+    /// Images found by [`Writer::deduplicate`], pointing to the image whose data they share
+    shared: HashMap<Bookmark, Bookmark>,
 }
 
 impl<I> Writer<I>
@@ -73,6 +354,7 @@ where
                     offset: WzOffset::from(0),
                 },
             ),
+            shared: HashMap::new(),
         }
     }
 
@@ -81,10 +363,78 @@ where
         &self.map
     }
 
+    /// Returns the package or image at `path`. Errors when nothing was added there.
+    pub fn get<S>(&self, path: S) -> Result<&Node<I>>
+    where
+        S: AsRef<Path>,
+    {
+        Ok(self.map.get(path)?)
+    }
+
+    /// Returns the names of the contents of the package at `path`, in the order they are written.
+    /// Errors when there is no package at `path`.
+    pub fn children<S>(&self, path: S) -> Result<Vec<String>>
+    where
+        S: AsRef<Path>,
+    {
+        let cursor = self.map.cursor_at(path.as_ref())?;
+        match cursor.get() {
+            Node::Package { .. } => Ok(cursor.list().map(String::from).collect()),
+            Node::Image { .. } => {
+                Err(PackageError::Path(path.as_ref().to_string_lossy().into()).into())
+            }
+        }
+    }
+
+    /// Removes the package or image at `path` along with everything in it. Images that shared the
+    /// data of a removed image, see [`Writer::deduplicate`], are written with their own again.
+    ///
+    /// Errors when nothing was added at `path` or when `path` is the root package.
+    pub fn remove<S>(&mut self, path: S) -> Result<()>
+    where
+        S: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let parent = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .ok_or(PackageError::Path(path.to_string_lossy().into()))?;
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or(PackageError::Path(path.to_string_lossy().into()))?;
+        self.map.cursor_mut_at(parent)?.delete(name)?;
+        let map = &self.map;
+        self.shared.retain(|copy, original| {
+            map.cursor_at_bookmark(*copy).is_ok() && map.cursor_at_bookmark(*original).is_ok()
+        });
+        Ok(())
+    }
+
+    /// Returns the number of images in the builder
+    pub fn len(&self) -> usize {
+        let mut len = 0;
+        self.map
+            .walk::<()>(|cursor| {
+                if let Node::Image { .. } = cursor.get() {
+                    len += 1;
+                }
+                Ok(())
+            })
+            .expect("counting images should not fail");
+        len
+    }
+
+    /// Returns `true` when no image was added
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     /// Adds a package to the builder. A package is essentially a directory but WZ calls it a
     /// package. When it and its contents are serialized, it is treated as a binary blob.
     ///
-    /// Errors when `path` does not start with the root package name.
+    /// Errors when `path` does not start with the root package name or nests packages deeper than
+    /// an archive can be read back.
     pub fn add_package<S>(&mut self, path: S) -> Result<()>
     where
         S: AsRef<Path>,
@@ -122,34 +472,265 @@ where
         Ok(())
     }
 
+    /// Runs [`ImageRef::prepare`] for every image on up to `threads` worker threads. Saving
+    /// afterwards only has to write the prepared images in order.
+    pub fn prepare(&mut self, threads: usize) -> Result<()>
+    where
+        I: Send,
+    {
+        let images = self
+            .map
+            .values_mut()
+            .filter_map(|node| match node {
+                Node::Image { image, .. } => Some(image),
+                Node::Package { .. } => None,
+            })
+            .collect::<Vec<&mut I>>();
+        let threads = threads.clamp(1, images.len().max(1));
+        let queue = Mutex::new(images.into_iter());
+        thread::scope(|scope| {
+            let workers = (0..threads)
+                .map(|_| {
+                    scope.spawn(|| loop {
+                        let next = queue.lock().expect("queue lock poisoned").next();
+                        match next {
+                            Some(image) => image.prepare()?,
+                            None => return Ok(()),
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+            workers
+                .into_iter()
+                .try_for_each(|worker| worker.join().expect("image preparation panicked"))
+        })
+    }
+
+    /// Stores images with identical data only once. The entries of the later copies point to the
+    /// data of the first one, which the client does not mind since images are never written to.
+    /// Call it after every image is added and prepared. Returns the path of every image that
+    /// shares data along with the path of the image it shares it with.
+    pub fn deduplicate(&mut self) -> Result<Vec<(String, String)>> {
+        let mut candidates = HashMap::<(i32, i32), Vec<(Bookmark, String)>>::new();
+        self.map.walk::<Error>(|cursor| {
+            if let Node::Image { image, .. } = cursor.get() {
+                candidates
+                    .entry((*image.size()?, *image.checksum()?))
+                    .or_default()
+                    .push((cursor.bookmark(), cursor.pwd()));
+            }
+            Ok(())
+        })?;
+
+        // Only images with the same size and checksum need to be compared byte for byte
+        self.shared.clear();
+        let mut shared = Vec::new();
+        for group in candidates.into_values().filter(|group| group.len() > 1) {
+            let mut originals = HashMap::<[u8; 32], (Bookmark, String)>::new();
+            for (bookmark, path) in group {
+                let digest = match self.map.cursor_at_bookmark(bookmark)?.get() {
+                    Node::Image { image, .. } => digest(image)?,
+                    Node::Package { .. } => continue,
+                };
+                match originals.get(&digest) {
+                    Some((original, original_path)) => {
+                        self.shared.insert(bookmark, *original);
+                        shared.push((path, original_path.clone()));
+                    }
+                    None => {
+                        originals.insert(digest, (bookmark, path));
+                    }
+                }
+            }
+        }
+        shared.sort();
+        Ok(shared)
+    }
+
     /// Generates the WZ archive and writes it to disk.
     ///
     /// The version must match the [`WzHeader`] and should match the added imges. If the image versions do
     /// not match the version provided here, decoding offsets contained in the images may not align
     /// properly.
     ///
+    /// The archive is written to `<path>.part` and renamed to `path` once complete, so a failed
+    /// write never leaves a truncated archive behind.
+    ///
     /// Errors when the provided version does not match the header's version hash. Or if any IO
     /// error occurs.
     pub fn save<S, E>(
         &mut self,
         path: S,
         version: u16,
-        mut header: WzHeader,
+        header: WzHeader,
+        encryptor: E,
+    ) -> Result<()>
+    where
+        S: AsRef<Path>,
+        E: Encryptor,
+    {
+        self.save_to(path.as_ref(), version, header, encryptor, false, None)
+    }
+
+    /// Same as [`Writer::save`] but an interrupted save can be continued.
+    ///
+    /// The end offset of every written image is recorded in `<path>.journal` once the image is
+    /// synced to disk. Calling this again with the same contents after a crash reuses
+    /// `<path>.part` and skips the images already written. The journal is ignored when the
+    /// contents changed and removed by [`Writer::save`] and [`Writer::save_signed`].
+    pub fn save_resumable<S, E>(
+        &mut self,
+        path: S,
+        version: u16,
+        header: WzHeader,
+        encryptor: E,
+    ) -> Result<()>
+    where
+        S: AsRef<Path>,
+        E: Encryptor,
+    {
+        self.save_to(path.as_ref(), version, header, encryptor, true, None)
+    }
+
+    /// Same as [`Writer::save`] but appends an Ed25519 signature over the SHA-256 of every image.
+    /// The signature is checked with [`Reader::verify_signature`](crate::archive::Reader::verify_signature).
+    pub fn save_signed<S, E>(
+        &mut self,
+        path: S,
+        version: u16,
+        header: WzHeader,
         encryptor: E,
+        key: &SigningKey,
     ) -> Result<()>
     where
         S: AsRef<Path>,
         E: Encryptor,
     {
-        // If file fails, no point in wasting time on the rest so do this first
-        let mut file = BufWriter::new(File::create(path)?);
+        self.save_to(path.as_ref(), version, header, encryptor, false, Some(key))
+    }
+
+    /// Same as [`Writer::save`] but returns the archive instead of writing it to a file. Useful
+    /// when the archive is sent elsewhere, e.g. over the network.
+    pub fn save_to_vec<E>(
+        &mut self,
+        version: u16,
+        mut header: WzHeader,
+        encryptor: E,
+    ) -> Result<Vec<u8>>
+    where
+        E: Encryptor,
+    {
+        let (version_checksum, _) = self.prepare_header(version, &mut header)?;
+        let mut bytes = io::Cursor::new(Vec::new());
+        let mut writer = WzWriter::new(
+            header.absolute_position,
+            version_checksum,
+            &mut bytes,
+            encryptor,
+        );
+        header.encode(&mut writer)?;
+        recursive_save(
+            &mut self.map.cursor(),
+            &mut writer,
+            &mut Progress::in_memory(),
+            &self.shared,
+        )?;
+        Ok(bytes.into_inner())
+    }
+
+    // *** PRIVATES *** //
+
+    fn save_to<E>(
+        &mut self,
+        path: &Path,
+        version: u16,
+        mut header: WzHeader,
+        encryptor: E,
+        resumable: bool,
+        key: Option<&SigningKey>,
+    ) -> Result<()>
+    where
+        E: Encryptor,
+    {
+        let part_path = with_suffix(path, ".part");
+        let journal_path = with_suffix(path, ".journal");
 
         let absolute_position = header.absolute_position;
-        let (version_hash, version_checksum) = checksum(&version.to_string());
-        if version_hash != header.version_hash {
-            return Err(PackageError::Checksum.into());
+        let (version_checksum, root_checksum) = self.prepare_header(version, &mut header)?;
+        let stamp = [
+            &header.size.to_le_bytes()[..],
+            &version_checksum.to_le_bytes(),
+            &root_checksum.to_le_bytes(),
+        ]
+        .concat();
+        let mut progress = if resumable {
+            Progress::resume(&part_path, &journal_path, &stamp)?
+        } else {
+            // A journal left by an interrupted resumable save no longer describes the part file
+            match fs::remove_file(&journal_path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+            Progress::new(&part_path)?
+        };
+
+        let mut file = BufWriter::new(progress.file.take().expect("file should be open"));
+        let mut writer = WzWriter::new(absolute_position, version_checksum, &mut file, encryptor);
+        header.encode(&mut writer)?;
+        recursive_save(
+            &mut self.map.cursor(),
+            &mut writer,
+            &mut progress,
+            &self.shared,
+        )?;
+        let mut file = file.into_inner().map_err(|e| e.into_error())?;
+
+        // The trailer is appended before the rename so the archive is never found unsigned
+        if let Some(key) = key {
+            let trailer = self.signature_trailer(&part_path, key)?;
+            file.seek(SeekFrom::End(0))?;
+            file.write_all(&trailer)?;
         }
-        self.calculate_metadata(absolute_position, version_checksum)?;
+        file.sync_all()?;
+
+        fs::rename(&part_path, path)?;
+        if resumable {
+            fs::remove_file(&journal_path)?;
+        }
+        Ok(())
+    }
+
+    /// Signs the SHA-256 of every image as written to the file at `path`
+    fn signature_trailer(&self, path: &Path, key: &SigningKey) -> Result<Vec<u8>> {
+        let mut file = BufReader::new(File::open(path)?);
+        let root_length = self.map.cursor().name().len();
+        let mut entries = Vec::new();
+        self.map.walk::<Error>(|cursor| {
+            if let Node::Image { image, offset } = cursor.get() {
+                let size = image.size()?;
+                let mut hasher = Sha256::new();
+                file.seek(SeekFrom::Start(**offset as u64))?;
+                io::copy(&mut (&mut file).take(*size as u64), &mut hasher)?;
+                entries.push(manifest::Entry {
+                    path: format!("{}{}", signature::ROOT, &cursor.pwd()[root_length..]),
+                    size: *size as u64,
+                    sha256: hasher.finalize().into(),
+                });
+            }
+            Ok(())
+        })?;
+        Ok(signature::trailer(entries, key))
+    }
+
+    /// Calculates the metadata and the header size. Returns the version checksum and the checksum
+    /// of the root package.
+    fn prepare_header(&mut self, version: u16, header: &mut WzHeader) -> Result<(u32, i32)> {
+        let version_checksum = header
+            .version_hash
+            .ok_or(PackageError::Unversioned)?
+            .checksum(version)
+            .ok_or(PackageError::Checksum)?;
+        self.calculate_metadata(header.absolute_position, version_checksum)?;
 
         // Modify the header sizes
         let cursor = self.map.cursor();
@@ -161,13 +742,13 @@ where
             _ => panic!("should never get here"),
         };
 
-        let mut writer = WzWriter::new(absolute_position, version_checksum, &mut file, encryptor);
-        header.encode(&mut writer)?;
-        recursive_save(&mut self.map.cursor(), &mut writer)
+        let root_checksum = match cursor.get() {
+            Node::Package { ref checksum, .. } => **checksum,
+            _ => panic!("should never get here"),
+        };
+        Ok((version_checksum, root_checksum))
     }
 
-    // *** PRIVATES *** //
-
     fn make_package_path<S>(&mut self, path: S) -> Result<CursorMut<Node<I>>>
     where
         S: AsRef<Path>,
@@ -177,6 +758,10 @@ where
             .as_ref()
             .strip_prefix(cursor.name())
             .map_err(|_| PackageError::MultipleRoots)?;
+        // Also bounds the recursion when the contents are calculated and written
+        if path.iter().count() > MAX_DEPTH {
+            return Err(PackageError::Depth.into());
+        }
         for part in path.iter() {
             let name = part
                 .to_str()
@@ -201,15 +786,139 @@ where
             absolute_position,
             version_checksum,
             &mut self.map.cursor_mut(),
+            &self.shared,
         )?;
         recursive_calculate_offset(
-            WzOffset::from(absolute_position as u32 + 2),
+            checked_offset(WzOffset::from(absolute_position), 2)?,
             &mut self.map.cursor_mut(),
+            &self.shared,
+            &mut HashMap::new(),
         )?;
         Ok(())
     }
 }
 
+/// Tracks the images written so far
+struct Progress {
+    file: Option<File>,
+    resume_from: u64,
+    journal: Option<File>,
+
+    /// Handle to the part file, synced before each journal record
+    part: Option<File>,
+}
+
+impl Progress {
+    fn new(part_path: &Path) -> Result<Self> {
+        Ok(Self {
+            file: Some(File::create(part_path)?),
+            resume_from: 0,
+            journal: None,
+            part: None,
+        })
+    }
+
+    /// Writes everything without a journal
+    fn in_memory() -> Self {
+        Self {
+            file: None,
+            resume_from: 0,
+            journal: None,
+            part: None,
+        }
+    }
+
+    /// Picks up from the journal if it was written for the same contents, otherwise starts over
+    fn resume(part_path: &Path, journal_path: &Path, stamp: &[u8]) -> Result<Self> {
+        let mut resume_from = 0;
+        if part_path.is_file() {
+            if let Ok(journal) = fs::read(journal_path) {
+                if journal.starts_with(stamp) {
+                    resume_from = journal[stamp.len()..]
+                        .chunks_exact(8)
+                        .last()
+                        .map_or(0, |c| {
+                            u64::from_le_bytes(c.try_into().expect("chunk is 8 bytes"))
+                        });
+                }
+            }
+        }
+        let file = if resume_from > 0 {
+            OpenOptions::new().write(true).open(part_path)?
+        } else {
+            File::create(part_path)?
+        };
+        let mut journal = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(journal_path)?;
+        if resume_from == 0 {
+            journal.set_len(0)?;
+            journal.write_all(stamp)?;
+            journal.sync_data()?;
+        }
+        Ok(Self {
+            part: Some(file.try_clone()?),
+            file: Some(file),
+            resume_from,
+            journal: Some(journal),
+        })
+    }
+
+    /// Records that everything up to `end` is on disk. The record is only written once the part
+    /// file is synced, so the journal never claims data that could still be lost.
+    fn completed<W, E>(&mut self, writer: &mut WzWriter<W, E>, end: u64) -> Result<()>
+    where
+        W: Write + Seek,
+        E: Encryptor,
+    {
+        if let (Some(journal), Some(part)) = (self.journal.as_mut(), self.part.as_ref()) {
+            writer.flush()?;
+            part.sync_data()?;
+            journal.write_all(&end.to_le_bytes())?;
+            journal.sync_data()?;
+        }
+        Ok(())
+    }
+}
+
+/// Appends `suffix` to the file name of `path`
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Returns `a + b` or [`PackageError::ArchiveTooLarge`] if the size no longer fits a [`WzInt`].
+/// Negative sizes fail with [`DecodeError::Length`].
+fn checked_size(a: i32, b: i32) -> Result<i32> {
+    if let Some(size) = [a, b].into_iter().find(|size| *size < 0) {
+        return Err(DecodeError::Length(size).into());
+    }
+    a.checked_add(b)
+        .ok_or_else(|| PackageError::ArchiveTooLarge.into())
+}
+
+/// Returns `offset` moved by `size` or [`PackageError::ArchiveTooLarge`] if it no longer fits a
+/// [`WzOffset`]. Offsets are always 32-bit in WZ archives, only the header size is 64-bit. Negative
+/// sizes fail with [`DecodeError::Length`].
+fn checked_offset(offset: WzOffset, size: i32) -> Result<WzOffset> {
+    let size = u32::try_from(size).map_err(|_| DecodeError::Length(size))?;
+    offset
+        .checked_add(size)
+        .ok_or_else(|| PackageError::ArchiveTooLarge.into())
+}
+
+/// Returns the SHA-256 digest of the serialized `image`
+fn digest<I>(image: &I) -> Result<[u8; 32]>
+where
+    I: ImageRef,
+{
+    let mut writer = WzWriter::new(0, 0, io::Cursor::new(Vec::new()), DummyEncryptor);
+    image.write(&mut writer)?;
+    Ok(Sha256::digest(writer.into_inner().into_inner()).into())
+}
+
 fn encode_obj<T>(absolute_position: i32, version_checksum: u32, obj: &T) -> Result<Vec<u8>>
 where
     T: Encode,
@@ -229,6 +938,7 @@ fn recursive_calculate_size_and_checksum<I>(
     absolute_position: i32,
     version_checksum: u32,
     cursor: &mut CursorMut<Node<I>>,
+    shared: &HashMap<Bookmark, Bookmark>,
 ) -> Result<(WzInt, WzInt)>
 where
     I: ImageRef,
@@ -245,8 +955,8 @@ where
         &WzInt::from(num_children as i32),
     )?;
 
-    // Set the size to 0--num_content is part of the package "size". It should panic if it
-    // overflows.
+    // Set the size to 0--num_content is part of the package "size". Overflowing it means the
+    // archive is too large.
     let mut calc_size = 0;
 
     // Set checksum to 0--not sure if the checksum includes num_content. But since size does not, I
@@ -258,9 +968,13 @@ where
         cursor.first_child()?;
         loop {
             // Calculate the checksum of the child and get its encoded size
-            let (child_size, child_checksum) =
-                recursive_calculate_size_and_checksum(absolute_position, version_checksum, cursor)?;
-            calc_size += *child_size;
+            let (child_size, child_checksum) = recursive_calculate_size_and_checksum(
+                absolute_position,
+                version_checksum,
+                cursor,
+                shared,
+            )?;
+            calc_size = checked_size(calc_size, *child_size)?;
             calc_checksum += Wrapping(*child_checksum);
             num_children -= 1;
             if num_children == 0 {
@@ -305,7 +1019,10 @@ where
     // Include content metadata here
     let (calc_size, calc_checksum) = match cursor.get() {
         Node::Package { .. } => (
-            calc_size + num_content.len() as i32 + content_ref.size_hint() as i32,
+            checked_size(
+                calc_size,
+                num_content.len() as i32 + content_ref.size_hint() as i32,
+            )?,
             calc_checksum
                 + num_content
                     .iter()
@@ -316,8 +1033,16 @@ where
                     .map(|b| Wrapping(*b as i32))
                     .sum::<Wrapping<i32>>(),
         ),
+        // Shared data is part of the package holding the original
+        Node::Image { .. } if shared.contains_key(&cursor.bookmark()) => (
+            content_ref.size_hint() as i32,
+            content_data
+                .iter()
+                .map(|b| Wrapping(*b as i32))
+                .sum::<Wrapping<i32>>(),
+        ),
         Node::Image { image, .. } => (
-            *image.size()? + content_ref.size_hint() as i32,
+            checked_size(*image.size()?, content_ref.size_hint() as i32)?,
             Wrapping(*image.checksum()?)
                 + content_data
                     .iter()
@@ -328,15 +1053,30 @@ where
     Ok((WzInt::from(calc_size), WzInt::from(calc_checksum.0)))
 }
 
-/// Calculates the offsets. If overflow occurs here, it should absolutely panic.
+/// Calculates the offsets. Fails with [`PackageError::ArchiveTooLarge`] if they overflow.
 fn recursive_calculate_offset<I>(
     current_offset: WzOffset,
     cursor: &mut CursorMut<Node<I>>,
+    shared: &HashMap<Bookmark, Bookmark>,
+    placed: &mut HashMap<Bookmark, WzOffset>,
 ) -> Result<WzOffset>
 where
     I: ImageRef,
 {
+    // Shared images point to the data of the original, which always comes first, and take up no
+    // space of their own
+    if let Some(original) = shared.get(&cursor.bookmark()) {
+        let original = *placed
+            .get(original)
+            .expect("originals are placed before their copies");
+        if let Node::Image { ref mut offset, .. } = cursor.get_mut() {
+            *offset = original;
+        }
+        return Ok(current_offset);
+    }
+
     // Apply the current offset
+    placed.insert(cursor.bookmark(), current_offset);
     match cursor.get_mut() {
         Node::Package { ref mut offset, .. } => *offset = current_offset,
         Node::Image { ref mut offset, .. } => *offset = current_offset,
@@ -344,15 +1084,15 @@ where
 
     // Calculate the sibling offset and return the number of children
     let next_offset = match cursor.get() {
-        Node::Package { size, .. } => *current_offset + **size as u32,
+        Node::Package { size, .. } => checked_offset(current_offset, **size)?,
         // If it is an image, return the next offset and stop here. Image's have no children.
-        Node::Image { ref image, .. } => return Ok(current_offset + (*image.size()?).into()),
+        Node::Image { ref image, .. } => return checked_offset(current_offset, *image.size()?),
     };
 
     // Get num content dn update next_offset
     let num_content = cursor.children().count() as i32;
     let header_size = WzInt::from(num_content).size_hint() as i32;
-    let next_offset = WzOffset::from(next_offset + header_size as u32);
+    let next_offset = checked_offset(next_offset, header_size)?;
 
     if num_content > 0 {
         // Total the metadata size to get the position of the first child
@@ -377,7 +1117,7 @@ where
                     *offset,
                 )),
             };
-            metadata_size += content_ref.size_hint() as i32;
+            metadata_size = checked_size(metadata_size, content_ref.size_hint() as i32)?;
             count -= 1;
             if count <= 0 {
                 break;
@@ -387,11 +1127,11 @@ where
         cursor.parent()?;
 
         // Modify children. The order is always the order of insertion.
-        let mut child_offset = current_offset + metadata_size.into();
+        let mut child_offset = checked_offset(current_offset, metadata_size)?;
         let mut count = num_content;
         cursor.first_child()?;
         loop {
-            child_offset = recursive_calculate_offset(child_offset, cursor)?;
+            child_offset = recursive_calculate_offset(child_offset, cursor, shared, placed)?;
             count -= 1;
             if count <= 0 {
                 break;
@@ -405,7 +1145,12 @@ where
 }
 
 /// Saves the WZ archive recursively
-fn recursive_save<I, W, E>(cursor: &mut Cursor<Node<I>>, writer: &mut WzWriter<W, E>) -> Result<()>
+fn recursive_save<I, W, E>(
+    cursor: &mut Cursor<Node<I>>,
+    writer: &mut WzWriter<W, E>,
+    progress: &mut Progress,
+    shared: &HashMap<Bookmark, Bookmark>,
+) -> Result<()>
 where
     I: ImageRef,
     W: Write + Seek,
@@ -414,8 +1159,21 @@ where
     let num_content = match cursor.get() {
         // Get number of children
         Node::Package { .. } => cursor.children().count() as i32,
-        // Write the image and return
-        Node::Image { ref image, .. } => return image.write(writer),
+        // The original already wrote the data
+        Node::Image { .. } if shared.contains_key(&cursor.bookmark()) => return Ok(()),
+        // Write the image unless a previous save already did, and return
+        Node::Image {
+            ref image,
+            ref offset,
+        } => {
+            let end = **offset as u64 + *image.size()? as u64;
+            if end <= progress.resume_from {
+                writer.seek(WzOffset::from(end as u32))?;
+                return Ok(());
+            }
+            image.write(writer)?;
+            return progress.completed(writer, end);
+        }
     };
 
     // Encode the length
@@ -455,7 +1213,7 @@ where
         let mut count = num_content;
         cursor.first_child()?;
         loop {
-            recursive_save(cursor, writer)?;
+            recursive_save(cursor, writer, progress, shared)?;
             count -= 1;
             if count <= 0 {
                 break;
@@ -467,3 +1225,561 @@ where
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+
+    use crate::archive::{
+        self,
+        patch::PatchImage,
+        reader::MAX_DEPTH,
+        writer::{AnyImage, EncodedImage, FileImage, ImageRef},
+        Reader, Writer,
+    };
+    use crate::error::{DecodeError, Error, PackageError, Result};
+    use crate::image;
+    use crate::io::{DummyDecryptor, DummyEncryptor, WzRead, WzWrite, WzWriter};
+    use crate::types::{WzHeader, WzInt};
+    use crypto::{Encryptor, KeyStream, GMS_IV, TRIMMED_KEY};
+    use std::io::{Seek, Write};
+    use std::{env, fs, path::PathBuf};
+
+    /// In-memory image that can be told to fail when written
+    #[derive(Debug)]
+    struct Blob {
+        data: Vec<u8>,
+        fail: bool,
+    }
+
+    impl ImageRef for Blob {
+        fn size(&self) -> Result<WzInt> {
+            Ok(WzInt::from(self.data.len() as i32))
+        }
+
+        fn checksum(&self) -> Result<WzInt> {
+            Ok(WzInt::from(
+                self.data.iter().map(|b| *b as i32).sum::<i32>(),
+            ))
+        }
+
+        fn write<W, E>(&self, writer: &mut WzWriter<W, E>) -> Result<()>
+        where
+            W: Write + Seek,
+            E: Encryptor,
+        {
+            if self.fail {
+                return Err(PackageError::Path(String::from("interrupted")).into());
+            }
+            writer.write_all(&self.data)
+        }
+    }
+
+    /// Image that only reports its size
+    struct Huge(i32);
+
+    impl ImageRef for Huge {
+        fn size(&self) -> Result<WzInt> {
+            Ok(WzInt::from(self.0))
+        }
+
+        fn checksum(&self) -> Result<WzInt> {
+            Ok(WzInt::from(0))
+        }
+
+        fn write<W, E>(&self, _writer: &mut WzWriter<W, E>) -> Result<()>
+        where
+            W: Write + Seek,
+            E: Encryptor,
+        {
+            unreachable!("sizes are checked before writing")
+        }
+    }
+
+    fn build(fail: [bool; 3]) -> Writer<Blob> {
+        let mut writer = Writer::new("Test");
+        for (i, fail) in fail.into_iter().enumerate() {
+            let data = vec![i as u8 + 1; 100 * (i + 1)];
+            writer
+                .add_image(format!("Test/Sub/{}.img", i), Blob { data, fail })
+                .expect("error adding image");
+        }
+        writer
+    }
+
+    #[test]
+    fn resumable_save() {
+        let dir = env::temp_dir().join("wz-writer-test");
+        fs::create_dir_all(&dir).expect("error creating directory");
+        let expected_path = dir.join("expected.wz");
+        let path = dir.join("Test.wz");
+        let part_path = dir.join("Test.wz.part");
+        let journal_path = dir.join("Test.wz.journal");
+        let _ = fs::remove_file(&path);
+
+        build([false; 3])
+            .save(&expected_path, 83, WzHeader::new(83), DummyEncryptor)
+            .expect("error saving");
+        assert!(!dir.join("expected.wz.part").exists());
+        let expected = fs::read(&expected_path).expect("error reading archive");
+
+        // Crash on the last image. Nothing is written to the final path.
+        assert!(build([false, false, true])
+            .save_resumable(&path, 83, WzHeader::new(83), DummyEncryptor)
+            .is_err());
+        assert!(!path.exists());
+        assert!(part_path.exists() && journal_path.exists());
+
+        // A plain save starts over and drops the journal, even when it fails as well
+        assert!(build([false, true, false])
+            .save(&path, 83, WzHeader::new(83), DummyEncryptor)
+            .is_err());
+        assert!(!journal_path.exists());
+        assert!(build([false, false, true])
+            .save_resumable(&path, 83, WzHeader::new(83), DummyEncryptor)
+            .is_err());
+        assert!(part_path.exists() && journal_path.exists());
+
+        // Images already written are skipped
+        build([true, true, false])
+            .save_resumable(&path, 83, WzHeader::new(83), DummyEncryptor)
+            .expect("error resuming");
+        assert!(!part_path.exists() && !journal_path.exists());
+        assert_eq!(fs::read(&path).expect("error reading archive"), expected);
+        let map = Reader::open(&path, DummyDecryptor)
+            .expect("error opening archive")
+            .map("Test")
+            .expect("error mapping archive");
+        assert!(map.get("Test/Sub/2.img").is_ok());
+        fs::remove_dir_all(&dir).expect("error removing directory");
+    }
+
+    #[test]
+    fn in_memory_save() {
+        let dir = env::temp_dir().join("wz-writer-vec-test");
+        fs::create_dir_all(&dir).expect("error creating directory");
+        let path = dir.join("Test.wz");
+        build([false; 3])
+            .save(&path, 83, WzHeader::new(83), DummyEncryptor)
+            .expect("error saving");
+
+        let bytes = build([false; 3])
+            .save_to_vec(83, WzHeader::new(83), DummyEncryptor)
+            .expect("error saving");
+        assert_eq!(bytes, fs::read(&path).expect("error reading archive"));
+        let map = Reader::from_bytes(bytes, DummyDecryptor)
+            .expect("error opening archive")
+            .map("Test")
+            .expect("error mapping archive");
+        assert!(map.get("Test/Sub/2.img").is_ok());
+        assert!(build([false; 3])
+            .save_to_vec(95, WzHeader::new(83), DummyEncryptor)
+            .is_err());
+        fs::remove_dir_all(&dir).expect("error removing directory");
+    }
+
+    #[test]
+    fn empty_contents() {
+        let mut writer = Writer::new("Test");
+        writer
+            .add_package("Test/Empty")
+            .expect("error adding package");
+        writer
+            .add_image(
+                "Test/Sub/1.img",
+                Blob {
+                    data: vec![1; 10],
+                    fail: false,
+                },
+            )
+            .expect("error adding image");
+        writer
+            .add_image(
+                "Test/zero.img",
+                Blob {
+                    data: Vec::new(),
+                    fail: false,
+                },
+            )
+            .expect("error adding image");
+        let bytes = writer
+            .save_to_vec(83, WzHeader::new(83), DummyEncryptor)
+            .expect("error saving");
+
+        // The empty image ends the archive, which must not stop the version from resolving
+        let mut reader = Reader::from_bytes(bytes, DummyDecryptor).expect("error opening archive");
+        let map = reader.map("Test").expect("error mapping archive");
+        assert_eq!(
+            map.cursor_at("Test/Empty")
+                .expect("missing package")
+                .child_count(),
+            0
+        );
+        let zero = map.get("Test/zero.img").expect("missing image");
+        assert!(zero.is_empty_image());
+        assert!(!map
+            .get("Test/Sub/1.img")
+            .expect("missing image")
+            .is_empty_image());
+
+        let image = reader
+            .map_image(&map, "Test/zero.img", |_| DummyDecryptor)
+            .expect("error mapping image");
+        assert_eq!(image.name(), "zero.img");
+        assert_eq!(image.cursor().child_count(), 0);
+        let manifest = archive::manifest(&mut reader.into_inner(), &map).expect("error hashing");
+        assert_eq!(manifest.entries()[1].size, 0);
+    }
+
+    #[test]
+    fn parallel_prepare() {
+        let dir = env::temp_dir().join("wz-writer-parallel-test");
+        fs::create_dir_all(&dir).expect("error creating directory");
+        let key = KeyStream::new(&TRIMMED_KEY, &GMS_IV);
+        let build = || {
+            let mut writer = Writer::new("TamingMob");
+            for i in 0..5 {
+                let map = image::Reader::open("testdata/v83-tamingmob.img", key.clone())
+                    .expect("error opening image")
+                    .map("tamingmob.img")
+                    .expect("error mapping image");
+                writer
+                    .add_image(
+                        format!("TamingMob/{}.img", i),
+                        EncodedImage::new(map, key.clone()),
+                    )
+                    .expect("error adding image");
+            }
+            writer
+        };
+
+        let serial_path = dir.join("serial.wz");
+        build()
+            .save(&serial_path, 83, WzHeader::new(83), key.clone())
+            .expect("error saving");
+        let parallel_path = dir.join("parallel.wz");
+        let mut writer = build();
+        writer.prepare(3).expect("error preparing");
+        writer
+            .save(&parallel_path, 83, WzHeader::new(83), key.clone())
+            .expect("error saving");
+        assert_eq!(
+            fs::read(&serial_path).expect("error reading archive"),
+            fs::read(&parallel_path).expect("error reading archive")
+        );
+
+        let mut reader = Reader::open(&parallel_path, key.clone()).expect("error opening archive");
+        let map = reader.map("TamingMob").expect("error mapping archive");
+        assert_eq!(map.cursor().children().count(), 5);
+        fs::remove_dir_all(&dir).expect("error removing directory");
+    }
+
+    #[test]
+    fn mixed_images() {
+        let key = KeyStream::new(&TRIMMED_KEY, &GMS_IV);
+        let source = "testdata/v83-base.wz";
+        let mut base = Reader::open(source, key.clone()).expect("error opening archive");
+        let base_map = base.map("Base").expect("error mapping archive");
+        let (offset, size, checksum) = match base_map.get("Base/smap.img") {
+            Ok(archive::reader::Node::Image {
+                offset,
+                size,
+                checksum,
+            }) => (*offset, *size, *checksum),
+            n => panic!("expected an image, found {:?}", n),
+        };
+        let mut copied = Vec::new();
+        base.into_inner()
+            .copy_to(&mut copied, offset, size)
+            .expect("error copying image");
+        let weapon = image::Reader::open("testdata/v83-weapon.img", key.clone())
+            .expect("error opening image")
+            .map("weapon.img")
+            .expect("error mapping image");
+
+        let mut writer = Writer::<AnyImage<_>>::new("Test");
+        writer
+            .add_image(
+                "Test/weapon.img",
+                EncodedImage::new(weapon, key.clone()).into(),
+            )
+            .expect("error adding image");
+        writer
+            .add_image(
+                "Test/copy.img",
+                AnyImage::from(PatchImage::Copy {
+                    path: PathBuf::from(source),
+                    offset,
+                    size,
+                    checksum,
+                }),
+            )
+            .expect("error adding image");
+        writer
+            .add_image("Test/data.img", PatchImage::Data(&copied).into())
+            .expect("error adding image");
+        let file = env::temp_dir().join("wz-file-image-test.img");
+        fs::write(&file, &copied).expect("error writing image");
+        let file_image = FileImage::new(&file).expect("error opening image");
+        assert_eq!(file_image.size().unwrap(), size);
+        assert_eq!(file_image.checksum().unwrap(), checksum);
+        writer
+            .add_image("Test/file.img", file_image.into())
+            .expect("error adding image");
+        writer.prepare(2).expect("error preparing");
+        let bytes = writer
+            .save_to_vec(83, WzHeader::new(83), key.clone())
+            .expect("error saving");
+
+        let mut reader = Reader::from_bytes(bytes, key.clone()).expect("error opening archive");
+        reader.resolve_version().expect("error resolving");
+        let map = reader.map("Test").expect("error mapping archive");
+        reader
+            .map_image(&map, "Test/weapon.img", |_| key.clone())
+            .expect("error mapping image");
+        let mut inner = reader.into_inner();
+        for path in ["Test/copy.img", "Test/data.img", "Test/file.img"] {
+            let offset = match map.get(path) {
+                Ok(archive::reader::Node::Image { offset, .. }) => *offset,
+                n => panic!("expected an image, found {:?}", n),
+            };
+            let mut data = Vec::new();
+            inner
+                .copy_to(&mut data, offset, size)
+                .expect("error copying image");
+            assert_eq!(data, copied);
+        }
+        fs::remove_file(&file).expect("error removing image");
+    }
+
+    #[test]
+    fn file_images() {
+        let dir = env::temp_dir().join("wz-file-images-test");
+        fs::create_dir_all(&dir).expect("error creating directory");
+        let contents = [
+            (String::from("Test/small.img"), vec![0xFFu8; 10]),
+            (
+                String::from("Test/Sub/large.img"),
+                (0..20000u32).map(|i| (i * 7) as u8).collect(),
+            ),
+        ];
+        let mut writer = Writer::<FileImage>::new("Test");
+        for (i, (path, data)) in contents.iter().enumerate() {
+            let file = dir.join(format!("{}.img", i));
+            fs::write(&file, data).expect("error writing image");
+            let image = FileImage::new(&file).expect("error opening image");
+            let checksum = data.iter().fold(0i32, |sum, b| sum.wrapping_add(*b as i32));
+            assert_eq!(image.size().unwrap(), WzInt::from(data.len()));
+            assert_eq!(image.checksum().unwrap(), WzInt::from(checksum));
+            writer.add_image(path, image).expect("error adding image");
+        }
+        let bytes = writer
+            .save_to_vec(83, WzHeader::new(83), DummyEncryptor)
+            .expect("error saving");
+
+        let mut reader = Reader::from_bytes(bytes, DummyDecryptor).expect("error opening archive");
+        let map = reader.map("Test").expect("error mapping archive");
+        let mut inner = reader.into_inner();
+        for (path, data) in &contents {
+            let (offset, size, checksum) = match map.get(path) {
+                Ok(archive::reader::Node::Image {
+                    offset,
+                    size,
+                    checksum,
+                }) => (*offset, *size, *checksum),
+                n => panic!("expected an image, found {:?}", n),
+            };
+            assert_eq!(size, WzInt::from(data.len()));
+            assert_eq!(
+                checksum,
+                WzInt::from(data.iter().fold(0i32, |sum, b| sum.wrapping_add(*b as i32)))
+            );
+            let mut written = Vec::new();
+            inner
+                .copy_to(&mut written, offset, size)
+                .expect("error copying image");
+            assert_eq!(&written, data);
+        }
+
+        // Changing the file after the image was created is caught when it is read again
+        let file = dir.join("changed.img");
+        fs::write(&file, [1u8; 10]).expect("error writing image");
+        let image = FileImage::new(&file).expect("error opening image");
+        fs::write(&file, [1u8; 12]).expect("error writing image");
+        assert!(matches!(
+            image.checksum(),
+            Err(Error::Package(PackageError::ImageChanged(_)))
+        ));
+
+        // A prepared image keeps its checksum but still fails to be written
+        fs::write(&file, [1u8; 10]).expect("error writing image");
+        let mut image = FileImage::new(&file).expect("error opening image");
+        image.prepare().expect("error preparing image");
+        fs::write(&file, [2u8; 5]).expect("error writing image");
+        assert_eq!(image.checksum().unwrap(), WzInt::from(10));
+        let mut writer = Writer::<FileImage>::new("Test");
+        writer
+            .add_image("Test/changed.img", image)
+            .expect("error adding image");
+        assert!(matches!(
+            writer.save_to_vec(83, WzHeader::new(83), DummyEncryptor),
+            Err(Error::Package(PackageError::ImageChanged(_)))
+        ));
+        fs::remove_dir_all(&dir).expect("error removing directory");
+    }
+
+    #[test]
+    fn package_depth() {
+        let mut writer = Writer::<Blob>::new("Test");
+        let deepest = format!("Test{}", "/P".repeat(MAX_DEPTH));
+        writer.add_package(&deepest).expect("error adding package");
+        writer
+            .add_image(
+                format!("{}/1.img", deepest),
+                Blob {
+                    data: vec![1; 10],
+                    fail: false,
+                },
+            )
+            .expect("error adding image");
+        assert!(matches!(
+            writer.add_package(format!("{}/P", deepest)),
+            Err(Error::Package(PackageError::Depth))
+        ));
+        let bytes = writer
+            .save_to_vec(83, WzHeader::new(83), DummyEncryptor)
+            .expect("error saving");
+        let map = Reader::from_bytes(bytes, DummyDecryptor)
+            .expect("error opening archive")
+            .map("Test")
+            .expect("error mapping archive");
+        assert!(map.get(format!("{}/1.img", deepest)).is_ok());
+    }
+
+    #[test]
+    fn archive_too_large() {
+        let path = env::temp_dir().join("wz-too-large-test.wz");
+        for sizes in [[i32::MAX / 2 + 1, i32::MAX / 2 + 1], [i32::MAX - 8, 0]] {
+            let mut writer = Writer::new("Test");
+            for (i, size) in sizes.into_iter().enumerate() {
+                writer
+                    .add_image(format!("Test/{}.img", i), Huge(size))
+                    .expect("error adding image");
+            }
+            match writer.save(&path, 83, WzHeader::new(83), DummyEncryptor) {
+                Err(Error::Package(PackageError::ArchiveTooLarge)) => {}
+                r => panic!("expected ArchiveTooLarge, found {:?}", r),
+            }
+            assert!(!path.exists());
+        }
+
+        // Negative sizes are not mistaken for overflows
+        let mut writer = Writer::new("Test");
+        writer
+            .add_image("Test/0.img", Huge(-1))
+            .expect("error adding image");
+        match writer.save(&path, 83, WzHeader::new(83), DummyEncryptor) {
+            Err(Error::Decode(DecodeError::Length(-1))) => {}
+            r => panic!("expected a length error, found {:?}", r),
+        }
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn edit_contents() {
+        let blob = |byte: u8| Blob {
+            data: vec![byte; 100],
+            fail: false,
+        };
+        let mut writer = Writer::new("Test");
+        assert!(writer.is_empty());
+        writer.add_image("Test/a.img", blob(1)).unwrap();
+        writer.add_image("Test/Sub/b.img", blob(1)).unwrap();
+        writer.add_image("Test/Sub/c.img", blob(2)).unwrap();
+        writer.add_package("Test/Empty").unwrap();
+        assert_eq!(writer.len(), 3);
+        assert_eq!(writer.children("Test").unwrap(), ["a.img", "Sub", "Empty"]);
+        assert_eq!(writer.children("Test/Sub").unwrap(), ["b.img", "c.img"]);
+        assert!(writer.children("Test/a.img").is_err());
+        match writer.get("Test/Sub/c.img") {
+            Ok(archive::writer::Node::Image { image, .. }) => assert_eq!(image.data, [2u8; 100]),
+            n => panic!("expected an image, found {:?}", n),
+        }
+        assert!(writer.get("Test/missing.img").is_err());
+
+        // b.img shares the data of a.img until a.img is removed
+        assert_eq!(writer.deduplicate().unwrap().len(), 1);
+        writer.remove("Test/a.img").unwrap();
+        writer.remove("Test/Empty").unwrap();
+        assert!(writer.remove("Test").is_err());
+        assert!(writer.remove("Test/a.img").is_err());
+        assert_eq!(writer.len(), 2);
+        assert_eq!(writer.children("Test").unwrap(), ["Sub"]);
+
+        let bytes = writer
+            .save_to_vec(83, WzHeader::new(83), DummyEncryptor)
+            .expect("error saving");
+        let mut reader = Reader::from_bytes(bytes, DummyDecryptor).expect("error opening archive");
+        reader.resolve_version().expect("error resolving");
+        let map = reader.map("Test").expect("error mapping archive");
+        assert_eq!(map.cursor().list().collect::<Vec<&str>>(), ["Sub"]);
+        let offset = |path: &str| match map.get(path).expect("missing image") {
+            archive::reader::Node::Image { offset, .. } => *offset,
+            _ => panic!("expected an image"),
+        };
+        assert_ne!(offset("Test/Sub/b.img"), offset("Test/Sub/c.img"));
+    }
+
+    #[test]
+    fn deduplicate() {
+        let build = || {
+            let mut writer = Writer::new("Test");
+            // The last image has the same size and checksum as the first but different data
+            let mut other = vec![0u8; 50];
+            other.extend([2u8; 50]);
+            let images = [
+                ("Test/a.img", vec![1u8; 100]),
+                ("Test/Sub/b.img", vec![1u8; 100]),
+                ("Test/Sub/c.img", vec![2u8; 100]),
+                ("Test/d.img", other),
+            ];
+            for (path, data) in images {
+                writer
+                    .add_image(path, Blob { data, fail: false })
+                    .expect("error adding image");
+            }
+            writer
+        };
+        let full = build()
+            .save_to_vec(83, WzHeader::new(83), DummyEncryptor)
+            .expect("error saving");
+
+        let mut writer = build();
+        let shared = writer.deduplicate().expect("error deduplicating");
+        assert_eq!(
+            shared,
+            vec![(String::from("Test/Sub/b.img"), String::from("Test/a.img"))]
+        );
+        let bytes = writer
+            .save_to_vec(83, WzHeader::new(83), DummyEncryptor)
+            .expect("error saving");
+        assert_eq!(bytes.len(), full.len() - 100);
+
+        let mut reader = Reader::from_bytes(bytes, DummyDecryptor).expect("error opening archive");
+        assert_eq!(reader.resolve_version().expect("error resolving"), 83);
+        let map = reader.map("Test").expect("error mapping archive");
+        let offset = |path: &str| match map.get(path).expect("missing image") {
+            archive::reader::Node::Image { offset, .. } => *offset,
+            _ => panic!("expected an image"),
+        };
+        assert_eq!(offset("Test/Sub/b.img"), offset("Test/a.img"));
+        assert_ne!(offset("Test/Sub/c.img"), offset("Test/a.img"));
+        let mut inner = reader.into_inner();
+        for (path, expected) in [("Test/Sub/b.img", 1u8), ("Test/Sub/c.img", 2u8)] {
+            let mut data = Vec::new();
+            inner
+                .copy_to(&mut data, offset(path), WzInt::from(100))
+                .expect("error copying image");
+            assert_eq!(data, vec![expected; 100]);
+        }
+    }
+}
@@ -13,6 +13,8 @@ mod xml;
 pub use self::image::ImageError;
 pub use self::xml::XmlError;
 pub use canvas::CanvasError;
+#[cfg(feature = "decode-context")]
+pub use decode::DecodeContext;
 pub use decode::DecodeError;
 pub use map::MapError;
 pub use package::PackageError;
@@ -52,7 +54,10 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::Canvas(e) => write!(f, "Canvas: {}", e),
-            Self::Decode(e) => write!(f, "Decode: {}", e),
+            Self::Decode(e) => match f.alternate() {
+                true => write!(f, "Decode: {:#}", e),
+                false => write!(f, "Decode: {}", e),
+            },
             Self::Image(e) => write!(f, "Image: {}", e),
             Self::Io(kind) => write!(f, "IO: {}", kind),
             Self::Map(e) => write!(f, "Map: {}", e),
@@ -69,6 +74,7 @@ impl From<CanvasError> for Error {
     }
 }
 
+#[cfg(feature = "canvas-codecs")]
 impl From<::image::error::ImageError> for Error {
     fn from(other: ::image::error::ImageError) -> Self {
         Self::Canvas(other.into())
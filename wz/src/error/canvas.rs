@@ -1,21 +1,35 @@
 //! Canvas Error Types
 
 use crate::types::{CanvasFormat, WzInt};
+#[cfg(feature = "canvas-codecs")]
 use image::error::ImageError;
 use std::fmt;
 
 /// Possible canvas errors
 #[derive(Debug)]
 pub enum CanvasError {
+    /// Animation encoding
+    Animation(String),
+
     /// Unknown Canvas type
     EncodingFormat(WzInt, u8),
 
+    /// Crop region `(x, y, width, height)` is empty or outside the canvas
+    Crop(u32, u32, u32, u32),
+
     /// Image Errors
+    #[cfg(feature = "canvas-codecs")]
     Image(image::error::ImageError),
 
+    /// Encoding or decoding the pixels needs the `canvas-codecs` feature
+    CodecsDisabled,
+
     /// Inflate
     Inflate(String),
 
+    /// Lazy canvas data has not been loaded
+    NotLoaded,
+
     /// Size mismatch
     SizeMismatch(CanvasFormat, u32, u32, usize),
 
@@ -26,11 +40,20 @@ pub enum CanvasError {
 impl fmt::Display for CanvasError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
+            Self::Animation(s) => write!(f, "Animation: {}", s),
             Self::EncodingFormat(t, t2) => {
                 write!(f, "Unknown encoding format: `({}, {})`", **t, *t2)
             }
+            Self::Crop(x, y, w, h) => write!(
+                f,
+                "Crop region is outside the canvas: {{ X({}), Y({}), Width({}), Height({}) }}",
+                x, y, w, h
+            ),
+            #[cfg(feature = "canvas-codecs")]
             Self::Image(e) => write!(f, "Image: {}", e),
+            Self::CodecsDisabled => write!(f, "Canvas codecs are disabled"),
             Self::Inflate(s) => write!(f, "Inflate: {}", s),
+            Self::NotLoaded => write!(f, "Canvas data has not been loaded"),
             Self::SizeMismatch(c, w, h, l) => write!(
                 f,
                 "Data length does not match Canvas Size {{ {:?}, Width({}), Height({}), PixelBytes({}) }}",
@@ -41,6 +64,7 @@ impl fmt::Display for CanvasError {
     }
 }
 
+#[cfg(feature = "canvas-codecs")]
 impl From<ImageError> for CanvasError {
     fn from(other: ImageError) -> Self {
         Self::Image(other)
@@ -1,7 +1,12 @@
 //! Decode Error types
 
+use crate::io::WzRead;
 use std::{fmt, string};
 
+/// Bytes kept on either side of the position a decoding error was detected at
+#[cfg(feature = "decode-context")]
+const CONTEXT_RADIUS: u32 = 16;
+
 /// Possible decoding errors
 #[derive(Debug)]
 pub enum DecodeError {
@@ -16,6 +21,112 @@ pub enum DecodeError {
 
     /// Unable to decode Unicode
     Unicode(string::FromUtf16Error),
+
+    /// Compressed float starts with a byte other than 0x00 or 0x80
+    FloatMarker(u8),
+
+    /// Error with the bytes surrounding the position it was detected at. The alternate format,
+    /// `{:#}`, prints them as a hexdump.
+    #[cfg(feature = "decode-context")]
+    Context(Box<DecodeError>, DecodeContext),
+}
+
+/// Bytes surrounding the position a decoding error was detected at
+#[cfg(feature = "decode-context")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeContext {
+    /// Position of the reader when the error was detected. Positions within images are relative
+    /// to the start of the image.
+    pub position: u32,
+
+    /// Position of the first byte
+    pub start: u32,
+
+    /// Bytes from `start`, up to 16 on either side of `position`
+    pub bytes: Vec<u8>,
+}
+
+#[cfg(feature = "decode-context")]
+impl DecodeContext {
+    /// Reads the bytes around the current position of `reader` and seeks back to it
+    fn capture<R>(reader: &mut R) -> crate::error::Result<Self>
+    where
+        R: WzRead + ?Sized,
+    {
+        let position = *reader.position()?;
+        let start = position.saturating_sub(CONTEXT_RADIUS);
+        reader.seek(start.into())?;
+        let mut bytes = vec![0u8; (position - start + CONTEXT_RADIUS) as usize];
+        let mut len = 0;
+        while len < bytes.len() {
+            match reader.read(&mut bytes[len..])? {
+                0 => break,
+                n => len += n,
+            }
+        }
+        bytes.truncate(len);
+        reader.seek(position.into())?;
+        Ok(Self {
+            position,
+            start,
+            bytes,
+        })
+    }
+}
+
+/// Prints rows of 16 bytes with their position and ASCII, marking the byte at `position`
+#[cfg(feature = "decode-context")]
+impl fmt::Display for DecodeContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let first_row = self.start / 16 * 16;
+        let end = self.start + self.bytes.len() as u32;
+        let mut row = first_row;
+        while row < end.max(self.position + 1) {
+            write!(f, "{:08X} ", row)?;
+            let mut ascii = String::new();
+            for i in row..row + 16 {
+                match i
+                    .checked_sub(self.start)
+                    .and_then(|i| self.bytes.get(i as usize))
+                {
+                    Some(byte) => {
+                        write!(f, " {:02X}", byte)?;
+                        ascii.push(match byte.is_ascii_graphic() || *byte == b' ' {
+                            true => *byte as char,
+                            false => '.',
+                        });
+                    }
+                    None => {
+                        write!(f, "   ")?;
+                        ascii.push(' ');
+                    }
+                }
+            }
+            writeln!(f, "  |{}|", ascii)?;
+            if (row..row + 16).contains(&self.position) {
+                let column = (self.position - row) as usize;
+                writeln!(f, "{:width$}^^", "", width = 10 + column * 3)?;
+            }
+            row += 16;
+        }
+        Ok(())
+    }
+}
+
+impl DecodeError {
+    /// Attaches the bytes around the current position of `reader`. Without the `decode-context`
+    /// feature, or if they cannot be read, the error is returned as is.
+    pub(crate) fn at<R>(self, reader: &mut R) -> Self
+    where
+        R: WzRead + ?Sized,
+    {
+        #[cfg(feature = "decode-context")]
+        if let Ok(context) = DecodeContext::capture(reader) {
+            return Self::Context(Box::new(self), context);
+        }
+        let _ = reader;
+        self
+    }
 }
 
 impl fmt::Display for DecodeError {
@@ -25,6 +136,12 @@ impl fmt::Display for DecodeError {
             Self::Offset(o) => write!(f, "Invalid offset: `{}`", o),
             Self::Utf8(e) => write!(f, "UTF-8: {}", e),
             Self::Unicode(e) => write!(f, "Unicode: {}", e),
+            Self::FloatMarker(m) => write!(f, "Invalid float marker: `0x{:02X}`", m),
+            #[cfg(feature = "decode-context")]
+            Self::Context(e, context) => match f.alternate() {
+                true => write!(f, "{} at 0x{:08X}\n{}", e, context.position, context),
+                false => write!(f, "{} at 0x{:08X}", e, context.position),
+            },
         }
     }
 }
@@ -40,3 +157,35 @@ impl From<string::FromUtf16Error> for DecodeError {
         Self::Unicode(other)
     }
 }
+
+#[cfg(all(test, feature = "decode-context"))]
+mod tests {
+
+    use crate::error::{DecodeError, Error};
+    use crate::io::{DummyDecryptor, WzRead, WzReader};
+    use crate::types::WzOffset;
+    use std::io::Cursor;
+
+    #[test]
+    fn hexdump_context() {
+        let data = (0..64u8).collect::<Vec<u8>>();
+        let mut reader = WzReader::new(0, 0, Cursor::new(data), DummyDecryptor);
+        reader.seek(WzOffset::from(20u32)).unwrap();
+        let error = DecodeError::Length(-1).at(&mut reader);
+        assert_eq!(*reader.position().unwrap(), 20);
+        match &error {
+            DecodeError::Context(_, context) => {
+                assert_eq!(context.start, 4);
+                assert_eq!(context.bytes, (4..36u8).collect::<Vec<u8>>());
+            }
+            e => panic!("expected a context, found {:?}", e),
+        }
+        assert_eq!(error.to_string(), "Invalid length: `-1` at 0x00000014");
+        let dump = format!("{:#}", Error::from(error));
+        let lines = dump.lines().collect::<Vec<&str>>();
+        assert_eq!(lines.len(), 5);
+        assert!(lines[1].starts_with("00000000              04 05"));
+        assert!(lines[2].starts_with("00000010  10 11 12 13 14"));
+        assert_eq!(lines[3], format!("{:22}^^", ""));
+    }
+}
@@ -5,9 +5,18 @@ use std::fmt;
 /// Possible image errors
 #[derive(Debug)]
 pub enum ImageError {
+    /// Encoded size and the budget it exceeds
+    Budget(u64, u64),
+
+    /// Objects are nested deeper than any real image
+    Depth,
+
     /// The Image root must be a [`ImgDir`](crate::types::Property::ImgDir)
     ImageRoot,
 
+    /// No animation frames found
+    Frames(String),
+
     /// Image name mismatch
     Name(String, String),
 
@@ -23,6 +32,12 @@ pub enum ImageError {
     /// Unknown Property Type
     PropertyType(u8),
 
+    /// Number of properties that do not match the schema
+    Schema(usize),
+
+    /// Property at the path does not hold the expected type
+    Type(String, &'static str),
+
     /// Unknown UOL type
     UolType(u8),
 
@@ -33,12 +48,17 @@ pub enum ImageError {
 impl fmt::Display for ImageError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
+            Self::Budget(s, b) => write!(f, "Image is {} bytes, over the budget of {}", s, b),
+            Self::Depth => write!(f, "Objects are nested too deeply"),
             Self::ImageRoot => write!(f, "The root of the image is not a property"),
+            Self::Frames(p) => write!(f, "No animation frames found in `{}`", p),
             Self::Name(e, v) => write!(f, "Expected the image to be called {}, found {}", e, v),
             Self::ObjectType(t) => write!(f, "Unknown Object type: `{}`", t),
             Self::Path(p) => write!(f, "Invalid path: `{}`", p),
             Self::Property(s) => write!(f, "Cannot construct property: `{}`", s),
             Self::PropertyType(t) => write!(f, "Unknown Property type: `{}`", t),
+            Self::Schema(n) => write!(f, "{} properties do not match the schema", n),
+            Self::Type(p, t) => write!(f, "Expected {} at `{}`", t, p),
             Self::UolType(t) => write!(f, "Unknown UOL type: `{}`", t),
             Self::Value(s) => write!(f, "Value cannot be parsed: `{}`", s),
         }
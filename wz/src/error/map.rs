@@ -11,6 +11,9 @@ pub enum MapError {
     /// Error when creating a new child node with a name that already exists at that position
     Duplicate(String),
 
+    /// Child index is past the end of the children
+    Index(usize),
+
     /// No children to move to
     NoChildren,
 
@@ -23,6 +26,9 @@ pub enum MapError {
     /// Error when the child node at the current position does not exist
     NotFound(String),
 
+    /// Bookmarked node was deleted or detached from the map
+    StaleBookmark,
+
     /// Invalid path. Typically only occurs when an empty path vector is provided.
     Path(String),
 }
@@ -32,10 +38,12 @@ impl fmt::Display for MapError {
         match self {
             Self::ClipboardEmpty => write!(f, "Clipboard is empty"),
             Self::Duplicate(name) => write!(f, "A node named {} already exists", name),
+            Self::Index(i) => write!(f, "Child index {} is out of range", i),
             Self::NoChildren => write!(f, "No children to move to"),
             Self::NoParent => write!(f, "No parent to move to"),
             Self::NoSibling => write!(f, "No sibling to move to"),
             Self::NotFound(name) => write!(f, "Could not find {}", name),
+            Self::StaleBookmark => write!(f, "Bookmarked node no longer exists"),
             Self::Path(p) => write!(f, "Invalid path: `{}`", p),
         }
     }
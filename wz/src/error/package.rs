@@ -1,5 +1,6 @@
 //! Package Error Types
 
+use crate::types::WzOffset;
 use std::fmt;
 
 /// Possible package errors
@@ -22,6 +23,40 @@ pub enum PackageError {
 
     /// Multiple Roots
     MultipleRoots,
+
+    /// Manifest line could not be parsed
+    Manifest(String),
+
+    /// Number of images that do not match the manifest
+    Verify(usize),
+
+    /// Patch file is malformed
+    Patch,
+
+    /// Image of the base archive does not match the one the patch was made from
+    PatchBase(String),
+
+    /// Archive signature is missing or does not match
+    Signature,
+
+    /// Contents do not fit in the 32-bit sizes and offsets of the format
+    ArchiveTooLarge,
+
+    /// Client name is not a region followed by a version
+    Client(String),
+
+    /// Archive has no version hash, as written by 64-bit clients
+    Unversioned,
+
+    /// Packages are nested deeper than any real archive, usually because one points back at
+    /// itself
+    Depth,
+
+    /// Image file changed size after it was added to the archive
+    ImageChanged(String),
+
+    /// Package contents found at an offset that was already mapped
+    SharedPackage(WzOffset),
 }
 
 impl fmt::Display for PackageError {
@@ -33,6 +68,17 @@ impl fmt::Display for PackageError {
             Self::Header => write!(f, "Invalid WZ archive header"),
             Self::Path(p) => write!(f, "Invalid path name: `{}`", p),
             Self::MultipleRoots => write!(f, "A WZ archive can only have 1 root"),
+            Self::Manifest(l) => write!(f, "Invalid manifest line: `{}`", l),
+            Self::Verify(n) => write!(f, "{} images do not match the manifest", n),
+            Self::Patch => write!(f, "Invalid patch file"),
+            Self::PatchBase(p) => write!(f, "Image does not match the patch: `{}`", p),
+            Self::Signature => write!(f, "Missing or invalid archive signature"),
+            Self::ArchiveTooLarge => write!(f, "Archive is too large for 32-bit offsets"),
+            Self::Client(c) => write!(f, "Unknown client: `{}`", c),
+            Self::Unversioned => write!(f, "Archives of 64-bit clients are not supported"),
+            Self::Depth => write!(f, "Packages are nested too deeply"),
+            Self::ImageChanged(p) => write!(f, "Image file changed size: `{}`", p),
+            Self::SharedPackage(o) => write!(f, "Package contents at {} are mapped twice", o),
         }
     }
 }
@@ -1,7 +1,32 @@
 //! WZ Image
 
+#[cfg(feature = "canvas-codecs")]
+pub mod animation;
+pub mod cache;
+pub mod editor;
+pub mod query;
 pub mod reader;
+pub mod scanner;
+pub mod schema;
+pub mod search;
+pub mod size;
+#[cfg(feature = "canvas-codecs")]
+pub mod sprites;
+pub mod strings;
+pub mod visitor;
 pub mod writer;
 
-pub use reader::Reader;
+#[cfg(feature = "canvas-codecs")]
+pub use animation::Animation;
+pub use cache::{Cache, Metrics};
+pub use editor::Editor;
+pub use query::FromProperty;
+pub use reader::{ObjectDecoder, Reader, Span};
+pub use scanner::Scanner;
+pub use schema::{validate_schema, Kind, Schema, Violation};
+pub use size::SizeReport;
+#[cfg(feature = "canvas-codecs")]
+pub use sprites::SpriteSheet;
+pub use strings::StringTable;
+pub use visitor::Visitor;
 pub use writer::Writer;
@@ -0,0 +1,148 @@
+//! WZ Image animations
+
+use crate::error::{CanvasError, Result};
+use crate::image::sprites::{self, Cell};
+use crate::map::Map;
+use crate::types::Property;
+use ::image::{
+    codecs::gif::{GifEncoder, Repeat},
+    Delay, Frame, RgbaImage,
+};
+use std::{ffi::OsStr, fs, path::Path};
+
+/// Frames of an animation node, ready to be saved as a GIF or APNG.
+///
+/// Frames are collected the same way as a [`SpriteSheet`](crate::image::SpriteSheet): every
+/// frame has the same size and the origins are lined up. Each frame is shown for its `delay`.
+///
+/// Example:
+///
+/// ```no_run
+/// use crypto::{KeyStream, GMS_IV, TRIMMED_KEY};
+/// use wz::image::{animation, Reader};
+///
+/// let mut reader = Reader::open("0100100.img", KeyStream::new(&TRIMMED_KEY, &GMS_IV)).unwrap();
+/// let map = reader.map("0100100.img").unwrap();
+/// animation::export(&map, "0100100.img/move").unwrap().save("move.gif").unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct Animation {
+    frames: Vec<(RgbaImage, i32)>,
+}
+
+/// Collects the frames of the animation at `path`
+pub fn export(map: &Map<Property>, path: &str) -> Result<Animation> {
+    let frames = sprites::render_frames(map, path)?
+        .into_iter()
+        .map(|Cell { image, delay, .. }| (image, delay.clamp(0, u16::MAX as i32)))
+        .collect();
+    Ok(Animation { frames })
+}
+
+impl Animation {
+    /// Returns the frames along with their delays in milliseconds
+    pub fn frames(&self) -> &[(RgbaImage, i32)] {
+        &self.frames
+    }
+
+    /// Encodes the animation as a looping GIF. GIF delays are stored in hundredths of a second,
+    /// so delays are rounded down to that.
+    pub fn to_gif(&self) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        {
+            let mut encoder = GifEncoder::new(&mut data);
+            encoder.set_repeat(Repeat::Infinite)?;
+            encoder.encode_frames(self.frames.iter().map(|(image, delay)| {
+                Frame::from_parts(
+                    image.clone(),
+                    0,
+                    0,
+                    Delay::from_numer_denom_ms(*delay as u32, 1),
+                )
+            }))?;
+        }
+        Ok(data)
+    }
+
+    /// Encodes the animation as a looping APNG
+    pub fn to_apng(&self) -> Result<Vec<u8>> {
+        let (width, height) = self.frames[0].0.dimensions();
+        let mut data = Vec::new();
+        let mut encoder = png::Encoder::new(&mut data, width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder
+            .set_animated(self.frames.len() as u32, 0)
+            .map_err(apng_error)?;
+        let mut writer = encoder.write_header().map_err(apng_error)?;
+        for (image, delay) in &self.frames {
+            writer
+                .set_frame_delay(*delay as u16, 1000)
+                .map_err(apng_error)?;
+            writer
+                .write_image_data(image.as_raw())
+                .map_err(apng_error)?;
+        }
+        writer.finish().map_err(apng_error)?;
+        Ok(data)
+    }
+
+    /// Saves the animation as an APNG when `path` ends in `.png` or `.apng`, otherwise as a GIF
+    pub fn save<S>(&self, path: S) -> Result<()>
+    where
+        S: AsRef<Path>,
+    {
+        let data = match path.as_ref().extension().and_then(OsStr::to_str) {
+            Some("png") | Some("apng") => self.to_apng()?,
+            _ => self.to_gif()?,
+        };
+        Ok(fs::write(path, data)?)
+    }
+}
+
+fn apng_error(e: png::EncodingError) -> CanvasError {
+    CanvasError::Animation(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::image::{animation, Reader, SpriteSheet};
+    use ::image::{codecs::gif::GifDecoder, AnimationDecoder};
+    use crypto::{KeyStream, GMS_IV, TRIMMED_KEY};
+    use std::io::Cursor;
+
+    #[test]
+    fn v83_walk_animation() {
+        let mut reader = Reader::open(
+            "testdata/v83-tamingmob.img",
+            KeyStream::new(&TRIMMED_KEY, &GMS_IV),
+        )
+        .expect("error opening image");
+        let map = reader.map("tamingmob.img").expect("error mapping image");
+        let animation = animation::export(&map, "tamingmob.img/walk1").expect("error exporting");
+        let sheet = SpriteSheet::from_map(&map, "tamingmob.img/walk1", 1).expect("error packing");
+        assert_eq!(animation.frames().len(), sheet.frames().len());
+        for ((image, delay), frame) in animation.frames().iter().zip(sheet.frames()) {
+            assert_eq!(image.dimensions(), (frame.width, frame.height));
+            assert_eq!(*delay, frame.delay);
+        }
+
+        let gif = animation.to_gif().expect("error encoding GIF");
+        let frames = GifDecoder::new(Cursor::new(gif))
+            .expect("error decoding GIF")
+            .into_frames()
+            .collect_frames()
+            .expect("error decoding frames");
+        assert_eq!(frames.len(), animation.frames().len());
+        let (numer, denom) = frames[0].delay().numer_denom_ms();
+        assert_eq!(numer / denom, animation.frames()[0].1 as u32);
+
+        let apng = animation.to_apng().expect("error encoding APNG");
+        let decoder = png::Decoder::new(Cursor::new(apng));
+        let reader = decoder.read_info().expect("error decoding APNG");
+        let control = reader.info().animation_control.expect("not animated");
+        assert_eq!(control.num_frames as usize, animation.frames().len());
+        assert!(animation::export(&map, "tamingmob.img/info").is_err());
+    }
+}
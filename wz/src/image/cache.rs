@@ -0,0 +1,279 @@
+//! WZ Image cache
+
+use crate::error::Result;
+use crate::map::Map;
+use crate::types::{Canvas, Property};
+use std::{collections::VecDeque, mem, sync::Arc};
+
+/// Cache hit and miss counters
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Metrics {
+    /// Lookups served from the cache
+    pub hits: u64,
+
+    /// Lookups that had to load the entry
+    pub misses: u64,
+
+    /// Entries dropped to stay within the budget
+    pub evictions: u64,
+}
+
+/// Cached value
+#[derive(Debug, Clone)]
+enum Value {
+    Image(Arc<Map<Property>>),
+    Canvas(Arc<Vec<u8>>),
+}
+
+#[derive(Debug)]
+struct Entry {
+    archive: String,
+    path: String,
+    value: Value,
+    size: usize,
+}
+
+/// Least-recently-used cache of parsed images and decompressed canvases.
+///
+/// Entries are keyed by archive name and path. The size of each entry is estimated from its
+/// contents and the least recently used entries are dropped once the byte budget is exceeded. An
+/// entry larger than the whole budget is returned without being kept.
+///
+/// Example:
+///
+/// ```no_run
+/// use crypto::{KeyStream, GMS_IV, TRIMMED_KEY};
+/// use wz::image::{Cache, Reader};
+///
+/// let mut cache = Cache::new(64 * 1024 * 1024);
+/// let image = cache
+///     .image("Mob", "0100100.img", || {
+///         Reader::open("0100100.img", KeyStream::new(&TRIMMED_KEY, &GMS_IV))?.map("0100100.img")
+///     })
+///     .unwrap();
+/// println!("{:?}", cache.metrics());
+/// ```
+#[derive(Debug)]
+pub struct Cache {
+    budget: usize,
+    used: usize,
+    entries: VecDeque<Entry>,
+    metrics: Metrics,
+}
+
+impl Cache {
+    /// Creates an empty cache holding at most `budget` bytes
+    pub fn new(budget: usize) -> Self {
+        Self {
+            budget,
+            used: 0,
+            entries: VecDeque::new(),
+            metrics: Metrics::default(),
+        }
+    }
+
+    /// Returns the byte budget
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+
+    /// Changes the byte budget, evicting entries if needed
+    pub fn set_budget(&mut self, budget: usize) {
+        self.budget = budget;
+        self.evict(0);
+    }
+
+    /// Returns the estimated number of bytes held
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    /// Returns the number of entries held
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns true if nothing is cached
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the hit and miss counters
+    pub fn metrics(&self) -> Metrics {
+        self.metrics
+    }
+
+    /// Drops every entry. The counters are kept.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.used = 0;
+    }
+
+    /// Returns the cached image or parses it with `load`
+    pub fn image<F>(&mut self, archive: &str, path: &str, load: F) -> Result<Arc<Map<Property>>>
+    where
+        F: FnOnce() -> Result<Map<Property>>,
+    {
+        if let Some(Value::Image(image)) = self.lookup(archive, path) {
+            return Ok(image);
+        }
+        let image = Arc::new(load()?);
+        let size = image_size(&image);
+        self.insert(archive, path, Value::Image(image.clone()), size);
+        Ok(image)
+    }
+
+    /// Returns the cached pixels of the canvas at `path` or decompresses them
+    pub fn canvas(&mut self, archive: &str, path: &str, canvas: &Canvas) -> Result<Arc<Vec<u8>>> {
+        if let Some(Value::Canvas(data)) = self.lookup(archive, path) {
+            return Ok(data);
+        }
+        let data = Arc::new(canvas.decompressed_data()?);
+        let size = data.len() + path.len();
+        self.insert(archive, path, Value::Canvas(data.clone()), size);
+        Ok(data)
+    }
+
+    /// Moves the entry to the front and returns it
+    fn lookup(&mut self, archive: &str, path: &str) -> Option<Value> {
+        let index = self
+            .entries
+            .iter()
+            .position(|entry| entry.archive == archive && entry.path == path);
+        match index {
+            Some(i) => {
+                self.metrics.hits += 1;
+                let entry = self.entries.remove(i).expect("cache entry should exist");
+                let value = entry.value.clone();
+                self.entries.push_front(entry);
+                Some(value)
+            }
+            None => {
+                self.metrics.misses += 1;
+                None
+            }
+        }
+    }
+
+    fn insert(&mut self, archive: &str, path: &str, value: Value, size: usize) {
+        if size > self.budget {
+            return;
+        }
+        self.evict(size);
+        self.used += size;
+        self.entries.push_front(Entry {
+            archive: String::from(archive),
+            path: String::from(path),
+            value,
+            size,
+        });
+    }
+
+    /// Drops the least recently used entries until `size` more bytes fit
+    fn evict(&mut self, size: usize) {
+        while self.used + size > self.budget {
+            match self.entries.pop_back() {
+                Some(entry) => {
+                    self.used -= entry.size;
+                    self.metrics.evictions += 1;
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+/// Estimates the memory held by a parsed image
+fn image_size(image: &Map<Property>) -> usize {
+    let mut size = 0;
+    image
+        .walk::<()>(|cursor| {
+            size += mem::size_of::<Property>() + cursor.name().len();
+            size += match cursor.get() {
+                Property::String(s) => s.as_ref().len(),
+                Property::Uol(u) => u.as_ref().len(),
+                Property::Canvas(c) => c.data().len(),
+                Property::Sound(s) => s.data().len(),
+                Property::Unknown(t) => t.len(),
+                Property::Error(e) => e.len(),
+                _ => 0,
+            };
+            Ok(())
+        })
+        .expect("walk should not fail");
+    size
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::image::{Cache, Reader};
+    use crate::map::Map;
+    use crate::types::Property;
+    use crypto::{KeyStream, GMS_IV, TRIMMED_KEY};
+    use std::sync::Arc;
+
+    #[test]
+    fn budget_eviction() {
+        let load = || {
+            Reader::open(
+                "testdata/v83-tamingmob.img",
+                KeyStream::new(&TRIMMED_KEY, &GMS_IV),
+            )?
+            .map("tamingmob.img")
+        };
+        let mut cache = Cache::new(usize::MAX);
+        let first = cache
+            .image("TamingMob", "a.img", load)
+            .expect("error loading");
+        let second = cache
+            .image("TamingMob", "a.img", load)
+            .expect("error loading");
+        assert!(Arc::ptr_eq(&first, &second));
+        let metrics = cache.metrics();
+        assert_eq!((metrics.hits, metrics.misses), (1, 1));
+
+        // Room for a single image
+        let size = cache.used();
+        cache.set_budget(size + size / 2);
+        cache
+            .image("TamingMob", "b.img", load)
+            .expect("error loading");
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.metrics().evictions, 1);
+        cache
+            .image("TamingMob", "b.img", load)
+            .expect("error loading");
+        assert_eq!(cache.metrics().hits, 2);
+
+        // Too large to keep
+        cache.set_budget(size / 2);
+        assert!(cache.is_empty());
+        cache
+            .image("TamingMob", "c.img", load)
+            .expect("error loading");
+        assert!(cache.is_empty());
+
+        let canvas_path = "tamingmob.img/walk1/0/0";
+        let mut cache = Cache::new(usize::MAX);
+        let image: Map<Property> = load().expect("error loading");
+        let canvas = match image.get(canvas_path).expect("missing canvas") {
+            Property::Canvas(canvas) => canvas,
+            _ => panic!("expected a canvas"),
+        };
+        let pixels = cache
+            .canvas("TamingMob", canvas_path, canvas)
+            .expect("error decompressing");
+        assert_eq!(
+            pixels.len(),
+            canvas
+                .decompressed_data()
+                .expect("error decompressing")
+                .len()
+        );
+        cache
+            .canvas("TamingMob", canvas_path, canvas)
+            .expect("error decompressing");
+        assert_eq!(cache.metrics().hits, 1);
+    }
+}
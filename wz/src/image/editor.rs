@@ -0,0 +1,510 @@
+//! WZ Image Editor
+
+use crate::error::{CanvasError, Error, ImageError, MapError, Result};
+use crate::image::{writer, Reader, Span};
+use crate::io::{Encode, SizeHint, WzRead, WzWrite};
+use crate::map::Map;
+use crate::types::{Property, UolObject};
+use std::path::Path;
+
+/// Edits a mapped WZ image.
+///
+/// Subtrees are copied as decoded properties rather than raw [`Span`]s. Raw bytes may reference
+/// strings elsewhere in the source image and would not survive the move.
+///
+/// Primitive values changed with [`Editor::set`] can be saved with [`Editor::write_to`], which
+/// reuses the stored bytes of the source image where it can:
+///
+/// ```no_run
+/// use crypto::{KeyStream, GMS_IV, TRIMMED_KEY};
+/// use std::{fs::File, io::BufWriter};
+/// use wz::image::{Editor, Reader};
+/// use wz::io::{WzImageWriter, WzWriter};
+/// use wz::types::{Property, WzInt};
+///
+/// let key = KeyStream::new(&TRIMMED_KEY, &GMS_IV);
+/// let mut source = Reader::open("0100100.img", key.clone()).unwrap();
+/// let mut editor = Editor::new(source.map_lazy("0100100.img").unwrap());
+/// editor
+///     .set("0100100.img/info/speed", Property::Int(WzInt::from(-20)))
+///     .unwrap();
+/// let file = BufWriter::new(File::create("0100100.new.img").unwrap());
+/// let mut inner = WzWriter::new(0, 0, file, key);
+/// editor
+///     .write_to(&mut source, &mut WzImageWriter::new(&mut inner))
+///     .unwrap();
+/// ```
+///
+/// Example:
+///
+/// ```no_run
+/// use crypto::{KeyStream, GMS_IV, TRIMMED_KEY};
+/// use wz::image::{Editor, Reader, Writer};
+///
+/// let key = KeyStream::new(&TRIMMED_KEY, &GMS_IV);
+/// let src = Reader::open("0100100.img", key.clone()).unwrap().map("0100100.img").unwrap();
+/// let dst = Reader::open("0100101.img", key.clone()).unwrap().map("0100101.img").unwrap();
+/// let mut editor = Editor::new(dst);
+/// editor
+///     .copy_subtree(&src, "0100100.img/move", "0100101.img/move2")
+///     .unwrap();
+/// Writer::from_map(editor.into_map()).save("0100101.img", key).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct Editor {
+    map: Map<Property>,
+    edits: Vec<String>,
+    restructured: bool,
+}
+
+impl Editor {
+    /// Creates a new editor of the mapped image
+    pub fn new(map: Map<Property>) -> Self {
+        Self {
+            map,
+            edits: Vec::new(),
+            restructured: false,
+        }
+    }
+
+    /// Returns the edited image
+    pub fn map(&self) -> &Map<Property> {
+        &self.map
+    }
+
+    /// Consumes the editor and returns the edited image
+    pub fn into_map(self) -> Map<Property> {
+        self.map
+    }
+
+    /// Copies the subtree at `src_path` within `src` to `dst_path`. Both paths include the image
+    /// name. The parent of `dst_path` must exist and the last component becomes the name of the
+    /// copy.
+    ///
+    /// UOLs pointing inside the copied subtree are rewritten to point inside the copy. UOLs
+    /// pointing elsewhere are left untouched. Canvases of lazily mapped images must be loaded
+    /// first. The image is left unchanged on error.
+    pub fn copy_subtree(
+        &mut self,
+        src: &Map<Property>,
+        src_path: &str,
+        dst_path: &str,
+    ) -> Result<()> {
+        let (dst_parent, dst_name) = split(dst_path)?;
+        if self.map.cursor_at(dst_parent)?.has_child(dst_name) {
+            return Err(MapError::Duplicate(String::from(dst_name)).into());
+        }
+
+        // Collect the subtree relative to its root
+        let src_root = src.cursor_at(src_path)?;
+        let src_root_path = src_root.pwd();
+        let mut nodes = Vec::new();
+        src_root.walk::<crate::error::Error>(|cursor| {
+            let path = cursor.pwd();
+            let relative = String::from(&path[src_root_path.len()..]);
+            let data = match cursor.get() {
+                Property::Canvas(canvas) if !canvas.is_loaded() => {
+                    return Err(CanvasError::NotLoaded.into())
+                }
+                Property::Uol(uol) => Property::Uol(rewrite_uol(
+                    uol,
+                    &path,
+                    &src_root_path,
+                    &format!("{}{}", dst_path, relative),
+                    dst_path,
+                )),
+                data => data.clone(),
+            };
+            nodes.push((relative, data));
+            Ok(())
+        })?;
+
+        // Nothing below can fail: the parent exists and the copy is new
+        self.restructured = true;
+        for (relative, data) in nodes {
+            let path = format!("{}{}", dst_path, relative);
+            let (parent, name) = split(&path)?;
+            self.map
+                .cursor_mut_at(parent)?
+                .create(String::from(name), data)?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the value of the property at `path`, which includes the image name. Only
+    /// primitive properties (null, numbers and strings) can be set, and only to primitive values.
+    pub fn set(&mut self, path: &str, property: Property) -> Result<()> {
+        let mut cursor = self.map.cursor_mut_at(path)?;
+        if !is_primitive(cursor.get()) || !is_primitive(&property) {
+            return Err(ImageError::Property(String::from(path)).into());
+        }
+        *cursor.get_mut() = property;
+        self.edits.push(cursor.pwd());
+        Ok(())
+    }
+
+    /// Writes the edited image. `source` must read the image the editor was created from and
+    /// `writer` must use the same key.
+    ///
+    /// When every edit fits in place, the stored bytes of `source` are copied and only the edited
+    /// values are rewritten, so canvases and sounds are never re-encoded. An edit fits when the
+    /// new value takes up as many bytes as the old one and no other string references it.
+    /// Otherwise the whole image is encoded from the map, loading lazily mapped canvases from
+    /// `source`.
+    pub fn write_to<R, W>(&self, source: &mut Reader<R>, writer: &mut W) -> Result<()>
+    where
+        R: WzRead,
+        W: WzWrite + ?Sized,
+    {
+        let patches = match self.patches(source)? {
+            Some(patches) => patches,
+            None => {
+                self.map.walk::<Error>(|cursor| {
+                    if let Property::Canvas(canvas) = cursor.get() {
+                        source.load(canvas)?;
+                    }
+                    Ok(())
+                })?;
+                return writer::write_map(&self.map, writer);
+            }
+        };
+        let length = source.length()?;
+        let data = source.read_span(Span {
+            offset: 0.into(),
+            length,
+        })?;
+        let mut position = 0;
+        for (span, property) in patches {
+            writer.write_all(&data[position..*span.offset as usize])?;
+            encode_value(writer, property)?;
+            position = (*span.offset + span.length) as usize;
+        }
+        writer.write_all(&data[position..])
+    }
+
+    /// Finds where the edited values are stored in `source`, in order. Returns `None` if any of
+    /// them does not fit in place.
+    fn patches<R>(&self, source: &mut Reader<R>) -> Result<Option<Vec<(Span, &Property)>>>
+    where
+        R: WzRead,
+    {
+        if self.restructured {
+            return Ok(None);
+        }
+        let references = source.references()?;
+        let mut patches = Vec::new();
+        for path in &self.edits {
+            let property = self.map.get(path)?;
+            let relative = path
+                .split_once('/')
+                .map(|(_, relative)| relative)
+                .ok_or_else(|| ImageError::Path(path.clone()))?;
+            let (tag, span) = source.value_span(relative)?;
+            let fits = match (tag, property) {
+                (0, Property::Null) | (2 | 11, Property::Short(_)) | (5, Property::Double(_)) => {
+                    true
+                }
+                (3 | 19, Property::Int(v)) => v.size_hint() == span.length,
+                (20, Property::Long(v)) => v.size_hint() == span.length,
+                (4, Property::Float(v)) => v.size_hint() == span.length,
+                // Strings are referenced from the position after their type byte
+                (8, Property::String(v)) => {
+                    v.size_hint() == span.length && !references.contains(&(*span.offset + 1))
+                }
+                _ => false,
+            };
+            if !fits {
+                return Ok(None);
+            }
+            patches.push((span, property));
+        }
+        patches.sort_by_key(|(span, _)| span.offset);
+        patches.dedup_by_key(|(span, _)| span.offset);
+        Ok(Some(patches))
+    }
+}
+
+fn is_primitive(property: &Property) -> bool {
+    matches!(
+        property,
+        Property::Null
+            | Property::Short(_)
+            | Property::Int(_)
+            | Property::Long(_)
+            | Property::Float(_)
+            | Property::Double(_)
+            | Property::String(_)
+    )
+}
+
+/// Encodes a primitive value without its name and type. Strings are always stored inline.
+fn encode_value<W>(writer: &mut W, property: &Property) -> Result<()>
+where
+    W: WzWrite + ?Sized,
+{
+    match property {
+        Property::Short(v) => v.encode(writer),
+        Property::Int(v) => v.encode(writer),
+        Property::Long(v) => v.encode(writer),
+        Property::Float(v) => v.encode(writer),
+        Property::Double(v) => v.encode(writer),
+        Property::String(v) => {
+            writer.write_byte(0)?;
+            v.as_ref().encode(writer)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Splits a path into its parent and name
+fn split(path: &str) -> Result<(&str, &str)> {
+    let invalid = || ImageError::Path(String::from(path));
+    let name = Path::new(path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(invalid)?;
+    let parent = Path::new(path)
+        .parent()
+        .and_then(|parent| parent.to_str())
+        .filter(|parent| !parent.is_empty())
+        .ok_or_else(invalid)?;
+    Ok((parent, name))
+}
+
+/// Rewrites the UOL at `path` if it points inside `src_root`. UOLs are relative to their parent.
+fn rewrite_uol(
+    uol: &UolObject,
+    path: &str,
+    src_root: &str,
+    new_path: &str,
+    dst_root: &str,
+) -> UolObject {
+    let base = parent(path);
+    let target = match resolve(base, uol.as_ref()) {
+        Some(target) => target,
+        None => return uol.clone(),
+    };
+    let rest = match target.strip_prefix(src_root) {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => rest,
+        _ => return uol.clone(),
+    };
+    UolObject::from(relative(parent(new_path), &format!("{}{}", dst_root, rest)))
+}
+
+fn parent(path: &str) -> &str {
+    path.rsplit_once('/')
+        .map(|(parent, _)| parent)
+        .unwrap_or("")
+}
+
+/// Resolves `relative` from `base`. Returns `None` if it climbs above the root.
+fn resolve(base: &str, relative: &str) -> Option<String> {
+    let mut components = base
+        .split('/')
+        .filter(|c| !c.is_empty())
+        .collect::<Vec<&str>>();
+    for component in relative.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop()?;
+            }
+            c => components.push(c),
+        }
+    }
+    Some(components.join("/"))
+}
+
+/// Returns the path from `base` to `target`
+fn relative(base: &str, target: &str) -> String {
+    let base = base.split('/').collect::<Vec<&str>>();
+    let target = target.split('/').collect::<Vec<&str>>();
+    let mut common = base
+        .iter()
+        .zip(target.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    // A UOL cannot be empty, so pointing at the base goes up and back down
+    if common == target.len() {
+        common -= 1;
+    }
+    let mut components = vec![".."; base.len() - common];
+    components.extend(&target[common..]);
+    components.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::error::Error;
+    use crate::image::{Editor, Reader};
+    use crate::io::{WzImageWriter, WzReader, WzWriter};
+    use crate::map::Map;
+    use crate::types::{Property, UolObject, UolString, WzInt};
+    use crypto::{KeyStream, GMS_IV, TRIMMED_KEY};
+    use std::{fs, io::Cursor};
+
+    fn save(editor: &Editor, data: &[u8]) -> Vec<u8> {
+        let key = KeyStream::new(&TRIMMED_KEY, &GMS_IV);
+        let mut source = Reader::new(WzReader::new(0, 0, Cursor::new(data), key.clone()));
+        let mut inner = WzWriter::new(0, 0, Cursor::new(Vec::new()), key);
+        editor
+            .write_to(&mut source, &mut WzImageWriter::new(&mut inner))
+            .expect("error writing image");
+        inner.into_inner().into_inner()
+    }
+
+    fn map(data: &[u8]) -> Map<Property> {
+        let key = KeyStream::new(&TRIMMED_KEY, &GMS_IV);
+        Reader::new(WzReader::new(0, 0, Cursor::new(data), key))
+            .map("weapon.img")
+            .expect("error mapping image")
+    }
+
+    #[test]
+    fn copy_rewrites_uols() {
+        let mut src = Map::new(String::from("a.img"), Property::ImgDir);
+        let mut cursor = src.cursor_mut();
+        cursor
+            .create(String::from("other"), Property::ImgDir)
+            .expect("error creating other")
+            .create(String::from("stand"), Property::ImgDir)
+            .expect("error creating stand")
+            .move_to("stand")
+            .expect("error moving to stand")
+            .create(String::from("0"), Property::Int(WzInt::from(1)))
+            .expect("error creating 0")
+            .create(
+                String::from("1"),
+                Property::Uol(UolObject::from("../stand/0")),
+            )
+            .expect("error creating 1")
+            .create(String::from("2"), Property::Uol(UolObject::from("0")))
+            .expect("error creating 2")
+            .create(
+                String::from("3"),
+                Property::Uol(UolObject::from("../other")),
+            )
+            .expect("error creating 3")
+            .create(
+                String::from("4"),
+                Property::Uol(UolObject::from("../stand")),
+            )
+            .expect("error creating 4");
+
+        let mut editor = Editor::new(Map::new(String::from("b.img"), Property::ImgDir));
+        editor
+            .copy_subtree(&src, "a.img/stand", "b.img/walk")
+            .expect("error copying");
+        let uol = |path: &str| match editor.map().get(path).expect("missing node") {
+            Property::Uol(u) => u.to_string(),
+            _ => panic!("expected a UOL"),
+        };
+        assert_eq!(uol("b.img/walk/1"), "0");
+        assert_eq!(uol("b.img/walk/2"), "0");
+        assert_eq!(uol("b.img/walk/3"), "../other");
+        assert_eq!(uol("b.img/walk/4"), "../walk");
+        assert!(editor
+            .copy_subtree(&src, "a.img/stand", "b.img/walk")
+            .is_err());
+        assert!(editor
+            .copy_subtree(&src, "a.img/stand", "b.img/missing/walk")
+            .is_err());
+    }
+
+    #[test]
+    fn copy_between_images() {
+        let key = KeyStream::new(&TRIMMED_KEY, &GMS_IV);
+        let src = Reader::open("testdata/v83-tamingmob.img", key.clone())
+            .expect("error opening image")
+            .map("tamingmob.img")
+            .expect("error mapping image");
+        let dst = Reader::open("testdata/v83-weapon.img", key)
+            .expect("error opening image")
+            .map("weapon.img")
+            .expect("error mapping image");
+        let mut editor = Editor::new(dst);
+        editor
+            .copy_subtree(&src, "tamingmob.img/walk1", "weapon.img/ride")
+            .expect("error copying");
+        let count = |map: &Map<Property>, path: &str| {
+            let mut count = 0;
+            map.cursor_at(path)
+                .expect("missing node")
+                .walk::<()>(|_| {
+                    count += 1;
+                    Ok(())
+                })
+                .expect("walk should not fail");
+            count
+        };
+        assert_eq!(
+            count(&src, "tamingmob.img/walk1"),
+            count(editor.map(), "weapon.img/ride")
+        );
+    }
+
+    #[test]
+    fn set_in_place() {
+        let data = fs::read("testdata/v83-weapon.img").expect("error reading image");
+        let original = map(&data);
+        let mut ints = Vec::new();
+        let mut strings = Vec::new();
+        original
+            .walk::<Error>(|cursor| {
+                match cursor.get() {
+                    Property::Int(v) if v.abs() < 100 => ints.push((cursor.pwd(), *v)),
+                    Property::String(v) => strings.push((cursor.pwd(), v.clone())),
+                    _ => {}
+                }
+                Ok(())
+            })
+            .expect("error walking");
+        let (int_path, int) = ints.first().expect("no small int").clone();
+        let int = WzInt::from(*int + 1);
+
+        // A small int keeps its size, so only its byte changes
+        let mut editor = Editor::new(map(&data));
+        editor
+            .set(&int_path, Property::Int(int))
+            .expect("error setting int");
+        let patched = save(&editor, &data);
+        assert_eq!(patched.len(), data.len());
+        assert_eq!(patched.iter().zip(&data).filter(|(a, b)| a != b).count(), 1);
+        let edited = map(&patched);
+        assert_eq!(
+            format!("{:?}", edited.get(&int_path).expect("missing int")),
+            format!("{:?}", Property::Int(int))
+        );
+
+        // A large int does not fit, so the image is encoded again
+        editor
+            .set(&int_path, Property::Int(WzInt::from(100000)))
+            .expect("error setting int");
+        let (string_path, _) = strings.first().expect("no string").clone();
+        editor
+            .set(&string_path, Property::String(UolString::from("edited")))
+            .expect("error setting string");
+        let encoded = map(&save(&editor, &data));
+        let mut count = 0;
+        encoded
+            .walk::<Error>(|cursor| {
+                let expected = match cursor.pwd() {
+                    p if p == int_path => Property::Int(WzInt::from(100000)),
+                    p if p == string_path => Property::String(UolString::from("edited")),
+                    p => original.get(&p).expect("missing property").clone(),
+                };
+                assert_eq!(format!("{:?}", cursor.get()), format!("{:?}", expected));
+                count += 1;
+                Ok(())
+            })
+            .expect("error walking");
+        assert!(count > ints.len() + strings.len());
+
+        assert!(editor
+            .set("weapon.img", Property::Int(WzInt::from(1)))
+            .is_err());
+        assert!(editor.set(&int_path, Property::ImgDir).is_err());
+        assert!(editor.set("weapon.img/missing", Property::Null).is_err());
+    }
+}
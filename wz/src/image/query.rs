@@ -0,0 +1,295 @@
+//! Typed queries of WZ image values
+
+use crate::error::{ImageError, MapError, Result};
+use crate::map::Map;
+use crate::types::{Canvas, Property, Sound, UolObject, Vector};
+use std::collections::HashSet;
+
+/// Value that can be extracted from a [`Property`] by [`Map::query`]
+pub trait FromProperty: Sized {
+    /// Name of the expected property type, used in errors
+    const EXPECTED: &'static str;
+
+    /// Returns the value held by `property`, or `None` if it holds something else
+    fn from_property(property: &Property) -> Option<Self>;
+}
+
+/// Integers are read from any integer property they fit in, and from strings holding one, which
+/// many images use for numbers
+macro_rules! integer_from_property {
+    ($t:ty, $name:expr) => {
+        impl FromProperty for $t {
+            const EXPECTED: &'static str = $name;
+
+            fn from_property(property: &Property) -> Option<Self> {
+                match property {
+                    Property::Short(v) => <$t>::try_from(*v).ok(),
+                    Property::Int(v) => <$t>::try_from(**v).ok(),
+                    Property::Long(v) => <$t>::try_from(**v).ok(),
+                    Property::String(v) => v.trim().parse().ok(),
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+integer_from_property!(i16, "short");
+integer_from_property!(i32, "int");
+integer_from_property!(i64, "long");
+
+/// Floating point numbers are also read from integer properties and strings
+macro_rules! float_from_property {
+    ($t:ty, $name:expr) => {
+        impl FromProperty for $t {
+            const EXPECTED: &'static str = $name;
+
+            fn from_property(property: &Property) -> Option<Self> {
+                match property {
+                    Property::Short(v) => Some(*v as $t),
+                    Property::Int(v) => Some(**v as $t),
+                    Property::Long(v) => Some(**v as $t),
+                    Property::Float(v) => Some(*v as $t),
+                    Property::Double(v) => Some(*v as $t),
+                    Property::String(v) => v.trim().parse().ok(),
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+float_from_property!(f32, "float");
+float_from_property!(f64, "double");
+
+impl FromProperty for String {
+    const EXPECTED: &'static str = "string";
+
+    fn from_property(property: &Property) -> Option<Self> {
+        match property {
+            Property::String(v) => Some(v.to_string()),
+            _ => None,
+        }
+    }
+}
+
+impl FromProperty for Vector {
+    const EXPECTED: &'static str = "vector";
+
+    fn from_property(property: &Property) -> Option<Self> {
+        match property {
+            Property::Vector(v) => Some(*v),
+            _ => None,
+        }
+    }
+}
+
+impl FromProperty for Canvas {
+    const EXPECTED: &'static str = "canvas";
+
+    fn from_property(property: &Property) -> Option<Self> {
+        match property {
+            Property::Canvas(v) => Some(v.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl FromProperty for Sound {
+    const EXPECTED: &'static str = "sound";
+
+    fn from_property(property: &Property) -> Option<Self> {
+        match property {
+            Property::Sound(v) => Some(v.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl FromProperty for UolObject {
+    const EXPECTED: &'static str = "uol";
+
+    fn from_property(property: &Property) -> Option<Self> {
+        match property {
+            Property::Uol(v) => Some(v.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// Every property matches
+impl FromProperty for Property {
+    const EXPECTED: &'static str = "property";
+
+    fn from_property(property: &Property) -> Option<Self> {
+        Some(property.clone())
+    }
+}
+
+impl Map<Property> {
+    /// Returns the value at `path`, relative to the root of the image. `*` matches any single
+    /// name and `**` any number of nested names, in which case the first match depth-first is
+    /// returned. Errors when nothing matches or the match holds something other than `T`.
+    ///
+    /// Example:
+    ///
+    /// ```no_run
+    /// use wz::{image::Reader, io::DummyDecryptor, types::Vector};
+    ///
+    /// let map = Reader::open("01302000.img", DummyDecryptor)
+    ///     .unwrap()
+    ///     .map("01302000.img")
+    ///     .unwrap();
+    /// let level = map.query::<i32>("info/reqLevel").unwrap();
+    /// let origin = map.query::<Vector>("info/icon/origin").unwrap();
+    /// ```
+    pub fn query<T>(&self, path: &str) -> Result<T>
+    where
+        T: FromProperty,
+    {
+        let found = self
+            .matches(path)
+            .into_iter()
+            .next()
+            .ok_or_else(|| MapError::NotFound(String::from(path)))?;
+        let property = self.get_segments(&found.iter().map(String::as_str).collect::<Vec<_>>())?;
+        T::from_property(property)
+            .ok_or_else(|| ImageError::Type(found.join("/"), T::EXPECTED).into())
+    }
+
+    /// Returns the full path and value of every property matching `pattern` that holds a `T`,
+    /// depth-first. Patterns are relative to the root of the image and support `*` and `**` like
+    /// [`Map::query`], e.g. `stand/*` or `**/origin`.
+    pub fn query_all<T>(&self, pattern: &str) -> Vec<(String, T)>
+    where
+        T: FromProperty,
+    {
+        self.matches(pattern)
+            .into_iter()
+            .filter_map(|found| {
+                let property = self
+                    .get_segments(&found.iter().map(String::as_str).collect::<Vec<_>>())
+                    .ok()?;
+                Some((found.join("/"), T::from_property(property)?))
+            })
+            .collect()
+    }
+
+    /// Returns the names, starting with the root, of every node matching `pattern`
+    fn matches(&self, pattern: &str) -> Vec<Vec<String>> {
+        let segments = pattern
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .collect::<Vec<_>>();
+        let mut prefix = vec![String::from(self.name())];
+        let mut found = Vec::new();
+        let mut seen = HashSet::new();
+        self.collect_matches(&mut prefix, &segments, &mut found, &mut seen);
+        found
+    }
+
+    fn collect_matches(
+        &self,
+        prefix: &mut Vec<String>,
+        segments: &[&str],
+        found: &mut Vec<Vec<String>>,
+        seen: &mut HashSet<Vec<String>>,
+    ) {
+        let (segment, rest) = match segments.split_first() {
+            Some(split) => split,
+            None => {
+                if seen.insert(prefix.clone()) {
+                    found.push(prefix.clone());
+                }
+                return;
+            }
+        };
+        let children =
+            match self.cursor_at_segments(&prefix.iter().map(String::as_str).collect::<Vec<_>>()) {
+                Ok(cursor) => cursor.list().map(String::from).collect::<Vec<_>>(),
+                Err(_) => return,
+            };
+        match *segment {
+            "**" => {
+                self.collect_matches(prefix, rest, found, seen);
+                for child in children {
+                    prefix.push(child);
+                    self.collect_matches(prefix, segments, found, seen);
+                    prefix.pop();
+                }
+            }
+            "*" => {
+                for child in children {
+                    prefix.push(child);
+                    self.collect_matches(prefix, rest, found, seen);
+                    prefix.pop();
+                }
+            }
+            name => {
+                if children.iter().any(|child| child == name) {
+                    prefix.push(String::from(name));
+                    self.collect_matches(prefix, rest, found, seen);
+                    prefix.pop();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::error::{Error, ImageError, MapError};
+    use crate::image::Reader;
+    use crate::types::{Canvas, Property, Vector};
+    use crypto::{KeyStream, GMS_IV, TRIMMED_KEY};
+
+    #[test]
+    fn typed_queries() {
+        let map = Reader::open(
+            "testdata/v83-weapon.img",
+            KeyStream::new(&TRIMMED_KEY, &GMS_IV),
+        )
+        .expect("error opening image")
+        .map("weapon.img")
+        .expect("error mapping image");
+
+        assert_eq!(map.query::<i32>("info/incPAD").unwrap(), 17);
+        assert_eq!(map.query::<i64>("info/attack").unwrap(), 1);
+        assert_eq!(map.query::<String>("info/islot").unwrap(), "Wp");
+        assert_eq!(
+            map.query::<Vector>("info/icon/origin").unwrap(),
+            Vector::from((0, 30))
+        );
+        assert_eq!(map.query::<i32>("*/level/info/1/exp").unwrap(), 10000);
+        match map.query::<i32>("info/islot") {
+            Err(Error::Image(ImageError::Type(path, "int"))) => {
+                assert_eq!(path, "weapon.img/info/islot")
+            }
+            r => panic!("expected a type error, found {:?}", r),
+        }
+        match map.query::<i32>("info/missing") {
+            Err(Error::Map(MapError::NotFound(_))) => {}
+            r => panic!("expected a missing path, found {:?}", r),
+        }
+
+        let canvases = map.query_all::<Canvas>("info/*");
+        let paths = canvases
+            .iter()
+            .map(|(path, _)| path.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(paths, ["weapon.img/info/icon", "weapon.img/info/iconRaw"]);
+        let origins = map.query_all::<Vector>("**/origin");
+        assert!(origins.len() >= 2);
+        assert!(origins.iter().all(|(path, _)| path.ends_with("/origin")));
+        let everything = map.query_all::<Property>("**");
+        let mut count = 0;
+        map.walk::<()>(|_| {
+            count += 1;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(everything.len(), count);
+        assert_eq!(map.query_all::<Property>("**/**").len(), everything.len());
+    }
+}
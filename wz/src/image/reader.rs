@@ -1,19 +1,53 @@
 //! WZ Image Reader
 
-use crate::error::{DecodeError, ImageError, Result};
+use crate::error::{DecodeError, Error, ImageError, Result};
 use crate::io::{Decode, WzImageReader, WzRead, WzReader};
 use crate::map::{CursorMut, Map};
-use crate::types::{raw, Canvas, Property, WzInt, WzOffset};
+use crate::types::{raw, Canvas, Property, StringDecoding, UolString, WzInt, WzOffset};
 use crypto::Decryptor;
-use std::{fs::File, io::BufReader, path::Path};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::BufReader,
+    path::Path,
+};
+
+/// Deepest objects are nested when mapping an image. Real images are only a few levels deep but
+/// the nesting is otherwise only bounded by the size of the image.
+pub(crate) const MAX_DEPTH: usize = 64;
+
+/// Location of an object within a WZ image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    /// Offset of the object from the start of the image
+    pub offset: WzOffset,
+
+    /// Number of bytes the object takes up, including all of its children
+    pub length: u32,
+}
+
+/// Decodes an object type this crate does not know into a property. It is given the bytes stored
+/// after the object tag, exactly as they are in the image.
+pub type ObjectDecoder = fn(&[u8]) -> Result<Property>;
 
 /// Reads a WZ image.
+///
+/// Objects with an unknown tag are mapped as [`Property::Unknown`] unless a decoder was
+/// registered for the tag with [`Reader::register`].
 #[derive(Debug)]
 pub struct Reader<R>
 where
     R: WzRead,
 {
     inner: R,
+    decoders: HashMap<String, ObjectDecoder>,
+}
+
+/// Settings shared while mapping an image
+struct Options<'a> {
+    lazy: bool,
+    recover: bool,
+    decoders: &'a HashMap<String, ObjectDecoder>,
 }
 
 impl<D> Reader<WzReader<BufReader<File>, D>>
@@ -24,9 +58,12 @@ where
     where
         S: AsRef<Path>,
     {
-        Ok(Self {
-            inner: WzReader::new(0, 0, BufReader::new(File::open(path)?), decryptor),
-        })
+        Ok(Self::new(WzReader::new(
+            0,
+            0,
+            BufReader::new(File::open(path)?),
+            decryptor,
+        )))
     }
 }
 
@@ -36,33 +73,235 @@ where
 {
     /// Creates a new WZ image reader
     pub fn new(inner: R) -> Self {
-        Self { inner }
+        Self {
+            inner,
+            decoders: HashMap::new(),
+        }
+    }
+
+    /// Registers `decoder` for objects tagged `tag`, replacing any decoder registered before.
+    /// Objects of the built-in types are always decoded by this crate.
+    pub fn register(&mut self, tag: &str, decoder: ObjectDecoder) -> &mut Self {
+        self.decoders.insert(String::from(tag), decoder);
+        self
+    }
+
+    /// Sets how strings that are not valid UTF-8 or UTF-16 are decoded. By default they are
+    /// decoded lossily, see [`StringDecoding`].
+    pub fn set_string_decoding(&mut self, decoding: StringDecoding) -> &mut Self {
+        self.inner.set_string_decoding(decoding);
+        self
     }
 
     /// Maps the archive contents. The root will be named `name`
     pub fn map(&mut self, name: &str) -> Result<Map<Property>> {
-        let mut map = Map::new(String::from(name), Property::ImgDir);
+        self.map_with(name, false, false)
+    }
+
+    /// Maps the archive contents like [`Reader::map`] but keeps going when an object cannot be
+    /// decoded. The object is mapped as a [`Property::Error`] holding the error and its siblings
+    /// are mapped as usual. The root will be named `name` and must decode.
+    pub fn map_recovering(&mut self, name: &str) -> Result<Map<Property>> {
+        self.map_with(name, false, true)
+    }
+
+    /// Maps the archive contents without reading canvas data. The root will be named `name`.
+    ///
+    /// Canvas data is read on demand with [`Reader::load`]. This keeps metadata-only scans from
+    /// holding every image in memory.
+    pub fn map_lazy(&mut self, name: &str) -> Result<Map<Property>> {
+        self.map_with(name, true, false)
+    }
+
+    /// Reads the data of a canvas mapped by [`Reader::map_lazy`]. Loaded canvases are left as is.
+    pub fn load<'a>(&mut self, canvas: &'a Canvas) -> Result<&'a [u8]> {
+        canvas.load(&mut WzImageReader::new(&mut self.inner))
+    }
+
+    /// Returns the location of the object at `path`. The path is relative to the image root, e.g.
+    /// `info/icon`. Primitive values have no span of their own.
+    pub fn span(&mut self, path: &str) -> Result<Span> {
         let mut reader = WzImageReader::new(&mut self.inner);
-        let object = raw::Object::decode(&mut reader)?;
-        match &object {
-            raw::Object::Property(p) => {
-                map_property_to(p, &mut reader, &mut map.cursor_mut())?;
-                Ok(map)
-            }
-            _ => Err(ImageError::ImageRoot.into()),
+        reader.seek_to_start()?;
+        let mut object = match raw::Object::decode_lazy(&mut reader)? {
+            raw::Object::Property(p) => raw::Object::Property(p),
+            _ => return Err(ImageError::ImageRoot.into()),
+        };
+        let mut span = None;
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            let child = find_child(&object, name, &mut reader)?
+                .ok_or_else(|| ImageError::Path(String::from(path)))?;
+            reader.seek(child.offset)?;
+            object = raw::Object::decode_lazy(&mut reader)?;
+            span = Some(child);
         }
+        Ok(span.ok_or_else(|| ImageError::Path(String::from(path)))?)
+    }
+
+    /// Returns the bytes of `span` exactly as they are stored.
+    ///
+    /// Strings within the span may reference strings stored earlier in the image, so the bytes
+    /// are only meaningful alongside the image they came from.
+    pub fn read_span(&mut self, span: Span) -> Result<Vec<u8>> {
+        let mut reader = WzImageReader::new(&mut self.inner);
+        reader.seek(span.offset)?;
+        let mut data = vec![0u8; span.length as usize];
+        reader.read_exact(&mut data)?;
+        Ok(data)
     }
 
     /// Consumes the archive and returns the inner reader
     pub fn into_inner(self) -> R {
         self.inner
     }
+
+    /// Returns the number of bytes the image takes up
+    pub(crate) fn length(&mut self) -> Result<u32> {
+        let mut reader = WzImageReader::new(&mut self.inner);
+        reader.seek_to_start()?;
+        raw::Object::decode_lazy(&mut reader)?;
+        Ok(*reader.position()?)
+    }
+
+    /// Returns the offsets of every string that is referenced from elsewhere in the image
+    pub(crate) fn references(&mut self) -> Result<HashSet<u32>> {
+        let mut reader = WzImageReader::new(&mut self.inner);
+        reader.seek_to_start()?;
+        reader.record_references();
+        let options = Options {
+            lazy: true,
+            recover: false,
+            decoders: &HashMap::new(),
+        };
+        map_image(&mut reader, "", &options)?;
+        Ok(reader.take_references())
+    }
+
+    /// Returns the property type tag and the location of the value of the primitive property at
+    /// `path`. The path is relative to the image root, e.g. `info/speed`.
+    pub(crate) fn value_span(&mut self, path: &str) -> Result<(u8, Span)> {
+        let invalid = || ImageError::Path(String::from(path));
+        let (parent, name) = match path.rsplit_once('/') {
+            Some((parent, name)) => (self.span(parent)?.offset, name),
+            None => (WzOffset::from(0), path),
+        };
+        let mut reader = WzImageReader::new(&mut self.inner);
+        reader.seek(parent)?;
+        match reader.read_object_tag()?.as_str() {
+            "Property" => {}
+            "Canvas" => {
+                u8::decode(&mut reader)?;
+                if u8::decode(&mut reader)? != 1 {
+                    return Err(invalid().into());
+                }
+            }
+            _ => return Err(invalid().into()),
+        }
+        u16::decode(&mut reader)?;
+        let num_contents = WzInt::decode(&mut reader)?;
+        for _ in 0..*num_contents {
+            let start = reader.position()?;
+            let found = UolString::decode(&mut reader)?.as_ref() == name;
+            let tag = u8::decode(&mut reader)?;
+            let offset = reader.position()?;
+            reader.seek(start)?;
+            let content = raw::ContentRef::decode(&mut reader)?;
+            if found {
+                return match content {
+                    raw::ContentRef::Object { .. } => Err(invalid().into()),
+                    _ => {
+                        let length = *reader.position()? - *offset;
+                        Ok((tag, Span { offset, length }))
+                    }
+                };
+            }
+        }
+        Err(invalid().into())
+    }
+
+    fn map_with(&mut self, name: &str, lazy: bool, recover: bool) -> Result<Map<Property>> {
+        let mut reader = WzImageReader::new(&mut self.inner);
+        let options = Options {
+            lazy,
+            recover,
+            decoders: &self.decoders,
+        };
+        map_image(&mut reader, name, &options)
+    }
+}
+
+/// Maps the image from the current position of `reader`
+fn map_image<R>(reader: &mut R, name: &str, options: &Options) -> Result<Map<Property>>
+where
+    R: WzRead,
+{
+    let mut map = Map::new(String::from(name), Property::ImgDir);
+    let object = raw::Object::decode(reader)?;
+    match &object {
+        raw::Object::Property(p) => {
+            map_property_to(p, reader, &mut map.cursor_mut(), options, 1)?;
+            Ok(map)
+        }
+        _ => Err(ImageError::ImageRoot.into()),
+    }
+}
+
+/// Finds the span of the child called `name`. Must be called right after decoding `object` since
+/// convex children follow the object tag directly.
+fn find_child<R>(object: &raw::Object, name: &str, reader: &mut R) -> Result<Option<Span>>
+where
+    R: WzRead,
+{
+    let property = match object {
+        raw::Object::Property(p) => p,
+        raw::Object::Canvas(raw::Canvas {
+            property: Some(p), ..
+        }) => p,
+        raw::Object::Convex => {
+            let index = match name.parse::<i32>() {
+                Ok(index) => index,
+                Err(_) => return Ok(None),
+            };
+            let num_objects = WzInt::decode(reader)?;
+            if num_objects.is_negative() {
+                return Err(DecodeError::Length(*num_objects).at(reader).into());
+            }
+            for i in 0..*num_objects {
+                let offset = reader.position()?;
+                // The length of an unknown object is not stored, so the next one cannot be found
+                if let raw::Object::Unknown(t) = raw::Object::decode_lazy(reader)? {
+                    return Err(ImageError::ObjectType(t).into());
+                }
+                if i == index {
+                    let length = *reader.position()? - *offset;
+                    return Ok(Some(Span { offset, length }));
+                }
+            }
+            return Ok(None);
+        }
+        _ => return Ok(None),
+    };
+    Ok(property.contents.iter().find_map(|content| match content {
+        raw::ContentRef::Object {
+            name: n,
+            offset,
+            size,
+        } if n.as_ref() == name => Some(Span {
+            offset: *offset,
+            length: *size,
+        }),
+        _ => None,
+    }))
 }
 
+/// Maps the contents of `property` below the cursor. `depth` is the depth of the contents within
+/// the image.
 fn map_property_to<R>(
     property: &raw::Property,
     reader: &mut R,
     cursor: &mut CursorMut<Property>,
+    options: &Options,
+    depth: usize,
 ) -> Result<()>
 where
     R: WzRead,
@@ -90,45 +329,76 @@ where
             raw::ContentRef::String { name, value } => {
                 cursor.create(String::from(name.as_ref()), Property::String(value.clone()))?;
             }
-            raw::ContentRef::Object { name, offset, .. } => {
-                map_object_to(name.as_ref(), *offset, reader, cursor)?;
+            raw::ContentRef::Object { name, offset, size } => {
+                let cursor_depth = options.recover.then(|| cursor.segments().len());
+                let name = name.as_ref();
+                match (
+                    cursor_depth,
+                    map_object_to(name, *offset, Some(*size), reader, cursor, options, depth),
+                ) {
+                    (Some(cursor_depth), Err(e)) => recover(name, cursor_depth, e, cursor)?,
+                    (_, result) => result?,
+                }
             }
         }
     }
     Ok(())
 }
 
+/// Replaces whatever was mapped of the object `name` with a [`Property::Error`] holding `error`.
+/// `depth` is the depth of the cursor before the object was mapped.
+fn recover(name: &str, depth: usize, error: Error, cursor: &mut CursorMut<Property>) -> Result<()> {
+    while cursor.segments().len() > depth {
+        cursor.parent()?;
+    }
+    if cursor.has_child(name) {
+        cursor.delete(name)?;
+    }
+    cursor.create(String::from(name), Property::Error(error.to_string()))?;
+    Ok(())
+}
+
+/// Maps the object at `offset`. `size` is the stored length of the object, which is only known
+/// for objects listed in a property. `depth` is the depth of the object within the image.
 fn map_object_to<R>(
     name: &str,
     offset: WzOffset,
+    size: Option<u32>,
     reader: &mut R,
     cursor: &mut CursorMut<Property>,
+    options: &Options,
+    depth: usize,
 ) -> Result<()>
 where
     R: WzRead,
 {
+    if depth > MAX_DEPTH {
+        return Err(ImageError::Depth.into());
+    }
+    let lazy = options.lazy;
     reader.seek(offset)?;
-    let object = raw::Object::decode(reader)?;
+    let object = if lazy {
+        raw::Object::decode_lazy(reader)?
+    } else {
+        raw::Object::decode(reader)?
+    };
     match &object {
         raw::Object::Property(p) => {
             cursor.create(String::from(name), Property::ImgDir)?;
             cursor.move_to(name)?;
-            map_property_to(p, reader, cursor)?;
+            map_property_to(p, reader, cursor, options, depth + 1)?;
             cursor.parent()?;
         }
         raw::Object::Canvas(c) => {
-            cursor.create(
-                String::from(name),
-                Property::Canvas(Canvas::new(
-                    c.width,
-                    c.height,
-                    c.format,
-                    Vec::from(c.data.as_slice()),
-                )),
-            )?;
+            let canvas = if lazy {
+                Canvas::lazy(c.width, c.height, c.format, c.offset, c.length)
+            } else {
+                Canvas::new(c.width, c.height, c.format, Vec::from(c.data.as_slice()))
+            };
+            cursor.create(String::from(name), Property::Canvas(canvas))?;
             if let Some(p) = &c.property {
                 cursor.move_to(name)?;
-                map_property_to(p, reader, cursor)?;
+                map_property_to(p, reader, cursor, options, depth + 1)?;
                 cursor.parent()?;
             }
         }
@@ -137,11 +407,13 @@ where
             cursor.move_to(name)?;
             let num_objects = WzInt::decode(reader)?;
             if num_objects.is_negative() {
-                return Err(DecodeError::Length(*num_objects).into());
+                return Err(DecodeError::Length(*num_objects).at(reader).into());
             }
             let num_objects = *num_objects as usize;
             for i in 0..num_objects {
-                map_object_to(&i.to_string(), reader.position()?, reader, cursor)?;
+                let position = reader.position()?;
+                let name = i.to_string();
+                map_object_to(&name, position, None, reader, cursor, options, depth + 1)?;
             }
             cursor.parent()?;
         }
@@ -154,6 +426,207 @@ where
         raw::Object::Sound(s) => {
             cursor.create(String::from(name), Property::Sound(s.clone()))?;
         }
+        raw::Object::Unknown(t) => {
+            let size = size.ok_or_else(|| ImageError::ObjectType(t.clone()))?;
+            let property = match options.decoders.get(t) {
+                Some(decoder) => {
+                    let length = (*offset + size)
+                        .checked_sub(*reader.position()?)
+                        .ok_or_else(|| ImageError::ObjectType(t.clone()))?;
+                    let mut data = vec![0u8; length as usize];
+                    reader.read_exact(&mut data)?;
+                    decoder(&data)?
+                }
+                None => Property::Unknown(t.clone()),
+            };
+            cursor.create(String::from(name), property)?;
+        }
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+
+    use crate::error::Result;
+    use crate::image::{Reader, Writer};
+    use crate::io::{Decode, Encode, WzImageReader, WzImageWriter, WzRead, WzReader, WzWriter};
+    use crate::map::Map;
+    use crate::types::{raw, Property, Vector, WzInt};
+    use crypto::{KeyStream, GMS_IV, TRIMMED_KEY};
+    use std::{fs, io::Cursor};
+
+    #[test]
+    fn object_spans() {
+        let mut reader = Reader::open(
+            "testdata/v83-weapon.img",
+            KeyStream::new(&TRIMMED_KEY, &GMS_IV),
+        )
+        .expect("error opening image");
+        let map = reader.map("weapon.img").expect("error mapping image");
+        let mut objects = 0;
+        map.walk::<()>(|cursor| {
+            let path = cursor.pwd();
+            let path = path.strip_prefix("weapon.img").expect("bad root");
+            let path = path.trim_start_matches('/');
+            match cursor.get() {
+                _ if path.is_empty() => {}
+                Property::ImgDir
+                | Property::Canvas(_)
+                | Property::Convex
+                | Property::Vector(_)
+                | Property::Uol(_)
+                | Property::Sound(_) => {
+                    let span = reader.span(path).expect("error finding span");
+                    let mut inner = WzImageReader::new(&mut reader.inner);
+                    inner.seek(span.offset).expect("error seeking");
+                    if let raw::Object::Convex =
+                        raw::Object::decode(&mut inner).expect("error decoding")
+                    {
+                        return Ok(());
+                    }
+                    let end = inner.position().expect("error reading position");
+                    assert_eq!(*end - *span.offset, span.length, "{}", path);
+                    objects += 1;
+                }
+                _ => assert!(reader.span(path).is_err()),
+            }
+            Ok(())
+        })
+        .expect("walk should not fail");
+        assert!(objects > 0);
+        assert!(reader.span("info/missing").is_err());
+    }
+
+    #[test]
+    fn lazy_canvas() {
+        let mut reader = Reader::open(
+            "testdata/v83-weapon.img",
+            KeyStream::new(&TRIMMED_KEY, &GMS_IV),
+        )
+        .expect("error opening image");
+        let eager = reader.map("weapon.img").expect("error mapping image");
+        let mut reader = Reader::open(
+            "testdata/v83-weapon.img",
+            KeyStream::new(&TRIMMED_KEY, &GMS_IV),
+        )
+        .expect("error opening image");
+        let lazy = reader.map_lazy("weapon.img").expect("error mapping image");
+        let mut canvases = 0;
+        lazy.walk::<()>(|cursor| {
+            if let Property::Canvas(canvas) = cursor.get() {
+                assert!(!canvas.is_loaded());
+                assert!(canvas.decompressed_data().is_err());
+                let expected = match eager.get(cursor.pwd()).expect("missing node") {
+                    Property::Canvas(c) => c.data(),
+                    _ => panic!("expected a canvas"),
+                };
+                assert_eq!(reader.load(canvas).expect("error loading"), expected);
+                assert!(canvas.is_loaded());
+                canvases += 1;
+            }
+            Ok(())
+        })
+        .expect("walk should not fail");
+        assert!(canvases > 0);
+    }
+
+    #[test]
+    fn recover_objects() {
+        let key = || KeyStream::new(&TRIMMED_KEY, &GMS_IV);
+        let mut reader =
+            Reader::open("testdata/v83-weapon.img", key()).expect("error opening image");
+        let expected = reader.map("weapon.img").expect("error mapping image");
+        let span = reader.span("info/icon").expect("error finding span");
+
+        // Break the object tag of the icon
+        let mut data = fs::read("testdata/v83-weapon.img").expect("error reading image");
+        data[*span.offset as usize] = 0xff;
+        let open = || Reader::new(WzReader::new(0, 0, Cursor::new(data.clone()), key()));
+        assert!(open().map("weapon.img").is_err());
+        let map = open()
+            .map_recovering("weapon.img")
+            .expect("error mapping image");
+        match map.get("weapon.img/info/icon").expect("missing icon") {
+            Property::Error(e) => assert!(e.contains("UOL type"), "{}", e),
+            p => panic!("expected an error, found {:?}", p),
+        }
+
+        // Everything else is mapped
+        let mut paths = 0;
+        expected
+            .walk::<()>(|cursor| {
+                let path = cursor.pwd();
+                if !path.starts_with("weapon.img/info/icon/") {
+                    assert!(map.get(&path).is_ok(), "{}", path);
+                    paths += 1;
+                }
+                Ok(())
+            })
+            .expect("walk should not fail");
+        let mut recovered = 0;
+        map.walk::<()>(|_| {
+            recovered += 1;
+            Ok(())
+        })
+        .expect("walk should not fail");
+        assert_eq!(recovered, paths);
+    }
+
+    fn encode_string(s: &str) -> Vec<u8> {
+        let mut writer = WzWriter::unencrypted(0, 0, Cursor::new(Vec::new()));
+        s.encode(&mut writer).expect("error encoding");
+        writer.into_inner().into_inner()
+    }
+
+    fn decode_vector(data: &[u8]) -> Result<Property> {
+        let mut reader = WzReader::unencrypted(0, 0, Cursor::new(data));
+        Ok(Property::Vector(Vector::decode(&mut reader)?))
+    }
+
+    #[test]
+    fn unknown_objects() {
+        let mut map = Map::new(String::from("test.img"), Property::ImgDir);
+        let vector = Vector::new(WzInt::from(3), WzInt::from(-4));
+        map.cursor_mut()
+            .create(String::from("v"), Property::Vector(vector))
+            .expect("error creating");
+        map.cursor_mut()
+            .create(String::from("n"), Property::Int(WzInt::from(5)))
+            .expect("error creating");
+        let mut writer = WzWriter::unencrypted(0, 0, Cursor::new(Vec::new()));
+        Writer::from_map(map)
+            .write_to(&mut WzImageWriter::new(&mut writer))
+            .expect("error writing");
+        let mut data = writer.into_inner().into_inner();
+
+        // Retag the vector as an object type from a newer client
+        let known = encode_string("Shape2D#Vector2D");
+        let unknown = encode_string("Shape2D#Vector3D");
+        let start = data
+            .windows(known.len())
+            .position(|w| w == known)
+            .expect("missing tag");
+        data[start..start + known.len()].copy_from_slice(&unknown);
+
+        let mut reader = Reader::new(WzReader::unencrypted(0, 0, Cursor::new(data.clone())));
+        let map = reader.map("test.img").expect("error mapping image");
+        match map.get("test.img/v").expect("missing object") {
+            Property::Unknown(t) => assert_eq!(t, "Shape2D#Vector3D"),
+            p => panic!("expected an unknown object, found {:?}", p),
+        }
+        assert!(matches!(map.get("test.img/n"), Ok(Property::Int(v)) if **v == 5));
+        let mut writer = WzWriter::unencrypted(0, 0, Cursor::new(Vec::new()));
+        assert!(Writer::from_map(map)
+            .write_to(&mut WzImageWriter::new(&mut writer))
+            .is_err());
+
+        let mut reader = Reader::new(WzReader::unencrypted(0, 0, Cursor::new(data)));
+        reader.register("Shape2D#Vector3D", decode_vector);
+        let map = reader.map("test.img").expect("error mapping image");
+        match map.get("test.img/v").expect("missing object") {
+            Property::Vector(v) => assert_eq!(*v, vector),
+            p => panic!("expected a vector, found {:?}", p),
+        }
+    }
+}
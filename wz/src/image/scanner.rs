@@ -0,0 +1,372 @@
+//! WZ Image Scanner
+
+use crate::error::{DecodeError, ImageError, Result};
+use crate::image::reader::MAX_DEPTH;
+use crate::io::{Decode, WzImageReader, WzRead, WzReader};
+use crate::types::{raw, Property, Sound, UolObject, Vector, WzInt, WzOffset};
+use crypto::Decryptor;
+use std::{fs::File, io::BufReader, path::Path};
+
+/// Streams the string and UOL properties of a WZ image without mapping it.
+///
+/// Canvas and sound payloads are skipped, so scanning is much cheaper than
+/// [`Reader::map`](crate::image::Reader::map) when only text is needed.
+///
+/// Example:
+///
+/// ```no_run
+/// use wz::{image::Scanner, io::DummyDecryptor, types::Property};
+///
+/// let mut scanner = Scanner::open("Mob.img", DummyDecryptor).unwrap();
+/// scanner
+///     .scan("Mob.img", |path, property| {
+///         if let Property::String(s) = property {
+///             println!("{}: {}", path, s.as_ref());
+///         }
+///         Ok(())
+///     })
+///     .unwrap();
+/// ```
+#[derive(Debug)]
+pub struct Scanner<R>
+where
+    R: WzRead,
+{
+    inner: R,
+}
+
+impl<D> Scanner<WzReader<BufReader<File>, D>>
+where
+    D: Decryptor,
+{
+    pub fn open<S>(path: S, decryptor: D) -> Result<Self>
+    where
+        S: AsRef<Path>,
+    {
+        Ok(Self {
+            inner: WzReader::new(0, 0, BufReader::new(File::open(path)?), decryptor),
+        })
+    }
+}
+
+impl<R> Scanner<R>
+where
+    R: WzRead,
+{
+    /// Creates a new WZ image scanner
+    pub fn new(inner: R) -> Self {
+        Self { inner }
+    }
+
+    /// Calls `closure` with the full path of every [`Property::String`] and [`Property::Uol`].
+    /// The root will be named `name`.
+    pub fn scan<F>(&mut self, name: &str, mut closure: F) -> Result<()>
+    where
+        F: FnMut(&str, &Property) -> Result<()>,
+    {
+        let mut reader = WzImageReader::new(&mut self.inner);
+        match raw::Object::decode(&mut reader)? {
+            raw::Object::Property(p) => scan_property(name, &p, &mut reader, &mut closure, 1),
+            _ => Err(ImageError::ImageRoot.into()),
+        }
+    }
+
+    /// Consumes the scanner and returns the inner reader
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+fn scan_property<R, F>(
+    path: &str,
+    property: &raw::Property,
+    reader: &mut R,
+    closure: &mut F,
+    depth: usize,
+) -> Result<()>
+where
+    R: WzRead,
+    F: FnMut(&str, &Property) -> Result<()>,
+{
+    for content in &property.contents {
+        match content {
+            raw::ContentRef::String { name, value } => {
+                closure(
+                    &format!("{}/{}", path, name.as_ref()),
+                    &Property::String(value.clone()),
+                )?;
+            }
+            raw::ContentRef::Object { name, offset, size } => {
+                scan_object(
+                    &format!("{}/{}", path, name.as_ref()),
+                    *offset,
+                    Some(*size),
+                    reader,
+                    closure,
+                    depth,
+                )?;
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Scans the object at `offset` and leaves `reader` at its end. `size` is the stored length of
+/// the object, which is only known for objects listed in a property. `depth` is the depth of the
+/// object within the image.
+fn scan_object<R, F>(
+    path: &str,
+    offset: WzOffset,
+    size: Option<u32>,
+    reader: &mut R,
+    closure: &mut F,
+    depth: usize,
+) -> Result<()>
+where
+    R: WzRead,
+    F: FnMut(&str, &Property) -> Result<()>,
+{
+    if depth > MAX_DEPTH {
+        return Err(ImageError::Depth.into());
+    }
+    reader.seek(offset)?;
+    let typename = reader.read_object_tag()?;
+    match typename.as_ref() {
+        "Property" => {
+            let property = raw::Property::decode(reader)?;
+            let end = reader.position()?;
+            scan_property(path, &property, reader, closure, depth + 1)?;
+            reader.seek(end)?;
+        }
+        "Canvas" => {
+            // Only the embedded properties are scanned. The pixel data is skipped.
+            let canvas = raw::Canvas::decode_lazy(reader)?;
+            if let Some(property) = &canvas.property {
+                let end = reader.position()?;
+                scan_property(path, property, reader, closure, depth + 1)?;
+                reader.seek(end)?;
+            }
+        }
+        "Shape2D#Convex2D" => {
+            let num_objects = WzInt::decode(reader)?;
+            if num_objects.is_negative() {
+                return Err(DecodeError::Length(*num_objects).at(reader).into());
+            }
+            // Convex children are stored back to back, so each one is read to its end
+            for i in 0..*num_objects {
+                let position = reader.position()?;
+                let path = format!("{}/{}", path, i);
+                scan_object(&path, position, None, reader, closure, depth + 1)?;
+            }
+        }
+        "Shape2D#Vector2D" => {
+            Vector::decode(reader)?;
+        }
+        "UOL" => closure(path, &Property::Uol(UolObject::decode(reader)?))?,
+        "Sound_DX8" => {
+            Sound::decode(reader)?;
+        }
+        // Unknown objects are skipped like any other object without text, which is only possible
+        // when their length is stored
+        t => {
+            let size = size.ok_or_else(|| ImageError::ObjectType(String::from(t)))?;
+            reader.seek(offset + size.into())?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::{
+        error::{Error, ImageError, Result},
+        image::{reader::MAX_DEPTH, Reader, Scanner},
+        io::{Encode, WzImageWriter, WzReader, WzWrite, WzWriter},
+        types::{Property, UolObject, UolString, Vector, WzInt},
+    };
+    use crypto::{KeyStream, GMS_IV, TRIMMED_KEY};
+    use std::io::Cursor;
+
+    #[test]
+    fn scan_matches_map() {
+        let mut reader = Reader::open(
+            "testdata/v83-tamingmob.img",
+            KeyStream::new(&TRIMMED_KEY, &GMS_IV),
+        )
+        .expect("error opening image");
+        let map = reader.map("tamingmob.img").expect("error mapping image");
+        let mut expected = Vec::new();
+        map.walk::<()>(|cursor| {
+            match cursor.get() {
+                Property::String(s) => expected.push((cursor.pwd(), s.to_string())),
+                Property::Uol(u) => expected.push((cursor.pwd(), u.to_string())),
+                _ => {}
+            }
+            Ok(())
+        })
+        .expect("walk should not fail");
+
+        let mut scanner = Scanner::open(
+            "testdata/v83-tamingmob.img",
+            KeyStream::new(&TRIMMED_KEY, &GMS_IV),
+        )
+        .expect("error opening image");
+        let mut scanned = Vec::new();
+        scanner
+            .scan("tamingmob.img", |path, property| {
+                match property {
+                    Property::String(s) => scanned.push((path.to_string(), s.to_string())),
+                    Property::Uol(u) => scanned.push((path.to_string(), u.to_string())),
+                    _ => {}
+                }
+                Ok(())
+            })
+            .expect("error scanning image");
+        assert!(!scanned.is_empty());
+        assert_eq!(scanned, expected);
+    }
+
+    /// Writes a property holding `count` contents, the first written by `contents`
+    fn write_property<W, F>(writer: &mut W, count: i32, contents: F) -> Result<()>
+    where
+        W: WzWrite,
+        F: FnOnce(&mut W) -> Result<()>,
+    {
+        writer.write_object_tag("Property")?;
+        0u16.encode(writer)?;
+        WzInt::from(count).encode(writer)?;
+        contents(writer)
+    }
+
+    fn write_string<W>(writer: &mut W, name: &str, value: &str) -> Result<()>
+    where
+        W: WzWrite,
+    {
+        UolString::from(name).encode(writer)?;
+        8u8.encode(writer)?;
+        UolString::from(value).encode(writer)
+    }
+
+    #[test]
+    fn scan_nested_convex() {
+        // shape: a convex holding a convex, a property and a UOL, followed by a string
+        let mut writer = WzWriter::unencrypted(0, 0, Cursor::new(Vec::new()));
+        let mut image_writer = WzImageWriter::new(&mut writer);
+        write_property(&mut image_writer, 2, |writer| {
+            UolString::from("shape").encode(writer)?;
+            9u8.encode(writer)?;
+            writer.with_length_prefix(|writer| {
+                writer.write_object_tag("Shape2D#Convex2D")?;
+                WzInt::from(3).encode(writer)?;
+                writer.write_object_tag("Shape2D#Convex2D")?;
+                WzInt::from(2).encode(writer)?;
+                writer.write_object_tag("Shape2D#Vector2D")?;
+                Vector::new(WzInt::from(1), WzInt::from(2)).encode(writer)?;
+                writer.write_object_tag("UOL")?;
+                UolObject::from("../a").encode(writer)?;
+                write_property(writer, 1, |writer| write_string(writer, "s", "inner"))?;
+                writer.write_object_tag("UOL")?;
+                UolObject::from("../b").encode(writer)
+            })?;
+            write_string(writer, "after", "tail")
+        })
+        .expect("error writing image");
+        let data = writer.into_inner().into_inner();
+
+        let map = Reader::new(WzReader::unencrypted(0, 0, Cursor::new(data.clone())))
+            .map("test.img")
+            .expect("error mapping image");
+        let mut expected = Vec::new();
+        map.walk::<()>(|cursor| {
+            match cursor.get() {
+                Property::String(s) => expected.push((cursor.pwd(), s.to_string())),
+                Property::Uol(u) => expected.push((cursor.pwd(), u.to_string())),
+                _ => {}
+            }
+            Ok(())
+        })
+        .expect("walk should not fail");
+
+        let mut scanned = Vec::new();
+        Scanner::new(WzReader::unencrypted(0, 0, Cursor::new(data)))
+            .scan("test.img", |path, property| {
+                match property {
+                    Property::String(s) => scanned.push((path.to_string(), s.to_string())),
+                    Property::Uol(u) => scanned.push((path.to_string(), u.to_string())),
+                    _ => {}
+                }
+                Ok(())
+            })
+            .expect("error scanning image");
+        let pairs = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(path, value)| (path.to_string(), value.to_string()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            scanned,
+            pairs(&[
+                ("test.img/shape/0/1", "../a"),
+                ("test.img/shape/1/s", "inner"),
+                ("test.img/shape/2", "../b"),
+                ("test.img/after", "tail"),
+            ])
+        );
+        assert_eq!(scanned, expected);
+    }
+
+    #[test]
+    fn nesting_depth() {
+        // deep: `levels` convexes nested in one another around a UOL
+        let image = |levels: usize| {
+            let mut writer = WzWriter::unencrypted(0, 0, Cursor::new(Vec::new()));
+            let mut image_writer = WzImageWriter::new(&mut writer);
+            write_property(&mut image_writer, 1, |writer| {
+                UolString::from("deep").encode(writer)?;
+                9u8.encode(writer)?;
+                writer.with_length_prefix(|writer| {
+                    for _ in 0..levels {
+                        writer.write_object_tag("Shape2D#Convex2D")?;
+                        WzInt::from(1).encode(writer)?;
+                    }
+                    writer.write_object_tag("UOL")?;
+                    UolObject::from("../a").encode(writer)
+                })
+            })
+            .expect("error writing image");
+            writer.into_inner().into_inner()
+        };
+        let map = |data: Vec<u8>| {
+            Reader::new(WzReader::unencrypted(0, 0, Cursor::new(data))).map("test.img")
+        };
+        let scan = |data: Vec<u8>| {
+            let mut count = 0;
+            Scanner::new(WzReader::unencrypted(0, 0, Cursor::new(data)))
+                .scan("test.img", |_, _| {
+                    count += 1;
+                    Ok(())
+                })
+                .map(|_| count)
+        };
+
+        // The UOL is as deep as objects can be
+        let deepest = image(MAX_DEPTH - 1);
+        let path = format!("test.img/deep{}", "/0".repeat(MAX_DEPTH - 1));
+        let mapped = map(deepest.clone()).expect("error mapping image");
+        assert!(matches!(mapped.get(&path), Ok(Property::Uol(_))));
+        assert_eq!(scan(deepest).expect("error scanning image"), 1);
+
+        let too_deep = image(MAX_DEPTH);
+        assert!(matches!(
+            map(too_deep.clone()),
+            Err(Error::Image(ImageError::Depth))
+        ));
+        assert!(matches!(
+            scan(too_deep),
+            Err(Error::Image(ImageError::Depth))
+        ));
+    }
+}
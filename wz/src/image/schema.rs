@@ -0,0 +1,326 @@
+//! WZ Image schemas
+
+use crate::error::{Error, ImageError, Result};
+use crate::io::xml::writer::ToXml;
+use crate::map::Map;
+use crate::types::Property;
+use std::{fmt, str::FromStr};
+
+/// Kind of property a [`Schema`] expects. Kinds are named after the XML tags of the properties,
+/// e.g. `int`, `canvas` or `extended`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Null,
+    Short,
+    Int,
+    Long,
+    Float,
+    Double,
+    String,
+    ImgDir,
+    Canvas,
+    Convex,
+    Vector,
+    Uol,
+    Sound,
+
+    /// Any of the integer and floating point properties
+    Number,
+
+    /// Anything, as long as it exists
+    Any,
+}
+
+impl Kind {
+    /// Returns `true` if `property` is of this kind
+    pub fn matches(&self, property: &Property) -> bool {
+        match self {
+            Self::Number => matches!(
+                property,
+                Property::Short(_)
+                    | Property::Int(_)
+                    | Property::Long(_)
+                    | Property::Float(_)
+                    | Property::Double(_)
+            ),
+            Self::Any => true,
+            kind => kind.name() == property.tag(),
+        }
+    }
+
+    /// Returns the name used in schemas
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Null => "null",
+            Self::Short => "short",
+            Self::Int => "int",
+            Self::Long => "long",
+            Self::Float => "float",
+            Self::Double => "double",
+            Self::String => "string",
+            Self::ImgDir => "imgdir",
+            Self::Canvas => "canvas",
+            Self::Convex => "extended",
+            Self::Vector => "vector",
+            Self::Uol => "uol",
+            Self::Sound => "sound",
+            Self::Number => "number",
+            Self::Any => "any",
+        }
+    }
+}
+
+impl FromStr for Kind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "null" => Self::Null,
+            "short" => Self::Short,
+            "int" => Self::Int,
+            "long" => Self::Long,
+            "float" => Self::Float,
+            "double" => Self::Double,
+            "string" => Self::String,
+            "imgdir" => Self::ImgDir,
+            "canvas" => Self::Canvas,
+            "extended" => Self::Convex,
+            "vector" => Self::Vector,
+            "uol" => Self::Uol,
+            "sound" => Self::Sound,
+            "number" => Self::Number,
+            "any" => Self::Any,
+            _ => return Err(ImageError::Value(String::from(s)).into()),
+        })
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Property of an image that does not match its [`Schema`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    /// Nothing exists at the path
+    Missing(String),
+
+    /// The property at the path is of another kind. Holds the path, the expected kind and the tag
+    /// of the property found.
+    Mistyped(String, Kind, &'static str),
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing(path) => write!(f, "{}: missing", path),
+            Self::Mistyped(path, kind, found) => {
+                write!(f, "{}: expected {}, found {}", path, kind, found)
+            }
+        }
+    }
+}
+
+/// Properties an image is expected to hold, e.g. every `Mob.img` must have an `info/level` int
+/// and a `stand/0` canvas. Paths are relative to the image root.
+///
+/// Schemas are usually written as a flat TOML table mapping paths to kinds. Tables prefix the
+/// paths below them:
+///
+/// ```toml
+/// "stand/0" = "canvas"
+///
+/// [info]
+/// level = "int"
+/// speed = "number"
+/// ```
+///
+/// Example:
+///
+/// ```no_run
+/// use std::fs;
+/// use wz::{image::{self, Reader, Schema}, io::DummyDecryptor};
+///
+/// let schema = Schema::from_toml(&fs::read_to_string("Mob.toml").unwrap()).unwrap();
+/// let map = Reader::open("0100100.img", DummyDecryptor).unwrap().map_lazy("0100100.img").unwrap();
+/// for violation in image::validate_schema(&map, &schema) {
+///     println!("{}", violation);
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Schema {
+    fields: Vec<(String, Kind)>,
+}
+
+impl Schema {
+    /// Creates an empty schema
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requires a property of `kind` at `path`
+    pub fn require(&mut self, path: &str, kind: Kind) -> &mut Self {
+        self.fields.push((String::from(path), kind));
+        self
+    }
+
+    /// Returns the required paths and kinds in the order they were added
+    pub fn fields(&self) -> &[(String, Kind)] {
+        &self.fields
+    }
+
+    /// Parses a schema written in TOML. Only tables and `key = "kind"` pairs are understood. Keys
+    /// are bare or quoted and dots in bare keys and table names separate path segments.
+    pub fn from_toml(toml: &str) -> Result<Self> {
+        let mut schema = Self::new();
+        let mut table = Vec::new();
+        for line in toml.lines() {
+            let trimmed = strip_comment(line).trim();
+            let invalid = || ImageError::Value(String::from(line));
+            if trimmed.is_empty() {
+                continue;
+            }
+            if let Some(name) = trimmed.strip_prefix('[') {
+                let name = name.strip_suffix(']').ok_or_else(invalid)?;
+                table = parse_key(name).ok_or_else(invalid)?;
+                continue;
+            }
+            let (key, value) = trimmed.split_once('=').ok_or_else(invalid)?;
+            let key = parse_key(key).ok_or_else(invalid)?;
+            let kind = unquote(value.trim()).ok_or_else(invalid)?.parse()?;
+            let path = [&table[..], &key[..]].concat().join("/");
+            schema.require(&path, kind);
+        }
+        Ok(schema)
+    }
+}
+
+/// Checks `image` against `schema` and returns every property that does not match, in the order
+/// of the schema
+pub fn validate_schema(image: &Map<Property>, schema: &Schema) -> Vec<Violation> {
+    schema
+        .fields
+        .iter()
+        .filter_map(|(path, kind)| {
+            match image.get(format!("{}/{}", image.name(), path.trim_matches('/'))) {
+                Ok(property) if kind.matches(property) => None,
+                Ok(property) => Some(Violation::Mistyped(path.clone(), *kind, property.tag())),
+                Err(_) => Some(Violation::Missing(path.clone())),
+            }
+        })
+        .collect()
+}
+
+/// Returns `line` without its comment
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), c) if q == c => quote = None,
+            (None, '#') => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Returns the contents of a quoted string
+fn unquote(s: &str) -> Option<&str> {
+    s.strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .or_else(|| s.strip_prefix('\'').and_then(|s| s.strip_suffix('\'')))
+}
+
+/// Splits a dotted key into its path segments
+fn parse_key(key: &str) -> Option<Vec<String>> {
+    let mut segments = Vec::new();
+    let mut rest = key.trim();
+    loop {
+        let (segment, remaining) = match rest.chars().next()? {
+            quote @ ('"' | '\'') => {
+                let end = rest[1..].find(quote)? + 1;
+                (&rest[1..end], &rest[end + 1..])
+            }
+            _ => {
+                let end = rest.find(['.', ' ', '\t']).unwrap_or(rest.len());
+                let segment = &rest[..end];
+                let bare = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+                if segment.is_empty() || !segment.chars().all(bare) {
+                    return None;
+                }
+                (segment, &rest[end..])
+            }
+        };
+        segments.push(String::from(segment));
+        let remaining = remaining.trim_start();
+        if remaining.is_empty() {
+            return Some(segments);
+        }
+        rest = remaining.strip_prefix('.')?.trim_start();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::image::{self, Kind, Reader, Schema, Violation};
+    use crypto::{KeyStream, GMS_IV, TRIMMED_KEY};
+
+    #[test]
+    fn validate_weapon() {
+        let schema = Schema::from_toml(
+            r#"
+            # Weapon schema
+            "info/icon" = "canvas"
+            [info]
+            islot = "string" # inline comment
+            incPAD = "number"
+            tuc = 'short'
+
+            [info.level."info"."1"]
+            exp = "int"
+            missing = "any"
+            "#,
+        )
+        .expect("error parsing schema");
+        assert_eq!(
+            schema.fields(),
+            [
+                (String::from("info/icon"), Kind::Canvas),
+                (String::from("info/islot"), Kind::String),
+                (String::from("info/incPAD"), Kind::Number),
+                (String::from("info/tuc"), Kind::Short),
+                (String::from("info/level/info/1/exp"), Kind::Int),
+                (String::from("info/level/info/1/missing"), Kind::Any),
+            ]
+        );
+
+        let map = Reader::open(
+            "testdata/v83-weapon.img",
+            KeyStream::new(&TRIMMED_KEY, &GMS_IV),
+        )
+        .expect("error opening image")
+        .map_lazy("weapon.img")
+        .expect("error mapping image");
+        let violations = image::validate_schema(&map, &schema);
+        assert_eq!(
+            violations,
+            [
+                Violation::Mistyped(String::from("info/tuc"), Kind::Short, "int"),
+                Violation::Missing(String::from("info/level/info/1/missing")),
+            ]
+        );
+        assert_eq!(
+            violations[0].to_string(),
+            "info/tuc: expected short, found int"
+        );
+
+        assert!(Schema::from_toml("level = int").is_err());
+        assert!(Schema::from_toml("level = \"integer\"").is_err());
+        assert!(Schema::from_toml("[info\nlevel = \"int\"").is_err());
+        assert!(Schema::from_toml("bad key = \"int\"").is_err());
+    }
+}
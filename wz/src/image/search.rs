@@ -0,0 +1,74 @@
+//! Searching WZ image values
+
+use crate::map::Map;
+use crate::types::Property;
+
+impl Map<Property> {
+    /// Returns the full path of every property `predicate` matches, depth-first.
+    ///
+    /// Example:
+    ///
+    /// ```no_run
+    /// use wz::{image::Reader, io::DummyDecryptor, types::Property};
+    ///
+    /// let map = Reader::open("Map.img", DummyDecryptor)
+    ///     .unwrap()
+    ///     .map("Map.img")
+    ///     .unwrap();
+    /// let paths = map.find_value(|property| match property {
+    ///     Property::String(s) => s.as_ref().contains("Henesys"),
+    ///     _ => false,
+    /// });
+    /// ```
+    pub fn find_value<P>(&self, mut predicate: P) -> Vec<String>
+    where
+        P: FnMut(&Property) -> bool,
+    {
+        let mut paths = Vec::new();
+        self.walk::<()>(|cursor| {
+            if predicate(cursor.get()) {
+                paths.push(cursor.pwd());
+            }
+            Ok(())
+        })
+        .expect("walk should not fail");
+        paths
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::{image::Reader, types::Property};
+    use crypto::{KeyStream, GMS_IV, TRIMMED_KEY};
+
+    #[test]
+    fn find_values() {
+        let map = Reader::open(
+            "testdata/v83-weapon.img",
+            KeyStream::new(&TRIMMED_KEY, &GMS_IV),
+        )
+        .expect("error opening image")
+        .map("weapon.img")
+        .expect("error mapping image");
+        let paths = map.find_value(|property| match property {
+            Property::Int(v) => **v == 17,
+            Property::String(s) => s.as_ref().contains("sword"),
+            _ => false,
+        });
+        assert_eq!(
+            paths,
+            [
+                "weapon.img/info/afterImage",
+                "weapon.img/info/sfx",
+                "weapon.img/info/incPAD"
+            ]
+        );
+        assert!(map.find_value(|_| false).is_empty());
+        assert_eq!(
+            map.find_value(|property| matches!(property, Property::ImgDir))
+                .first(),
+            Some(&String::from("weapon.img"))
+        );
+    }
+}
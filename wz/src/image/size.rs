@@ -0,0 +1,206 @@
+//! WZ Image size accounting
+
+use crate::error::Result;
+use crate::image::writer;
+use crate::io::{WzImageWriter, WzWriter};
+use crate::map::{Bookmark, Cursor, Map};
+use crate::types::Property;
+use std::collections::HashMap;
+use std::io::{self, Seek, SeekFrom, Write};
+
+/// Encoded size of a property and everything under it
+///
+/// The bytes of every property are attributed to its type: strings, canvases, sounds or other. The
+/// properties of a canvas count towards their own types, so only the canvas header and pixel data
+/// are attributed to canvases. Names and property headers count towards the type of the property
+/// they belong to.
+///
+/// Example:
+///
+/// ```no_run
+/// use crypto::{KeyStream, GMS_IV, TRIMMED_KEY};
+/// use wz::image::Reader;
+///
+/// let map = Reader::open("Mob.img", KeyStream::new(&TRIMMED_KEY, &GMS_IV))
+///     .unwrap()
+///     .map("Mob.img")
+///     .unwrap();
+/// let report = map.size_report().unwrap();
+/// for child in &report.children {
+///     println!("{}: {} bytes, {} in canvases", child.name, child.size, child.canvases);
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SizeReport {
+    /// Name of the property
+    pub name: String,
+
+    /// Encoded size of the property and its children
+    pub size: u64,
+
+    /// Bytes taken by string properties
+    pub strings: u64,
+
+    /// Bytes taken by canvases, not counting their properties
+    pub canvases: u64,
+
+    /// Bytes taken by sounds
+    pub sounds: u64,
+
+    /// Bytes taken by every other property
+    pub other: u64,
+
+    /// Reports of the children, in image order
+    pub children: Vec<SizeReport>,
+}
+
+impl SizeReport {
+    /// Returns the report of the property at `path`, relative to this one
+    pub fn get(&self, path: &str) -> Option<&SizeReport> {
+        path.split('/')
+            .filter(|name| !name.is_empty())
+            .try_fold(self, |report, name| {
+                report.children.iter().find(|child| child.name == name)
+            })
+    }
+
+    /// Builds the report of the property at `cursor` from the reports of its children
+    fn new(cursor: &Cursor<Property>, size: u64, children: Vec<SizeReport>) -> Self {
+        let own = size.saturating_sub(children.iter().map(|child| child.size).sum());
+        let mut report = Self {
+            name: String::from(cursor.name()),
+            size,
+            strings: children.iter().map(|child| child.strings).sum(),
+            canvases: children.iter().map(|child| child.canvases).sum(),
+            sounds: children.iter().map(|child| child.sounds).sum(),
+            other: children.iter().map(|child| child.other).sum(),
+            children,
+        };
+        match cursor.get() {
+            Property::String(_) => report.strings += own,
+            Property::Canvas(_) => report.canvases += own,
+            Property::Sound(_) => report.sounds += own,
+            _ => report.other += own,
+        }
+        report
+    }
+}
+
+impl Map<Property> {
+    /// Encodes the image and reports the size of every property. Canvases must be loaded, so
+    /// images mapped with [`Reader::map_lazy`](crate::image::Reader::map_lazy) need their
+    /// canvases loaded first.
+    ///
+    /// The sizes are those of an image encoded by [`Writer`](crate::image::Writer), which may
+    /// differ slightly from the image the map was read from.
+    pub fn size_report(&self) -> Result<SizeReport> {
+        let mut sizes = HashMap::<Bookmark, u64>::new();
+        let mut inner = WzWriter::unencrypted(0, 0, Tally::default());
+        writer::write_map_measured(
+            self,
+            &mut WzImageWriter::new(&mut inner),
+            &mut |cursor, size| {
+                sizes.insert(cursor.bookmark(), size);
+            },
+        )?;
+        let total = inner.into_inner().len;
+
+        // Children are measured before their parents, so the reports are built in reverse walk
+        // order with the finished children waiting on a stack
+        let mut order = Vec::new();
+        let _ = self.walk::<()>(|cursor| {
+            order.push(cursor.bookmark());
+            Ok(())
+        });
+        let mut finished: Vec<SizeReport> = Vec::new();
+        for bookmark in order.into_iter().rev() {
+            let cursor = self.cursor_at_bookmark(bookmark)?;
+            let size = sizes.get(&bookmark).copied().unwrap_or(total);
+            let start = finished.len() - cursor.child_count();
+            let children = finished.drain(start..).rev().collect();
+            finished.push(SizeReport::new(&cursor, size, children));
+        }
+        Ok(finished.pop().expect("the root is always walked"))
+    }
+}
+
+/// Sink that only keeps track of how much is written to it
+#[derive(Default)]
+struct Tally {
+    position: u64,
+    len: u64,
+}
+
+impl Write for Tally {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.position += buf.len() as u64;
+        self.len = self.len.max(self.position);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for Tally {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position.ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?;
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::image::{writer, Reader};
+    use crate::io::{WzImageWriter, WzWriter};
+    use crate::types::Property;
+    use crypto::{KeyStream, GMS_IV, TRIMMED_KEY};
+    use std::io::Cursor;
+
+    #[test]
+    fn weapon_sizes() {
+        let mut reader = Reader::open(
+            "testdata/v83-weapon.img",
+            KeyStream::new(&TRIMMED_KEY, &GMS_IV),
+        )
+        .expect("error opening image");
+        let map = reader.map("weapon.img").expect("error mapping image");
+        let report = map.size_report().expect("error measuring image");
+        assert_eq!(report.name, "weapon.img");
+        assert!(report.strings > 0 && report.canvases > 0);
+        assert_eq!(
+            report.strings + report.canvases + report.sounds + report.other,
+            report.size
+        );
+
+        // The total is the size of the whole image as written
+        let mut inner = WzWriter::unencrypted(0, 0, Cursor::new(Vec::new()));
+        writer::write_map(&map, &mut WzImageWriter::new(&mut inner)).expect("error writing image");
+        assert_eq!(report.size, inner.into_inner().into_inner().len() as u64);
+
+        // Every subtree adds up and matches the map
+        let mut checked = 0;
+        map.walk::<()>(|cursor| {
+            let path = cursor.pwd();
+            let (_, relative) = path.split_once('/').unwrap_or(("", ""));
+            let child = report.get(relative).expect("missing report");
+            assert_eq!(child.name, cursor.name());
+            assert_eq!(child.children.len(), cursor.child_count());
+            assert!(child.children.iter().map(|c| c.size).sum::<u64>() <= child.size);
+            if let Property::Canvas(_) = cursor.get() {
+                assert!(child.canvases > 0);
+            }
+            checked += 1;
+            Ok(())
+        })
+        .unwrap();
+        assert!(checked > 1);
+    }
+}
@@ -0,0 +1,422 @@
+//! WZ Image sprite sheets
+
+use crate::error::{ImageError, Result};
+use crate::map::Map;
+use crate::types::{Canvas, Property};
+use ::image::{imageops, ImageFormat, RgbaImage};
+use std::{fmt::Write, fs, path::Path};
+
+/// Delay used when a frame does not specify one
+pub const DEFAULT_DELAY: i32 = 100;
+
+/// Animation frame within a [`SpriteSheet`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// Name of the frame node
+    pub name: String,
+
+    /// Left edge of the frame within the sheet
+    pub x: u32,
+
+    /// Top edge of the frame within the sheet
+    pub y: u32,
+
+    /// Width of the frame
+    pub width: u32,
+
+    /// Height of the frame
+    pub height: u32,
+
+    /// Origin of the frame relative to its top-left corner
+    pub origin: (i32, i32),
+
+    /// Frame delay in milliseconds
+    pub delay: i32,
+}
+
+/// Animation frames packed into a single image.
+///
+/// Frames are the numbered children of an animation node. A frame is either a canvas or a
+/// property holding canvas layers. Every frame is drawn into a cell of the same size with the
+/// origins lined up, so the frames can be played back without any offsets.
+///
+/// Sheets built with [`SpriteSheet::from_map_trimmed`] crop the transparent border of every
+/// frame instead. Each frame then keeps its own size and origin.
+///
+/// Example:
+///
+/// ```no_run
+/// use crypto::{KeyStream, GMS_IV, TRIMMED_KEY};
+/// use wz::image::{Reader, SpriteSheet};
+///
+/// let mut reader = Reader::open("0100100.img", KeyStream::new(&TRIMMED_KEY, &GMS_IV)).unwrap();
+/// let map = reader.map("0100100.img").unwrap();
+/// let sheet = SpriteSheet::from_map(&map, "0100100.img/move", 2).unwrap();
+/// sheet.save("move.png", "move.json").unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct SpriteSheet {
+    image: RgbaImage,
+    scale: u32,
+    frames: Vec<Frame>,
+}
+
+/// Frame drawn around its origin
+pub(crate) struct Cell {
+    pub(crate) name: String,
+    pub(crate) image: RgbaImage,
+    pub(crate) origin: (i32, i32),
+    pub(crate) delay: i32,
+}
+
+/// Canvas layer of a frame along with its origin
+struct Layer<'a> {
+    canvas: &'a Canvas,
+    origin: (i32, i32),
+}
+
+impl SpriteSheet {
+    /// Builds the sprite sheet of the animation at `path`. The sheet is enlarged by `scale`.
+    pub fn from_map(map: &Map<Property>, path: &str, scale: u32) -> Result<Self> {
+        Self::build(map, path, scale, false)
+    }
+
+    /// Builds the sprite sheet of the animation at `path` with the transparent border of every
+    /// frame cropped. The frame origins are adjusted to match. The sheet is enlarged by `scale`.
+    pub fn from_map_trimmed(map: &Map<Property>, path: &str, scale: u32) -> Result<Self> {
+        Self::build(map, path, scale, true)
+    }
+
+    fn build(map: &Map<Property>, path: &str, scale: u32, trim: bool) -> Result<Self> {
+        if scale == 0 {
+            return Err(ImageError::Value(String::from("scale must be at least 1")).into());
+        }
+
+        // Draw every frame with the origins lined up
+        let mut cells = render_frames(map, path)?;
+        if trim {
+            for cell in &mut cells {
+                let (x, y, width, height) = opaque_bounds(&cell.image);
+                cell.image = imageops::crop_imm(&cell.image, x, y, width, height).to_image();
+                cell.origin = (cell.origin.0 - x as i32, cell.origin.1 - y as i32);
+            }
+        }
+
+        // Lay the cells out in a roughly square grid
+        let cell_width = cells.iter().map(|c| c.image.width()).max().unwrap_or(1);
+        let cell_height = cells.iter().map(|c| c.image.height()).max().unwrap_or(1);
+        let columns = (cells.len() as f64).sqrt().ceil() as u32;
+        let rows = (cells.len() as u32).div_ceil(columns);
+        let mut image = RgbaImage::new(columns * cell_width, rows * cell_height);
+        let mut sheet_frames = Vec::with_capacity(cells.len());
+        for (i, cell) in cells.into_iter().enumerate() {
+            let x = (i as u32 % columns) * cell_width;
+            let y = (i as u32 / columns) * cell_height;
+            imageops::replace(&mut image, &cell.image, x as i64, y as i64);
+            sheet_frames.push(Frame {
+                name: cell.name,
+                x: x * scale,
+                y: y * scale,
+                width: cell.image.width() * scale,
+                height: cell.image.height() * scale,
+                origin: (cell.origin.0 * scale as i32, cell.origin.1 * scale as i32),
+                delay: cell.delay,
+            });
+        }
+        if scale > 1 {
+            image = imageops::resize(
+                &image,
+                image.width() * scale,
+                image.height() * scale,
+                imageops::FilterType::Nearest,
+            );
+        }
+        Ok(Self {
+            image,
+            scale,
+            frames: sheet_frames,
+        })
+    }
+
+    /// Returns the sheet image
+    pub fn image(&self) -> &RgbaImage {
+        &self.image
+    }
+
+    /// Returns the scale the sheet was built with
+    pub fn scale(&self) -> u32 {
+        self.scale
+    }
+
+    /// Returns the frames in playback order
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    /// Returns the frame metadata as JSON
+    pub fn metadata(&self) -> String {
+        let mut json = String::new();
+        let _ = write!(
+            json,
+            "{{\n  \"width\": {},\n  \"height\": {},\n  \"scale\": {},\n  \"frames\": [",
+            self.image.width(),
+            self.image.height(),
+            self.scale
+        );
+        for (i, frame) in self.frames.iter().enumerate() {
+            let _ = write!(
+                json,
+                "{}\n    {{ \"name\": \"{}\", \"x\": {}, \"y\": {}, \"width\": {}, \"height\": {}, \
+                 \"origin\": {{ \"x\": {}, \"y\": {} }}, \"delay\": {} }}",
+                if i == 0 { "" } else { "," },
+                escape(&frame.name),
+                frame.x,
+                frame.y,
+                frame.width,
+                frame.height,
+                frame.origin.0,
+                frame.origin.1,
+                frame.delay
+            );
+        }
+        json.push_str("\n  ]\n}\n");
+        json
+    }
+
+    /// Saves the sheet as a PNG and the metadata as JSON
+    pub fn save<S, T>(&self, png: S, json: T) -> Result<()>
+    where
+        S: AsRef<Path>,
+        T: AsRef<Path>,
+    {
+        self.image.save_with_format(png, ImageFormat::Png)?;
+        Ok(fs::write(json, self.metadata())?)
+    }
+}
+
+/// Draws the numbered frames of the animation at `path` in order. Every frame has the same size
+/// and origin.
+pub(crate) fn render_frames(map: &Map<Property>, path: &str) -> Result<Vec<Cell>> {
+    // Collect the numbered frames in order
+    let mut names = child_names(map, path)?
+        .into_iter()
+        .filter_map(|name| name.parse::<u32>().ok().map(|i| (i, name)))
+        .collect::<Vec<(u32, String)>>();
+    names.sort();
+    let mut frames = Vec::new();
+    for (_, name) in names {
+        let frame_path = format!("{}/{}", path, name);
+        let (layers, delay) = match map.get(&frame_path)? {
+            Property::Canvas(canvas) => (
+                vec![Layer {
+                    canvas,
+                    origin: origin(map, &frame_path),
+                }],
+                delay(map, &frame_path),
+            ),
+            Property::ImgDir => {
+                let mut layers = Vec::new();
+                for layer in child_names(map, &frame_path)? {
+                    let layer_path = format!("{}/{}", frame_path, layer);
+                    if let Property::Canvas(canvas) = map.get(&layer_path)? {
+                        layers.push(Layer {
+                            canvas,
+                            origin: origin(map, &layer_path),
+                        });
+                    }
+                }
+                (layers, delay(map, &frame_path))
+            }
+            _ => continue,
+        };
+        if !layers.is_empty() {
+            frames.push((name, layers, delay));
+        }
+    }
+    if frames.is_empty() {
+        return Err(ImageError::Frames(String::from(path)).into());
+    }
+
+    // Extents of the cell around the shared origin
+    let (mut left, mut top, mut right, mut bottom) = (0, 0, 0, 0);
+    for layer in frames.iter().flat_map(|(_, layers, _)| layers) {
+        let (width, height) = (*layer.canvas.width(), *layer.canvas.height());
+        left = left.max(layer.origin.0);
+        top = top.max(layer.origin.1);
+        right = right.max(width - layer.origin.0);
+        bottom = bottom.max(height - layer.origin.1);
+    }
+    let cell_width = (left + right).max(1) as u32;
+    let cell_height = (top + bottom).max(1) as u32;
+
+    // Draw every frame with the origins lined up
+    let mut cells = Vec::with_capacity(frames.len());
+    for (name, layers, delay) in frames {
+        let mut image = RgbaImage::new(cell_width, cell_height);
+        for layer in layers {
+            imageops::overlay(
+                &mut image,
+                &layer.canvas.image_buffer()?,
+                (left - layer.origin.0) as i64,
+                (top - layer.origin.1) as i64,
+            );
+        }
+        cells.push(Cell {
+            name,
+            image,
+            origin: (left, top),
+            delay,
+        });
+    }
+    Ok(cells)
+}
+
+/// Returns the `(x, y, width, height)` of the smallest region holding every visible pixel. Fully
+/// transparent images keep their top-left pixel.
+fn opaque_bounds(image: &RgbaImage) -> (u32, u32, u32, u32) {
+    let (mut left, mut top, mut right, mut bottom) = (u32::MAX, u32::MAX, 0, 0);
+    for (x, y, pixel) in image.enumerate_pixels() {
+        if pixel[3] != 0 {
+            left = left.min(x);
+            top = top.min(y);
+            right = right.max(x + 1);
+            bottom = bottom.max(y + 1);
+        }
+    }
+    if left == u32::MAX {
+        return (0, 0, 1, 1);
+    }
+    (left, top, right - left, bottom - top)
+}
+
+/// Returns the names of the node's children
+fn child_names(map: &Map<Property>, path: &str) -> Result<Vec<String>> {
+    let cursor = map.cursor_at(path)?;
+    let names = cursor.list().map(String::from).collect();
+    Ok(names)
+}
+
+/// Returns the origin child of the node or `(0, 0)`
+fn origin(map: &Map<Property>, path: &str) -> (i32, i32) {
+    match map.get(format!("{}/origin", path)) {
+        Ok(Property::Vector(v)) => (*v.x, *v.y),
+        _ => (0, 0),
+    }
+}
+
+/// Returns the delay child of the node or [`DEFAULT_DELAY`]. Canvas frames keep it within the
+/// canvas.
+fn delay(map: &Map<Property>, path: &str) -> i32 {
+    match map.get(format!("{}/delay", path)) {
+        Ok(Property::Int(v)) => **v,
+        Ok(Property::Short(v)) => *v as i32,
+        _ => DEFAULT_DELAY,
+    }
+}
+
+/// Escapes `s` to be written within a JSON string
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::Frame;
+    use crate::image::{Reader, SpriteSheet};
+    use ::image::RgbaImage;
+    use crypto::{KeyStream, GMS_IV, TRIMMED_KEY};
+
+    #[test]
+    fn escape_metadata() {
+        let sheet = SpriteSheet {
+            image: RgbaImage::new(1, 1),
+            scale: 1,
+            frames: vec![Frame {
+                name: String::from("a\n\"b\\\u{1}\t\u{e9}"),
+                x: 0,
+                y: 0,
+                width: 1,
+                height: 1,
+                origin: (0, 0),
+                delay: 100,
+            }],
+        };
+        let metadata = sheet.metadata();
+        assert!(
+            metadata.contains(r#""name": "a\u000a\"b\\\u0001\u0009é""#),
+            "{}",
+            metadata
+        );
+        assert!(!metadata.chars().any(|c| c.is_control() && c != '\n'));
+    }
+
+    #[test]
+    fn v83_walk_sheet() {
+        let mut reader = Reader::open(
+            "testdata/v83-tamingmob.img",
+            KeyStream::new(&TRIMMED_KEY, &GMS_IV),
+        )
+        .expect("error opening image");
+        let map = reader.map("tamingmob.img").expect("error mapping image");
+        let sheet = SpriteSheet::from_map(&map, "tamingmob.img/walk1", 1).expect("error packing");
+        let frames = sheet.frames();
+        assert!(!frames.is_empty());
+        assert_eq!(frames[0].name, "0");
+        assert!(frames.iter().all(|f| f.delay > 0));
+        assert_eq!(sheet.metadata().matches("\"name\"").count(), frames.len());
+        for frame in frames {
+            assert!(frame.x + frame.width <= sheet.image().width());
+            assert!(frame.y + frame.height <= sheet.image().height());
+            assert_eq!(frame.width, frames[0].width);
+            assert_eq!(frame.origin, frames[0].origin);
+        }
+
+        let scaled = SpriteSheet::from_map(&map, "tamingmob.img/walk1", 2).expect("error packing");
+        assert_eq!(scaled.image().width(), sheet.image().width() * 2);
+        assert_eq!(scaled.frames()[1].x, frames[1].x * 2);
+        assert!(SpriteSheet::from_map(&map, "tamingmob.img/info", 1).is_err());
+
+        // Trimmed frames keep the pixels that were visible around the same origin
+        let trimmed =
+            SpriteSheet::from_map_trimmed(&map, "tamingmob.img/walk1", 1).expect("error packing");
+        assert_eq!(trimmed.frames().len(), frames.len());
+        assert!(
+            trimmed.image().width() * trimmed.image().height()
+                <= sheet.image().width() * sheet.image().height()
+        );
+        for (frame, full) in trimmed.frames().iter().zip(frames) {
+            assert!(frame.width <= full.width && frame.height <= full.height);
+            let dx = (full.origin.0 - frame.origin.0) as u32;
+            let dy = (full.origin.1 - frame.origin.1) as u32;
+            let visible = |x: u32, y: u32| sheet.image().get_pixel(full.x + x, full.y + y)[3] != 0;
+            for y in 0..full.height {
+                for x in 0..full.width {
+                    let inside =
+                        x >= dx && x < dx + frame.width && y >= dy && y < dy + frame.height;
+                    if inside {
+                        assert_eq!(
+                            trimmed
+                                .image()
+                                .get_pixel(frame.x + x - dx, frame.y + y - dy),
+                            sheet.image().get_pixel(full.x + x, full.y + y)
+                        );
+                    } else {
+                        assert!(!visible(x, y));
+                    }
+                }
+            }
+        }
+    }
+}
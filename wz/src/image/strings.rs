@@ -0,0 +1,250 @@
+//! WZ Image string tables
+
+use crate::error::{Error, ImageError, Result};
+use crate::map::Map;
+use crate::types::{Property, UolString};
+use std::fmt::Write;
+
+/// String property within a [`StringTable`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// Path of the node holding the string, starting with the image name
+    pub path: String,
+
+    /// Name of the string property
+    pub field: String,
+
+    /// String value
+    pub value: String,
+}
+
+/// Flat list of every string property in an image, e.g. the names and descriptions of
+/// `String.wz/Mob.img`.
+///
+/// Tables are exported as CSV or JSON for translation. An edited CSV is read back with
+/// [`StringTable::from_csv`] and written into the image with [`StringTable::apply`].
+///
+/// Example:
+///
+/// ```no_run
+/// use crypto::{KeyStream, GMS_IV, TRIMMED_KEY};
+/// use std::fs;
+/// use wz::image::{Reader, StringTable, Writer};
+///
+/// let key = KeyStream::new(&TRIMMED_KEY, &GMS_IV);
+/// let mut map = Reader::open("Mob.img", key.clone()).unwrap().map("Mob.img").unwrap();
+/// fs::write("Mob.csv", StringTable::from_map(&map).to_csv()).unwrap();
+///
+/// // Translate Mob.csv, then apply it
+/// let table = StringTable::from_csv(&fs::read_to_string("Mob.csv").unwrap()).unwrap();
+/// table.apply(&mut map).unwrap();
+/// Writer::from_map(map).save("Mob.img", key).unwrap();
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StringTable {
+    entries: Vec<Entry>,
+}
+
+impl StringTable {
+    /// Collects the string properties of the image in walk order
+    pub fn from_map(map: &Map<Property>) -> Self {
+        let mut entries = Vec::new();
+        map.walk::<()>(|cursor| {
+            if let Property::String(value) = cursor.get() {
+                let path = cursor.pwd();
+                let (parent, field) = path.rsplit_once('/').unwrap_or(("", &path));
+                entries.push(Entry {
+                    path: String::from(parent),
+                    field: String::from(field),
+                    value: String::from(value.as_ref()),
+                });
+            }
+            Ok(())
+        })
+        .expect("walk should not fail");
+        Self { entries }
+    }
+
+    /// Parses a table written by [`StringTable::to_csv`]. The header row is required.
+    pub fn from_csv(csv: &str) -> Result<Self> {
+        let mut rows = parse_csv(csv)?.into_iter();
+        match rows.next() {
+            Some(header) if header == ["path", "field", "value"] => {}
+            _ => return Err(ImageError::Value(String::from("missing CSV header")).into()),
+        }
+        let entries = rows
+            .map(|row| match <[String; 3]>::try_from(row) {
+                Ok([path, field, value]) => Ok(Entry { path, field, value }),
+                Err(row) => Err(ImageError::Value(row.join(",")).into()),
+            })
+            .collect::<Result<Vec<Entry>>>()?;
+        Ok(Self { entries })
+    }
+
+    /// Returns the entries
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Writes the table as CSV with a `path,field,value` header
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("path,field,value\n");
+        for entry in &self.entries {
+            let _ = writeln!(
+                csv,
+                "{},{},{}",
+                csv_field(&entry.path),
+                csv_field(&entry.field),
+                csv_field(&entry.value)
+            );
+        }
+        csv
+    }
+
+    /// Writes the table as a JSON array of `{ "path", "field", "value" }` objects
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("[");
+        for (i, entry) in self.entries.iter().enumerate() {
+            let _ = write!(
+                json,
+                "{}\n  {{ \"path\": \"{}\", \"field\": \"{}\", \"value\": \"{}\" }}",
+                if i == 0 { "" } else { "," },
+                json_escape(&entry.path),
+                json_escape(&entry.field),
+                json_escape(&entry.value)
+            );
+        }
+        json.push_str("\n]\n");
+        json
+    }
+
+    /// Writes the values into the image and returns how many changed. Every entry must name an
+    /// existing string property. The image is left unchanged on error.
+    pub fn apply(&self, map: &mut Map<Property>) -> Result<usize> {
+        let mut changes = Vec::new();
+        for entry in &self.entries {
+            let path = format!("{}/{}", entry.path, entry.field);
+            match map.get(&path) {
+                Ok(Property::String(value)) if value.as_ref() != entry.value => {
+                    changes.push((path, &entry.value))
+                }
+                Ok(Property::String(_)) => {}
+                _ => return Err(ImageError::Path(path).into()),
+            }
+        }
+        for (path, value) in &changes {
+            *map.cursor_mut_at(path)?.get_mut() = Property::String(UolString::from(value.as_str()));
+        }
+        Ok(changes.len())
+    }
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        String::from(s)
+    }
+}
+
+/// Splits CSV into rows of fields. Quoted fields may hold commas, quotes and newlines.
+fn parse_csv(csv: &str) -> Result<Vec<Vec<String>>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = csv.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            (true, '"') => quoted = false,
+            (true, c) => field.push(c),
+            (false, '"') if field.is_empty() => quoted = true,
+            (false, ',') => row.push(std::mem::take(&mut field)),
+            (false, '\r') => {}
+            (false, '\n') => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            (false, c) => field.push(c),
+        }
+    }
+    if quoted {
+        return Err(Error::from(ImageError::Value(String::from(
+            "unterminated quote in CSV",
+        ))));
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+fn json_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::image::{Reader, StringTable};
+    use crate::types::Property;
+    use crypto::{KeyStream, GMS_IV, TRIMMED_KEY};
+
+    #[test]
+    fn csv_round_trip() {
+        let mut map = Reader::open(
+            "testdata/v83-weapon.img",
+            KeyStream::new(&TRIMMED_KEY, &GMS_IV),
+        )
+        .expect("error opening image")
+        .map("weapon.img")
+        .expect("error mapping image");
+        let table = StringTable::from_map(&map);
+        assert!(!table.entries().is_empty());
+        let parsed = StringTable::from_csv(&table.to_csv()).expect("error parsing CSV");
+        assert_eq!(parsed, table);
+        assert_eq!(parsed.apply(&mut map).expect("error applying"), 0);
+
+        // Translate the first string
+        let entry = &table.entries()[0];
+        let csv = format!(
+            "path,field,value\n{},{},\"Translated, \"\"quoted\"\"\nvalue\"\n",
+            entry.path, entry.field
+        );
+        let edited = StringTable::from_csv(&csv).expect("error parsing CSV");
+        assert_eq!(edited.apply(&mut map).expect("error applying"), 1);
+        match map
+            .get(format!("{}/{}", entry.path, entry.field))
+            .expect("missing string")
+        {
+            Property::String(s) => assert_eq!(s.as_ref(), "Translated, \"quoted\"\nvalue"),
+            _ => panic!("expected a string"),
+        }
+        assert!(
+            StringTable::from_csv("path,field,value\nweapon.img/missing,name,x\n")
+                .expect("error parsing CSV")
+                .apply(&mut map)
+                .is_err()
+        );
+        assert!(table.to_json().starts_with('['));
+    }
+}
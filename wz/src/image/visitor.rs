@@ -0,0 +1,218 @@
+//! WZ Image property visitor
+
+use crate::error::{Error, Result};
+use crate::map::Map;
+use crate::types::{Canvas, Property, Sound, UolObject, UolString, Vector, WzInt, WzLong};
+
+/// Receives every property of an image, one method per property type.
+///
+/// Every method does nothing by default, so analysis tools only implement the types they care
+/// about. `path` is the full path of the property, starting with the image name. Returning an error
+/// stops the walk.
+///
+/// Example:
+///
+/// ```no_run
+/// use crypto::{KeyStream, GMS_IV, TRIMMED_KEY};
+/// use wz::{error::Result, image::{Reader, Visitor}, types::Canvas};
+///
+/// #[derive(Default)]
+/// struct CanvasArea(u64);
+///
+/// impl Visitor for CanvasArea {
+///     fn visit_canvas(&mut self, _path: &str, value: &Canvas) -> Result<()> {
+///         self.0 += *value.width() as u64 * *value.height() as u64;
+///         Ok(())
+///     }
+/// }
+///
+/// let map = Reader::open("Mob.img", KeyStream::new(&TRIMMED_KEY, &GMS_IV))
+///     .unwrap()
+///     .map("Mob.img")
+///     .unwrap();
+/// let mut area = CanvasArea::default();
+/// map.accept(&mut area).unwrap();
+/// println!("{} pixels", area.0);
+/// ```
+#[allow(unused_variables)]
+pub trait Visitor {
+    /// Visits a [`Property::Null`]
+    fn visit_null(&mut self, path: &str) -> Result<()> {
+        Ok(())
+    }
+
+    /// Visits a [`Property::Short`]
+    fn visit_short(&mut self, path: &str, value: i16) -> Result<()> {
+        Ok(())
+    }
+
+    /// Visits a [`Property::Int`]
+    fn visit_int(&mut self, path: &str, value: WzInt) -> Result<()> {
+        Ok(())
+    }
+
+    /// Visits a [`Property::Long`]
+    fn visit_long(&mut self, path: &str, value: WzLong) -> Result<()> {
+        Ok(())
+    }
+
+    /// Visits a [`Property::Float`]
+    fn visit_float(&mut self, path: &str, value: f32) -> Result<()> {
+        Ok(())
+    }
+
+    /// Visits a [`Property::Double`]
+    fn visit_double(&mut self, path: &str, value: f64) -> Result<()> {
+        Ok(())
+    }
+
+    /// Visits a [`Property::String`]
+    fn visit_string(&mut self, path: &str, value: &UolString) -> Result<()> {
+        Ok(())
+    }
+
+    /// Visits a [`Property::ImgDir`]. Its children are visited afterwards.
+    fn visit_img_dir(&mut self, path: &str) -> Result<()> {
+        Ok(())
+    }
+
+    /// Visits a [`Property::Canvas`]. Its children are visited afterwards.
+    fn visit_canvas(&mut self, path: &str, value: &Canvas) -> Result<()> {
+        Ok(())
+    }
+
+    /// Visits a [`Property::Convex`]. Its vectors are visited afterwards.
+    fn visit_convex(&mut self, path: &str) -> Result<()> {
+        Ok(())
+    }
+
+    /// Visits a [`Property::Vector`]
+    fn visit_vector(&mut self, path: &str, value: &Vector) -> Result<()> {
+        Ok(())
+    }
+
+    /// Visits a [`Property::Uol`]
+    fn visit_uol(&mut self, path: &str, value: &UolObject) -> Result<()> {
+        Ok(())
+    }
+
+    /// Visits a [`Property::Sound`]
+    fn visit_sound(&mut self, path: &str, value: &Sound) -> Result<()> {
+        Ok(())
+    }
+
+    /// Visits a [`Property::Unknown`]. `tag` is the object tag.
+    fn visit_unknown(&mut self, path: &str, tag: &str) -> Result<()> {
+        Ok(())
+    }
+
+    /// Visits a [`Property::Error`]. `error` is the decoding error.
+    fn visit_error(&mut self, path: &str, error: &str) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl Map<Property> {
+    /// Passes every property of the image to `visitor`, depth-first
+    pub fn accept<V>(&self, visitor: &mut V) -> Result<()>
+    where
+        V: Visitor + ?Sized,
+    {
+        self.walk::<Error>(|cursor| {
+            let path = cursor.pwd();
+            match cursor.get() {
+                Property::Null => visitor.visit_null(&path),
+                Property::Short(v) => visitor.visit_short(&path, *v),
+                Property::Int(v) => visitor.visit_int(&path, *v),
+                Property::Long(v) => visitor.visit_long(&path, *v),
+                Property::Float(v) => visitor.visit_float(&path, *v),
+                Property::Double(v) => visitor.visit_double(&path, *v),
+                Property::String(v) => visitor.visit_string(&path, v),
+                Property::ImgDir => visitor.visit_img_dir(&path),
+                Property::Canvas(v) => visitor.visit_canvas(&path, v),
+                Property::Convex => visitor.visit_convex(&path),
+                Property::Vector(v) => visitor.visit_vector(&path, v),
+                Property::Uol(v) => visitor.visit_uol(&path, v),
+                Property::Sound(v) => visitor.visit_sound(&path, v),
+                Property::Unknown(t) => visitor.visit_unknown(&path, t),
+                Property::Error(e) => visitor.visit_error(&path, e),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::error::{ImageError, Result};
+    use crate::image::{Reader, Visitor};
+    use crate::types::{Canvas, Property, UolString, WzInt};
+    use crypto::{KeyStream, GMS_IV, TRIMMED_KEY};
+
+    #[derive(Default)]
+    struct Counter {
+        ints: usize,
+        strings: usize,
+        canvases: usize,
+    }
+
+    impl Visitor for Counter {
+        fn visit_int(&mut self, _path: &str, _value: WzInt) -> Result<()> {
+            self.ints += 1;
+            Ok(())
+        }
+
+        fn visit_string(&mut self, _path: &str, _value: &UolString) -> Result<()> {
+            self.strings += 1;
+            Ok(())
+        }
+
+        fn visit_canvas(&mut self, path: &str, _value: &Canvas) -> Result<()> {
+            if self.canvases == 1 {
+                return Err(ImageError::Path(path.into()).into());
+            }
+            self.canvases += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn visit_weapon() {
+        let map = Reader::open(
+            "testdata/v83-weapon.img",
+            KeyStream::new(&TRIMMED_KEY, &GMS_IV),
+        )
+        .expect("error opening image")
+        .map("weapon.img")
+        .expect("error mapping image");
+        let (mut ints, mut strings, mut canvases) = (0, 0, 0);
+        map.walk::<()>(|cursor| {
+            match cursor.get() {
+                Property::Int(_) => ints += 1,
+                Property::String(_) => strings += 1,
+                Property::Canvas(_) => canvases += 1,
+                _ => {}
+            }
+            Ok(())
+        })
+        .unwrap();
+        assert!(ints > 0 && strings > 0 && canvases > 1);
+
+        // The visitor errors on the second canvas
+        let mut counter = Counter::default();
+        assert!(map.accept(&mut counter).is_err());
+        assert_eq!(counter.canvases, 1);
+
+        // The default methods do nothing
+        struct Noop;
+        impl Visitor for Noop {}
+        map.accept(&mut Noop).expect("error visiting");
+
+        let mut counter = Counter {
+            canvases: 2,
+            ..Counter::default()
+        };
+        map.accept(&mut counter).expect("error visiting");
+        assert_eq!((counter.ints, counter.strings), (ints, strings));
+    }
+}
@@ -63,14 +63,42 @@ impl Writer {
         // easier to encode since there are no checksums to calculate and the size is always 4
         // bytes long which makes it possible to retroactively fill in. So most of the complex
         // structure encoding is done here.
-        encode_object(writer, &mut self.map.cursor())
+        write_map(&self.map, writer)
     }
 }
 
-fn recursive_write<W>(writer: &mut W, cursor: &mut Cursor<Property>) -> Result<()>
+/// Encodes the image mapped in `map`
+pub(crate) fn write_map<W>(map: &Map<Property>, writer: &mut W) -> Result<()>
 where
     W: WzWrite + ?Sized,
 {
+    write_map_measured(map, writer, &mut |_, _| {})
+}
+
+/// Encodes the image mapped in `map`, passing every property but the root to `measured` along with
+/// its encoded size once it is written
+pub(crate) fn write_map_measured<W, F>(
+    map: &Map<Property>,
+    writer: &mut W,
+    measured: &mut F,
+) -> Result<()>
+where
+    W: WzWrite + ?Sized,
+    F: FnMut(&Cursor<Property>, u64),
+{
+    encode_object(writer, &mut map.cursor(), measured)
+}
+
+fn recursive_write<W, F>(
+    writer: &mut W,
+    cursor: &mut Cursor<Property>,
+    measured: &mut F,
+) -> Result<()>
+where
+    W: WzWrite + ?Sized,
+    F: FnMut(&Cursor<Property>, u64),
+{
+    let start = writer.position()?;
     let prop = cursor.get();
     match prop {
         Property::Null
@@ -89,22 +117,13 @@ where
             UolString::from(cursor.name()).encode(writer)?;
             9u8.encode(writer)?;
 
-            // Save the size position and write 0 for now
-            let size_position = writer.position()?;
-            0u32.encode(writer)?;
-
-            // Encode the object
-            encode_object(writer, cursor)?;
-
-            // Go back and write the size
-            let current_position = writer.position()?;
-            writer.seek(size_position)?;
-            let size = *current_position - *size_position - 4;
-            size.encode(writer)?;
-            writer.seek(current_position)?;
-            Ok(())
+            writer.with_length_prefix(|writer| encode_object(writer, cursor, measured))
         }
-    }
+        Property::Unknown(tag) => Err(ImageError::ObjectType(tag.clone()).into()),
+        Property::Error(e) => Err(ImageError::Property(e.clone()).into()),
+    }?;
+    measured(cursor, (*writer.position()? - *start) as u64);
+    Ok(())
 }
 
 fn encode_property<W>(writer: &mut W, name: &str, property: &Property) -> Result<()>
@@ -142,16 +161,21 @@ where
     }
 }
 
-fn encode_object<W>(writer: &mut W, cursor: &mut Cursor<Property>) -> Result<()>
+fn encode_object<W, F>(
+    writer: &mut W,
+    cursor: &mut Cursor<Property>,
+    measured: &mut F,
+) -> Result<()>
 where
     W: WzWrite + ?Sized,
+    F: FnMut(&Cursor<Property>, u64),
 {
     match cursor.get() {
         Property::ImgDir => {
             writer.write_object_tag("Property")?;
             0u16.encode(writer)?;
             WzInt::from(cursor.children().count()).encode(writer)?;
-            encode_object_children(writer, cursor)?;
+            encode_object_children(writer, cursor, measured)?;
         }
         Property::Canvas(val) => {
             writer.write_object_tag("Canvas")?;
@@ -162,7 +186,7 @@ where
                 1u8.encode(writer)?;
                 0u16.encode(writer)?;
                 WzInt::from(num_children as i32).encode(writer)?;
-                encode_object_children(writer, cursor)?;
+                encode_object_children(writer, cursor, measured)?;
             } else {
                 0u8.encode(writer)?;
             }
@@ -174,7 +198,9 @@ where
             if num_children > 0 {
                 cursor.first_child()?;
                 loop {
-                    encode_object(writer, cursor)?;
+                    let start = writer.position()?;
+                    encode_object(writer, cursor, measured)?;
+                    measured(cursor, (*writer.position()? - *start) as u64);
                     num_children -= 1;
                     if num_children == 0 {
                         break;
@@ -202,15 +228,20 @@ where
 }
 
 #[inline]
-fn encode_object_children<W>(writer: &mut W, cursor: &mut Cursor<Property>) -> Result<()>
+fn encode_object_children<W, F>(
+    writer: &mut W,
+    cursor: &mut Cursor<Property>,
+    measured: &mut F,
+) -> Result<()>
 where
     W: WzWrite + ?Sized,
+    F: FnMut(&Cursor<Property>, u64),
 {
     let mut num_children = cursor.children().count();
     if num_children > 0 {
         cursor.first_child()?;
         loop {
-            recursive_write(writer, cursor)?;
+            recursive_write(writer, cursor, measured)?;
             num_children -= 1;
             if num_children == 0 {
                 break;
@@ -1,5 +1,8 @@
 //! WZ IO
 
+#[cfg(test)]
+mod audit;
+mod checksum;
 mod decode;
 mod encode;
 mod read;
@@ -9,7 +12,8 @@ pub mod xml;
 
 pub(crate) use encode::SizeHint;
 
+pub use checksum::checksum_reader;
 pub use decode::Decode;
 pub use encode::Encode;
-pub use read::{DummyDecryptor, WzImageReader, WzRead, WzReader};
+pub use read::{DummyDecryptor, WzDecryptingReader, WzImageReader, WzRead, WzReader};
 pub use write::{DummyEncryptor, WzImageWriter, WzWrite, WzWriter};
@@ -0,0 +1,357 @@
+//! Size Hint Audit
+
+use crate::error::Result;
+use crate::io::{Encode, SizeHint, WzWrite, WzWriter};
+use std::{fmt, io::Cursor};
+
+/// Object whose size hint disagrees with the number of bytes it encodes to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SizeMismatch {
+    pub(crate) object: String,
+    pub(crate) hint: u32,
+    pub(crate) written: u32,
+}
+
+/// Encodes objects into memory and records every object whose [`SizeHint`] is wrong.
+///
+/// Archive offsets are computed from size hints before anything is written, so a wrong hint
+/// silently shifts every offset that follows the object.
+#[derive(Debug, Default)]
+pub(crate) struct SizeAudit {
+    mismatches: Vec<SizeMismatch>,
+}
+
+impl SizeAudit {
+    /// Encodes `object` and compares the bytes written against its size hint
+    pub(crate) fn check<T>(&mut self, object: &T) -> Result<&mut Self>
+    where
+        T: Encode + SizeHint + fmt::Debug,
+    {
+        let mut writer = WzWriter::unencrypted(0, 0, Cursor::new(Vec::new()));
+        object.encode(&mut writer)?;
+        let written = *writer.position()?;
+        let hint = object.size_hint();
+        if hint != written {
+            self.mismatches.push(SizeMismatch {
+                object: format!("{:?}", object),
+                hint,
+                written,
+            });
+        }
+        Ok(self)
+    }
+
+    /// Returns the recorded mismatches
+    pub(crate) fn mismatches(&self) -> &[SizeMismatch] {
+        &self.mismatches
+    }
+
+    /// Returns an error describing every mismatch, if any
+    pub(crate) fn report(&self) -> std::result::Result<(), String> {
+        if self.mismatches.is_empty() {
+            return Ok(());
+        }
+        let lines: Vec<String> = self
+            .mismatches
+            .iter()
+            .map(|m| {
+                format!(
+                    "{}: size_hint() is {} but {} bytes were written",
+                    m.object, m.hint, m.written
+                )
+            })
+            .collect();
+        Err(lines.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::io::audit::SizeAudit;
+    use crate::io::{
+        Decode, DummyDecryptor, DummyEncryptor, Encode, SizeHint, WzRead, WzReader, WzWrite,
+        WzWriter,
+    };
+    use crate::types::raw::package::{ContentRef, Metadata};
+    use crate::types::raw::Package;
+    use crate::types::{
+        Sound, SoundHeader, UolObject, UolString, Vector, WavHeader, WzInt, WzLong, WzOffset,
+    };
+    use crypto::{Decryptor, Encryptor, KeyStream, GMS_IV, TRIMMED_KEY};
+    use proptest::{collection::vec, prelude::*};
+    use std::io::Cursor;
+
+    /// Writes one byte less than it claims
+    #[derive(Debug)]
+    struct Short;
+
+    impl Encode for Short {
+        fn encode<W>(&self, writer: &mut W) -> crate::error::Result<()>
+        where
+            W: WzWrite + ?Sized,
+        {
+            writer.write_byte(0)
+        }
+    }
+
+    impl SizeHint for Short {
+        fn size_hint(&self) -> u32 {
+            2
+        }
+    }
+
+    #[test]
+    fn size_hints() {
+        let strings = [
+            String::new(),
+            String::from("a"),
+            "x".repeat(127),
+            "x".repeat(128),
+            "x".repeat(300),
+            String::from("한글"),
+            "한".repeat(200),
+            String::from("mixed é"),
+        ];
+        let mut audit = SizeAudit::default();
+        for v in [0i8, -1, i8::MIN, i8::MAX] {
+            audit.check(&v).unwrap();
+        }
+        for v in [0i16, i16::MIN] {
+            audit.check(&v).unwrap().check(&(v as u16)).unwrap();
+        }
+        for v in [0i32, -129, i32::MAX] {
+            audit.check(&v).unwrap().check(&(v as u32)).unwrap();
+        }
+        for v in [0i64, i64::MIN] {
+            audit.check(&v).unwrap().check(&(v as u64)).unwrap();
+        }
+        for v in [0u8, 0x80, 0xff] {
+            audit.check(&v).unwrap();
+        }
+        for v in [0f32, -0f32, 0.5, f32::NAN, f32::MAX] {
+            audit.check(&v).unwrap().check(&(v as f64)).unwrap();
+        }
+        for v in [0, 127, -127, -128, 128, i32::MIN, i32::MAX] {
+            audit.check(&WzInt::from(v)).unwrap();
+            audit
+                .check(&WzLong::from(v as i64 * 0x1_0000_0000))
+                .unwrap();
+        }
+        audit
+            .check(&WzOffset::from(0x1234u32))
+            .unwrap()
+            .check(&Vector::new(WzInt::from(-300), WzInt::from(5)))
+            .unwrap();
+        for s in &strings {
+            audit
+                .check(&s.as_str())
+                .unwrap()
+                .check(s)
+                .unwrap()
+                .check(&UolString::from(s.as_str()))
+                .unwrap()
+                .check(&UolObject::from(s.as_str()))
+                .unwrap();
+            let metadata = Metadata::new(
+                s.clone(),
+                WzInt::from(1000),
+                WzInt::from(-5),
+                WzOffset::from(0x100u32),
+            );
+            audit
+                .check(&ContentRef::Package(metadata.clone()))
+                .unwrap()
+                .check(&ContentRef::Image(metadata.clone()))
+                .unwrap()
+                .check(&metadata)
+                .unwrap();
+        }
+        let pcm = [
+            1, 0, 2, 0, 0x44, 0xac, 0, 0, 0x10, 0xb1, 2, 0, 4, 0, 16, 0, 0, 0,
+        ];
+        let mp3 = [
+            0x55, 0, 2, 0, 0x44, 0xac, 0, 0, 0x10, 0xb1, 2, 0, 4, 0, 0, 0, 2, 0, 1, 2,
+        ];
+        for header in [&pcm[..], &mp3[..]] {
+            let wav = WavHeader::from_slice(header).unwrap();
+            let sound_header = SoundHeader::from(wav.clone());
+            assert_eq!(WavHeader::try_from(sound_header.clone()).unwrap(), wav);
+            audit
+                .check(&wav)
+                .unwrap()
+                .check(&wav.audio_format)
+                .unwrap()
+                .check(&sound_header)
+                .unwrap()
+                .check(&Sound::new(WzInt::from(1500), sound_header, vec![7; 300]))
+                .unwrap();
+        }
+        if let Err(report) = audit.report() {
+            panic!("size hint mismatches:\n{}", report);
+        }
+
+        let mut audit = SizeAudit::default();
+        audit.check(&Short).unwrap();
+        assert_eq!(audit.mismatches().len(), 1);
+        assert_eq!(
+            audit.report(),
+            Err(String::from(
+                "Short: size_hint() is 2 but 1 bytes were written"
+            ))
+        );
+    }
+
+    /// Encodes `value` after `padding` bytes, then decodes it. Returns the decoded value and the
+    /// number of bytes written.
+    fn roundtrip_with<T, E, D>(
+        value: &T,
+        padding: u64,
+        absolute_position: i32,
+        version_checksum: u32,
+        encryptor: E,
+        decryptor: D,
+    ) -> (T, u32)
+    where
+        T: Encode + Decode,
+        E: Encryptor,
+        D: Decryptor,
+    {
+        let mut inner = Cursor::new(vec![0; padding as usize]);
+        inner.set_position(padding);
+        let mut writer = WzWriter::new(absolute_position, version_checksum, inner, encryptor);
+        value.encode(&mut writer).unwrap();
+        let written = *writer.position().unwrap() - padding as u32;
+
+        let mut inner = Cursor::new(writer.into_inner().into_inner());
+        inner.set_position(padding);
+        let mut reader = WzReader::new(absolute_position, version_checksum, inner, decryptor);
+        let decoded = T::decode(&mut reader).unwrap();
+        assert_eq!(*reader.position().unwrap(), padding as u32 + written);
+        (decoded, written)
+    }
+
+    /// [`roundtrip_with`] with and without encryption
+    fn roundtrip<T>(
+        value: &T,
+        padding: u64,
+        absolute_position: i32,
+        version_checksum: u32,
+    ) -> [(T, u32); 2]
+    where
+        T: Encode + Decode,
+    {
+        let key = KeyStream::new(&TRIMMED_KEY, &GMS_IV);
+        [
+            roundtrip_with(
+                value,
+                padding,
+                absolute_position,
+                version_checksum,
+                key.clone(),
+                key,
+            ),
+            roundtrip_with(
+                value,
+                padding,
+                absolute_position,
+                version_checksum,
+                DummyEncryptor,
+                DummyDecryptor,
+            ),
+        ]
+    }
+
+    fn int() -> impl Strategy<Value = i32> {
+        let boundaries = [0, 1, -1, 127, 128, -127, -128, -129, i32::MIN, i32::MAX];
+        prop_oneof![any::<i32>(), proptest::sample::select(boundaries.to_vec())]
+    }
+
+    fn long() -> impl Strategy<Value = i64> {
+        let boundaries = [0, 127, 128, -127, -128, -129, i64::MIN, i64::MAX];
+        prop_oneof![any::<i64>(), proptest::sample::select(boundaries.to_vec())]
+    }
+
+    /// ASCII and unicode strings around the lengths where the length marker changes
+    fn string() -> impl Strategy<Value = String> {
+        prop_oneof![
+            vec(0u8..0x80, 0..300).prop_map(|bytes| bytes.into_iter().map(char::from).collect()),
+            vec(any::<char>(), 0..300).prop_map(String::from_iter),
+            (125usize..130, any::<char>()).prop_map(|(length, c)| c.to_string().repeat(length)),
+        ]
+    }
+
+    fn content() -> impl Strategy<Value = ContentRef> {
+        (any::<bool>(), string(), int(), int(), any::<u32>()).prop_map(
+            |(package, name, size, checksum, offset)| {
+                let metadata = Metadata::new(
+                    name,
+                    WzInt::from(size),
+                    WzInt::from(checksum),
+                    WzOffset::from(offset),
+                );
+                match package {
+                    true => ContentRef::Package(metadata),
+                    false => ContentRef::Image(metadata),
+                }
+            },
+        )
+    }
+
+    proptest! {
+        #[test]
+        fn roundtrip_ints(value in int(), long in long()) {
+            for (decoded, written) in roundtrip(&WzInt::from(value), 0, 0, 0) {
+                prop_assert_eq!(decoded, WzInt::from(value));
+                prop_assert_eq!(written, WzInt::from(value).size_hint());
+            }
+            for (decoded, written) in roundtrip(&WzLong::from(long), 0, 0, 0) {
+                prop_assert_eq!(decoded, WzLong::from(long));
+                prop_assert_eq!(written, WzLong::from(long).size_hint());
+            }
+            for (decoded, written) in roundtrip(&value, 0, 0, 0) {
+                prop_assert_eq!(decoded, value);
+                prop_assert_eq!(written, value.size_hint());
+            }
+            for (decoded, written) in roundtrip(&long, 0, 0, 0) {
+                prop_assert_eq!(decoded, long);
+                prop_assert_eq!(written, long.size_hint());
+            }
+        }
+
+        #[test]
+        fn roundtrip_strings(value in string()) {
+            for (decoded, written) in roundtrip(&value, 0, 0, 0) {
+                prop_assert_eq!(&decoded, &value);
+                prop_assert_eq!(written, value.size_hint());
+            }
+        }
+
+        #[test]
+        fn roundtrip_offsets(
+            value in any::<u32>(),
+            padding in 0u64..0x200,
+            absolute_position in 0i32..0x100,
+            version_checksum in any::<u32>(),
+        ) {
+            let offset = WzOffset::from(value);
+            for (decoded, written) in roundtrip(&offset, padding, absolute_position, version_checksum) {
+                prop_assert_eq!(decoded, offset);
+                prop_assert_eq!(written, offset.size_hint());
+            }
+        }
+
+        #[test]
+        fn roundtrip_packages(
+            contents in vec(content(), 0..8),
+            padding in 0u64..0x200,
+            version_checksum in any::<u32>(),
+        ) {
+            let package = Package { contents };
+            for (decoded, written) in roundtrip(&package, padding, 60, version_checksum) {
+                prop_assert_eq!(&decoded.contents, &package.contents);
+                prop_assert_eq!(written, package.size_hint());
+            }
+        }
+    }
+}
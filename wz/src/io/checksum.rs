@@ -0,0 +1,55 @@
+//! WZ checksums
+
+use crate::{error::Result, types::WzInt};
+use std::io::{ErrorKind, Read};
+use std::num::Wrapping;
+
+/// Reads `reader` to the end and returns the number of bytes read along with their WZ checksum,
+/// the wrapping sum of every byte. The data is read in chunks, so files of any size can be
+/// checksummed without loading them into memory.
+pub fn checksum_reader<R>(mut reader: R) -> Result<(u64, WzInt)>
+where
+    R: Read,
+{
+    let mut buf = [0u8; 8192];
+    let mut size = 0u64;
+    let mut checksum = Wrapping(0i32);
+    loop {
+        let len = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        size += len as u64;
+        checksum += buf[..len]
+            .iter()
+            .map(|b| Wrapping(*b as i32))
+            .sum::<Wrapping<i32>>();
+    }
+    Ok((size, WzInt::from(checksum.0)))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::io::checksum_reader;
+    use crate::types::WzInt;
+    use std::io::Read;
+
+    #[test]
+    fn streaming_checksum() {
+        assert_eq!(checksum_reader(&[][..]).unwrap(), (0, WzInt::from(0)));
+        let data = (0..=255u8).cycle().take(100_000).collect::<Vec<u8>>();
+        let expected = data.iter().map(|b| *b as i32).sum::<i32>();
+        assert_eq!(
+            checksum_reader(data.as_slice()).unwrap(),
+            (100_000, WzInt::from(expected))
+        );
+
+        // The sum wraps around like the client's
+        let (size, checksum) = checksum_reader(std::io::repeat(0xff).take(9_000_000)).unwrap();
+        assert_eq!(size, 9_000_000);
+        assert_eq!(*checksum, (255i64 * 9_000_000) as i32);
+    }
+}
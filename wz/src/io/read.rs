@@ -2,14 +2,16 @@
 
 use crate::error::{ImageError, Result};
 use crate::io::Decode;
-use crate::types::{WzInt, WzOffset};
+use crate::types::{string::decode_string, StringDecoding, UolString, WzInt, WzOffset};
 use std::io::Write;
 
+mod decrypting;
 mod dummy_decryptor;
 mod image;
 mod reader;
 
 pub use self::image::WzImageReader;
+pub use decrypting::WzDecryptingReader;
 pub use dummy_decryptor::DummyDecryptor;
 pub use reader::WzReader;
 
@@ -46,16 +48,27 @@ pub trait WzRead {
     /// Decrypts a vector of bytes
     fn decrypt(&mut self, bytes: &mut Vec<u8>);
 
+    /// Returns how strings that are not valid UTF-8 or UTF-16 are decoded
+    fn string_decoding(&self) -> StringDecoding;
+
+    /// Sets how strings that are not valid UTF-8 or UTF-16 are decoded
+    fn set_string_decoding(&mut self, decoding: StringDecoding);
+
     /// Reads a UOL string
     fn read_uol_string(&mut self) -> Result<String> {
+        Ok(self.read_uol_string_exact()?.into_string())
+    }
+
+    /// Reads a UOL string along with the form and contents it was stored with
+    fn read_uol_string_exact(&mut self) -> Result<UolString> {
         let check = u8::decode(self)?;
         match check {
-            0 => String::decode(self),
+            0 => decode_string(self),
             1 => {
                 let offset = WzOffset::from(u32::decode(self)?);
                 let pos = self.position()?;
                 self.seek(offset)?;
-                let string = String::decode(self)?;
+                let string = decode_string(self)?;
                 self.seek(pos)?;
                 Ok(string)
             }
@@ -106,18 +119,18 @@ pub trait WzRead {
 
     /// Reads a string as if it were utf8. This function does not do UTF-8 conversion but will read
     /// the amount of bytes required to convert to utf8.
+    ///
+    /// Strings are always masked, even in unencrypted archives, so they cannot be borrowed from
+    /// the input. The mask is removed in place to keep it to a single allocation.
     fn read_utf8_bytes(&mut self, len: usize) -> Result<Vec<u8>> {
         let mut buf = self.read_vec(len)?;
         self.decrypt(&mut buf);
         let mut mask = 0xaa;
-        Ok(buf
-            .iter()
-            .map(|b| {
-                let c = b ^ mask;
-                mask = mask.checked_add(1).unwrap_or(0);
-                c
-            })
-            .collect())
+        for b in buf.iter_mut() {
+            *b ^= mask;
+            mask = mask.checked_add(1).unwrap_or(0);
+        }
+        Ok(buf)
     }
 
     /// Reads a string as if it were unicode (or wchar). This function does not do unicode
@@ -0,0 +1,90 @@
+//! WZ Decrypting Reader
+
+use crate::error::Result;
+use crate::io::WzRead;
+use crate::types::{StringDecoding, WzInt, WzOffset};
+use crypto::Decryptor;
+use std::io::Write;
+
+/// Wraps a WzRead but decrypts strings with its own decryptor.
+///
+/// Some clients only encrypt part of their images, e.g. GMS v65 to v83 only encrypt the images
+/// listed in List.wz. This lets a single archive reader decode both kinds of images.
+#[derive(Debug)]
+pub struct WzDecryptingReader<'a, R, D>
+where
+    R: WzRead + ?Sized,
+    D: Decryptor,
+{
+    inner: &'a mut R,
+    decryptor: D,
+}
+
+impl<'a, R, D> WzDecryptingReader<'a, R, D>
+where
+    R: WzRead + ?Sized,
+    D: Decryptor,
+{
+    /// Creates a new [`WzDecryptingReader`]
+    pub fn new(inner: &'a mut R, decryptor: D) -> Self {
+        Self { inner, decryptor }
+    }
+}
+
+impl<R, D> WzRead for WzDecryptingReader<'_, R, D>
+where
+    R: WzRead + ?Sized,
+    D: Decryptor,
+{
+    fn absolute_position(&self) -> i32 {
+        self.inner.absolute_position()
+    }
+
+    fn version_checksum(&self) -> u32 {
+        self.inner.version_checksum()
+    }
+
+    fn set_version_checksum(&mut self, version_checksum: u32) {
+        self.inner.set_version_checksum(version_checksum)
+    }
+
+    fn string_decoding(&self) -> StringDecoding {
+        self.inner.string_decoding()
+    }
+
+    fn set_string_decoding(&mut self, decoding: StringDecoding) {
+        self.inner.set_string_decoding(decoding)
+    }
+
+    fn position(&mut self) -> Result<WzOffset> {
+        self.inner.position()
+    }
+
+    fn seek(&mut self, pos: WzOffset) -> Result<WzOffset> {
+        self.inner.seek(pos)
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.inner.read(buf)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+        self.inner.read_exact(buf)
+    }
+
+    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> Result<usize> {
+        self.inner.read_to_end(buf)
+    }
+
+    fn copy_to<W>(&mut self, dest: &mut W, offset: WzOffset, size: WzInt) -> Result<()>
+    where
+        W: Write,
+    {
+        self.inner.copy_to(dest, offset, size)
+    }
+
+    #[inline]
+    fn decrypt(&mut self, bytes: &mut Vec<u8>) {
+        self.decryptor.decrypt(bytes)
+    }
+}
@@ -7,5 +7,6 @@ pub struct DummyDecryptor;
 
 impl Decryptor for DummyDecryptor {
     /// Empty function that does nothing to the provided bytes
+    #[inline(always)]
     fn decrypt(&mut self, _: &mut Vec<u8>) {}
 }
@@ -2,8 +2,11 @@
 
 use crate::error::{ImageError, Result};
 use crate::io::{Decode, WzRead};
-use crate::types::{WzInt, WzOffset};
-use std::{collections::HashMap, io::Write};
+use crate::types::{string::decode_string, StringDecoding, UolString, WzInt, WzOffset};
+use std::{
+    collections::{HashMap, HashSet},
+    io::Write,
+};
 
 /// WZ Image Reader
 ///
@@ -18,7 +21,8 @@ where
 {
     inner: &'a mut R,
     offset: WzOffset,
-    cache: HashMap<u32, String>,
+    cache: HashMap<u32, UolString>,
+    references: Option<HashSet<u32>>,
 }
 
 impl<'a, R> WzImageReader<'a, R>
@@ -31,6 +35,7 @@ where
             inner,
             offset: WzOffset::from(0),
             cache: HashMap::new(),
+            references: None,
         }
     }
 
@@ -40,6 +45,23 @@ where
             inner,
             offset,
             cache: HashMap::new(),
+            references: None,
+        }
+    }
+
+    /// Starts keeping track of the offsets that strings are referenced from
+    pub(crate) fn record_references(&mut self) {
+        self.references = Some(HashSet::new());
+    }
+
+    /// Returns the offsets referenced since [`WzImageReader::record_references`] was called
+    pub(crate) fn take_references(&mut self) -> HashSet<u32> {
+        self.references.take().unwrap_or_default()
+    }
+
+    fn reference(&mut self, offset: u32) {
+        if let Some(references) = &mut self.references {
+            references.insert(offset);
         }
     }
 }
@@ -60,6 +82,14 @@ where
         self.inner.set_version_checksum(version_checksum)
     }
 
+    fn string_decoding(&self) -> StringDecoding {
+        self.inner.string_decoding()
+    }
+
+    fn set_string_decoding(&mut self, decoding: StringDecoding) {
+        self.inner.set_string_decoding(decoding)
+    }
+
     fn position(&mut self) -> Result<WzOffset> {
         Ok(self.inner.position()? - self.offset)
     }
@@ -95,27 +125,29 @@ where
         self.inner.copy_to(dest, self.offset + offset, size)
     }
 
+    #[inline]
     fn decrypt(&mut self, bytes: &mut Vec<u8>) {
         self.inner.decrypt(bytes)
     }
 
-    fn read_uol_string(&mut self) -> Result<String> {
+    fn read_uol_string_exact(&mut self) -> Result<UolString> {
         let check = u8::decode(self)?;
         match check {
             0 => {
                 let position = self.position()?;
-                let string = String::decode(self)?;
+                let string = decode_string(self)?;
                 self.cache.insert(*position, string.clone());
                 Ok(string)
             }
             1 => {
                 let offset = u32::decode(self)?;
+                self.reference(offset);
                 Ok(match self.cache.get(&offset) {
-                    Some(string) => string.to_string(),
+                    Some(string) => string.clone(),
                     None => {
                         let pos = self.position()?;
                         self.seek(offset.into())?;
-                        let string = String::decode(self)?;
+                        let string = decode_string(self)?;
                         self.seek(pos)?;
                         string
                     }
@@ -130,12 +162,13 @@ where
         match check {
             0x73 => {
                 let position = self.position()?;
-                let string = String::decode(self)?;
+                let string = decode_string(self)?;
                 self.cache.insert(*position, string.clone());
-                Ok(string)
+                Ok(string.into_string())
             }
             0x1b => {
                 let offset = u32::decode(self)?;
+                self.reference(offset);
                 Ok(match self.cache.get(&offset) {
                     Some(string) => string.to_string(),
                     None => {
@@ -2,7 +2,7 @@
 
 use crate::error::Result;
 use crate::io::{DummyDecryptor, WzRead};
-use crate::types::{WzInt, WzOffset};
+use crate::types::{StringDecoding, WzInt, WzOffset};
 use crypto::{Decryptor, KeyStream};
 use std::io::{Read, Seek, SeekFrom, Write};
 
@@ -57,6 +57,9 @@ where
     /// Some versions of WZ archives have encrypted strings. A [`DummyDecryptor`] is provided for
     /// versions that do not.
     decryptor: D,
+
+    /// How strings that are not valid UTF-8 or UTF-16 are decoded
+    string_decoding: StringDecoding,
 }
 
 impl<R> WzReader<R, DummyDecryptor>
@@ -96,6 +99,7 @@ where
             version_checksum,
             reader,
             decryptor,
+            string_decoding: StringDecoding::default(),
         }
     }
 
@@ -122,6 +126,14 @@ where
         self.version_checksum = version_checksum;
     }
 
+    fn string_decoding(&self) -> StringDecoding {
+        self.string_decoding
+    }
+
+    fn set_string_decoding(&mut self, decoding: StringDecoding) {
+        self.string_decoding = decoding;
+    }
+
     fn position(&mut self) -> Result<WzOffset> {
         Ok(WzOffset::from(self.reader.stream_position()?))
     }
@@ -164,6 +176,7 @@ where
         Ok(())
     }
 
+    #[inline]
     fn decrypt(&mut self, bytes: &mut Vec<u8>) {
         self.decryptor.decrypt(bytes)
     }
@@ -2,7 +2,7 @@
 
 use crate::error::Result;
 use crate::io::Encode;
-use crate::types::{WzInt, WzOffset};
+use crate::types::{string::encode_string, UolString, WzInt, WzOffset};
 use std::io::Read;
 
 mod dummy_encryptor;
@@ -46,6 +46,12 @@ pub trait WzWrite {
         string.encode(self)
     }
 
+    /// Writes a [`UolString`] in the form and with the contents it was decoded with (images only)
+    fn write_uol_string_exact(&mut self, string: &UolString) -> Result<()> {
+        0u8.encode(self)?;
+        encode_string(string, string.form(), string.raw_bytes(), self)
+    }
+
     /// Writes the object tag string (images only)
     fn write_object_tag(&mut self, tag: &str) -> Result<()> {
         0x73u8.encode(self)?;
@@ -62,6 +68,22 @@ pub trait WzWrite {
         self.seek(WzOffset::from(self.absolute_position() as u32 + offset))
     }
 
+    /// Reserves a 4-byte size, runs `encode` and then fills in the number of bytes it wrote
+    fn with_length_prefix<F>(&mut self, encode: F) -> Result<()>
+    where
+        F: FnOnce(&mut Self) -> Result<()>,
+    {
+        let size_position = self.position()?;
+        0u32.encode(self)?;
+        encode(self)?;
+        let current_position = self.position()?;
+        self.seek(size_position)?;
+        let size = *current_position - *size_position - 4;
+        size.encode(self)?;
+        self.seek(current_position)?;
+        Ok(())
+    }
+
     /// Writes a single byte
     fn write_byte(&mut self, byte: u8) -> Result<()> {
         self.write_all(&[byte])
@@ -1,8 +1,9 @@
 //! WZ Image Writer
 
 use crate::error::Result;
-use crate::io::{encode::SizeHint, Encode, WzWrite};
-use crate::types::{WzInt, WzOffset};
+use crate::io::{Encode, WzWrite};
+use crate::types::string::{encode_string, string_size_hint};
+use crate::types::{RawString, StringForm, UolString, WzInt, WzOffset};
 use std::{collections::HashMap, io::Read};
 
 /// WZ Image Writer
@@ -43,19 +44,34 @@ where
     }
 
     #[inline]
-    fn write_from_cache(&mut self, string: &str, not_cached: u8, cached: u8) -> Result<()> {
+    fn write_from_cache(
+        &mut self,
+        string: &str,
+        form: StringForm,
+        raw: Option<&RawString>,
+        not_cached: u8,
+        cached: u8,
+    ) -> Result<()> {
         // WZ images don't seem to bother with UOLs if the length is greater than 4. I assume there
         // is some calculation based on the encoded size and not the string length, but I could be
         // wrong. I just set threshold to size_hint() > 5 to match what I've witnessed decoding.
-        if string.size_hint() > 5 && self.cache.contains_key(string) {
+        //
+        // Strings that could not be decoded are not cached as other strings may decode to the
+        // same replacement characters.
+        if raw.is_none()
+            && string_size_hint(string, form, raw) > 5
+            && self.cache.contains_key(string)
+        {
             cached.encode(self)?;
             let offset = *self.cache.get(string).expect("cache should have string");
             offset.encode(self)
         } else {
             not_cached.encode(self)?;
             let position = self.position()?;
-            self.cache.insert(string.to_string(), *position);
-            string.encode(self)
+            if raw.is_none() {
+                self.cache.insert(string.to_string(), *position);
+            }
+            encode_string(string, form, raw, self)
         }
     }
 }
@@ -103,15 +119,20 @@ where
         self.inner.copy_from(src, size)
     }
 
+    #[inline]
     fn encrypt(&mut self, bytes: &mut Vec<u8>) {
         self.inner.encrypt(bytes)
     }
 
     fn write_uol_string(&mut self, string: &str) -> Result<()> {
-        self.write_from_cache(string, 0, 1)
+        self.write_from_cache(string, StringForm::default(), None, 0, 1)
+    }
+
+    fn write_uol_string_exact(&mut self, string: &UolString) -> Result<()> {
+        self.write_from_cache(string, string.form(), string.raw_bytes(), 0, 1)
     }
 
     fn write_object_tag(&mut self, tag: &str) -> Result<()> {
-        self.write_from_cache(tag, 0x73, 0x1b)
+        self.write_from_cache(tag, StringForm::default(), None, 0x73, 0x1b)
     }
 }
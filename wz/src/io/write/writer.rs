@@ -103,6 +103,11 @@ where
     pub fn into_inner(self) -> W {
         self.writer
     }
+
+    /// Flushes the underlying writer
+    pub fn flush(&mut self) -> Result<()> {
+        Ok(self.writer.flush()?)
+    }
 }
 
 impl<W, E> WzWrite for WzWriter<W, E>
@@ -155,6 +160,7 @@ where
         Ok(())
     }
 
+    #[inline]
     fn encrypt(&mut self, bytes: &mut Vec<u8>) {
         self.encryptor.encrypt(bytes);
     }
@@ -163,7 +169,10 @@ where
 #[cfg(test)]
 mod tests {
 
-    use crate::{io::WzWriter, types::WzHeader};
+    use crate::{
+        io::{Encode, WzWrite, WzWriter},
+        types::WzHeader,
+    };
     use crypto::{checksum, KeyStream, GMS_IV, TRIMMED_KEY};
     use std::io::Cursor;
 
@@ -189,4 +198,21 @@ mod tests {
             Cursor::new(vec![0u8; 60]),
         );
     }
+
+    #[test]
+    fn length_prefix() {
+        let mut writer = WzWriter::unencrypted(0, 0, Cursor::new(Vec::new()));
+        0xffu8.encode(&mut writer).expect("error writing");
+        writer
+            .with_length_prefix(|writer| {
+                writer.with_length_prefix(|writer| writer.write_all(&[1, 2, 3]))?;
+                writer.write_byte(4)
+            })
+            .expect("error writing");
+        5u8.encode(&mut writer).expect("error writing");
+        assert_eq!(
+            writer.into_inner().into_inner(),
+            [0xff, 8, 0, 0, 0, 3, 0, 0, 0, 1, 2, 3, 4, 5]
+        );
+    }
 }
@@ -1 +1,212 @@
+//! XML Reader
+
+#[allow(hidden_glob_reexports)]
+use crate::error::Result;
+use crate::io::xml::writer::Dialect;
+use crate::map::Map;
+use std::io::Read;
+
 pub use xml::reader::*;
+
+use xml::attribute::OwnedAttribute;
+
+/// Tells the [`XmlReader`] how to build the object from an element
+pub trait FromXml: Sized {
+    /// Returns the name and object described by the `tag` element and its `attributes`, written in
+    /// `dialect`
+    fn from_xml(
+        tag: &str,
+        attributes: &[OwnedAttribute],
+        dialect: Dialect,
+    ) -> Result<(String, Self)>;
+}
+
+/// Reads XML written by [`XmlWriter`](crate::io::xml::writer::XmlWriter) back into a
+/// [`Map`]. The root element becomes the root of the map and every nested element becomes a
+/// child. Text is ignored.
+pub struct XmlReader<R>
+where
+    R: Read,
+{
+    reader: EventReader<R>,
+    dialect: Dialect,
+}
+
+impl<R> XmlReader<R>
+where
+    R: Read,
+{
+    /// Creates a new [`XmlReader`] that wraps a primitive reader.
+    pub fn new(reader: R) -> Self {
+        Self::with_dialect(reader, Dialect::Native)
+    }
+
+    /// Creates a new [`XmlReader`] that reads XML written in `dialect`, e.g. a HaRepacker dump
+    pub fn with_dialect(reader: R, dialect: Dialect) -> Self {
+        Self {
+            reader: EventReader::new(reader),
+            dialect,
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader.into_inner()
+    }
+
+    /// Reads the whole document
+    pub fn read<T>(&mut self) -> Result<Map<T>>
+    where
+        T: FromXml,
+    {
+        let mut map = loop {
+            match self.reader.next()? {
+                XmlEvent::StartElement {
+                    name, attributes, ..
+                } => {
+                    let (name, data) = T::from_xml(&name.local_name, &attributes, self.dialect)?;
+                    break Map::new(name, data);
+                }
+                XmlEvent::EndDocument => {
+                    return Err(Error::from((&self.reader, "No root element")).into())
+                }
+                _ => {}
+            }
+        };
+        let mut cursor = map.cursor_mut();
+        let mut depth = 0;
+        loop {
+            match self.reader.next()? {
+                XmlEvent::StartElement {
+                    name, attributes, ..
+                } => {
+                    let (name, data) = T::from_xml(&name.local_name, &attributes, self.dialect)?;
+                    cursor.create(name.clone(), data)?;
+                    cursor.move_to(&name)?;
+                    depth += 1;
+                }
+                XmlEvent::EndElement { .. } if depth == 0 => break,
+                XmlEvent::EndElement { .. } => {
+                    cursor.parent()?;
+                    depth -= 1;
+                }
+                XmlEvent::EndDocument => break,
+                _ => {}
+            }
+        }
+        Ok(map)
+    }
+}
+
+#[cfg(all(test, feature = "canvas-codecs"))]
+mod tests {
+
+    use crate::{
+        error::Error,
+        image::Reader,
+        io::xml::{
+            reader::XmlReader,
+            writer::{DataMode, Dialect, ToXml, XmlOptions, XmlWriter},
+        },
+        map::Map,
+        types::{Property, Sound, SoundHeader, WavHeader, WzInt},
+    };
+    use crypto::{KeyStream, GMS_IV, TRIMMED_KEY};
+    use std::io::Cursor;
+
+    fn contents(map: &Map<Property>, dialect: Dialect) -> Vec<String> {
+        let mut contents = Vec::new();
+        map.walk::<Error>(|cursor| {
+            let property = cursor.get();
+            let mut attributes = property.attributes(cursor.name());
+            attributes.extend(property.payload(dialect, None));
+            contents.push(format!(
+                "{} {} {:?}",
+                cursor.pwd(),
+                property.tag(),
+                attributes
+            ));
+            Ok(())
+        })
+        .expect("error walking map");
+        contents
+    }
+
+    fn round_trip(map: &Map<Property>, embed: bool) -> Map<Property> {
+        let mut writer = XmlWriter::new(Cursor::new(Vec::new())).embed(embed);
+        writer.write(&mut map.cursor()).expect("error writing XML");
+        let data = writer.into_inner().into_inner();
+        XmlReader::new(data.as_slice())
+            .read()
+            .expect("error reading XML")
+    }
+
+    fn weapon() -> Map<Property> {
+        let mut reader = Reader::open(
+            "testdata/v83-weapon.img",
+            KeyStream::new(&TRIMMED_KEY, &GMS_IV),
+        )
+        .expect("error opening image");
+        let mut map = reader.map("weapon.img").expect("error mapping image");
+        let header = SoundHeader::from(WavHeader::pcm(1, 22050, 16));
+        map.cursor_mut()
+            .create(
+                String::from("sound"),
+                Property::Sound(Sound::new(WzInt::from(10), header, vec![1, 2, 3, 4])),
+            )
+            .expect("error adding sound");
+        map
+    }
+
+    #[test]
+    fn server_round_trip() {
+        let mut map = weapon();
+        assert_eq!(
+            contents(&round_trip(&map, true), Dialect::Native),
+            contents(&map, Dialect::Native)
+        );
+
+        // Without the payload canvases keep their size only and sounds cannot be read
+        assert!(
+            XmlReader::new(&b"<imgdir name=\"a.img\"><sound name=\"s\"/></imgdir>"[..])
+                .read::<Property>()
+                .is_err()
+        );
+        map.cursor_mut()
+            .delete("sound")
+            .expect("error deleting sound");
+        let blank = round_trip(&map, false);
+        map.walk::<Error>(|cursor| {
+            if let Property::Canvas(canvas) = cursor.get() {
+                match blank.get(cursor.pwd()).expect("missing canvas") {
+                    Property::Canvas(c) => {
+                        assert_eq!((c.width(), c.height()), (canvas.width(), canvas.height()))
+                    }
+                    p => panic!("expected a canvas, found {:?}", p),
+                }
+            }
+            Ok(())
+        })
+        .expect("error walking map");
+    }
+
+    #[test]
+    fn harepacker_import() {
+        let map = weapon();
+        let options = XmlOptions {
+            dialect: Dialect::HaRepacker,
+            data: DataMode::Inline,
+            ..Default::default()
+        };
+        let mut writer = XmlWriter::with_options(Cursor::new(Vec::new()), options);
+        writer.write(&mut map.cursor()).expect("error writing XML");
+        let data = writer.into_inner().into_inner();
+        let imported = XmlReader::with_dialect(data.as_slice(), Dialect::HaRepacker)
+            .read()
+            .expect("error reading XML");
+        // Canvases may be stored in another format but keep their pixels
+        assert_eq!(
+            contents(&imported, Dialect::HaRepacker),
+            contents(&map, Dialect::HaRepacker)
+        );
+    }
+}
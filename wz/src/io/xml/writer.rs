@@ -2,12 +2,70 @@
 
 #[allow(hidden_glob_reexports)]
 use crate::error::Result;
-use crate::io::xml::{attribute::Attribute, namespace::Namespace};
+use crate::io::xml::{attribute::Attribute, common::XmlVersion, namespace::Namespace};
 use crate::map::Cursor;
 use std::{borrow::Cow, io::Write};
 
 pub use xml::writer::*;
 
+/// XML shapes [`XmlWriter`] can write. They only differ in how binary data is written, see
+/// [`ToXml::payload`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Dialect {
+    /// Canvases embed their raw data with the `format` and sounds embed the file written by
+    /// [`Sound::save_to_file`](crate::types::Sound::save_to_file) with the `duration`. This is what
+    /// [`XmlReader`](crate::io::xml::reader::XmlReader) reads back.
+    #[default]
+    Native,
+
+    /// HaRepacker's export. Canvases embed a PNG. Sounds embed their header in `basehead` and
+    /// their data in `basedata`, with the duration in `length`. The document is declared
+    /// standalone.
+    HaRepacker,
+
+    /// WzDumper's export. Binary data is written like [`Dialect::HaRepacker`] but the document
+    /// declaration is left alone.
+    WzDumper,
+}
+
+/// Where [`XmlWriter`] puts the binary data of canvases and sounds
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DataMode {
+    /// Leave it out
+    #[default]
+    Omit,
+
+    /// Embed it as base64 attributes
+    Inline,
+
+    /// Reference a file with a `src` attribute. The path is the object path followed by
+    /// [`ToXml::extension`], e.g. `weapon.img/info/icon.png`. The files are left to the caller.
+    Files,
+}
+
+/// Options of [`XmlWriter`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XmlOptions {
+    /// Shape of the written XML
+    pub dialect: Dialect,
+
+    /// Indentation of nested elements. `None` writes everything on one line.
+    pub indent: Option<String>,
+
+    /// Where canvas and sound data goes
+    pub data: DataMode,
+}
+
+impl Default for XmlOptions {
+    fn default() -> Self {
+        Self {
+            dialect: Dialect::Native,
+            indent: Some(String::from("  ")),
+            data: DataMode::Omit,
+        }
+    }
+}
+
 /// Tells the [`XmlWriter`] how to write the object
 pub trait ToXml {
     /// Returns the tag name
@@ -20,6 +78,18 @@ pub trait ToXml {
     fn text(&self) -> Option<&str> {
         None
     }
+
+    /// Extension of the file the binary data is saved to in [`DataMode::Files`]. `None` when the
+    /// object has no binary data.
+    fn extension(&self) -> Option<&'static str> {
+        None
+    }
+
+    /// Extra attributes that carry the binary data of the object in `dialect`. They reference the
+    /// file at `src` when given and embed the data otherwise. Not written in [`DataMode::Omit`].
+    fn payload(&self, _dialect: Dialect, _src: Option<&str>) -> Vec<(String, String)> {
+        Vec::new()
+    }
 }
 
 /// A very basic XML writer that does almost no validation. Use with caution. Children are written
@@ -30,6 +100,8 @@ where
     W: Write,
 {
     writer: EventWriter<W>,
+    options: XmlOptions,
+    declared: bool,
 }
 
 impl<W> XmlWriter<W>
@@ -38,16 +110,41 @@ where
 {
     /// Creates a new [`XmlWriter`] that wraps a primitive writer.
     pub fn new(writer: W) -> Self {
+        Self::with_options(writer, XmlOptions::default())
+    }
+
+    /// Creates a new [`XmlWriter`] with the privided config.
+    pub fn from_config(writer: EventWriter<W>) -> Self {
         Self {
-            writer: EmitterConfig::new()
+            writer,
+            options: XmlOptions::default(),
+            declared: false,
+        }
+    }
+
+    /// Creates a new [`XmlWriter`] that wraps a primitive writer and writes with `options`
+    pub fn with_options(writer: W, options: XmlOptions) -> Self {
+        let config = match &options.indent {
+            Some(indent) => EmitterConfig::new()
                 .perform_indent(true)
-                .create_writer(writer),
+                .indent_string(indent.clone()),
+            None => EmitterConfig::new().perform_indent(false),
+        };
+        Self {
+            writer: config.create_writer(writer),
+            options,
+            declared: false,
         }
     }
 
-    /// Creates a new [`XmlWriter`] with the privided config.
-    pub fn from_config(writer: EventWriter<W>) -> Self {
-        Self { writer }
+    /// Shorthand for embedding the data in [`DataMode::Inline`], e.g. so canvases and sounds can
+    /// be read back with their data
+    pub fn embed(mut self, embed: bool) -> Self {
+        self.options.data = match embed {
+            true => DataMode::Inline,
+            false => DataMode::Omit,
+        };
+        self
     }
 
     pub fn into_inner(self) -> W {
@@ -59,8 +156,25 @@ where
     where
         E: ToXml,
     {
+        if !self.declared && self.options.dialect == Dialect::HaRepacker {
+            self.writer.write(XmlEvent::StartDocument {
+                version: XmlVersion::Version10,
+                encoding: Some("UTF-8"),
+                standalone: Some(true),
+            })?;
+        }
+        self.declared = true;
         let data = cursor.get();
-        let attributes = data.attributes(cursor.name());
+        let mut attributes = data.attributes(cursor.name());
+        match (self.options.data, data.extension()) {
+            (DataMode::Omit, _) => {}
+            (DataMode::Inline, _) => attributes.extend(data.payload(self.options.dialect, None)),
+            (DataMode::Files, Some(extension)) => {
+                let src = format!("{}.{}", cursor.pwd(), extension);
+                attributes.extend(data.payload(self.options.dialect, Some(&src)));
+            }
+            (DataMode::Files, None) => {}
+        }
         self.writer.write(XmlEvent::StartElement {
             name: data.tag().into(),
             attributes: Cow::Owned(
@@ -99,6 +213,13 @@ mod tests {
         io::xml::writer::{EmitterConfig, ToXml, XmlWriter},
         map::Map,
     };
+    #[cfg(feature = "canvas-codecs")]
+    use crate::{
+        io::xml::writer::{DataMode, Dialect, XmlOptions},
+        types::{Canvas, Property, Sound, SoundHeader, WavHeader, WzInt},
+    };
+    #[cfg(feature = "canvas-codecs")]
+    use base64::{engine::general_purpose::STANDARD, Engine};
     use std::io::Cursor;
 
     impl ToXml for i32 {
@@ -152,4 +273,65 @@ mod tests {
             r#"<int name="n1" value="100"><int name="n1_1" value="150"><int name="n1_1_1" value="155"><int name="n1_1_1_1" value="255" /></int><int name="n1_1_2" value="175" /></int></int>"#
         )
     }
+
+    #[cfg(feature = "canvas-codecs")]
+    fn write_property(map: &Map<Property>, options: XmlOptions) -> String {
+        let mut writer = XmlWriter::with_options(Cursor::new(Vec::new()), options);
+        writer.write(&mut map.cursor()).expect("error writing XML");
+        String::from_utf8(writer.into_inner().into_inner()).expect("invalid UTF-8 encoding")
+    }
+
+    #[test]
+    #[cfg(feature = "canvas-codecs")]
+    fn write_options() {
+        let mut map = Map::new(String::from("a.img"), Property::ImgDir);
+        let header = SoundHeader::from(WavHeader::pcm(1, 22050, 16));
+        map.cursor_mut()
+            .create(
+                String::from("icon"),
+                Property::Canvas(Canvas::blank(WzInt::from(1), WzInt::from(1))),
+            )
+            .expect("error creating icon")
+            .create(
+                String::from("sound"),
+                Property::Sound(Sound::new(WzInt::from(10), header, vec![1, 2])),
+            )
+            .expect("error creating sound");
+
+        let data = write_property(
+            &map,
+            XmlOptions {
+                indent: None,
+                data: DataMode::Files,
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            data.as_str(),
+            r#"<?xml version="1.0" encoding="UTF-8"?><imgdir name="a.img"><canvas name="icon" width="1" height="1" src="a.img/icon.png" format="1" /><sound name="sound" src="a.img/sound.wav" duration="10" /></imgdir>"#
+        );
+
+        let data = write_property(
+            &map,
+            XmlOptions {
+                dialect: Dialect::HaRepacker,
+                indent: Some(String::from("\t")),
+                data: DataMode::Inline,
+            },
+        );
+        assert!(data.starts_with(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n<imgdir name=\"a.img\">\n\t<canvas"
+        ));
+        let png = STANDARD.encode(
+            map.get("a.img/icon")
+                .map(|p| match p {
+                    Property::Canvas(c) => c.png().expect("error encoding PNG"),
+                    _ => unreachable!(),
+                })
+                .expect("missing icon"),
+        );
+        assert!(data.contains(&format!(r#"basedata="{}""#, png)));
+        assert!(data.contains(r#"<sound name="sound" length="10" basehead=""#));
+        assert!(data.contains(r#"basedata="AQI=" />"#));
+    }
 }
@@ -8,3 +8,5 @@ pub mod io;
 pub mod list;
 pub mod map;
 pub mod types;
+pub mod versions;
+pub mod vfs;
@@ -1,65 +1,397 @@
 //! List.wz Decoder
 
-use crate::error::{Error, Result};
-use crate::io::{Decode, DummyDecryptor, WzRead, WzReader};
-use crypto::Decryptor;
+use crate::error::Result;
+use crypto::{Decryptor, Encryptor};
+use std::collections::HashSet;
 use std::fs::File;
-use std::io::{BufReader, ErrorKind};
+use std::io::{BufRead, BufReader, Read, Write};
 use std::path::Path;
-use std::slice::Iter;
 
+/// Image path listed in List.wz
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// Path of the image, e.g. `Mob/0100100.img`
+    pub path: String,
+
+    /// Offset of the entry within List.wz
+    pub offset: u64,
+
+    /// Length of the path in UTF-16 code units
+    pub length: u32,
+}
+
+/// Way an entry of List.wz is malformed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Malformed {
+    /// The entry runs past the end of the file. Nothing after it can be read.
+    Truncated,
+
+    /// The path is not valid UTF-16. The entry is skipped.
+    Utf16,
+
+    /// The path is not followed by a null terminator. The entry is kept.
+    Terminator,
+}
+
+/// Reads List.wz, the list of images that older clients encrypt.
+///
+/// Malformed entries are recorded instead of stopping the parse. They are available through
+/// [`Reader::malformed`].
+#[derive(Debug, Clone, Default)]
 pub struct Reader {
-    strings: Vec<String>,
+    entries: Vec<Entry>,
+    malformed: Vec<(u64, Malformed)>,
+    listed: HashSet<String>,
 }
 
 impl Reader {
-    pub fn parse<S, D>(path: S, mut decryptor: D) -> Result<Self>
+    pub fn parse<S, D>(path: S, decryptor: D) -> Result<Self>
     where
         S: AsRef<Path>,
         D: Decryptor,
     {
-        let mut strings = Vec::new();
-        let mut reader = WzReader::new(0, 0, BufReader::new(File::open(path)?), DummyDecryptor);
-        loop {
-            let length = match u32::decode(&mut reader) {
-                Ok(n) => n,
-                Err(Error::Io(ErrorKind::UnexpectedEof)) => break,
-                Err(e) => return Err(e),
-            };
-            strings.push(read_unicode_bytes(
-                &mut reader,
-                &mut decryptor,
-                length as usize,
-            )?);
-            u16::decode(&mut reader)?; // NULL-byte
+        Self::collect(Self::iter(BufReader::new(File::open(path)?), decryptor))
+    }
+
+    /// Parses the contents of List.wz
+    pub fn from_bytes<D>(data: &[u8], decryptor: D) -> Self
+    where
+        D: Decryptor,
+    {
+        Self::collect(Self::iter(data, decryptor)).expect("reading a slice should not fail")
+    }
+
+    /// Returns a lazy iterator over the entries of the List.wz contents in `reader`. Entries are
+    /// decrypted as they are read, so only one is held in memory at a time.
+    ///
+    /// Example:
+    ///
+    /// ```no_run
+    /// use crypto::{KeyStream, GMS_IV, TRIMMED_KEY};
+    /// use std::{fs::File, io::BufReader};
+    /// use wz::list::Reader;
+    ///
+    /// let file = BufReader::new(File::open("List.wz").unwrap());
+    /// for entry in Reader::iter(file, KeyStream::new(&TRIMMED_KEY, &GMS_IV)) {
+    ///     println!("{}", entry.unwrap().path);
+    /// }
+    /// ```
+    pub fn iter<R, D>(reader: R, decryptor: D) -> Iter<R, D>
+    where
+        R: BufRead,
+        D: Decryptor,
+    {
+        Iter {
+            reader,
+            decryptor,
+            position: 0,
+            pending: None,
+            malformed: Vec::new(),
+            done: false,
         }
-        let last_index = strings.len() - 1;
-        let mut last = String::from(&strings[last_index]);
-        last.pop();
-        last.push('g');
-        strings[last_index] = last;
-        Ok(Self { strings })
     }
 
-    pub fn strings(&self) -> Iter<'_, String> {
-        self.strings.iter()
+    fn collect<R, D>(mut iter: Iter<R, D>) -> Result<Self>
+    where
+        R: BufRead,
+        D: Decryptor,
+    {
+        let entries = iter.by_ref().collect::<Result<Vec<Entry>>>()?;
+        let listed = entries.iter().map(|entry| normalize(&entry.path)).collect();
+        Ok(Self {
+            entries,
+            malformed: iter.malformed,
+            listed,
+        })
+    }
+
+    pub fn strings(&self) -> impl Iterator<Item = &String> {
+        self.entries.iter().map(|entry| &entry.path)
+    }
+
+    /// Returns the entries in file order
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Returns the offset of every malformed entry along with what is wrong with it
+    pub fn malformed(&self) -> &[(u64, Malformed)] {
+        &self.malformed
+    }
+
+    /// Returns true if the image at `path` is listed. Separators may be `/` or `\` and the
+    /// comparison ignores case, e.g. `mob\0100100.img` matches `Mob/0100100.img`.
+    pub fn contains(&self, path: &str) -> bool {
+        self.listed.contains(&normalize(path))
     }
 }
 
-fn read_unicode_bytes<D>(
-    reader: &mut WzReader<BufReader<File>, DummyDecryptor>,
-    decryptor: &mut D,
-    len: usize,
-) -> Result<String>
+/// Lazy iterator over the entries of List.wz. Created with [`Reader::iter`].
+///
+/// Malformed entries are skipped like [`Reader`] does and are available through
+/// [`Iter::malformed`]. Iteration stops after the first IO error.
+#[derive(Debug)]
+pub struct Iter<R, D>
 where
+    R: BufRead,
     D: Decryptor,
 {
-    let mut buf = reader.read_vec(len * 2)?;
-    decryptor.decrypt(&mut buf);
-    Ok(String::from_utf16(
-        buf.chunks(2)
+    reader: R,
+    decryptor: D,
+    position: u64,
+    pending: Option<Entry>,
+    malformed: Vec<(u64, Malformed)>,
+    done: bool,
+}
+
+impl<R, D> Iter<R, D>
+where
+    R: BufRead,
+    D: Decryptor,
+{
+    /// Returns the offset of every malformed entry read so far along with what is wrong with it
+    pub fn malformed(&self) -> &[(u64, Malformed)] {
+        &self.malformed
+    }
+
+    /// Reads the next entry. Returns `None` for skipped entries and once the end is reached.
+    fn read_entry(&mut self) -> Result<Option<Entry>> {
+        let offset = self.position;
+        if self.reader.fill_buf()?.is_empty() {
+            self.done = true;
+            return Ok(None);
+        }
+        let length = match self.read_up_to(4)?.try_into() {
+            Ok(b) => u32::from_le_bytes(b),
+            Err(_) => return self.truncated(offset),
+        };
+        let size = (length as u64) * 2;
+        let mut buf = self.read_up_to(size)?;
+        let terminator = self.read_up_to(2)?;
+        if buf.len() as u64 != size || terminator.len() != 2 {
+            return self.truncated(offset);
+        }
+        self.position += 4 + size + 2;
+        if terminator != [0, 0] {
+            self.malformed.push((offset, Malformed::Terminator));
+        }
+        self.decryptor.decrypt(&mut buf);
+        let units = buf
+            .chunks(2)
             .map(|c| u16::from_le_bytes([c[0], c[1]]))
-            .collect::<Vec<u16>>()
-            .as_slice(),
-    )?)
+            .collect::<Vec<u16>>();
+        match String::from_utf16(&units) {
+            Ok(path) => Ok(Some(Entry {
+                path,
+                offset,
+                length,
+            })),
+            Err(_) => {
+                self.malformed.push((offset, Malformed::Utf16));
+                Ok(None)
+            }
+        }
+    }
+
+    /// Reads `len` bytes or fewer if the end is reached
+    fn read_up_to(&mut self, len: u64) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        self.reader.by_ref().take(len).read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn truncated(&mut self, offset: u64) -> Result<Option<Entry>> {
+        self.malformed.push((offset, Malformed::Truncated));
+        self.done = true;
+        Ok(None)
+    }
+}
+
+impl<R, D> Iterator for Iter<R, D>
+where
+    R: BufRead,
+    D: Decryptor,
+{
+    type Item = Result<Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // One entry is held back since the last one has to be corrected
+        while !self.done {
+            match self.read_entry() {
+                Ok(Some(entry)) => match self.pending.replace(entry) {
+                    Some(previous) => return Some(Ok(previous)),
+                    None => continue,
+                },
+                Ok(None) => continue,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+
+        // The last character of the last entry is stored incorrectly
+        self.pending.take().map(|mut last| {
+            last.path.pop();
+            last.path.push('g');
+            Ok(last)
+        })
+    }
+}
+
+/// Writes List.wz one entry at a time
+///
+/// Example:
+///
+/// ```no_run
+/// use crypto::{KeyStream, GMS_IV, TRIMMED_KEY};
+/// use std::{fs::File, io::BufWriter};
+/// use wz::list::Writer;
+///
+/// let file = BufWriter::new(File::create("List.wz").unwrap());
+/// let mut writer = Writer::new(file, KeyStream::new(&TRIMMED_KEY, &GMS_IV));
+/// writer.append("Mob/0100100.img").unwrap();
+/// writer.append("Npc/1012000.img").unwrap();
+/// ```
+#[derive(Debug)]
+pub struct Writer<W, E>
+where
+    W: Write,
+    E: Encryptor,
+{
+    writer: W,
+    encryptor: E,
+}
+
+impl<W, E> Writer<W, E>
+where
+    W: Write,
+    E: Encryptor,
+{
+    /// Creates a new [`Writer`]
+    pub fn new(writer: W, encryptor: E) -> Self {
+        Self { writer, encryptor }
+    }
+
+    /// Writes the entry for the image at `path`
+    pub fn append(&mut self, path: &str) -> Result<()> {
+        let units = path.encode_utf16().collect::<Vec<u16>>();
+        let mut buf = units
+            .iter()
+            .flat_map(|u| u.to_le_bytes())
+            .collect::<Vec<u8>>();
+        self.encryptor.encrypt(&mut buf);
+        self.writer.write_all(&(units.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&buf)?;
+        Ok(self.writer.write_all(&[0, 0])?)
+    }
+
+    /// Returns the underlying writer
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+fn normalize(path: &str) -> String {
+    path.split(['/', '\\'])
+        .filter(|name| !name.is_empty())
+        .collect::<Vec<&str>>()
+        .join("/")
+        .to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::error::Result;
+    use crate::list::{Entry, Malformed, Reader, Writer};
+    use crypto::{Encryptor, KeyStream, GMS_IV, TRIMMED_KEY};
+
+    fn entry(units: &[u16], terminator: u16) -> Vec<u8> {
+        let mut bytes = units
+            .iter()
+            .flat_map(|u| u.to_le_bytes())
+            .collect::<Vec<u8>>();
+        KeyStream::new(&TRIMMED_KEY, &GMS_IV).encrypt(&mut bytes);
+        let mut data = (units.len() as u32).to_le_bytes().to_vec();
+        data.extend(bytes);
+        data.extend(terminator.to_le_bytes());
+        data
+    }
+
+    fn utf16(s: &str) -> Vec<u16> {
+        s.encode_utf16().collect()
+    }
+
+    #[test]
+    fn malformed_entries() {
+        let mut data = entry(&utf16("Mob/0100100.img"), 0);
+        data.extend(entry(&utf16("Npc/1012000.img"), 7));
+        let invalid = data.len() as u64;
+        data.extend(entry(&[0x4d, 0xd800], 0));
+        data.extend(entry(&utf16("Skill/000.img"), 0));
+        data.extend(entry(&utf16("Map/Map/Map0/000010000.imX"), 0));
+        let truncated = data.len() as u64;
+        data.extend([0xff, 0, 0, 0, 1, 2]);
+
+        let reader = Reader::from_bytes(&data, KeyStream::new(&TRIMMED_KEY, &GMS_IV));
+        let paths = reader.strings().cloned().collect::<Vec<String>>();
+        assert_eq!(
+            paths,
+            [
+                "Mob/0100100.img",
+                "Npc/1012000.img",
+                "Skill/000.img",
+                "Map/Map/Map0/000010000.img"
+            ]
+        );
+        let entries = reader.entries();
+        assert_eq!((entries[0].offset, entries[0].length), (0, 15));
+        assert_eq!(entries[1].offset, 4 + 15 * 2 + 2);
+        assert_eq!(
+            reader.malformed(),
+            [
+                (entries[1].offset, Malformed::Terminator),
+                (invalid, Malformed::Utf16),
+                (truncated, Malformed::Truncated)
+            ]
+        );
+
+        assert!(reader.contains("Mob/0100100.img"));
+        assert!(reader.contains("\\mob\\0100100.img"));
+        assert!(reader.contains("Map//Map/Map0/000010000.img/"));
+        assert!(!reader.contains("Mob/0100101.img"));
+        assert!(!reader.contains("Mob"));
+    }
+
+    #[test]
+    fn iterate_and_append() {
+        let key = || KeyStream::new(&TRIMMED_KEY, &GMS_IV);
+        let paths = ["Mob/0100100.img", "Npc/1012000.img", "Skill/000.img"];
+        let mut writer = Writer::new(Vec::new(), key());
+        for path in paths {
+            writer.append(path).expect("error appending");
+        }
+        let mut data = writer.into_inner();
+        assert_eq!(data, [0, 1, 2].map(|i| entry(&utf16(paths[i]), 0)).concat());
+
+        let mut iter = Reader::iter(&data[..], key());
+        let entries = iter
+            .by_ref()
+            .collect::<Result<Vec<Entry>>>()
+            .expect("error reading");
+        assert_eq!(entries, Reader::from_bytes(&data, key()).entries());
+        assert_eq!(
+            entries.iter().map(|e| &e.path[..]).collect::<Vec<_>>(),
+            paths
+        );
+        assert!(iter.malformed().is_empty());
+        assert!(iter.next().is_none());
+
+        // Nothing after a truncated entry is read
+        data.truncate(data.len() - 3);
+        let mut iter = Reader::iter(&data[..], key());
+        assert_eq!(iter.by_ref().count(), 2);
+        assert_eq!(iter.malformed().len(), 1);
+    }
 }
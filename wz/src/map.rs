@@ -2,13 +2,15 @@
 
 use crate::error::MapError;
 use indextree::{Arena, NodeId};
-use std::path::Path;
+use std::{ops::ControlFlow, path::Path};
 
+mod bookmark;
 mod children;
 mod cursor;
 mod cursor_mut;
 mod node;
 
+pub use bookmark::Bookmark;
 pub use children::{ChildNames, Children};
 pub use cursor::Cursor;
 pub use cursor_mut::CursorMut;
@@ -19,6 +21,9 @@ use std::fmt::Debug;
 
 /// A named tree structure. Each node in the tree is given a name. The full path name is guaranteed
 /// to be unique.
+///
+/// Paths separate names with `/`, so a node whose name contains `/` cannot be reached through
+/// them. The `_segments` variants take the names as a slice instead, e.g. `["root", "a/b"]`.
 #[derive(Debug)]
 pub struct Map<T> {
     arena: Arena<MapNode<T>>,
@@ -59,6 +64,39 @@ impl<T> Map<T> {
         Ok(CursorMut::new(self.get_id(path)?, &mut self.arena))
     }
 
+    /// Creates a read-only cursor at the node reached by following `segments`, starting with the
+    /// root name
+    pub fn cursor_at_segments(&self, segments: &[&str]) -> Result<Cursor<'_, T>, MapError> {
+        Ok(Cursor::new(self.get_id_segments(segments)?, &self.arena))
+    }
+
+    /// Creates a mutable cursor at the node reached by following `segments`, starting with the
+    /// root name
+    pub fn cursor_mut_at_segments(
+        &mut self,
+        segments: &[&str],
+    ) -> Result<CursorMut<'_, T>, MapError> {
+        Ok(CursorMut::new(
+            self.get_id_segments(segments)?,
+            &mut self.arena,
+        ))
+    }
+
+    /// Creates a read-only cursor at the bookmarked node. Errors when the node was deleted or
+    /// detached from the map.
+    pub fn cursor_at_bookmark(&self, bookmark: Bookmark) -> Result<Cursor<'_, T>, MapError> {
+        Ok(Cursor::new(self.resolve(bookmark)?, &self.arena))
+    }
+
+    /// Creates a mutable cursor at the bookmarked node. Errors when the node was deleted or
+    /// detached from the map.
+    pub fn cursor_mut_at_bookmark(
+        &mut self,
+        bookmark: Bookmark,
+    ) -> Result<CursorMut<'_, T>, MapError> {
+        Ok(CursorMut::new(self.resolve(bookmark)?, &mut self.arena))
+    }
+
     /// Returns the name of the root node
     pub fn name(&self) -> &str {
         self.arena
@@ -75,6 +113,40 @@ impl<T> Map<T> {
         Ok(())
     }
 
+    /// Moves the node at `old_path` so it ends up at `new_path`, renaming it if the last
+    /// components differ. The parent of `new_path` must exist. Errors when a node already exists
+    /// at `new_path` or the node would be moved inside itself. The map is unchanged on error.
+    pub fn rename_path<S, U>(&mut self, old_path: S, new_path: U) -> Result<(), MapError>
+    where
+        S: AsRef<Path>,
+        U: AsRef<Path>,
+    {
+        let id = self.get_non_root_id(old_path)?;
+        let (parent, name) = split_path(new_path.as_ref())?;
+        let parent_id = self.get_id(parent)?;
+        self.relocate(id, parent_id, name)
+    }
+
+    /// Moves the subtree at `src` to be a child of the node at `dst`, keeping its name. Errors
+    /// when `dst` already has a child with that name or is inside the subtree. The map is
+    /// unchanged on error.
+    pub fn move_subtree<S, U>(&mut self, src: S, dst: U) -> Result<(), MapError>
+    where
+        S: AsRef<Path>,
+        U: AsRef<Path>,
+    {
+        let id = self.get_non_root_id(src)?;
+        let parent_id = self.get_id(dst)?;
+        let name = self
+            .arena
+            .get(id)
+            .expect("node should exist")
+            .get()
+            .name
+            .clone();
+        self.relocate(id, parent_id, &name)
+    }
+
     /// Gets the data at the path. Errors when the node does not exist.
     pub fn get<S>(&self, path: S) -> Result<&T, MapError>
     where
@@ -88,6 +160,17 @@ impl<T> Map<T> {
             .data)
     }
 
+    /// Gets the data at the node reached by following `segments`, starting with the root name.
+    /// Errors when the node does not exist.
+    pub fn get_segments(&self, segments: &[&str]) -> Result<&T, MapError> {
+        Ok(&self
+            .arena
+            .get(self.get_id_segments(segments)?)
+            .expect("get() node should exist")
+            .get()
+            .data)
+    }
+
     /// Walks the map depth-first
     pub fn walk<E>(&self, closure: impl FnMut(Cursor<T>) -> Result<(), E>) -> Result<(), E>
     where
@@ -96,13 +179,108 @@ impl<T> Map<T> {
         self.cursor().walk(closure)
     }
 
+    /// Walks the map depth-first with mutable access to the data. See [`CursorMut::walk_mut`].
+    pub fn walk_mut<E>(
+        &mut self,
+        closure: impl FnMut(&str, &mut T) -> Result<(), E>,
+    ) -> Result<(), E>
+    where
+        E: Debug,
+    {
+        self.cursor_mut().walk_mut(closure)
+    }
+
+    /// Walks the map breadth-first. See [`Cursor::walk_breadth`].
+    pub fn walk_breadth<E>(
+        &self,
+        closure: impl FnMut(Cursor<T>) -> Result<ControlFlow<()>, E>,
+    ) -> Result<(), E>
+    where
+        E: Debug,
+    {
+        self.cursor().walk_breadth(closure)
+    }
+
+    /// Walks the nodes matching the predicate depth-first. See [`Cursor::walk_filtered`].
+    pub fn walk_filtered<E>(
+        &self,
+        predicate: impl FnMut(&T) -> bool,
+        closure: impl FnMut(Cursor<T>) -> Result<ControlFlow<()>, E>,
+    ) -> Result<(), E>
+    where
+        E: Debug,
+    {
+        self.cursor().walk_filtered(predicate, closure)
+    }
+
     /// Creates a printable string of the tree structure. To be used in `{:?}` formatting.
     pub fn debug_pretty_print(&self) -> DebugPrettyPrint<'_, MapNode<T>> {
         self.root.debug_pretty_print(&self.arena)
     }
 
+    /// Returns mutable references to the data of every node in no particular order. Nodes cut
+    /// but not yet purged are included.
+    pub(crate) fn values_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.arena
+            .iter_mut()
+            .filter(|node| !node.is_removed())
+            .map(|node| &mut node.get_mut().data)
+    }
+
     // *** PRIVATES *** //
 
+    fn resolve(&self, bookmark: Bookmark) -> Result<NodeId, MapError> {
+        // Removed nodes fail the lookup and cut nodes are no longer under the root
+        match self.arena.get(bookmark.id) {
+            Some(_) if bookmark.id.ancestors(&self.arena).last() == Some(self.root) => {
+                Ok(bookmark.id)
+            }
+            _ => Err(MapError::StaleBookmark),
+        }
+    }
+
+    fn get_non_root_id<S>(&self, path: S) -> Result<NodeId, MapError>
+    where
+        S: AsRef<Path>,
+    {
+        let id = self.get_id(path.as_ref())?;
+        if id == self.root {
+            return Err(MapError::Path(path.as_ref().to_string_lossy().into()));
+        }
+        Ok(id)
+    }
+
+    /// Checks everything before touching the tree so a failed move leaves it unchanged
+    fn relocate(&mut self, id: NodeId, parent_id: NodeId, name: &str) -> Result<(), MapError> {
+        if parent_id
+            .ancestors(&self.arena)
+            .any(|ancestor| ancestor == id)
+        {
+            return Err(MapError::Path(name.to_string()));
+        }
+        let collision = parent_id.children(&self.arena).any(|child| {
+            child != id
+                && self
+                    .arena
+                    .get(child)
+                    .expect("child should exist")
+                    .get()
+                    .name
+                    == name
+        });
+        if collision {
+            return Err(MapError::Duplicate(name.to_string()));
+        }
+        id.detach(&mut self.arena);
+        parent_id.append(id, &mut self.arena);
+        self.arena
+            .get_mut(id)
+            .expect("node should exist")
+            .get_mut()
+            .name = name.to_string();
+        Ok(())
+    }
+
     fn get_id<S>(&self, path: S) -> Result<NodeId, MapError>
     where
         S: AsRef<Path>,
@@ -123,12 +301,40 @@ impl<T> Map<T> {
         }
         Ok(cursor.position)
     }
+
+    fn get_id_segments(&self, segments: &[&str]) -> Result<NodeId, MapError> {
+        let (_, names) = segments
+            .split_first()
+            .filter(|(root, _)| **root == self.name())
+            .ok_or_else(|| MapError::Path(segments.join("/")))?;
+        let mut cursor = self.cursor();
+        for name in names {
+            cursor.move_to(name)?;
+        }
+        Ok(cursor.position)
+    }
+}
+
+/// Splits a path into its parent and the name of the last component
+fn split_path(path: &Path) -> Result<(&Path, &str), MapError> {
+    let invalid = || MapError::Path(path.to_string_lossy().into());
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(invalid)?;
+    let parent = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .ok_or_else(invalid)?;
+    Ok((parent, name))
 }
 
 #[cfg(test)]
 mod tests {
 
+    use crate::error::MapError;
     use crate::map::Map;
+    use std::ops::ControlFlow;
 
     #[test]
     fn make_map() {
@@ -174,4 +380,203 @@ mod tests {
         );
         assert!(map.get("n1/n1_1/fail").is_err());
     }
+
+    #[test]
+    fn slash_in_names() {
+        let mut map = Map::new(String::from("root"), 0);
+        map.cursor_mut()
+            .create(String::from("a/b"), 1)
+            .expect("error creating a/b")
+            .move_to("a/b")
+            .expect("error moving into a/b")
+            .create(String::from("c"), 2)
+            .expect("error creating c");
+        assert!(map.get("root/a/b").is_err());
+        assert_eq!(*map.get_segments(&["root", "a/b"]).expect("missing a/b"), 1);
+        let cursor = map
+            .cursor_at_segments(&["root", "a/b", "c"])
+            .expect("missing c");
+        assert_eq!(cursor.segments(), ["root", "a/b", "c"]);
+        assert_eq!(cursor.pwd(), "root/a/b/c");
+        *map.cursor_mut_at_segments(&["root", "a/b", "c"])
+            .expect("missing c")
+            .get_mut() = 3;
+        assert_eq!(
+            *map.get_segments(&["root", "a/b", "c"]).expect("missing c"),
+            3
+        );
+        assert!(map.get_segments(&["root", "a", "b"]).is_err());
+        assert!(map.get_segments(&["other", "a/b"]).is_err());
+        assert!(map.get_segments(&[]).is_err());
+    }
+
+    #[test]
+    fn move_paths() {
+        let mut map = Map::new(String::from("n1"), 100);
+        map.cursor_mut()
+            .create(String::from("a"), 1)
+            .expect("error creating a")
+            .create(String::from("b"), 2)
+            .expect("error creating b")
+            .move_to("a")
+            .expect("error moving into a")
+            .create(String::from("a1"), 11)
+            .expect("error creating a1");
+
+        map.rename_path("n1/a/a1", "n1/b/b1")
+            .expect("error renaming a1");
+        assert_eq!(*map.get("n1/b/b1").expect("missing b1"), 11);
+        assert!(map.get("n1/a/a1").is_err());
+        map.move_subtree("n1/b", "n1/a").expect("error moving b");
+        assert_eq!(*map.get("n1/a/b/b1").expect("missing b1"), 11);
+
+        // Failures leave the map unchanged
+        match map.rename_path("n1/a/b", "n1/a") {
+            Err(MapError::Duplicate(_)) => {}
+            r => panic!("expected MapError::Duplicate, found {:?}", r),
+        }
+        assert!(map.move_subtree("n1/a", "n1/a/b").is_err());
+        assert!(map.rename_path("n1/a", "n1/missing/a").is_err());
+        assert!(map.rename_path("n1", "n1/a/n1").is_err());
+        assert_eq!(*map.get("n1/a/b/b1").expect("missing b1"), 11);
+        assert_eq!(&map.cursor().list().collect::<Vec<&str>>(), &["a"]);
+    }
+
+    #[test]
+    fn walk_mut() {
+        let mut map = Map::new(String::from("n1"), 1);
+        map.cursor_mut()
+            .create(String::from("a"), 2)
+            .expect("error creating a")
+            .create(String::from("b"), 3)
+            .expect("error creating b")
+            .move_to("a")
+            .expect("error moving into a")
+            .create(String::from("a1"), 4)
+            .expect("error creating a1");
+
+        let mut names = Vec::new();
+        map.walk_mut::<()>(|path, data| {
+            names.push(String::from(path));
+            *data *= 10;
+            Ok(())
+        })
+        .expect("walk should not fail");
+        assert_eq!(names, ["n1", "n1/a", "n1/a/a1", "n1/b"]);
+        assert_eq!(*map.get("n1/a/a1").expect("missing a1"), 40);
+
+        // The walk stops at the first error
+        let result = map.walk_mut(|path, data| match path {
+            "n1/a" => Err(path.to_string()),
+            _ => {
+                *data += 1;
+                Ok(())
+            }
+        });
+        assert_eq!(result, Err(String::from("n1/a")));
+        assert_eq!(*map.get("n1").expect("missing n1"), 11);
+        assert_eq!(*map.get("n1/a/a1").expect("missing a1"), 40);
+    }
+
+    #[test]
+    fn walk_orders() {
+        let mut map = Map::new(String::from("n1"), 1);
+        map.cursor_mut()
+            .create(String::from("a"), 2)
+            .expect("error creating a")
+            .create(String::from("b"), 3)
+            .expect("error creating b")
+            .move_to("a")
+            .expect("error moving into a")
+            .create(String::from("a1"), 4)
+            .expect("error creating a1");
+
+        let mut names = Vec::new();
+        map.walk_breadth::<()>(|cursor| {
+            names.push(cursor.pwd());
+            Ok(ControlFlow::Continue(()))
+        })
+        .expect("walk should not fail");
+        assert_eq!(names, ["n1", "n1/a", "n1/b", "n1/a/a1"]);
+
+        let mut names = Vec::new();
+        map.walk_filtered::<()>(
+            |data| data % 2 == 0,
+            |cursor| {
+                names.push(cursor.pwd());
+                Ok(ControlFlow::Continue(()))
+            },
+        )
+        .expect("walk should not fail");
+        assert_eq!(names, ["n1/a", "n1/a/a1"]);
+
+        let mut visited = 0;
+        map.walk_breadth::<()>(|_| {
+            visited += 1;
+            Ok(match visited {
+                2 => ControlFlow::Break(()),
+                _ => ControlFlow::Continue(()),
+            })
+        })
+        .expect("walk should not fail");
+        assert_eq!(visited, 2);
+    }
+
+    #[test]
+    fn bookmarks() {
+        let mut map = Map::new(String::from("n1"), 1);
+        map.cursor_mut()
+            .create(String::from("a"), 2)
+            .expect("error creating a")
+            .create(String::from("b"), 3)
+            .expect("error creating b");
+        let a = map.cursor_at("n1/a").expect("missing a").bookmark();
+        let b = map.cursor_at("n1/b").expect("missing b").bookmark();
+
+        map.rename_path("n1/a", "n1/b/c").expect("error moving a");
+        let cursor = map.cursor_at_bookmark(a).expect("bookmark should be valid");
+        assert_eq!(cursor.pwd(), "n1/b/c");
+        assert_eq!(*cursor.get(), 2);
+
+        *map.cursor_mut_at_bookmark(b)
+            .expect("bookmark should be valid")
+            .get_mut() = 30;
+        assert_eq!(*map.get("n1/b").expect("missing b"), 30);
+
+        map.cursor_mut().cut("b").expect("error cutting b");
+        match map.cursor_at_bookmark(b) {
+            Err(MapError::StaleBookmark) => {}
+            r => panic!("expected MapError::StaleBookmark, found {:?}", r),
+        }
+        map.cursor_mut()
+            .create(String::from("b"), 4)
+            .expect("error creating b")
+            .delete("b")
+            .expect("error deleting b");
+        assert!(map.cursor_at_bookmark(a).is_err());
+    }
+
+    #[test]
+    fn sibling_indices() {
+        let mut map = Map::new(String::from("root"), 0);
+        let mut cursor = map.cursor_mut();
+        for name in ["a", "b", "c"] {
+            cursor
+                .create(String::from(name), 1)
+                .expect("error creating child");
+        }
+        assert_eq!(cursor.child_count(), 3);
+        assert_eq!(cursor.index(), 0);
+        cursor.move_before("c", "a").expect("error moving c");
+
+        let cursor = map.cursor();
+        assert_eq!(cursor.child_count(), 3);
+        let indices = ["c", "a", "b"].map(|name| {
+            let mut child = map.cursor();
+            child.move_to(name).expect("missing child");
+            assert_eq!(child.child_count(), 0);
+            child.index()
+        });
+        assert_eq!(indices, [0, 1, 2]);
+    }
 }
@@ -0,0 +1,19 @@
+//! Saved cursor position
+
+use indextree::NodeId;
+
+/// Lightweight handle to a node in a [`Map`](crate::map::Map). Unlike a path, a bookmark stays
+/// valid when the node is renamed or moved and when unrelated nodes are changed. It becomes stale
+/// once the node is deleted or detached from the map.
+///
+/// A bookmark should only be used with the map it was taken from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Bookmark {
+    pub(crate) id: NodeId,
+}
+
+impl Bookmark {
+    pub(crate) fn new(id: NodeId) -> Self {
+        Self { id }
+    }
+}
@@ -3,9 +3,9 @@
 //! Used to navigate the map. This is to abstract the internals so no undefined behavior can occur.
 
 use crate::error::MapError;
-use crate::map::{ChildNames, Children, MapNode};
+use crate::map::{Bookmark, ChildNames, Children, MapNode};
 use indextree::{Arena, DebugPrettyPrint, NodeId};
-use std::{collections::VecDeque, fmt::Debug};
+use std::{collections::VecDeque, fmt::Debug, ops::ControlFlow};
 
 /// A cursor with read-only access to the contents of the [`Map`](crate::map::Map)
 #[derive(Debug)]
@@ -21,6 +21,12 @@ impl<'a, T> Cursor<'a, T> {
 
     /// Returns the path of the current position as a vector of names starting with the root
     pub fn pwd(&'a self) -> String {
+        self.segments().join("/")
+    }
+
+    /// Returns the names from the root to the current position. Unlike [`pwd`](Self::pwd), names
+    /// containing `/` stay intact.
+    pub fn segments(&self) -> Vec<&str> {
         let mut path = VecDeque::new();
         for id in self.position.ancestors(self.arena) {
             path.push_front(
@@ -32,7 +38,7 @@ impl<'a, T> Cursor<'a, T> {
                     .as_str(),
             );
         }
-        path.make_contiguous().join("/")
+        path.into()
     }
 
     /// Returns a vector containing the names of the current position's children
@@ -45,6 +51,17 @@ impl<'a, T> Cursor<'a, T> {
         Children::new(self.position, self.arena)
     }
 
+    /// Returns the number of children of the current position
+    pub fn child_count(&self) -> usize {
+        self.position.children(self.arena).count()
+    }
+
+    /// Returns the position of the current node among its siblings, starting at 0. Maps read from
+    /// a WZ file keep the order of the file until they are rearranged.
+    pub fn index(&self) -> usize {
+        self.position.preceding_siblings(self.arena).count() - 1
+    }
+
     /// Returns true if the child exists. This is slightly more efficient than doing
     /// list().contains().
     pub fn has_child(&self, name: &str) -> bool {
@@ -61,6 +78,11 @@ impl<'a, T> Cursor<'a, T> {
             .as_str()
     }
 
+    /// Saves the current position. See [`Map::cursor_at_bookmark`](crate::map::Map::cursor_at_bookmark).
+    pub fn bookmark(&self) -> Bookmark {
+        Bookmark::new(self.position)
+    }
+
     /// Returns the data at the current position
     pub fn get(&self) -> &T {
         &self
@@ -148,6 +170,49 @@ impl<'a, T> Cursor<'a, T> {
         Ok(())
     }
 
+    /// Walks the map breadth-first. The walk stops early when the closure returns
+    /// [`ControlFlow::Break`].
+    pub fn walk_breadth<E>(
+        &self,
+        mut closure: impl FnMut(Cursor<T>) -> Result<ControlFlow<()>, E>,
+    ) -> Result<(), E>
+    where
+        E: Debug,
+    {
+        let mut queue = VecDeque::from([self.position]);
+        while let Some(id) = queue.pop_front() {
+            if closure(Cursor::new(id, self.arena))?.is_break() {
+                break;
+            }
+            queue.extend(id.children(self.arena));
+        }
+        Ok(())
+    }
+
+    /// Walks the map depth-first, only calling the closure on nodes whose data matches the
+    /// predicate. The walk stops early when the closure returns [`ControlFlow::Break`].
+    pub fn walk_filtered<E>(
+        &self,
+        mut predicate: impl FnMut(&T) -> bool,
+        mut closure: impl FnMut(Cursor<T>) -> Result<ControlFlow<()>, E>,
+    ) -> Result<(), E>
+    where
+        E: Debug,
+    {
+        for id in self.position.descendants(self.arena) {
+            let data = &self
+                .arena
+                .get(id)
+                .expect("walked node should exist")
+                .get()
+                .data;
+            if predicate(data) && closure(Cursor::new(id, self.arena))?.is_break() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
     /// Creates a printable string of the tree structure. To be used in `{:?}` formatting.
     pub fn debug_pretty_print(&'a self) -> DebugPrettyPrint<'a, MapNode<T>> {
         self.position.debug_pretty_print(self.arena)
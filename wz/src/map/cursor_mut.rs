@@ -3,9 +3,9 @@
 //! Used to navigate the map. This is to abstract the internals so no undefined behavior can occur.
 
 use crate::error::MapError;
-use crate::map::{ChildNames, Children, Cursor, MapNode};
+use crate::map::{Bookmark, ChildNames, Children, Cursor, MapNode};
 use indextree::{Arena, DebugPrettyPrint, NodeId};
-use std::{collections::VecDeque, fmt::Debug};
+use std::{cmp::Ordering, collections::VecDeque, fmt::Debug, ops::ControlFlow};
 
 /// A cursor with mutable access to the contents of the [`Map`](crate::map::Map)
 #[derive(Debug)]
@@ -26,6 +26,12 @@ impl<'a, T> CursorMut<'a, T> {
 
     /// Returns the path of the current position as a vector of names starting with the root
     pub fn pwd(&'a self) -> String {
+        self.segments().join("/")
+    }
+
+    /// Returns the names from the root to the current position. Unlike [`pwd`](Self::pwd), names
+    /// containing `/` stay intact.
+    pub fn segments(&self) -> Vec<&str> {
         let mut path = VecDeque::new();
         for id in self.position.ancestors(self.arena) {
             path.push_front(
@@ -37,7 +43,7 @@ impl<'a, T> CursorMut<'a, T> {
                     .as_str(),
             );
         }
-        path.make_contiguous().join("/")
+        path.into()
     }
 
     /// Returns a vector containing the names of the current position's children
@@ -50,6 +56,17 @@ impl<'a, T> CursorMut<'a, T> {
         Children::new(self.position, self.arena)
     }
 
+    /// Returns the number of children of the current position
+    pub fn child_count(&self) -> usize {
+        self.position.children(self.arena).count()
+    }
+
+    /// Returns the position of the current node among its siblings, starting at 0. Maps read from
+    /// a WZ file keep the order of the file until they are rearranged.
+    pub fn index(&self) -> usize {
+        self.position.preceding_siblings(self.arena).count() - 1
+    }
+
     /// Returns true if the child exists.
     pub fn has_child(&self, name: &str) -> bool {
         self.get_id(self.position, name).is_ok()
@@ -65,6 +82,11 @@ impl<'a, T> CursorMut<'a, T> {
             .as_str()
     }
 
+    /// Saves the current position. See [`Map::cursor_at_bookmark`](crate::map::Map::cursor_at_bookmark).
+    pub fn bookmark(&self) -> Bookmark {
+        Bookmark::new(self.position)
+    }
+
     /// Returns the data at the current position
     pub fn get(&self) -> &T {
         &self
@@ -152,6 +174,29 @@ impl<'a, T> CursorMut<'a, T> {
         Ok(())
     }
 
+    /// Walks the map breadth-first. See [`Cursor::walk_breadth`].
+    pub fn walk_breadth<E>(
+        &self,
+        closure: impl FnMut(Cursor<T>) -> Result<ControlFlow<()>, E>,
+    ) -> Result<(), E>
+    where
+        E: Debug,
+    {
+        Cursor::new(self.position, self.arena).walk_breadth(closure)
+    }
+
+    /// Walks the nodes matching the predicate depth-first. See [`Cursor::walk_filtered`].
+    pub fn walk_filtered<E>(
+        &self,
+        predicate: impl FnMut(&T) -> bool,
+        closure: impl FnMut(Cursor<T>) -> Result<ControlFlow<()>, E>,
+    ) -> Result<(), E>
+    where
+        E: Debug,
+    {
+        Cursor::new(self.position, self.arena).walk_filtered(predicate, closure)
+    }
+
     /// Creates a printable string of the tree structure. To be used in `{:?}` formatting.
     pub fn debug_pretty_print(&'a self) -> DebugPrettyPrint<'a, MapNode<T>> {
         self.position.debug_pretty_print(self.arena)
@@ -184,6 +229,32 @@ impl<'a, T> CursorMut<'a, T> {
             .data
     }
 
+    /// Walks the map depth-first with mutable access to the data of every node, passing its path
+    /// along. The walk stops at the first error, which is returned.
+    pub fn walk_mut<E>(
+        &mut self,
+        mut closure: impl FnMut(&str, &mut T) -> Result<(), E>,
+    ) -> Result<(), E>
+    where
+        E: Debug,
+    {
+        let ids = self
+            .position
+            .descendants(self.arena)
+            .collect::<Vec<NodeId>>();
+        for id in ids {
+            let path = Cursor::new(id, self.arena).pwd();
+            let data = &mut self
+                .arena
+                .get_mut(id)
+                .expect("walked node should exist")
+                .get_mut()
+                .data;
+            closure(&path, data)?;
+        }
+        Ok(())
+    }
+
     /// Creates a new child at the current position. Errors when a child with the provided name
     /// already exists.
     pub fn create(&mut self, name: String, data: T) -> Result<&mut Self, MapError> {
@@ -196,6 +267,61 @@ impl<'a, T> CursorMut<'a, T> {
         }
     }
 
+    /// Creates a new child at `index` among the children at the current position. An index equal
+    /// to the number of children appends the child. Errors when a child with the provided name
+    /// already exists or the index is out of range.
+    pub fn create_at(
+        &mut self,
+        index: usize,
+        name: String,
+        data: T,
+    ) -> Result<&mut Self, MapError> {
+        if self.has_child(name.as_str()) {
+            return Err(MapError::Duplicate(name));
+        }
+        let sibling = self.position.children(self.arena).nth(index);
+        if sibling.is_none() && self.position.children(self.arena).count() != index {
+            return Err(MapError::Index(index));
+        }
+        let node = self.arena.new_node(MapNode::new(name, data));
+        match sibling {
+            Some(sibling) => sibling.insert_before(node, self.arena),
+            None => self.position.append(node, self.arena),
+        }
+        Ok(self)
+    }
+
+    /// Moves the child `name` in front of its sibling `other` at the current position. Errors when
+    /// either child does not exist.
+    pub fn move_before(&mut self, name: &str, other: &str) -> Result<&mut Self, MapError> {
+        let id = self.get_id(self.position, name)?;
+        let other_id = self.get_id(self.position, other)?;
+        if id != other_id {
+            id.detach(self.arena);
+            other_id.insert_before(id, self.arena);
+        }
+        Ok(self)
+    }
+
+    /// Reorders the children at the current position with the comparator. The comparator is given
+    /// the name and data of two children. The sort is stable.
+    pub fn sort_children_by<F>(&mut self, mut compare: F) -> &mut Self
+    where
+        F: FnMut((&str, &T), (&str, &T)) -> Ordering,
+    {
+        let mut ids = self.position.children(self.arena).collect::<Vec<NodeId>>();
+        ids.sort_by(|a, b| {
+            let a = self.arena.get(*a).expect("child should exist").get();
+            let b = self.arena.get(*b).expect("child should exist").get();
+            compare((a.name.as_str(), &a.data), (b.name.as_str(), &b.data))
+        });
+        for id in ids {
+            id.detach(self.arena);
+            self.position.append(id, self.arena);
+        }
+        self
+    }
+
     /// Detaches the child with the given name at the current position. This function adds that
     /// child to a clipboard. If the clipboard already contains a node previously cut, that node
     /// will be purged from the map. Errors when the child does not exist. If an error occurs, the
@@ -276,6 +402,35 @@ mod tests {
         assert_eq!(&cursor.list().collect::<Vec<&str>>(), &["n1_1", "n1_2"]);
     }
 
+    #[test]
+    fn order_nodes() {
+        let mut map = Map::new(String::from("n1"), 100);
+        let mut cursor = map.cursor_mut();
+        cursor
+            .create(String::from("b"), 2)
+            .expect("error creating b")
+            .create_at(0, String::from("a"), 1)
+            .expect("error creating a")
+            .create_at(2, String::from("c"), 3)
+            .expect("error creating c");
+        assert_eq!(&cursor.list().collect::<Vec<&str>>(), &["a", "b", "c"]);
+        match cursor.create_at(5, String::from("d"), 4) {
+            Err(MapError::Index(5)) => {}
+            r => panic!("expected MapError::Index, found {:?}", r),
+        }
+        cursor
+            .move_before("c", "a")
+            .expect("error moving c")
+            .move_before("b", "b")
+            .expect("error moving b");
+        assert_eq!(&cursor.list().collect::<Vec<&str>>(), &["c", "a", "b"]);
+        assert!(cursor.move_before("d", "a").is_err());
+        cursor.sort_children_by(|(_, a), (_, b)| b.cmp(a));
+        assert_eq!(&cursor.list().collect::<Vec<&str>>(), &["c", "b", "a"]);
+        cursor.sort_children_by(|(a, _), (b, _)| a.cmp(b));
+        assert_eq!(&cursor.list().collect::<Vec<&str>>(), &["a", "b", "c"]);
+    }
+
     #[test]
     fn remove_node() {
         let mut map = Map::new(String::from("n1"), 100);
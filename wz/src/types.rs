@@ -9,21 +9,26 @@ mod offset;
 mod primitives;
 mod property;
 mod sound;
-mod string;
+pub(crate) mod string;
 mod uol;
 mod vector;
+mod version;
 
 pub(crate) mod macros;
 pub(crate) mod raw;
 
-pub use canvas::{Canvas, CanvasFormat};
-pub use header::WzHeader;
+#[cfg(feature = "canvas-codecs")]
+pub use canvas::{Bc3Quality, ReencodeOptions};
+pub use canvas::{Canvas, CanvasFormat, Compressor, Zlib};
+pub use header::{HeaderWarning, WzHeader};
 pub use int::{WzInt, WzLong};
 pub use offset::WzOffset;
 pub use property::Property;
-pub use sound::{Sound, SoundHeader, WavHeader};
+pub use sound::{AudioFormat, Mp3Format, Sound, SoundHeader, WavHeader};
+pub use string::{RawString, StringDecoding, StringEncoding, StringForm};
 pub use uol::{UolObject, UolString};
 pub use vector::Vector;
+pub use version::VersionHash;
 
 pub trait VerboseDebug {
     fn debug(&self, f: &mut dyn io::Write) -> io::Result<()>;
@@ -1,19 +1,50 @@
 //! Parsed Canvas type
 
 use crate::error::{CanvasError, Result};
-use crate::io::{xml::writer::ToXml, Decode, Encode, WzRead, WzWrite};
-use crate::types::{VerboseDebug, WzInt};
-use deflate::deflate_bytes_zlib;
-use image::{ImageFormat, RgbaImage};
-use inflate::inflate_bytes_zlib;
-use std::{fmt, io, path::Path};
+use crate::io::{
+    xml::writer::{Dialect, ToXml},
+    Decode, Encode, WzRead, WzWrite,
+};
+use crate::types::{raw::read_raw_image_data, VerboseDebug, WzInt, WzOffset};
+use base64::{engine::general_purpose::STANDARD, Engine};
+#[cfg(feature = "canvas-codecs")]
+use image::{imageops, ImageFormat, RgbaImage};
+#[cfg(feature = "canvas-codecs")]
+use std::path::Path;
+use std::{fmt, io, sync::OnceLock};
 
+mod compression;
+#[cfg(feature = "canvas-codecs")]
 mod conversions;
+#[cfg(feature = "canvas-codecs")]
+mod quantize;
+#[cfg(feature = "canvas-codecs")]
 mod squish;
 
+pub use self::compression::{Compressor, Zlib};
+#[cfg(feature = "canvas-codecs")]
+pub use self::squish::Bc3Quality;
+#[cfg(feature = "canvas-codecs")]
 pub(crate) use self::squish::*;
+#[cfg(feature = "canvas-codecs")]
 pub(crate) use conversions::*;
 
+/// Largest per-channel error BC3 may introduce before [`Canvas::from_image_auto`] rejects it
+#[cfg(feature = "canvas-codecs")]
+const BC3_TOLERANCE: u8 = 8;
+
+/// Options of [`Canvas::reencode`]
+#[cfg(feature = "canvas-codecs")]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ReencodeOptions {
+    /// Spread the rounding error of formats with fewer bits per channel to the neighbouring
+    /// pixels. Only used by [`CanvasFormat::Bgra4444`] and [`CanvasFormat::Rgb565`].
+    pub dither: bool,
+
+    /// Quality of [`CanvasFormat::Bc3`]
+    pub quality: Bc3Quality,
+}
+
 /// Canvas Image format types.
 ///
 /// This is non-exhaustive. I stopped at v172 and later versions have more formats.
@@ -105,12 +136,16 @@ impl Encode for CanvasFormat {
 /// Later version of MS will have empty canvases that point to other canvas objects as reference.
 /// This is useful to cut down on unnecessary duplicated data. So if a plain white PNG is saved, it
 /// is likely, the source is elsewhere in the WZ image.
+///
+/// Canvases mapped with [`Reader::map_lazy`](crate::image::Reader::map_lazy) only know where
+/// their data is. The data is read once by [`Canvas::load`] and kept afterwards.
 #[derive(Clone, PartialEq, Eq)]
 pub struct Canvas {
     width: WzInt,
     height: WzInt,
     format: CanvasFormat,
-    data: Vec<u8>,
+    data: OnceLock<Vec<u8>>,
+    location: Option<(WzOffset, usize)>,
 }
 
 impl Canvas {
@@ -120,23 +155,26 @@ impl Canvas {
             width,
             height,
             format,
-            data,
+            data: OnceLock::from(data),
+            location: None,
         }
     }
 
-    /// Creates a new [`Canvas`] from a provided image and encoding format
-    pub fn from_image<S>(path: S, format: CanvasFormat) -> Result<Self>
-    where
-        S: AsRef<Path>,
-    {
-        let img = image::io::Reader::open(path)?.decode()?;
-        let (width, height, data) = encode_image(format, img.into_rgba8())?;
-        Ok(Self::new(
-            width.into(),
-            height.into(),
+    /// Creates a new [`Canvas`] whose `length` bytes of data sit at `offset` within the image
+    pub fn lazy(
+        width: WzInt,
+        height: WzInt,
+        format: CanvasFormat,
+        offset: WzOffset,
+        length: usize,
+    ) -> Self {
+        Self {
+            width,
+            height,
             format,
-            deflate_bytes_zlib(&data),
-        ))
+            data: OnceLock::new(),
+            location: Some((offset, length)),
+        }
     }
 
     /// Returns the width of the image
@@ -154,17 +192,123 @@ impl Canvas {
         self.format
     }
 
-    /// Returns a reference to the raw data
+    /// Returns true if the data is in memory
+    pub fn is_loaded(&self) -> bool {
+        self.data.get().is_some()
+    }
+
+    /// Returns the offset and length of the data within the image if the canvas was mapped lazily
+    pub fn location(&self) -> Option<(WzOffset, usize)> {
+        self.location
+    }
+
+    /// Reads the data from `reader` if it is not loaded yet. `reader` must be positioned the same
+    /// way as the reader the canvas was mapped with.
+    pub fn load<R>(&self, reader: &mut R) -> Result<&[u8]>
+    where
+        R: WzRead + ?Sized,
+    {
+        if let Some(data) = self.data.get() {
+            return Ok(data);
+        }
+        let (offset, length) = self.location.ok_or(CanvasError::NotLoaded)?;
+        reader.seek(offset)?;
+        let data = read_raw_image_data(reader, length)?;
+        Ok(self.data.get_or_init(|| data))
+    }
+
+    /// Returns a reference to the raw data. Empty if the data has not been loaded.
     pub fn data(&self) -> &[u8] {
-        &self.data
+        self.data.get().map(Vec::as_slice).unwrap_or_default()
     }
 
-    /// Returns a vector of the decompressed raw data
+    /// Returns a vector of the decompressed raw data. The pixels are still in the canvas
+    /// [`format`](Canvas::format), so this works without the `canvas-codecs` feature.
     pub fn decompressed_data(&self) -> Result<Vec<u8>> {
-        match inflate_bytes_zlib(&self.data) {
-            Ok(d) => Ok(d),
-            Err(e) => Err(CanvasError::Inflate(e).into()),
-        }
+        let data = self.data.get().ok_or(CanvasError::NotLoaded)?;
+        Zlib::default().decompress(data)
+    }
+}
+
+/// Encoding and decoding of the pixels. Needs the `canvas-codecs` feature.
+#[cfg(feature = "canvas-codecs")]
+impl Canvas {
+    /// Creates a new fully transparent [`Canvas`] in [`CanvasFormat::Bgra4444`]
+    pub fn blank(width: WzInt, height: WzInt) -> Self {
+        let length = (*width).max(0) as usize * (*height).max(0) as usize * 2;
+        Self::new(
+            width,
+            height,
+            CanvasFormat::Bgra4444,
+            compress(&vec![0u8; length]),
+        )
+    }
+
+    /// Creates a new [`Canvas`] from a provided image and encoding format
+    pub fn from_image<S>(path: S, format: CanvasFormat) -> Result<Self>
+    where
+        S: AsRef<Path>,
+    {
+        Self::from_image_with_quality(path, format, Bc3Quality::default())
+    }
+
+    /// Creates a new [`Canvas`] from a provided image and encoding format. `quality` trades speed
+    /// for accuracy when encoding [`CanvasFormat::Bc3`].
+    pub fn from_image_with_quality<S>(
+        path: S,
+        format: CanvasFormat,
+        quality: Bc3Quality,
+    ) -> Result<Self>
+    where
+        S: AsRef<Path>,
+    {
+        Self::from_image_with_compressor(path, format, quality, &Zlib::default())
+    }
+
+    /// Creates a new [`Canvas`] from a provided image and encoding format, compressing the pixels
+    /// with `compressor`, e.g. [`Zlib::BEST`] for the smallest output
+    pub fn from_image_with_compressor<S, C>(
+        path: S,
+        format: CanvasFormat,
+        quality: Bc3Quality,
+        compressor: &C,
+    ) -> Result<Self>
+    where
+        S: AsRef<Path>,
+        C: Compressor + ?Sized,
+    {
+        let img = image::io::Reader::open(path)?.decode()?;
+        let (width, height, data) = encode_image(format, img.into_rgba8(), quality)?;
+        Ok(Self::new(
+            width.into(),
+            height.into(),
+            format,
+            compressor.compress(&data)?,
+        ))
+    }
+
+    /// Creates a new [`Canvas`] from a provided image, picking the smallest format that keeps the
+    /// image intact. Formats that store the pixels exactly are preferred. BC3 is used when no channel
+    /// is off by more than 8, otherwise the image is stored as Bgra8888. The choice is available
+    /// through [`Canvas::format`].
+    pub fn from_image_auto<S>(path: S) -> Result<Self>
+    where
+        S: AsRef<Path>,
+    {
+        Self::from_buffer_auto(image::io::Reader::open(path)?.decode()?.into_rgba8())
+    }
+
+    /// Creates a new [`Canvas`] from decoded pixels, picking the format like
+    /// [`Canvas::from_image_auto`]
+    pub fn from_buffer_auto(img: RgbaImage) -> Result<Self> {
+        let format = select_format(&img)?;
+        let (width, height, data) = encode_image(format, img, Bc3Quality::default())?;
+        Ok(Self::new(
+            width.into(),
+            height.into(),
+            format,
+            compress(&data),
+        ))
     }
 
     /// Returns the decoded image data
@@ -172,6 +316,44 @@ impl Canvas {
         decode_image(self)
     }
 
+    /// Returns the `width` by `height` region whose top-left corner is at `(x, y)` as a new canvas
+    /// in the same format. Errors when the region is empty, lies outside the canvas or has a size
+    /// the format cannot store.
+    pub fn cropped(&self, x: u32, y: u32, width: u32, height: u32) -> Result<Self> {
+        let img = self.image_buffer()?;
+        if width == 0
+            || height == 0
+            || x.checked_add(width).is_none_or(|right| right > img.width())
+            || y.checked_add(height)
+                .is_none_or(|bottom| bottom > img.height())
+        {
+            return Err(CanvasError::Crop(x, y, width, height).into());
+        }
+        let img = imageops::crop_imm(&img, x, y, width, height).to_image();
+        let (width, height, data) = encode_image(self.format, img, Bc3Quality::default())?;
+        Ok(Self::new(
+            width.into(),
+            height.into(),
+            self.format,
+            compress(&data),
+        ))
+    }
+
+    /// Returns the canvas encoded as `format`. Every pixel is rounded to the closest one the
+    /// format can store, e.g. a [`CanvasFormat::Bgra8888`] sprite becomes roughly half the size
+    /// as [`CanvasFormat::Bgra4444`]. Formats without alpha make every pixel opaque.
+    pub fn reencode(&self, format: CanvasFormat, options: &ReencodeOptions) -> Result<Self> {
+        let mut img = self.image_buffer()?;
+        quantize::quantize(&mut img, format, options.dither);
+        let (width, height, data) = encode_image(format, img, options.quality)?;
+        Ok(Self::new(
+            width.into(),
+            height.into(),
+            format,
+            compress(&data),
+        ))
+    }
+
     /// Saves the image to file
     pub fn save_to_file<S>(&self, path: &S, format: ImageFormat) -> Result<()>
     where
@@ -179,6 +361,13 @@ impl Canvas {
     {
         Ok(self.image_buffer()?.save_with_format(&path, format)?)
     }
+
+    /// Returns the image encoded as a PNG
+    pub fn png(&self) -> Result<Vec<u8>> {
+        let mut png = io::Cursor::new(Vec::new());
+        self.image_buffer()?.write_to(&mut png, ImageFormat::Png)?;
+        Ok(png.into_inner())
+    }
 }
 
 impl fmt::Debug for Canvas {
@@ -195,7 +384,10 @@ impl VerboseDebug for Canvas {
     fn debug(&self, f: &mut dyn io::Write) -> io::Result<()> {
         f.write_fmt(format_args!(
             "Canvas {{ width: {:?}, height: {:?}, format: {:?}, data: {:x?} }}",
-            self.width, self.height, self.format, self.data
+            self.width,
+            self.height,
+            self.format,
+            self.data()
         ))
     }
 }
@@ -205,13 +397,14 @@ impl Encode for Canvas {
     where
         W: WzWrite + ?Sized,
     {
+        let data = self.data.get().ok_or(CanvasError::NotLoaded)?;
         self.width.encode(writer)?;
         self.height.encode(writer)?;
         self.format.encode(writer)?;
         0i32.encode(writer)?;
-        (self.data.len() as i32 + 1).encode(writer)?;
+        (data.len() as i32 + 1).encode(writer)?;
         0u8.encode(writer)?;
-        writer.write_all(&self.data)
+        writer.write_all(data)
     }
 }
 
@@ -227,18 +420,100 @@ impl ToXml for Canvas {
             (String::from("height"), self.height.to_string()),
         ]
     }
+
+    fn extension(&self) -> Option<&'static str> {
+        Some("png")
+    }
+
+    fn payload(&self, dialect: Dialect, src: Option<&str>) -> Vec<(String, String)> {
+        let mut payload = Vec::new();
+        if let Some(src) = src {
+            payload.push((String::from("src"), src.to_string()));
+        }
+        match dialect {
+            Dialect::Native => {
+                payload.push((String::from("format"), self.format.to_int().to_string()));
+                if let (None, Some(data)) = (src, self.data.get()) {
+                    payload.push((String::from("basedata"), STANDARD.encode(data)));
+                }
+            }
+            // Canvases that cannot be decoded are written without their data
+            #[cfg(feature = "canvas-codecs")]
+            Dialect::HaRepacker | Dialect::WzDumper => {
+                if let (None, Ok(png)) = (src, self.png()) {
+                    payload.push((String::from("basedata"), STANDARD.encode(png)));
+                }
+            }
+            #[cfg(not(feature = "canvas-codecs"))]
+            Dialect::HaRepacker | Dialect::WzDumper => {}
+        }
+        payload
+    }
+}
+
+/// Compresses `data` with the default [`Zlib`]
+#[cfg(feature = "canvas-codecs")]
+fn compress(data: &[u8]) -> Vec<u8> {
+    Zlib::default()
+        .compress(data)
+        .expect("compressing into memory cannot fail")
 }
 
-fn encode_image(format: CanvasFormat, img: RgbaImage) -> Result<(u32, u32, Vec<u8>)> {
+#[cfg(feature = "canvas-codecs")]
+fn encode_image(
+    format: CanvasFormat,
+    img: RgbaImage,
+    quality: Bc3Quality,
+) -> Result<(u32, u32, Vec<u8>)> {
     match format {
         CanvasFormat::Bgra4444 => Ok(to_bgra4444(img)),
         CanvasFormat::Bgra8888 => Ok(to_bgra8888(img)),
         CanvasFormat::Rgb565 => Ok(to_rgb565(img)),
         CanvasFormat::CompressedRgb565 => compress_rgb565(img),
-        CanvasFormat::Bc3 => to_bc3(img),
+        CanvasFormat::Bc3 => to_bc3(img, quality),
+    }
+}
+
+#[cfg(feature = "canvas-codecs")]
+fn select_format(img: &RgbaImage) -> Result<CanvasFormat> {
+    let (width, height) = img.dimensions();
+    let opaque = img.pixels().all(|p| p[3] == u8::MAX);
+    let exact565 = opaque
+        && img.pixels().all(|p| {
+            let rgb = split565(join565(p[0], p[1], p[2]));
+            rgb == [p[0], p[1], p[2]]
+        });
+    if exact565 && width % 16 == 0 && height % 16 == 0 {
+        // Every 16x16 block must be a single color
+        let uniform = img
+            .enumerate_pixels()
+            .all(|(x, y, p)| p == img.get_pixel(x - x % 16, y - y % 16));
+        if uniform {
+            return Ok(CanvasFormat::CompressedRgb565);
+        }
+    }
+    if exact565 {
+        return Ok(CanvasFormat::Rgb565);
+    }
+    if img.pixels().all(|p| p.0.iter().all(|c| c >> 4 == c & 0xf)) {
+        return Ok(CanvasFormat::Bgra4444);
+    }
+    if width % 4 == 0 && height % 4 == 0 {
+        let (_, _, data) = to_bc3(img.clone(), Bc3Quality::default())?;
+        let decoded = from_bc3(width, height, data)?;
+        let close = img.pixels().zip(decoded.pixels()).all(|(a, b)| {
+            a.0.iter()
+                .zip(b.0.iter())
+                .all(|(a, b)| a.abs_diff(*b) <= BC3_TOLERANCE)
+        });
+        if close {
+            return Ok(CanvasFormat::Bc3);
+        }
     }
+    Ok(CanvasFormat::Bgra8888)
 }
 
+#[cfg(feature = "canvas-codecs")]
 fn decode_image(canvas: &Canvas) -> Result<RgbaImage> {
     let width = *canvas.width() as u32;
     let height = *canvas.height() as u32;
@@ -251,3 +526,136 @@ fn decode_image(canvas: &Canvas) -> Result<RgbaImage> {
         CanvasFormat::Bc3 => from_bc3(width, height, data),
     }
 }
+
+#[cfg(all(test, feature = "canvas-codecs"))]
+mod tests {
+
+    use crate::types::{
+        canvas::{compress, encode_image, select_format, split565},
+        Bc3Quality, Canvas, CanvasFormat, ReencodeOptions,
+    };
+    use image::{imageops, Rgba, RgbaImage};
+
+    #[test]
+    fn auto_format() {
+        let blocks = RgbaImage::from_fn(32, 32, |x, y| {
+            if (x / 16 + y / 16) % 2 == 0 {
+                Rgba([0xff, 0, 0, 0xff])
+            } else {
+                Rgba([0, 0, 0xff, 0xff])
+            }
+        });
+        assert_eq!(
+            select_format(&blocks).expect("select failed"),
+            CanvasFormat::CompressedRgb565
+        );
+
+        let opaque = RgbaImage::from_fn(10, 10, |x, y| {
+            let rgb = split565((x * 2048 + y * 37) as u16);
+            Rgba([rgb[0], rgb[1], rgb[2], 0xff])
+        });
+        assert_eq!(
+            select_format(&opaque).expect("select failed"),
+            CanvasFormat::Rgb565
+        );
+
+        let alpha = RgbaImage::from_fn(10, 10, |x, y| {
+            Rgba([0x11, (x * 0x11) as u8, 0, (y * 0x11) as u8])
+        });
+        assert_eq!(
+            select_format(&alpha).expect("select failed"),
+            CanvasFormat::Bgra4444
+        );
+
+        // Odd sizes cannot be BC3
+        let noisy = RgbaImage::from_fn(9, 7, |x, y| {
+            Rgba([(x * 29 + y * 7) as u8, (x * y) as u8, 3, 0x80])
+        });
+        assert_eq!(
+            select_format(&noisy).expect("select failed"),
+            CanvasFormat::Bgra8888
+        );
+    }
+
+    #[test]
+    fn crop() {
+        let img = RgbaImage::from_fn(12, 8, |x, y| Rgba([x as u8, y as u8, 0x40, 0xff]));
+        let (width, height, data) =
+            encode_image(CanvasFormat::Bgra8888, img.clone(), Bc3Quality::default())
+                .expect("encode failed");
+        let canvas = Canvas::new(
+            width.into(),
+            height.into(),
+            CanvasFormat::Bgra8888,
+            compress(&data),
+        );
+        let cropped = canvas.cropped(3, 2, 5, 6).expect("crop failed");
+        assert_eq!((*cropped.width(), *cropped.height()), (5, 6));
+        assert_eq!(cropped.format(), CanvasFormat::Bgra8888);
+        assert_eq!(
+            cropped.image_buffer().expect("decode failed"),
+            imageops::crop_imm(&img, 3, 2, 5, 6).to_image()
+        );
+        assert!(canvas.cropped(0, 0, 12, 8).is_ok());
+        assert!(canvas.cropped(8, 0, 5, 1).is_err());
+        assert!(canvas.cropped(0, 0, 0, 1).is_err());
+        assert!(canvas.cropped(u32::MAX, 0, 2, 1).is_err());
+    }
+
+    #[test]
+    fn reencode() {
+        // Horizontal gradient that bands in 4 bits per channel, with a transparent border
+        let img = RgbaImage::from_fn(64, 8, |x, y| match y {
+            0 => Rgba([0, 0, 0, 0]),
+            _ => Rgba([x as u8 * 2, 0x80, 0xff - x as u8, 0xff]),
+        });
+        let (width, height, data) =
+            encode_image(CanvasFormat::Bgra8888, img.clone(), Bc3Quality::default())
+                .expect("encode failed");
+        let canvas = Canvas::new(
+            width.into(),
+            height.into(),
+            CanvasFormat::Bgra8888,
+            compress(&data),
+        );
+
+        let plain = canvas
+            .reencode(CanvasFormat::Bgra4444, &ReencodeOptions::default())
+            .expect("reencode failed");
+        let dithered = canvas
+            .reencode(
+                CanvasFormat::Bgra4444,
+                &ReencodeOptions {
+                    dither: true,
+                    ..ReencodeOptions::default()
+                },
+            )
+            .expect("reencode failed");
+        for reencoded in [&plain, &dithered] {
+            assert_eq!(reencoded.format(), CanvasFormat::Bgra4444);
+            assert_eq!((*reencoded.width(), *reencoded.height()), (64, 8));
+            assert!(reencoded.decompressed_data().unwrap().len() < data.len());
+        }
+
+        // Without dithering every channel is the closest multiple of 17
+        let plain = plain.image_buffer().expect("decode failed");
+        for (a, b) in img.pixels().zip(plain.pixels()) {
+            assert!(a.0.iter().zip(b.0.iter()).all(|(a, b)| a.abs_diff(*b) <= 8));
+        }
+
+        // Dithering keeps the average color closer to the original than rounding does
+        let dithered = dithered.image_buffer().expect("decode failed");
+        assert!(dithered.pixels().take(64).all(|p| p.0 == [0, 0, 0, 0]));
+        assert_ne!(dithered, plain);
+        let sum = |img: &RgbaImage| img.pixels().map(|p| p[0] as i32).sum::<i32>();
+        assert!((sum(&dithered) - sum(&img)).abs() < (sum(&plain) - sum(&img)).abs());
+
+        // Formats without alpha drop it
+        let opaque = canvas
+            .reencode(CanvasFormat::Rgb565, &ReencodeOptions::default())
+            .expect("reencode failed")
+            .image_buffer()
+            .expect("decode failed");
+        assert!(opaque.pixels().all(|p| p[3] == 0xff));
+    }
+}
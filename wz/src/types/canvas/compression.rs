@@ -0,0 +1,109 @@
+//! Canvas data compression
+
+use crate::error::{CanvasError, Result};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use std::io::{ErrorKind, Read, Write};
+
+/// Compresses and decompresses the pixel data of canvases. Canvases store zlib streams, so the
+/// output of [`Compressor::compress`] must be one.
+pub trait Compressor {
+    /// Returns `data` compressed
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>>;
+
+    /// Returns `data` decompressed
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>>;
+}
+
+/// [`Compressor`] backed by flate2. The level ranges from 0 (stored) to 9 (smallest).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Zlib {
+    level: u32,
+}
+
+impl Zlib {
+    /// Fastest level
+    pub const FAST: Self = Self { level: 1 };
+
+    /// Smallest output
+    pub const BEST: Self = Self { level: 9 };
+
+    /// Creates a new [`Zlib`] compressing at `level`. Levels above 9 are treated as 9.
+    pub fn new(level: u32) -> Self {
+        Self {
+            level: level.min(9),
+        }
+    }
+
+    /// Returns the compression level
+    pub fn level(&self) -> u32 {
+        self.level
+    }
+}
+
+impl Default for Zlib {
+    /// Level 6, the usual trade-off between speed and size
+    fn default() -> Self {
+        Self::new(6)
+    }
+}
+
+impl Compressor for Zlib {
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::new(self.level));
+        encoder.write_all(data)?;
+        Ok(encoder.finish()?)
+    }
+
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut decompressed = Vec::new();
+        match ZlibDecoder::new(data).read_to_end(&mut decompressed) {
+            Ok(_) => Ok(decompressed),
+            // Some canvases hold streams that are cut short. The pixels read so far are kept.
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(decompressed),
+            Err(e) => Err(CanvasError::Inflate(e.to_string()).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::image::Reader;
+    use crate::types::{Compressor, Property, Zlib};
+    use crypto::{KeyStream, GMS_IV, TRIMMED_KEY};
+
+    #[test]
+    fn zlib_levels() {
+        let mut reader = Reader::open(
+            "testdata/v83-weapon.img",
+            KeyStream::new(&TRIMMED_KEY, &GMS_IV),
+        )
+        .expect("error opening image");
+        let map = reader.map("weapon.img").expect("error mapping image");
+        let canvas = match map.get("weapon.img/info/icon").expect("missing icon") {
+            Property::Canvas(canvas) => canvas,
+            p => panic!("expected a canvas, found {:?}", p),
+        };
+        let data = canvas.decompressed_data().expect("error decompressing");
+
+        let sizes = [Zlib::new(0), Zlib::FAST, Zlib::default(), Zlib::BEST].map(|zlib| {
+            let compressed = zlib.compress(&data).expect("error compressing");
+            assert_eq!(
+                zlib.decompress(&compressed).expect("error decompressing"),
+                data
+            );
+            compressed.len()
+        });
+        assert!(sizes[0] > data.len());
+        assert!(sizes[1] > sizes[3]);
+        assert_eq!(Zlib::new(12), Zlib::BEST);
+        assert!(Zlib::default().decompress(&[0xff; 16]).is_err());
+
+        // Truncated streams keep what was decompressed
+        let compressed = Zlib::default().compress(&data).expect("error compressing");
+        let truncated = Zlib::default()
+            .decompress(&compressed[..compressed.len() / 2])
+            .expect("error decompressing");
+        assert!(!truncated.is_empty() && data.starts_with(&truncated));
+    }
+}
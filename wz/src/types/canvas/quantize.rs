@@ -0,0 +1,68 @@
+//! Quantization of pixels to lower precision formats
+
+use crate::types::canvas::{join565, split565};
+use crate::types::CanvasFormat;
+use image::RgbaImage;
+
+/// Floyd-Steinberg weights of the right, bottom-left, bottom and bottom-right neighbours
+const WEIGHTS: [f32; 4] = [7.0 / 16.0, 3.0 / 16.0, 5.0 / 16.0, 1.0 / 16.0];
+
+/// Returns the closest pixel `format` can store, or `None` if it stores pixels exactly
+fn nearest(format: CanvasFormat, rgba: [u8; 4]) -> Option<[u8; 4]> {
+    match format {
+        // Each 4-bit channel expands to `c << 4 | c`, i.e. multiples of 17
+        CanvasFormat::Bgra4444 => Some(rgba.map(|c| ((c as u16 + 8) / 17 * 17) as u8)),
+        CanvasFormat::Rgb565 | CanvasFormat::CompressedRgb565 => {
+            // Alpha is dropped when encoding
+            let [r, g, b] = split565(join565(rgba[0], rgba[1], rgba[2]));
+            Some([r, g, b, rgba[3]])
+        }
+        CanvasFormat::Bgra8888 | CanvasFormat::Bc3 => None,
+    }
+}
+
+/// Rounds every pixel of `img` to the closest one `format` can store. With `dither` the rounding
+/// error is spread to the neighbouring pixels, which hides the banding of smooth gradients. Fully
+/// transparent pixels neither take nor spread any error so they stay cheap to compress.
+///
+/// BC3 is lossy in its own way and compressed RGB565 needs uniform blocks, so neither is dithered.
+pub(crate) fn quantize(img: &mut RgbaImage, format: CanvasFormat, dither: bool) {
+    let dither = dither && matches!(format, CanvasFormat::Bgra4444 | CanvasFormat::Rgb565);
+    let width = img.width() as usize;
+    // Error carried into the current and next row, with a pixel of padding on both sides
+    let mut current = vec![[0f32; 4]; width + 2];
+    let mut next = vec![[0f32; 4]; width + 2];
+    for y in 0..img.height() {
+        for x in 0..width {
+            let pixel = img.get_pixel_mut(x as u32, y);
+            let transparent = pixel[3] == 0;
+            let wanted = match dither && !transparent {
+                true => {
+                    let carried = current[x + 1];
+                    let mut wanted = [0u8; 4];
+                    for c in 0..4 {
+                        wanted[c] = (pixel[c] as f32 + carried[c]).round().clamp(0.0, 255.0) as u8;
+                    }
+                    wanted
+                }
+                false => pixel.0,
+            };
+            let quantized = match nearest(format, wanted) {
+                Some(quantized) => quantized,
+                None => return,
+            };
+            if dither && !transparent {
+                for c in 0..4 {
+                    let error = wanted[c] as f32 - quantized[c] as f32;
+                    current[x + 2][c] += error * WEIGHTS[0];
+                    next[x][c] += error * WEIGHTS[1];
+                    next[x + 1][c] += error * WEIGHTS[2];
+                    next[x + 2][c] += error * WEIGHTS[3];
+                }
+            }
+            pixel.0 = quantized;
+        }
+        std::mem::swap(&mut current, &mut next);
+        next.iter_mut().for_each(|error| *error = [0.0; 4]);
+    }
+}
@@ -3,7 +3,39 @@
 use crate::error::{CanvasError, Result};
 use crate::types::CanvasFormat;
 use image::{Pixel, RgbaImage};
-use squish::{Format, Params};
+use rayon::prelude::*;
+use squish::{Algorithm, Format, Params};
+
+/// Rows of 4x4 blocks compressed by each task
+const BAND_BLOCKS: usize = 16;
+
+/// BC compression quality. Higher quality is slower.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Bc3Quality {
+    /// Range fit. Fastest with the most error.
+    Fast,
+
+    /// Cluster fit
+    #[default]
+    Normal,
+
+    /// Iterative cluster fit. Slowest with the least error.
+    Best,
+}
+
+impl Bc3Quality {
+    fn params(&self) -> Params {
+        let algorithm = match self {
+            Bc3Quality::Fast => Algorithm::RangeFit,
+            Bc3Quality::Normal => Algorithm::ClusterFit,
+            Bc3Quality::Best => Algorithm::IterativeClusterFit,
+        };
+        Params {
+            algorithm,
+            ..Params::default()
+        }
+    }
+}
 
 fn from_bc(format: Format, width: usize, height: usize, data: Vec<u8>) -> RgbaImage {
     let mut output = vec![0u8; width * height * 4];
@@ -11,10 +43,29 @@ fn from_bc(format: Format, width: usize, height: usize, data: Vec<u8>) -> RgbaIm
     RgbaImage::from_raw(width as u32, height as u32, output).expect("BC3 size should be good")
 }
 
-fn to_bc(format: Format, width: usize, height: usize, data: Vec<u8>) -> (u32, u32, Vec<u8>) {
-    let output_size = format.compressed_size(width, height);
-    let mut output = vec![0u8; output_size];
-    format.compress(&data, width, height, Params::default(), &mut output);
+/// Blocks are independent, so the image is split into bands of block rows that are compressed in
+/// parallel and joined back in order.
+fn to_bc(
+    format: Format,
+    width: usize,
+    height: usize,
+    data: Vec<u8>,
+    quality: Bc3Quality,
+) -> (u32, u32, Vec<u8>) {
+    if width == 0 || height == 0 {
+        return (width as u32, height as u32, Vec::new());
+    }
+    let params = quality.params();
+    let output = data
+        .par_chunks(width * 4 * BAND_BLOCKS * 4)
+        .map(|band| {
+            let rows = band.len() / (width * 4);
+            let mut output = vec![0u8; format.compressed_size(width, rows)];
+            format.compress(band, width, rows, params, &mut output);
+            output
+        })
+        .collect::<Vec<Vec<u8>>>()
+        .concat();
     (width as u32, height as u32, output)
 }
 
@@ -36,7 +87,7 @@ pub(crate) fn from_bc3(width: u32, height: u32, data: Vec<u8>) -> Result<RgbaIma
 }
 
 /// DirectX DXGI_FORMAT_BC3
-pub(crate) fn to_bc3(img: RgbaImage) -> Result<(u32, u32, Vec<u8>)> {
+pub(crate) fn to_bc3(img: RgbaImage, quality: Bc3Quality) -> Result<(u32, u32, Vec<u8>)> {
     let (width, height) = img.dimensions();
     if width % 4 != 0 || height % 4 != 0 {
         return Err(CanvasError::SizeMismatch(
@@ -57,6 +108,7 @@ pub(crate) fn to_bc3(img: RgbaImage) -> Result<(u32, u32, Vec<u8>)> {
                 [rgba[0], rgba[1], rgba[2], rgba[3]]
             })
             .collect::<Vec<u8>>(),
+        quality,
     ))
 }
 
@@ -79,3 +131,28 @@ pub(crate) fn to_bc3(img: RgbaImage) -> Result<(u32, u32, Vec<u8>)> {
  *     to_bc(Format::Bc5, width as usize, height as usize, img.into_raw())
  * }
  */
+
+#[cfg(test)]
+mod tests {
+
+    use crate::types::canvas::squish::{to_bc, Bc3Quality, BAND_BLOCKS};
+    use squish::Format;
+
+    #[test]
+    fn banded_bc3() {
+        let (width, height) = (8, BAND_BLOCKS * 4 * 2 + 4);
+        let data = (0..width * height * 4)
+            .map(|i| (i * 7) as u8)
+            .collect::<Vec<u8>>();
+        let mut expected = vec![0u8; Format::Bc3.compressed_size(width, height)];
+        Format::Bc3.compress(
+            &data,
+            width,
+            height,
+            Bc3Quality::Best.params(),
+            &mut expected,
+        );
+        let (_, _, output) = to_bc(Format::Bc3, width, height, data, Bc3Quality::Best);
+        assert_eq!(output, expected);
+    }
+}
@@ -2,10 +2,26 @@
 
 use crate::error::{PackageError, Result};
 use crate::io::{Encode, WzWrite};
-use crate::types::{macros, VerboseDebug};
-use crypto::checksum;
+use crate::types::{macros, VerboseDebug, VersionHash};
 use std::{io, io::Read};
 
+/// Identifier of every WZ archive. ASCII for "PKG1"
+const IDENTIFIER: [u8; 4] = [0x50, 0x4b, 0x47, 0x31];
+
+/// Description written by the official tools
+const DESCRIPTION: &str = "Package file v1.0 Copyright 2002 Wizet, ZMS";
+
+/// Nonstandard header contents that were accepted because the header was read leniently
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeaderWarning {
+    /// The identifier is not "PKG1"
+    Identifier([u8; 4]),
+
+    /// The description is not valid UTF-8. Invalid sequences were replaced with U+FFFD, so the
+    /// description can no longer be written back as is.
+    Description,
+}
+
 /// Header of the WZ archive
 ///
 /// The `WzHeader` object contains 2 important pieces of information used in parsing WZ archives:
@@ -27,12 +43,13 @@ pub struct WzHeader {
     /// here. The actual value used for WZ archives is signed but it probably shouldn't be.
     pub absolute_position: i32,
 
-    /// Description of the WZ package. Should be "Package file v1.0 Copyright 2002 Wizet, ZMS"
+    /// Description of the WZ package. Usually "Package file v1.0 Copyright 2002 Wizet, ZMS" but any
+    /// description is kept as read. The `absolute_position` depends on its length.
     pub description: String,
 
-    /// Encrypted version (not really encrypted since it is irreversable. More like a checksum or
-    /// non-cryptographic hash.
-    pub version_hash: u16,
+    /// Encrypted version. `None` for the archives of 64-bit clients, which have no version hash
+    /// and start their contents right after the description.
+    pub version_hash: Option<VersionHash>,
 }
 
 macros::impl_debug!(WzHeader);
@@ -40,13 +57,18 @@ macros::impl_debug!(WzHeader);
 impl WzHeader {
     /// Creates new header with default values.
     pub fn new(version: u16) -> Self {
-        let (version_hash, _) = checksum(&version.to_string());
+        Self::with_description(version, DESCRIPTION)
+    }
+
+    /// Creates new header with a custom description. The `absolute_position` is placed right
+    /// after it.
+    pub fn with_description(version: u16, description: &str) -> Self {
         Self {
-            identifier: [0x50, 0x4b, 0x47, 0x31],
+            identifier: IDENTIFIER,
             size: 0,
-            absolute_position: 60,
-            description: String::from("Package file v1.0 Copyright 2002 Wizet, ZMS"),
-            version_hash,
+            absolute_position: 17 + description.len() as i32,
+            description: String::from(description),
+            version_hash: Some(VersionHash::new(version)),
         }
     }
 
@@ -55,6 +77,22 @@ impl WzHeader {
     where
         R: Read,
     {
+        Ok(Self::from_reader_with(reader, true)?.0)
+    }
+
+    /// Reads the header at the beginning of the WZ archive.
+    ///
+    /// A nonstandard identifier or a description that is not UTF-8 is an error when `strict` is
+    /// set. Otherwise the header is read anyway and the problems are returned as warnings.
+    pub fn from_reader_with<R>(
+        reader: &mut R,
+        strict: bool,
+    ) -> Result<(WzHeader, Vec<HeaderWarning>)>
+    where
+        R: Read,
+    {
+        let mut warnings = Vec::new();
+
         // Fill the readerfer and ensure there are at least 16 bytes
         let mut data = [0u8; 16];
         reader.read_exact(&mut data)?;
@@ -62,8 +100,11 @@ impl WzHeader {
         // Read the identifier
         let mut identifier = [0u8; 4];
         identifier.copy_from_slice(&data[0..4]);
-        if identifier != [0x50, 0x4b, 0x47, 0x31] {
-            return Err(PackageError::Header.into());
+        if identifier != IDENTIFIER {
+            if strict {
+                return Err(PackageError::Header.into());
+            }
+            warnings.push(HeaderWarning::Identifier(identifier));
         }
 
         // Read the size
@@ -75,42 +116,47 @@ impl WzHeader {
         let mut absolute_position = [0u8; 4];
         absolute_position.copy_from_slice(&data[12..16]);
         let absolute_position = i32::from_le_bytes(absolute_position);
-        if absolute_position.is_negative() {
+        if absolute_position < 17 {
             return Err(PackageError::Header.into());
         }
 
         // Read the description
         let mut description = vec![0u8; (absolute_position as usize) - 17];
         reader.read_exact(&mut description)?;
-        let description = String::from_utf8(description).map_err(|_| PackageError::Header)?;
+        let description = match String::from_utf8(description) {
+            Ok(description) => description,
+            Err(_) if strict => return Err(PackageError::Header.into()),
+            Err(e) => {
+                warnings.push(HeaderWarning::Description);
+                String::from_utf8_lossy(e.as_bytes()).into_owned()
+            }
+        };
 
         // Skip the null
         let mut skip = [0];
         reader.read_exact(&mut skip)?;
 
-        // Read the encrypted version and bruteforce the checksum
+        // Read the encrypted version. Anything that cannot be a hash is already the start of the
+        // contents of a 64-bit archive.
         let mut version_hash = [0u8; 2];
         reader.read_exact(&mut version_hash)?;
         let version_hash = u16::from_le_bytes(version_hash);
+        let version_hash = VersionHash::is_valid(version_hash).then_some(version_hash.into());
 
-        Ok(WzHeader {
+        let header = WzHeader {
             identifier,
             size,
             absolute_position,
             description,
             version_hash,
-        })
+        };
+        Ok((header, warnings))
     }
 
-    pub(crate) fn possible_versions(version_hash: u16) -> Vec<(u16, u32)> {
-        let mut versions = Vec::new();
-        for version in 1..1000 {
-            let (calc_version_hash, version_checksum) = checksum(&version.to_string());
-            if calc_version_hash == version_hash {
-                versions.push((version, version_checksum));
-            }
-        }
-        versions
+    /// Returns every version whose hash matches `version_hash` along with its checksum. See
+    /// [`VersionHash::possible_versions`].
+    pub fn possible_versions(version_hash: u16) -> &'static [(u16, u32)] {
+        VersionHash::from(version_hash).possible_versions()
     }
 }
 
@@ -120,20 +166,29 @@ impl Encode for WzHeader {
     where
         W: WzWrite + ?Sized,
     {
+        // The contents start right after the description
+        if self.absolute_position != 17 + self.description.len() as i32 {
+            return Err(PackageError::Header.into());
+        }
         writer.write_all(&self.identifier)?;
         self.size.encode(writer)?;
         self.absolute_position.encode(writer)?;
         writer.write_all(self.description.as_bytes())?;
         writer.write_byte(0)?;
-        self.version_hash.encode(writer)
+        match &self.version_hash {
+            Some(version_hash) => version_hash.encode(writer),
+            None => Ok(()),
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
 
-    use crate::types::WzHeader;
-    use std::fs::File;
+    use crate::io::{Encode, WzWriter};
+    use crate::types::{HeaderWarning, VersionHash, WzHeader};
+    use crypto::checksum;
+    use std::{fs::File, io::Cursor};
 
     #[test]
     fn v83_header() {
@@ -146,7 +201,7 @@ mod tests {
             &header.description,
             "Package file v1.0 Copyright 2002 Wizet, ZMS"
         );
-        assert_eq!(header.version_hash, 172);
+        assert_eq!(header.version_hash, Some(VersionHash::from(172)));
     }
 
     #[test]
@@ -160,6 +215,70 @@ mod tests {
             &header.description,
             "Package file v1.0 Copyright 2002 Wizet, ZMS"
         );
-        assert_eq!(header.version_hash, 7);
+        assert_eq!(header.version_hash, Some(VersionHash::from(7)));
+    }
+
+    #[test]
+    fn possible_versions() {
+        let versions = WzHeader::possible_versions(172);
+        assert!(versions.iter().any(|(version, _)| *version == 83));
+        assert!(versions.iter().any(|(version, _)| *version > 1000));
+        assert!(versions.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert!(versions.contains(&(83, checksum("83").1)));
+        assert!(WzHeader::possible_versions(0x100).is_empty());
+    }
+
+    #[test]
+    fn custom_description() {
+        let header = WzHeader::with_description(83, "Repacked by hand");
+        assert_eq!(header.absolute_position, 17 + 16);
+        let mut writer = WzWriter::unencrypted(0, 0, Cursor::new(Vec::new()));
+        header.encode(&mut writer).expect("error encoding header");
+        let data = writer.into_inner().into_inner();
+        assert_eq!(data.len(), header.absolute_position as usize + 2);
+        let read = WzHeader::from_reader(&mut data.as_slice()).expect("error reading header");
+        assert_eq!(read, header);
+
+        // The description has to fit before the contents
+        let mut moved = header.clone();
+        moved.description.push('!');
+        let mut writer = WzWriter::unencrypted(0, 0, Cursor::new(Vec::new()));
+        assert!(moved.encode(&mut writer).is_err());
+
+        // Nonstandard headers only open leniently
+        let mut data = data;
+        data[3] = b'2';
+        data[16] = 0xff;
+        assert!(WzHeader::from_reader(&mut data.as_slice()).is_err());
+        assert!(WzHeader::from_reader_with(&mut data.as_slice(), true).is_err());
+        let (read, warnings) =
+            WzHeader::from_reader_with(&mut data.as_slice(), false).expect("error reading header");
+        assert_eq!(
+            warnings,
+            [
+                HeaderWarning::Identifier(*b"PKG2"),
+                HeaderWarning::Description
+            ]
+        );
+        assert_eq!(read.description, "\u{fffd}epacked by hand");
+        assert_eq!(read.version_hash, header.version_hash);
+    }
+
+    #[test]
+    fn unversioned_header() {
+        let mut header = WzHeader::new(83);
+        header.version_hash = None;
+        let mut writer = WzWriter::unencrypted(0, 0, Cursor::new(Vec::new()));
+        header.encode(&mut writer).expect("error encoding header");
+        let mut data = writer.into_inner().into_inner();
+        assert_eq!(data.len(), header.absolute_position as usize);
+
+        // 64-bit archives start with the number of root contents, usually more than a byte can hold
+        data.extend([0x80, 0x01]);
+        let read = WzHeader::from_reader(&mut data.as_slice()).expect("error reading header");
+        assert_eq!(read, header);
+        data[header.absolute_position as usize + 1] = 0;
+        let read = WzHeader::from_reader(&mut data.as_slice()).expect("error reading header");
+        assert_eq!(read.version_hash, Some(VersionHash::from(0x80)));
     }
 }
@@ -13,6 +13,11 @@ use std::{
 /// This is a compressed `i32`. WZ archives use both `i32` and `WzInt` so a separate structure was
 /// created to differentiate them.
 #[derive(Clone, Copy, Debug, PartialOrd, PartialEq, Ord, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
 pub struct WzInt(i32);
 
 macros::impl_num!(WzInt, i32);
@@ -70,6 +75,11 @@ impl SizeHint for WzInt {
 /// This is a compressed `i64`. WZ archives use both `i64` and `WzLong` so a separate structure was
 /// created to differentiate them.
 #[derive(Clone, Copy, Debug, PartialOrd, PartialEq, Ord, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
 pub struct WzLong(i64);
 
 macros::impl_num!(WzLong, i64);
@@ -4,7 +4,8 @@ use crate::error::Result;
 use crate::io::{Decode, Encode, SizeHint, WzRead, WzWrite};
 use crate::types::{macros, VerboseDebug};
 use std::{
-    io,
+    cmp::Ordering,
+    fmt, io,
     ops::{Add, Deref, DerefMut, Div, Mul, Rem, Sub},
 };
 
@@ -16,7 +17,9 @@ use std::{
 /// impossible to drop older WZ archives into the latest MS game data. This also means the version
 /// must be known when reading or writing WZ archives. The `archive::Reader` structure offers a
 /// method to bruteforce the version but it should not be relied on to work 100% of the time.
-#[derive(Clone, Copy, Debug, PartialOrd, PartialEq, Ord, Eq)]
+///
+/// Offsets are displayed in hex, e.g. `0x0000003C`, and compare against 64-bit file lengths.
+#[derive(Clone, Copy, PartialOrd, PartialEq, Ord, Eq)]
 pub struct WzOffset(u32);
 
 macros::impl_num!(WzOffset, u32);
@@ -60,7 +63,30 @@ impl WzOffset {
         offset.wrapping_add(abs_pos.wrapping_mul(2))
     }
 
-    fn encode_with(&self, position: WzOffset, abs_pos: i32, version_checksum: u32) -> u32 {
+    /// Moves the offset from an archive whose contents start at `from` to one whose contents start
+    /// at `to`. Used when copying contents between archives with different header sizes.
+    pub fn rebase(&self, from: i32, to: i32) -> Self {
+        Self(self.0.wrapping_add((to as u32).wrapping_sub(from as u32)))
+    }
+
+    /// Re-encodes `value`, stored at `position` in an archive whose contents start at `from`, for
+    /// the same contents in an archive whose contents start at `to`. Both the stored offset and
+    /// the position it is stored at are moved.
+    pub fn rebase_encoded(
+        value: u32,
+        position: WzOffset,
+        from: i32,
+        to: i32,
+        version_checksum: u32,
+    ) -> u32 {
+        WzOffset::new(value, position, from, version_checksum)
+            .rebase(from, to)
+            .encode_with(position.rebase(from, to), to, version_checksum)
+    }
+
+    /// Returns the encoded offset as it is stored at `position` in an archive whose contents
+    /// start at `abs_pos`. This is the inverse of [`WzOffset::new`].
+    pub fn encode_with(&self, position: WzOffset, abs_pos: i32, version_checksum: u32) -> u32 {
         let enc_offset = *position;
         let abs_pos = abs_pos as u32;
         let magic = 0x581C3F6D;
@@ -77,6 +103,41 @@ impl WzOffset {
         let offset = offset.wrapping_sub(abs_pos.wrapping_mul(2));
         offset ^ enc_offset
     }
+
+    /// Returns the offset `len` bytes further, or `None` if it no longer fits in 32 bits
+    pub fn checked_add(&self, len: u32) -> Option<Self> {
+        self.0.checked_add(len).map(Self)
+    }
+
+    /// Returns the offset rounded up to a multiple of `alignment`, or `None` if `alignment` is zero
+    /// or the rounded offset no longer fits in 32 bits
+    pub fn align_to(&self, alignment: u32) -> Option<Self> {
+        self.0.checked_next_multiple_of(alignment).map(Self)
+    }
+}
+
+impl fmt::Display for WzOffset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{:08X}", self.0)
+    }
+}
+
+impl fmt::Debug for WzOffset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "WzOffset({})", self)
+    }
+}
+
+impl PartialEq<u64> for WzOffset {
+    fn eq(&self, other: &u64) -> bool {
+        self.0 as u64 == *other
+    }
+}
+
+impl PartialOrd<u64> for WzOffset {
+    fn partial_cmp(&self, other: &u64) -> Option<Ordering> {
+        (self.0 as u64).partial_cmp(other)
+    }
 }
 
 impl Decode for WzOffset {
@@ -121,6 +182,7 @@ impl SizeHint for WzOffset {
 mod tests {
 
     use crate::types::WzOffset;
+    use crypto::checksum;
 
     #[test]
     fn wz_offset() {
@@ -146,4 +208,51 @@ mod tests {
         assert!(wz_offset < WzOffset::from(test3));
         assert!(wz_offset < WzOffset::from(test4));
     }
+
+    #[test]
+    fn offset_math() {
+        let offset = WzOffset::from(0x3Cu32);
+        assert_eq!(offset.to_string(), "0x0000003C");
+        assert_eq!(format!("{:?}", offset), "WzOffset(0x0000003C)");
+        assert_eq!(offset.checked_add(4), Some(WzOffset::from(0x40u32)));
+        assert_eq!(WzOffset::from(u32::MAX).checked_add(1), None);
+        assert_eq!(offset.align_to(16), Some(WzOffset::from(0x40u32)));
+        assert_eq!(offset.align_to(4), Some(offset));
+        assert_eq!(offset.align_to(0), None);
+        assert_eq!(WzOffset::from(u32::MAX).align_to(16), None);
+
+        // File lengths may not fit in 32 bits
+        assert!(offset < 0x3Du64);
+        assert!(offset == 0x3Cu64);
+        assert!(WzOffset::from(u32::MAX) < u64::from(u32::MAX) + 1);
+    }
+
+    #[test]
+    fn rebase_offsets() {
+        let (_, version_checksum) = checksum("83");
+        let offset = WzOffset::from(1234u32);
+        let position = WzOffset::from(100u32);
+        let encoded = offset.encode_with(position, 60, version_checksum);
+        assert_eq!(
+            WzOffset::new(encoded, position, 60, version_checksum),
+            offset
+        );
+        assert_ne!(
+            WzOffset::new(encoded, position, 80, version_checksum),
+            offset
+        );
+
+        // The header grows by 20 bytes
+        assert_eq!(offset.rebase(60, 80), WzOffset::from(1254u32));
+        assert_eq!(offset.rebase(60, 80).rebase(80, 60), offset);
+        assert_eq!(
+            WzOffset::from(10u32).rebase(60, 20),
+            WzOffset::from(u32::MAX - 29)
+        );
+        let rebased = WzOffset::rebase_encoded(encoded, position, 60, 80, version_checksum);
+        assert_eq!(
+            WzOffset::new(rebased, WzOffset::from(120u32), 80, version_checksum),
+            WzOffset::from(1254u32)
+        );
+    }
 }
@@ -1,6 +1,6 @@
 //! Primitive WZ Formats
 
-use crate::error::Result;
+use crate::error::{DecodeError, Result};
 use crate::io::{Decode, Encode, SizeHint, WzRead, WzWrite};
 use crate::types::{macros, VerboseDebug};
 use std::io;
@@ -233,14 +233,15 @@ impl Decode for f32 {
     where
         R: WzRead + ?Sized,
     {
-        Ok(match reader.read_byte()? {
+        match reader.read_byte()? {
+            0 => Ok(0f32),
             0x80 => {
                 let mut buf = [0u8; 4];
                 reader.read_exact(&mut buf)?;
-                f32::from_le_bytes(buf)
+                Ok(f32::from_le_bytes(buf))
             }
-            _ => 0f32,
-        })
+            marker => Err(DecodeError::FloatMarker(marker).at(reader).into()),
+        }
     }
 }
 
@@ -249,7 +250,9 @@ impl Encode for f32 {
     where
         W: WzWrite + ?Sized,
     {
-        if *self as u32 == 0 {
+        // Only positive zero uses the short form so every other bit pattern, including negative
+        // zero and NaN, survives a round trip
+        if self.to_bits() == 0 {
             writer.write_byte(0)
         } else {
             writer.write_byte(0x80)?;
@@ -261,7 +264,7 @@ impl Encode for f32 {
 impl SizeHint for f32 {
     #[inline]
     fn size_hint(&self) -> u32 {
-        if *self as u32 == 0 {
+        if self.to_bits() == 0 {
             1
         } else {
             5
@@ -295,3 +298,71 @@ impl SizeHint for f64 {
         8
     }
 }
+
+#[cfg(test)]
+mod tests {
+
+    use crate::io::{Decode, Encode, SizeHint, WzReader, WzWriter};
+    use proptest::prelude::*;
+    use std::io::Cursor;
+
+    fn round_trip<T>(value: T) -> T
+    where
+        T: Decode + Encode + SizeHint,
+    {
+        let mut writer = WzWriter::unencrypted(0, 0, Cursor::new(Vec::new()));
+        value.encode(&mut writer).expect("error encoding");
+        let data = writer.into_inner().into_inner();
+        assert_eq!(data.len() as u32, value.size_hint());
+        let mut reader = WzReader::unencrypted(0, 0, Cursor::new(data));
+        T::decode(&mut reader).expect("error decoding")
+    }
+
+    /// Random bit patterns along with special values: zeros, NaNs with payloads, infinities
+    fn bits() -> impl Strategy<Value = u64> {
+        let special = [
+            0,
+            1,
+            0x8000_0000,
+            0x8000_0000_0000_0000,
+            0x7fc0_0000,
+            0xffc0_0001,
+            0x7ff8_0000_0000_0001,
+            0x7f80_0000,
+            0xff80_0000,
+            0x3f00_0000,
+            0xbf80_0000,
+            u64::MAX,
+        ];
+        prop_oneof![any::<u64>(), proptest::sample::select(special.to_vec())]
+    }
+
+    proptest! {
+        #[test]
+        fn float_round_trip(bits in bits()) {
+            let value = f32::from_bits(bits as u32);
+            prop_assert_eq!(round_trip(value).to_bits(), value.to_bits(), "{:?}", value);
+            let value = f64::from_bits(bits);
+            prop_assert_eq!(round_trip(value).to_bits(), value.to_bits(), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn float_markers() {
+        assert_eq!(0f32.size_hint(), 1);
+        assert_eq!((-0f32).size_hint(), 5);
+        assert_eq!(0.5f32.size_hint(), 5);
+        for marker in (1..=u8::MAX).filter(|m| *m != 0x80) {
+            let data = vec![marker, 0, 0, 0, 0];
+            let mut reader = WzReader::unencrypted(0, 0, Cursor::new(data));
+            let error = f32::decode(&mut reader).expect_err("unknown marker decoded");
+            assert!(
+                error
+                    .to_string()
+                    .contains(&format!("Invalid float marker: `0x{:02X}`", marker)),
+                "{}",
+                error
+            );
+        }
+    }
+}
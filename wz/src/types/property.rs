@@ -1,13 +1,29 @@
 //! Image Property
 
-use crate::io::xml::writer::ToXml;
-use crate::types::{Canvas, Sound, UolObject, UolString, Vector, VerboseDebug, WzInt, WzLong};
-use std::io;
+#[cfg(not(feature = "canvas-codecs"))]
+use crate::error::CanvasError;
+use crate::error::{ImageError, Result};
+use crate::io::xml::{
+    attribute::OwnedAttribute,
+    reader::FromXml,
+    writer::{Dialect, ToXml},
+};
+use crate::types::{
+    Canvas, CanvasFormat, Sound, SoundHeader, UolObject, UolString, Vector, VerboseDebug, WzInt,
+    WzLong,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+#[cfg(feature = "canvas-codecs")]
+use image::ImageFormat;
+use std::{fmt, io, str::FromStr};
+
+/// Characters of a string shown by the [`Property`] `Display` before it is cut short
+const DISPLAY_LENGTH: usize = 40;
 
 /// Possible WZ image contents.
 ///
 /// This list has flattened to include both primitive properties and more complex objects.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Property {
     /// Null value
     Null,
@@ -51,6 +67,15 @@ pub enum Property {
 
     /// Holds WAV sound data
     Sound(Sound),
+
+    /// An object type this crate cannot decode, holding its object tag. The object's contents are
+    /// skipped, so images holding one cannot be written back.
+    Unknown(String),
+
+    /// An object that could not be decoded by
+    /// [`Reader::map_recovering`](crate::image::Reader::map_recovering), holding the error.
+    /// Images holding one cannot be written back.
+    Error(String),
 }
 
 impl VerboseDebug for Property {
@@ -69,10 +94,50 @@ impl VerboseDebug for Property {
             Property::Vector(v) => v.debug(f),
             Property::Uol(v) => v.debug(f),
             Property::Sound(v) => v.debug(f),
+            Property::Unknown(tag) => f.write_fmt(format_args!("Unknown({})", tag)),
+            Property::Error(e) => f.write_fmt(format_args!("Error({})", e)),
         }
     }
 }
 
+/// Shows the type of the property followed by its value, e.g. `int      17`, `canvas   64x32
+/// Bgra4444` or `string   "Orange Mushroom"`. Long strings are cut short.
+impl fmt::Display for Property {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tag = self.tag();
+        match self {
+            Property::Null | Property::ImgDir | Property::Convex => write!(f, "{}", tag),
+            Property::Short(v) => write!(f, "{:<8} {}", tag, v),
+            Property::Int(v) => write!(f, "{:<8} {}", tag, **v),
+            Property::Long(v) => write!(f, "{:<8} {}", tag, **v),
+            Property::Float(v) => write!(f, "{:<8} {}", tag, v),
+            Property::Double(v) => write!(f, "{:<8} {}", tag, v),
+            Property::String(v) => write!(f, "{:<8} \"{}\"", tag, abbreviate(v)),
+            Property::Canvas(v) => write!(
+                f,
+                "{:<8} {}x{} {:?}",
+                tag,
+                *v.width(),
+                *v.height(),
+                v.format()
+            ),
+            Property::Vector(v) => write!(f, "{:<8} ({}, {})", tag, *v.x, *v.y),
+            Property::Uol(v) => write!(f, "{:<8} {}", tag, abbreviate(v)),
+            Property::Sound(v) => write!(f, "{:<8} {} ms", tag, *v.duration()),
+            Property::Unknown(object_tag) => write!(f, "{:<8} {}", tag, object_tag),
+            Property::Error(e) => write!(f, "{:<8} {}", tag, abbreviate(e)),
+        }
+    }
+}
+
+/// Returns `s`, cut short with `...` when it is longer than [`DISPLAY_LENGTH`] characters
+fn abbreviate(s: &str) -> String {
+    match s.char_indices().nth(DISPLAY_LENGTH) {
+        Some((end, _)) => format!("{}...", &s[..end]),
+        None => String::from(s),
+    }
+}
+
 impl ToXml for Property {
     fn tag(&self) -> &'static str {
         match &self {
@@ -89,6 +154,8 @@ impl ToXml for Property {
             Property::Vector(v) => v.tag(),
             Property::Uol(v) => v.tag(),
             Property::Sound(v) => v.tag(),
+            Property::Unknown(_) => "unknown",
+            Property::Error(_) => "error",
         }
     }
 
@@ -122,6 +189,172 @@ impl ToXml for Property {
             Property::Vector(v) => v.attributes(name),
             Property::Uol(v) => v.attributes(name),
             Property::Sound(v) => v.attributes(name),
+            Property::Unknown(tag) => vec![
+                (String::from("name"), name.to_string()),
+                (String::from("type"), tag.clone()),
+            ],
+            Property::Error(e) => vec![
+                (String::from("name"), name.to_string()),
+                (String::from("message"), e.clone()),
+            ],
+        }
+    }
+
+    fn extension(&self) -> Option<&'static str> {
+        match &self {
+            Property::Canvas(v) => v.extension(),
+            Property::Sound(v) => v.extension(),
+            _ => None,
+        }
+    }
+
+    fn payload(&self, dialect: Dialect, src: Option<&str>) -> Vec<(String, String)> {
+        match &self {
+            Property::Canvas(v) => v.payload(dialect, src),
+            Property::Sound(v) => v.payload(dialect, src),
+            _ => Vec::new(),
         }
     }
 }
+
+/// Reads the server XML written by [`XmlWriter`](crate::io::xml::writer::XmlWriter) or by
+/// HaRepacker and WzDumper. Canvases and sounds need the `basedata` written with
+/// [`DataMode::Inline`](crate::io::xml::writer::DataMode::Inline).
+/// A canvas without it becomes a blank canvas of the same size, a sound without it is an error.
+///
+/// The embedded PNG of HaRepacker and WzDumper canvases is stored in the smallest format that keeps
+/// it intact, see `Canvas::from_image_auto`. Blank and PNG canvases need the `canvas-codecs`
+/// feature.
+impl FromXml for Property {
+    fn from_xml(
+        tag: &str,
+        attributes: &[OwnedAttribute],
+        dialect: Dialect,
+    ) -> Result<(String, Self)> {
+        let name = attribute(attributes, "name")?.to_string();
+        let property = match tag {
+            "null" => Property::Null,
+            "short" => Property::Short(parse(attribute(attributes, "value")?)?),
+            "int" => Property::Int(WzInt::from(parse::<i32>(attribute(attributes, "value")?)?)),
+            "long" => Property::Long(WzLong::from(parse::<i64>(attribute(attributes, "value")?)?)),
+            "float" => Property::Float(parse(attribute(attributes, "value")?)?),
+            "double" => Property::Double(parse(attribute(attributes, "value")?)?),
+            "string" => Property::String(UolString::from(attribute(attributes, "value")?)),
+            "imgdir" => Property::ImgDir,
+            "canvas" => {
+                let width = WzInt::from(parse::<i32>(attribute(attributes, "width")?)?);
+                let height = WzInt::from(parse::<i32>(attribute(attributes, "height")?)?);
+                match (dialect, attribute(attributes, "basedata")) {
+                    (Dialect::Native, Ok(data)) => {
+                        let format = WzInt::from(parse::<i32>(attribute(attributes, "format")?)?);
+                        Property::Canvas(Canvas::new(
+                            width,
+                            height,
+                            CanvasFormat::from_int(format)?,
+                            decode(data)?,
+                        ))
+                    }
+                    (_, Ok(data)) => {
+                        Property::Canvas(encode_canvas(width, height, Some(decode(data)?))?)
+                    }
+                    (_, Err(_)) => Property::Canvas(encode_canvas(width, height, None)?),
+                }
+            }
+            "extended" => Property::Convex,
+            "vector" => Property::Vector(Vector::new(
+                WzInt::from(parse::<i32>(attribute(attributes, "x")?)?),
+                WzInt::from(parse::<i32>(attribute(attributes, "y")?)?),
+            )),
+            "uol" => Property::Uol(UolObject::from(attribute(attributes, "value")?.to_string())),
+            "sound" if dialect == Dialect::Native => {
+                let duration = WzInt::from(parse::<i32>(attribute(attributes, "duration")?)?);
+                let data = decode(attribute(attributes, "basedata")?)?;
+                Property::Sound(Sound::from_bytes(&data, duration)?)
+            }
+            "sound" => {
+                let duration = WzInt::from(parse::<i32>(attribute(attributes, "length")?)?);
+                let header = SoundHeader::from_slice(&decode(attribute(attributes, "basehead")?)?)?;
+                let data = decode(attribute(attributes, "basedata")?)?;
+                Property::Sound(Sound::new(duration, header, data))
+            }
+            "unknown" => Property::Unknown(attribute(attributes, "type")?.to_string()),
+            "error" => Property::Error(attribute(attributes, "message")?.to_string()),
+            t => return Err(ImageError::ObjectType(t.into()).into()),
+        };
+        Ok((name, property))
+    }
+}
+
+/// Returns the value of the attribute called `name`
+fn attribute<'a>(attributes: &'a [OwnedAttribute], name: &str) -> Result<&'a str> {
+    attributes
+        .iter()
+        .find(|a| a.name.local_name == name)
+        .map(|a| a.value.as_str())
+        .ok_or_else(|| ImageError::Property(name.into()).into())
+}
+
+fn parse<T>(value: &str) -> Result<T>
+where
+    T: FromStr,
+{
+    T::from_str(value).map_err(|_| ImageError::Value(value.into()).into())
+}
+
+/// Encodes the embedded `png`, or a blank canvas of `width` by `height` without one
+#[cfg(feature = "canvas-codecs")]
+fn encode_canvas(width: WzInt, height: WzInt, png: Option<Vec<u8>>) -> Result<Canvas> {
+    match png {
+        Some(png) => {
+            let img = image::load_from_memory_with_format(&png, ImageFormat::Png)?;
+            Canvas::from_buffer_auto(img.into_rgba8())
+        }
+        None => Ok(Canvas::blank(width, height)),
+    }
+}
+
+#[cfg(not(feature = "canvas-codecs"))]
+fn encode_canvas(_width: WzInt, _height: WzInt, _png: Option<Vec<u8>>) -> Result<Canvas> {
+    Err(CanvasError::CodecsDisabled.into())
+}
+
+fn decode(value: &str) -> Result<Vec<u8>> {
+    STANDARD
+        .decode(value)
+        .map_err(|_| ImageError::Value(String::from("base64")).into())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::types::{Canvas, CanvasFormat, Property, UolString, Vector, WzInt};
+
+    #[test]
+    fn display_properties() {
+        assert_eq!(Property::ImgDir.to_string(), "imgdir");
+        assert_eq!(Property::Int(WzInt::from(17)).to_string(), "int      17");
+        assert_eq!(
+            Property::Vector(Vector::new(WzInt::from(-3), WzInt::from(4))).to_string(),
+            "vector   (-3, 4)"
+        );
+        let canvas = Canvas::new(
+            WzInt::from(64),
+            WzInt::from(32),
+            CanvasFormat::Bgra4444,
+            Vec::new(),
+        );
+        assert_eq!(
+            Property::Canvas(canvas).to_string(),
+            "canvas   64x32 Bgra4444"
+        );
+        assert_eq!(
+            Property::String(UolString::from("Orange Mushroom")).to_string(),
+            "string   \"Orange Mushroom\""
+        );
+        let long = "가".repeat(50);
+        assert_eq!(
+            Property::String(UolString::from(long.as_str())).to_string(),
+            format!("string   \"{}...\"", "가".repeat(40))
+        );
+    }
+}
@@ -7,7 +7,7 @@ mod property;
 
 pub(crate) mod package;
 
-pub(crate) use canvas::Canvas;
+pub(crate) use canvas::{read_raw_image_data, Canvas};
 pub(crate) use content::ContentRef;
 pub(crate) use object::Object;
 pub(crate) use package::Package;
@@ -10,45 +10,72 @@ pub(crate) struct Canvas {
     pub(crate) height: WzInt,
     pub(crate) format: CanvasFormat,
     pub(crate) data: Vec<u8>,
+    pub(crate) offset: WzOffset,
+    pub(crate) length: usize,
     pub(crate) property: Option<Property>,
 }
 
+impl Canvas {
+    /// Decodes the canvas but skips over the image data. `data` is left empty.
+    pub(crate) fn decode_lazy<R>(reader: &mut R) -> Result<Self>
+    where
+        R: WzRead + ?Sized,
+    {
+        decode_canvas(reader, true)
+    }
+}
+
 impl Decode for Canvas {
     fn decode<R>(reader: &mut R) -> Result<Self>
     where
         R: WzRead + ?Sized,
     {
-        u8::decode(reader)?;
-        let property = match u8::decode(reader)? {
-            1 => Some(Property::decode(reader)?),
-            _ => None,
-        };
-        let width = WzInt::decode(reader)?;
-        let height = WzInt::decode(reader)?;
-        if width > 0x10000 || height > 0x10000 {
-            return Err(CanvasError::TooBig(*width as u32, *height as u32).into());
-        }
-        let format = CanvasFormat::decode(reader)?;
-        i32::decode(reader)?;
-        let length = i32::decode(reader)?;
-        if length.is_negative() {
-            return Err(DecodeError::Length(length).into());
-        }
-        let length = length as usize - 1;
-        u8::decode(reader)?;
-        let data = read_raw_image_data(reader, length)?;
+        decode_canvas(reader, false)
+    }
+}
 
-        Ok(Self {
-            width,
-            height,
-            format,
-            data,
-            property,
-        })
+fn decode_canvas<R>(reader: &mut R, lazy: bool) -> Result<Canvas>
+where
+    R: WzRead + ?Sized,
+{
+    u8::decode(reader)?;
+    let property = match u8::decode(reader)? {
+        1 => Some(Property::decode(reader)?),
+        _ => None,
+    };
+    let width = WzInt::decode(reader)?;
+    let height = WzInt::decode(reader)?;
+    if width > 0x10000 || height > 0x10000 {
+        return Err(CanvasError::TooBig(*width as u32, *height as u32).into());
+    }
+    let format = CanvasFormat::decode(reader)?;
+    i32::decode(reader)?;
+    let length = i32::decode(reader)?;
+    if length.is_negative() {
+        return Err(DecodeError::Length(length).at(reader).into());
     }
+    let length = length as usize - 1;
+    u8::decode(reader)?;
+    let offset = reader.position()?;
+    let data = if lazy {
+        reader.seek(offset + length.into())?;
+        Vec::new()
+    } else {
+        read_raw_image_data(reader, length)?
+    };
+
+    Ok(Canvas {
+        width,
+        height,
+        format,
+        data,
+        offset,
+        length,
+        property,
+    })
 }
 
-fn read_raw_image_data<R>(reader: &mut R, length: usize) -> Result<Vec<u8>>
+pub(crate) fn read_raw_image_data<R>(reader: &mut R, length: usize) -> Result<Vec<u8>>
 where
     R: WzRead + ?Sized,
 {
@@ -28,8 +28,12 @@ pub(crate) enum ContentRef {
     /// UOL
     String { name: UolString, value: UolString },
 
-    /// Complex object
-    Object { name: UolString, offset: WzOffset },
+    /// Complex object. `size` is the number of bytes the object takes up from `offset`.
+    Object {
+        name: UolString,
+        offset: WzOffset,
+        size: u32,
+    },
 }
 
 impl Decode for ContentRef {
@@ -68,7 +72,7 @@ impl Decode for ContentRef {
                 let size = u32::decode(reader)?;
                 let offset = reader.position()?;
                 reader.seek(offset + size.into())?;
-                Ok(Self::Object { name, offset })
+                Ok(Self::Object { name, offset, size })
             }
             t => Err(ImageError::PropertyType(t).into()),
         }
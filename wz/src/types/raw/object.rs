@@ -1,6 +1,6 @@
 //! WZ Property Object
 
-use crate::error::{ImageError, Result};
+use crate::error::Result;
 use crate::io::{Decode, WzRead};
 use crate::types::raw::{Canvas, Property};
 use crate::types::{Sound, UolObject, Vector};
@@ -25,6 +25,19 @@ pub(crate) enum Object {
 
     /// Sound_DX8
     Sound(Sound),
+
+    /// Unknown object type. Only the object tag is read.
+    Unknown(String),
+}
+
+impl Object {
+    /// Decodes the object without reading canvas data
+    pub(crate) fn decode_lazy<R>(reader: &mut R) -> Result<Self>
+    where
+        R: WzRead + ?Sized,
+    {
+        decode_object(reader, true)
+    }
 }
 
 impl Decode for Object {
@@ -32,15 +45,23 @@ impl Decode for Object {
     where
         R: WzRead + ?Sized,
     {
-        let typename = reader.read_object_tag()?;
-        match typename.as_ref() {
-            "Property" => Ok(Self::Property(Property::decode(reader)?)),
-            "Canvas" => Ok(Self::Canvas(Canvas::decode(reader)?)),
-            "Shape2D#Convex2D" => Ok(Self::Convex),
-            "Shape2D#Vector2D" => Ok(Self::Vector(Vector::decode(reader)?)),
-            "UOL" => Ok(Self::Uol(UolObject::decode(reader)?)),
-            "Sound_DX8" => Ok(Self::Sound(Sound::decode(reader)?)),
-            t => Err(ImageError::ObjectType(String::from(t)).into()),
-        }
+        decode_object(reader, false)
+    }
+}
+
+fn decode_object<R>(reader: &mut R, lazy: bool) -> Result<Object>
+where
+    R: WzRead + ?Sized,
+{
+    let typename = reader.read_object_tag()?;
+    match typename.as_ref() {
+        "Property" => Ok(Object::Property(Property::decode(reader)?)),
+        "Canvas" if lazy => Ok(Object::Canvas(Canvas::decode_lazy(reader)?)),
+        "Canvas" => Ok(Object::Canvas(Canvas::decode(reader)?)),
+        "Shape2D#Convex2D" => Ok(Object::Convex),
+        "Shape2D#Vector2D" => Ok(Object::Vector(Vector::decode(reader)?)),
+        "UOL" => Ok(Object::Uol(UolObject::decode(reader)?)),
+        "Sound_DX8" => Ok(Object::Sound(Sound::decode(reader)?)),
+        t => Ok(Object::Unknown(String::from(t))),
     }
 }
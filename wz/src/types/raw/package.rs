@@ -1,7 +1,7 @@
 //! WZ Package types
 
 use crate::error::{DecodeError, Result};
-use crate::io::{Decode, Encode, WzRead, WzWrite};
+use crate::io::{Decode, Encode, SizeHint, WzRead, WzWrite};
 use crate::types::WzInt;
 
 mod content;
@@ -33,7 +33,7 @@ impl Decode for Package {
     {
         let num_contents = WzInt::decode(reader)?;
         if num_contents.is_negative() {
-            return Err(DecodeError::Length(*num_contents).into());
+            return Err(DecodeError::Length(*num_contents).at(reader).into());
         }
         let num_contents = *num_contents as usize;
         let mut contents = Vec::with_capacity(num_contents);
@@ -57,3 +57,15 @@ impl Encode for Package {
         Ok(())
     }
 }
+
+impl SizeHint for Package {
+    #[inline]
+    fn size_hint(&self) -> u32 {
+        WzInt::from(self.contents.len() as i32).size_hint()
+            + self
+                .contents
+                .iter()
+                .map(|content| content.size_hint())
+                .sum::<u32>()
+    }
+}
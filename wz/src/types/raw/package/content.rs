@@ -20,6 +20,13 @@ impl ContentRef {
             ContentRef::Image(ref data) => data.offset,
         }
     }
+
+    pub(crate) fn size(&self) -> WzInt {
+        match &self {
+            ContentRef::Package(ref data) => data.size,
+            ContentRef::Image(ref data) => data.size,
+        }
+    }
 }
 
 impl Decode for ContentRef {
@@ -134,7 +141,7 @@ impl Metadata {
     {
         if offset.is_negative() {
             // sanity check
-            return Err(DecodeError::Offset(offset).into());
+            return Err(DecodeError::Offset(offset).at(reader).into());
         }
 
         // Get current position
@@ -19,7 +19,7 @@ impl Decode for Property {
         u16::decode(reader)?;
         let num_contents = WzInt::decode(reader)?;
         if num_contents.is_negative() {
-            return Err(DecodeError::Length(*num_contents).into());
+            return Err(DecodeError::Length(*num_contents).at(reader).into());
         }
         let num_contents = *num_contents as usize;
         let mut contents = Vec::with_capacity(num_contents);
@@ -4,16 +4,18 @@
 //! the decoded size? The size should be ignored when parsing this. It is quite annoying.
 
 use crate::error::{DecodeError, Result};
-use crate::io::{xml::writer::ToXml, Decode, Encode, SizeHint, WzRead, WzWrite};
+use crate::io::{xml::writer::{Dialect, ToXml}, Decode, Encode, SizeHint, WzRead, WzWrite};
 use crate::types::{VerboseDebug, WzInt};
-use std::{io, fmt, fs, io::Write, path::Path};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use std::{io, fmt, fs, io::Read, io::Write, path::Path};
 
 mod format;
 mod header;
+mod mp3;
 
 use header::HEADER;
 
-pub use format::AudioFormat;
+pub use format::{AudioFormat, Mp3Format};
 pub use header::{SoundHeader, WavHeader};
 
 /// Sound property found in WZ images
@@ -39,16 +41,20 @@ impl Sound {
         where
         S: AsRef<Path>,
         {
-            let data = fs::read(path)?;
-            let header = SoundHeader::from_slice(&data)?;
-            let data = data.as_slice()[HEADER.len() + 1 + header.as_bytes().len()..].to_vec();
-            Ok(Self {
-                duration,
-                header,
-                data,
-            })
+            Self::from_bytes(&fs::read(path)?, duration)
         }
 
+    /// Constructs a Sound object from the contents of a file written by [`Sound::save_to_file`]
+    pub fn from_bytes(bytes: &[u8], duration: WzInt) -> Result<Self> {
+        let header = SoundHeader::from_slice(bytes)?;
+        let data = bytes[HEADER.len() + 1 + header.as_bytes().len()..].to_vec();
+        Ok(Self {
+            duration,
+            header,
+            data,
+        })
+    }
+
     pub fn duration(&self) -> WzInt {
         self.duration
     }
@@ -61,16 +67,40 @@ impl Sound {
         self.data.as_slice()
     }
 
+    /// Returns a reader over the audio payload, e.g. to feed a decoder without copying the data
+    pub fn stream(&self) -> impl Read + '_ {
+        self.data.as_slice()
+    }
+
+    /// Derives the duration in milliseconds from the audio payload. Useful when the stored
+    /// duration is zero or wrong. MP3 payloads are measured by walking their frame headers and
+    /// PCM payloads from the byte rate.
+    ///
+    /// Returns `None` for other formats or when the payload cannot be measured.
+    pub fn probe_duration(&self) -> Option<WzInt> {
+        let header = WavHeader::from_slice(self.header.as_bytes()).ok()?;
+        let milliseconds = match header.audio_format {
+            AudioFormat::Mp3 => mp3::duration(&self.data)?,
+            AudioFormat::Pcm if header.bytes_per_second > 0 => {
+                (self.data.len() as u64 * 1000 / header.bytes_per_second as u64) as u32
+            }
+            _ => return None,
+        };
+        Some(WzInt::from(milliseconds as i32))
+    }
+
+    /// Returns the header and data in the layout written by [`Sound::save_to_file`]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let bytes = self.header.as_bytes();
+        [HEADER, &[bytes.len() as u8], bytes, &self.data].concat()
+    }
+
     pub fn save_to_file<S>(&self, path: S) -> Result<()>
         where
         S: AsRef<Path>,
         {
-            let bytes = self.header.as_bytes();
             let mut file = fs::File::create(path)?;
-            file.write_all(HEADER)?;
-            file.write_all(&[bytes.len() as u8])?;
-            file.write_all(bytes)?;
-            Ok(file.write_all(&self.data)?)
+            Ok(file.write_all(&self.to_bytes())?)
         }
 }
 
@@ -100,7 +130,7 @@ impl Decode for Sound {
             u8::decode(reader)?; // garbage byte?
             let data_len = WzInt::decode(reader)?;
             if data_len.is_negative() {
-                return Err(DecodeError::Length(*data_len).into());
+                return Err(DecodeError::Length(*data_len).at(reader).into());
             }
             let data_len = *data_len as usize;
             let duration = WzInt::decode(reader)?;
@@ -136,7 +166,7 @@ impl Encode for Sound {
 
 impl SizeHint for Sound {
     fn size_hint(&self) -> u32 {
-        2 + WzInt::from(self.data.len() as i32).size_hint()
+        1 + WzInt::from(self.data.len() as i32).size_hint()
             + self.duration.size_hint()
             + self.header.size_hint()
             + self.data.len() as u32
@@ -151,4 +181,34 @@ impl ToXml for Sound {
     fn attributes(&self, name: &str) -> Vec<(String, String)> {
         vec![(String::from("name"), name.to_string())]
     }
+
+    fn extension(&self) -> Option<&'static str> {
+        Some("wav")
+    }
+
+    fn payload(&self, dialect: Dialect, src: Option<&str>) -> Vec<(String, String)> {
+        let duration = self.duration.to_string();
+        let mut payload = Vec::new();
+        if let Some(src) = src {
+            payload.push((String::from("src"), src.to_string()));
+        }
+        match (dialect, src) {
+            (Dialect::Native, Some(_)) => payload.push((String::from("duration"), duration)),
+            (Dialect::Native, None) => payload.extend([
+                (String::from("duration"), duration),
+                (String::from("basedata"), STANDARD.encode(self.to_bytes())),
+            ]),
+            (_, Some(_)) => payload.push((String::from("length"), duration)),
+            (_, None) => {
+                let header = self.header.as_bytes();
+                let basehead = [HEADER, &[header.len() as u8], header].concat();
+                payload.extend([
+                    (String::from("length"), duration),
+                    (String::from("basehead"), STANDARD.encode(basehead)),
+                    (String::from("basedata"), STANDARD.encode(&self.data)),
+                ]);
+            }
+        }
+        payload
+    }
 }
@@ -1,37 +1,147 @@
 //! Audio Formats
 
 use crate::{
-    error::Result,
+    error::{Result, SoundError},
     io::{Decode, Encode, SizeHint, WzRead, WzWrite},
 };
 
+/// `wFormatTag` of a WAVEFORMATEX header, i.e. the codec of the sound data
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum AudioFormat {
+    /// Uncompressed PCM (`0x0001`)
     Pcm,
+
+    /// MPEG layer III (`0x0055`). This is what nearly every sound in the client uses.
     Mp3,
+
+    /// Windows Media Audio 9 (`0x0161`)
+    Wma,
+
+    /// Any other format tag. The header is kept intact so it can be written back as is.
     Unknown(u16),
 }
 
+impl AudioFormat {
+    /// Format tag of PCM
+    pub const PCM: u16 = 0x0001;
+
+    /// Format tag of MPEG layer III
+    pub const MP3: u16 = 0x0055;
+
+    /// Format tag of Windows Media Audio 9
+    pub const WMA: u16 = 0x0161;
+}
+
 impl From<AudioFormat> for u16 {
     fn from(other: AudioFormat) -> Self {
         match other {
-            AudioFormat::Pcm => 1,
-            AudioFormat::Mp3 => 85,
+            AudioFormat::Pcm => AudioFormat::PCM,
+            AudioFormat::Mp3 => AudioFormat::MP3,
+            AudioFormat::Wma => AudioFormat::WMA,
             AudioFormat::Unknown(t) => t,
         }
     }
 }
 
+/// Maps a format tag to its variant. Tags without a variant become [`AudioFormat::Unknown`].
 impl From<u16> for AudioFormat {
     fn from(other: u16) -> Self {
         match other {
-            1 => Self::Pcm,
-            85 => Self::Mp3,
+            Self::PCM => Self::Pcm,
+            Self::MP3 => Self::Mp3,
+            Self::WMA => Self::Wma,
             t => Self::Unknown(t),
         }
     }
 }
 
+/// Extra bytes of an MP3 WAVEFORMATEX header (MPEGLAYER3WAVEFORMAT).
+///
+/// Example:
+///
+/// ```
+/// use wz::types::{Mp3Format, WavHeader};
+///
+/// // 128 kbps stereo at 44.1 kHz
+/// let header = WavHeader::mp3(2, 44100, 128_000);
+/// let format = Mp3Format::from_bytes(&header.extra).unwrap();
+/// assert_eq!(format.block_size, 417);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mp3Format {
+    /// `wID`, always 1 (MPEGLAYER3_ID_MPEG)
+    pub id: u16,
+
+    /// `fdwFlags`, the padding mode of the frames
+    pub flags: u32,
+
+    /// `nBlockSize`, the size of a frame in bytes
+    pub block_size: u16,
+
+    /// `nFramesPerBlock`, always 1
+    pub frames_per_block: u16,
+
+    /// `nCodecDelay`, the number of samples the decoder delays the output by
+    pub codec_delay: u16,
+}
+
+impl Mp3Format {
+    /// Length of the extra bytes
+    pub const LENGTH: usize = 12;
+
+    /// `wID` of MPEG audio
+    pub const ID_MPEG: u16 = 1;
+
+    /// `fdwFlags` when the padding of every frame follows the ISO standard
+    pub const FLAG_PADDING_ISO: u32 = 0;
+
+    /// `fdwFlags` when every frame is padded
+    pub const FLAG_PADDING_ON: u32 = 1;
+
+    /// `fdwFlags` when no frame is padded
+    pub const FLAG_PADDING_OFF: u32 = 2;
+
+    /// Codec delay written by the Fraunhofer encoder, which the client sounds were made with
+    pub const CODEC_DELAY: u16 = 1393;
+
+    /// Describes MPEG-1 layer III frames at `sampling_rate` and `bitrate` in bits per second
+    pub fn new(sampling_rate: u32, bitrate: u32) -> Self {
+        Self {
+            id: Self::ID_MPEG,
+            flags: Self::FLAG_PADDING_ISO,
+            block_size: (144 * bitrate / sampling_rate.max(1)) as u16,
+            frames_per_block: 1,
+            codec_delay: Self::CODEC_DELAY,
+        }
+    }
+
+    /// Parses the extra bytes of an MP3 header
+    pub fn from_bytes(extra: &[u8]) -> Result<Self> {
+        if extra.len() != Self::LENGTH {
+            return Err(SoundError::ExtraLength(extra.len()).into());
+        }
+        let u16_at = |i: usize| u16::from_le_bytes([extra[i], extra[i + 1]]);
+        Ok(Self {
+            id: u16_at(0),
+            flags: u32::from_le_bytes([extra[2], extra[3], extra[4], extra[5]]),
+            block_size: u16_at(6),
+            frames_per_block: u16_at(8),
+            codec_delay: u16_at(10),
+        })
+    }
+
+    /// Returns the extra bytes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut extra = Vec::with_capacity(Self::LENGTH);
+        extra.extend_from_slice(&self.id.to_le_bytes());
+        extra.extend_from_slice(&self.flags.to_le_bytes());
+        extra.extend_from_slice(&self.block_size.to_le_bytes());
+        extra.extend_from_slice(&self.frames_per_block.to_le_bytes());
+        extra.extend_from_slice(&self.codec_delay.to_le_bytes());
+        extra
+    }
+}
+
 impl Decode for AudioFormat {
     fn decode<R>(reader: &mut R) -> Result<Self>
     where
@@ -46,11 +156,7 @@ impl Encode for AudioFormat {
     where
         W: WzWrite + ?Sized,
     {
-        match self {
-            Self::Pcm => 1u16.encode(writer),
-            Self::Mp3 => 85u16.encode(writer),
-            Self::Unknown(b) => b.encode(writer),
-        }
+        u16::from(*self).encode(writer)
     }
 }
 
@@ -59,3 +165,32 @@ impl SizeHint for AudioFormat {
         2
     }
 }
+
+#[cfg(test)]
+mod tests {
+
+    use crate::types::sound::{AudioFormat, Mp3Format, SoundHeader, WavHeader};
+
+    #[test]
+    fn mp3_header() {
+        for tag in [0x0001, 0x0055, 0x0161, 0x0002] {
+            assert_eq!(u16::from(AudioFormat::from(tag)), tag);
+        }
+        assert_eq!(AudioFormat::from(0x0161), AudioFormat::Wma);
+
+        let header = WavHeader::mp3(2, 44100, 128_000);
+        let bytes = SoundHeader::from(header.clone());
+        assert_eq!(bytes.as_bytes().len(), 18 + Mp3Format::LENGTH);
+        let parsed = WavHeader::from_slice(bytes.as_bytes()).expect("error parsing header");
+        assert_eq!(parsed, header);
+        let format = parsed
+            .mp3_format()
+            .expect("not an MP3 header")
+            .expect("error parsing extra bytes");
+        assert_eq!(format, Mp3Format::new(44100, 128_000));
+        assert_eq!(format.block_size, 417);
+        assert!(Mp3Format::from_bytes(&format.to_bytes()[1..]).is_err());
+        assert!(WavHeader::pcm(2, 44100, 16).mp3_format().is_none());
+        assert_eq!(WavHeader::pcm(2, 44100, 16).bytes_per_second, 176400);
+    }
+}
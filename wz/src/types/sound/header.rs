@@ -3,7 +3,7 @@
 use crate::{
     error::{Error, Result, SoundError},
     io::{Decode, Encode, SizeHint, WzRead, WzWrite},
-    types::sound::AudioFormat,
+    types::sound::{AudioFormat, Mp3Format},
 };
 use std::fmt;
 
@@ -57,6 +57,7 @@ impl From<WavHeader> for SoundHeader {
         header.extend_from_slice(&other.bytes_per_sample.to_le_bytes());
         header.extend_from_slice(&other.bits_per_sample.to_le_bytes());
         if !other.extra.is_empty() {
+            header.extend_from_slice(&(other.extra.len() as u16).to_le_bytes());
             header.append(&mut other.extra);
         }
         Self { header }
@@ -129,6 +130,42 @@ pub struct WavHeader {
 }
 
 impl WavHeader {
+    /// Creates the header of uncompressed PCM data
+    pub fn pcm(channel_count: u16, sampling_rate: u32, bits_per_sample: u16) -> Self {
+        let bytes_per_sample = channel_count * bits_per_sample / 8;
+        Self {
+            audio_format: AudioFormat::Pcm,
+            channel_count,
+            sampling_rate,
+            bytes_per_second: sampling_rate * bytes_per_sample as u32,
+            bytes_per_sample,
+            bits_per_sample,
+            extra: Vec::new(),
+        }
+    }
+
+    /// Creates the header of MPEG-1 layer III data encoded at a constant `bitrate` in bits per
+    /// second, including the [`Mp3Format`] extra bytes the client expects
+    pub fn mp3(channel_count: u16, sampling_rate: u32, bitrate: u32) -> Self {
+        Self {
+            audio_format: AudioFormat::Mp3,
+            channel_count,
+            sampling_rate,
+            bytes_per_second: bitrate / 8,
+            bytes_per_sample: 1,
+            bits_per_sample: 0,
+            extra: Mp3Format::new(sampling_rate, bitrate).to_bytes(),
+        }
+    }
+
+    /// Parses the extra bytes of an MP3 header. Returns `None` for other formats.
+    pub fn mp3_format(&self) -> Option<Result<Mp3Format>> {
+        match self.audio_format {
+            AudioFormat::Mp3 => Some(Mp3Format::from_bytes(&self.extra)),
+            _ => None,
+        }
+    }
+
     pub fn from_slice(header: &[u8]) -> Result<Self> {
         let audio_format = AudioFormat::from(u16::from_le_bytes([header[0], header[1]]));
         let channel_count = u16::from_le_bytes([header[2], header[3]]);
@@ -145,8 +182,8 @@ impl WavHeader {
             (0, Vec::new())
         };
 
-        // Sanity check
-        if header.len() != 18 + extra_len {
+        // Sanity check. Headers without the extra length field are 16 bytes long
+        if header.len() > 16 && header.len() != 18 + extra_len {
             return Err(SoundError::ExtraLength(extra_len).into());
         }
 
@@ -183,6 +220,7 @@ impl Encode for WavHeader {
         self.bytes_per_sample.encode(writer)?;
         self.bits_per_sample.encode(writer)?;
         if !self.extra.is_empty() {
+            (self.extra.len() as u16).encode(writer)?;
             writer.write_all(&self.extra)?;
         }
         Ok(())
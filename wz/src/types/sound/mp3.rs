@@ -0,0 +1,168 @@
+//! MP3 frame parsing
+
+/// Bitrates in kbps indexed by `[table][bitrate index]`. The tables are MPEG-1 layers I, II and
+/// III followed by MPEG-2 layer I and MPEG-2 layers II and III.
+const BITRATES: [[u32; 15]; 5] = [
+    [
+        0, 32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448,
+    ],
+    [
+        0, 32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384,
+    ],
+    [
+        0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+    ],
+    [
+        0, 32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256,
+    ],
+    [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+];
+
+/// Sampling rates of MPEG-1. MPEG-2 halves them and MPEG-2.5 quarters them.
+const SAMPLING_RATES: [u32; 3] = [44100, 48000, 32000];
+
+/// Parsed MP3 frame header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Frame {
+    length: usize,
+    samples: u32,
+    sampling_rate: u32,
+}
+
+impl Frame {
+    fn parse(header: [u8; 4]) -> Option<Self> {
+        let header = u32::from_be_bytes(header);
+        if header >> 21 != 0x7ff {
+            return None;
+        }
+        // 0 is MPEG-2.5, 2 is MPEG-2 and 3 is MPEG-1
+        let version = (header >> 19) & 3;
+        // 1 is layer III, 2 is layer II and 3 is layer I
+        let layer = (header >> 17) & 3;
+        let bitrate = ((header >> 12) & 0xf) as usize;
+        let sampling_rate = ((header >> 10) & 3) as usize;
+        let padding = (header >> 9) & 1;
+        if version == 1 || layer == 0 || bitrate == 0 || bitrate == 15 || sampling_rate == 3 {
+            return None;
+        }
+
+        let table = match (version, layer) {
+            (3, layer) => 3 - layer as usize,
+            (_, 3) => 3,
+            _ => 4,
+        };
+        let bitrate = BITRATES[table][bitrate] * 1000;
+        let sampling_rate = SAMPLING_RATES[sampling_rate]
+            >> match version {
+                3 => 0,
+                2 => 1,
+                _ => 2,
+            };
+        let (samples, length) = match (version, layer) {
+            (_, 3) => (384, (12 * bitrate / sampling_rate + padding) * 4),
+            (3, _) | (_, 2) => (1152, 144 * bitrate / sampling_rate + padding),
+            _ => (576, 72 * bitrate / sampling_rate + padding),
+        };
+        Some(Self {
+            length: length as usize,
+            samples,
+            sampling_rate,
+        })
+    }
+}
+
+/// Returns the duration of the MP3 `data` in milliseconds by adding up the samples of every frame.
+/// An ID3v2 tag at the start is skipped and garbage between frames is stepped over.
+///
+/// Returns `None` when no frame is found.
+pub(crate) fn duration(data: &[u8]) -> Option<u32> {
+    let mut position = id3_length(data);
+    let mut seconds = 0.0;
+    let mut frames = 0;
+    while let Some(header) = data.get(position..position + 4) {
+        // The last frame may be cut short, it is counted anyway
+        match Frame::parse(header.try_into().expect("slice is 4 bytes")) {
+            Some(frame) => {
+                seconds += frame.samples as f64 / frame.sampling_rate as f64;
+                frames += 1;
+                position += frame.length;
+            }
+            None => position += 1,
+        }
+    }
+    match frames {
+        0 => None,
+        _ => Some((seconds * 1000.0).round() as u32),
+    }
+}
+
+/// Returns the length of the ID3v2 tag at the start of `data`, or 0 if there is none
+fn id3_length(data: &[u8]) -> usize {
+    match data.get(0..10) {
+        Some(tag) if tag.starts_with(b"ID3") => {
+            let size = tag[6..10]
+                .iter()
+                .fold(0usize, |size, b| (size << 7) | (*b & 0x7f) as usize);
+            // A footer is present when bit 4 of the flags is set
+            let footer = if tag[5] & 0x10 != 0 { 10 } else { 0 };
+            10 + size + footer
+        }
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::types::sound::{mp3, AudioFormat, Sound, SoundHeader, WavHeader};
+    use crate::types::WzInt;
+    use std::io::Read;
+
+    fn frames(header: [u8; 4], length: usize, count: usize) -> Vec<u8> {
+        let mut frame = header.to_vec();
+        frame.resize(length, 0);
+        frame.repeat(count)
+    }
+
+    fn sound(audio_format: AudioFormat, data: Vec<u8>) -> Sound {
+        let header = SoundHeader::from(WavHeader {
+            audio_format,
+            channel_count: 2,
+            sampling_rate: 44100,
+            bytes_per_second: 176400,
+            bytes_per_sample: 4,
+            bits_per_sample: 16,
+            extra: Vec::new(),
+        });
+        Sound::new(WzInt::from(0), header, data)
+    }
+
+    #[test]
+    fn probe_duration() {
+        // ID3v2 tag, garbage, then 100 MPEG-1 layer III frames at 128 kbps and 44.1 kHz
+        let mut data = b"ID3\x04\x00\x00\x00\x00\x00\x05tag..".to_vec();
+        data.extend([0xff, 0x00, 0x12]);
+        data.extend(frames([0xff, 0xfb, 0x90, 0x00], 417, 100));
+        assert_eq!(mp3::duration(&data), Some(2612));
+
+        // MPEG-2 layer III at 64 kbps and 22.05 kHz has 576 samples per frame
+        let data = frames([0xff, 0xf3, 0x80, 0x00], 208, 50);
+        assert_eq!(mp3::duration(&data), Some(1306));
+        assert_eq!(mp3::duration(&[0u8; 64]), None);
+
+        let mp3 = sound(AudioFormat::Mp3, frames([0xff, 0xfb, 0x90, 0x00], 417, 100));
+        assert_eq!(mp3.probe_duration(), Some(WzInt::from(2612)));
+        let pcm = sound(AudioFormat::Pcm, vec![0u8; 88200]);
+        assert_eq!(pcm.probe_duration(), Some(WzInt::from(500)));
+        assert_eq!(
+            sound(AudioFormat::Unknown(2), Vec::new()).probe_duration(),
+            None
+        );
+
+        let mut streamed = Vec::new();
+        pcm.stream()
+            .read_to_end(&mut streamed)
+            .expect("error streaming");
+        assert_eq!(streamed, pcm.data());
+    }
+}
@@ -2,104 +2,241 @@
 
 use crate::error::{DecodeError, Result};
 use crate::io::{Decode, Encode, SizeHint, WzRead, WzWrite};
-use crate::types::{macros, VerboseDebug};
+use crate::types::{macros, UolString, VerboseDebug};
 use std::io;
 
 macros::impl_debug!(&str);
 macros::impl_debug!(String);
 
-impl Encode for &str {
-    fn encode<W>(&self, writer: &mut W) -> Result<()>
-    where
-        W: WzWrite + ?Sized,
-    {
-        // If length is 0 just write 0 and be done with it
-        if self.len() == 0 {
-            return writer.write_byte(0);
-        }
+/// How a string was stored, kept so it can be encoded back to the same bytes
+///
+/// Strings are normally stored as UTF-8 when they are ASCII, as UTF-16 otherwise, and with a one
+/// byte length when it fits. Some images store ASCII as UTF-16, other text in the one byte form
+/// or use the long length marker regardless. Decoded strings record how they were stored and the
+/// default form is the normal one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct StringForm {
+    /// How the characters are stored
+    pub encoding: StringEncoding,
 
-        // If everything is ASCII, encode as UTF-8, else Unicode
-        if self.is_ascii() {
-            let length = self.len() as i32;
-            // Write the length
-            // length CAN equal i8::MAX here as the 2s compliment is not i8::MIN
-            if length > (i8::MAX as i32) {
-                writer.write_byte(i8::MIN as u8)?;
-                length.encode(writer)?;
-            } else {
-                writer.write_byte((-length) as u8)?;
-            }
-            // Write the string
-            writer.write_utf8_bytes(self.as_bytes())
-        } else {
-            let bytes = self.encode_utf16().collect::<Vec<u16>>();
-            let length = bytes.len() as i32;
-            // Write the length
-            // If lenth is equal to i8::MAX it will be treated as a long-length marker
-            if length >= (i8::MAX as i32) {
-                writer.write_byte(i8::MAX as u8)?;
-                length.encode(writer)?;
-            } else {
-                writer.write_byte(length as u8)?;
-            }
-            // Write the string
-            writer.write_unicode_bytes(&bytes)
+    /// Length stored after the long length marker although it fits in one byte
+    pub long_length: bool,
+}
+
+/// How the characters of a string are stored
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum StringEncoding {
+    /// UTF-8 when the string is ASCII and UTF-16 otherwise
+    #[default]
+    Auto,
+
+    /// One byte form, stored with a negative length, decoded as UTF-8
+    Utf8,
+
+    /// UTF-16, stored with a positive length
+    Unicode,
+}
+
+impl StringEncoding {
+    /// Returns if `string` is stored as UTF-8
+    fn is_utf8(&self, string: &str) -> bool {
+        match self {
+            Self::Auto => string.is_ascii(),
+            Self::Utf8 => true,
+            Self::Unicode => false,
         }
     }
 }
 
-impl SizeHint for &str {
-    #[inline]
-    fn size_hint(&self) -> u32 {
-        let length = self.len() as u32;
+/// How strings that are not valid UTF-8 or UTF-16 are decoded, set with
+/// [`WzRead::set_string_decoding`]
+///
+/// Such strings usually come from a wrong key or a legacy encoding.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StringDecoding {
+    /// Invalid sequences are replaced with U+FFFD and the stored bytes are kept, see
+    /// [`UolString::raw_bytes`]
+    #[default]
+    Lossy,
+
+    /// Invalid strings are decoding errors
+    Strict,
+}
+
+/// Stored contents of a string that could not be decoded, once unmasked
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RawString {
+    /// Bytes of a string stored as UTF-8
+    Utf8(Vec<u8>),
 
+    /// Code units of a string stored as UTF-16
+    Unicode(Vec<u16>),
+}
+
+/// Encodes `string` in the given form. Strings that could not be decoded are encoded from `raw`.
+pub(crate) fn encode_string<W>(
+    string: &str,
+    form: StringForm,
+    raw: Option<&RawString>,
+    writer: &mut W,
+) -> Result<()>
+where
+    W: WzWrite + ?Sized,
+{
+    match raw {
+        Some(RawString::Utf8(bytes)) => encode_utf8(bytes, form, writer),
+        Some(RawString::Unicode(units)) => encode_unicode(units, form, writer),
         // If length is 0 just write 0 and be done with it
-        if length == 0 {
-            return 1;
-        }
+        None if string.is_empty() => writer.write_byte(0),
+        // If everything is ASCII, encode as UTF-8, else Unicode, unless it was stored otherwise
+        None if form.encoding.is_utf8(string) => encode_utf8(string.as_bytes(), form, writer),
+        None => encode_unicode(&string.encode_utf16().collect::<Vec<u16>>(), form, writer),
+    }
+}
+
+fn encode_utf8<W>(bytes: &[u8], form: StringForm, writer: &mut W) -> Result<()>
+where
+    W: WzWrite + ?Sized,
+{
+    let length = bytes.len() as i32;
+    // Write the length
+    // length CAN equal i8::MAX here as the 2s compliment is not i8::MIN
+    if length > (i8::MAX as i32) || form.long_length {
+        writer.write_byte(i8::MIN as u8)?;
+        length.encode(writer)?;
+    } else {
+        writer.write_byte((-length) as u8)?;
+    }
+    // Write the string
+    writer.write_utf8_bytes(bytes)
+}
+
+fn encode_unicode<W>(units: &[u16], form: StringForm, writer: &mut W) -> Result<()>
+where
+    W: WzWrite + ?Sized,
+{
+    let length = units.len() as i32;
+    // Write the length
+    // If lenth is equal to i8::MAX it will be treated as a long-length marker
+    if length >= (i8::MAX as i32) || form.long_length {
+        writer.write_byte(i8::MAX as u8)?;
+        length.encode(writer)?;
+    } else {
+        writer.write_byte(length as u8)?;
+    }
+    // Write the string
+    writer.write_unicode_bytes(units)
+}
 
-        // If everything is ASCII, encode as UTF-8, else Unicode
-        if self.is_ascii() {
+/// Returns the encoded size of `string` in the given form, or of `raw` if it is set
+pub(crate) fn string_size_hint(string: &str, form: StringForm, raw: Option<&RawString>) -> u32 {
+    // The marker and length take 5 bytes, or 1 if the length fits
+    let prefix = |long: bool| match long || form.long_length {
+        true => 5,
+        false => 1,
+    };
+    match raw {
+        Some(RawString::Utf8(bytes)) => {
+            let len = bytes.len() as u32;
+            prefix(len > (i8::MAX as u32)) + len
+        }
+        Some(RawString::Unicode(units)) => {
+            let len = units.len() as u32;
+            prefix(len >= (i8::MAX as u32)) + len * 2
+        }
+        // If length is 0 just write 0 and be done with it
+        None if string.is_empty() => 1,
+        // If everything is ASCII, encode as UTF-8, else Unicode, unless it was stored otherwise
+        None if form.encoding.is_utf8(string) => {
             // length CAN equal i8::MAX here as the 2s compliment is not i8::MIN
-            if length > (i8::MAX as u32) {
-                5 + length
-            } else {
-                1 + length
-            }
-        } else {
+            let len = string.len() as u32;
+            prefix(len > (i8::MAX as u32)) + len
+        }
+        None => {
+            // Unicode lengths count UTF-16 code units, not UTF-8 bytes
+            let len = string.encode_utf16().count() as u32;
             // If lenth is equal to i8::MAX it will be treated as a long-length marker
-            if length >= (i8::MAX as u32) {
-                5 + (length * 2)
-            } else {
-                1 + (length * 2)
-            }
+            prefix(len >= (i8::MAX as u32)) + len * 2
         }
     }
 }
 
+/// Decodes a string along with the form it was stored in. Strings that are not valid UTF-8 or
+/// UTF-16 are replaced or rejected depending on [`WzRead::string_decoding`].
+pub(crate) fn decode_string<R>(reader: &mut R) -> Result<UolString>
+where
+    R: WzRead + ?Sized,
+{
+    let check = i8::decode(reader)?;
+    let (length, marker) = match check {
+        i8::MIN | i8::MAX => (i32::decode(reader)?, true),
+        0 => return Ok(UolString::from("")),
+        _ => ((check as i32).wrapping_abs(), false),
+    };
+    // Sanity check
+    if length <= 0 {
+        return Err(DecodeError::Length(length).at(reader).into());
+    }
+    if check < 0 {
+        // UTF-8. Valid strings take over the buffer instead of being copied.
+        let form = StringForm {
+            encoding: StringEncoding::Utf8,
+            long_length: marker && length <= (i8::MAX as i32),
+        };
+        match String::from_utf8(reader.read_utf8_bytes(length as usize)?) {
+            Ok(val) => Ok(UolString::decoded(val, form, None)),
+            Err(e) => match reader.string_decoding() {
+                StringDecoding::Lossy => Ok(UolString::decoded(
+                    String::from_utf8_lossy(e.as_bytes()).into_owned(),
+                    form,
+                    Some(RawString::Utf8(e.into_bytes())),
+                )),
+                StringDecoding::Strict => Err(DecodeError::from(e).at(reader).into()),
+            },
+        }
+    } else {
+        // Unicode
+        let units = reader.read_unicode_bytes(length as usize)?;
+        let form = StringForm {
+            encoding: StringEncoding::Unicode,
+            long_length: marker && length < (i8::MAX as i32),
+        };
+        match String::from_utf16(&units) {
+            Ok(val) => Ok(UolString::decoded(val, form, None)),
+            Err(e) => match reader.string_decoding() {
+                StringDecoding::Lossy => Ok(UolString::decoded(
+                    String::from_utf16_lossy(&units),
+                    form,
+                    Some(RawString::Unicode(units)),
+                )),
+                StringDecoding::Strict => Err(DecodeError::from(e).at(reader).into()),
+            },
+        }
+    }
+}
+
+impl Encode for &str {
+    fn encode<W>(&self, writer: &mut W) -> Result<()>
+    where
+        W: WzWrite + ?Sized,
+    {
+        encode_string(self, StringForm::default(), None, writer)
+    }
+}
+
+impl SizeHint for &str {
+    #[inline]
+    fn size_hint(&self) -> u32 {
+        string_size_hint(self, StringForm::default(), None)
+    }
+}
+
 impl Decode for String {
     fn decode<R>(reader: &mut R) -> Result<Self>
     where
         R: WzRead + ?Sized,
     {
-        let check = i8::decode(reader)?;
-        let length = match check {
-            i8::MIN | i8::MAX => i32::decode(reader)?,
-            0 => return Ok(String::from("")),
-            _ => (check as i32).wrapping_abs(),
-        };
-        // Sanity check
-        if length <= 0 {
-            return Err(DecodeError::Length(length).into());
-        }
-        let val = if check < 0 {
-            // UTF-8
-            String::from_utf8_lossy(reader.read_utf8_bytes(length as usize)?.as_slice()).into()
-        } else {
-            // Unicode
-            String::from_utf16_lossy(reader.read_unicode_bytes(length as usize)?.as_slice())
-        };
-        Ok(val)
+        Ok(decode_string(reader)?.into_string())
     }
 }
 
@@ -118,3 +255,136 @@ impl SizeHint for String {
         self.as_str().size_hint()
     }
 }
+
+#[cfg(test)]
+mod tests {
+
+    use crate::io::{Decode, Encode, SizeHint, WzRead, WzReader, WzWrite, WzWriter};
+    use crate::types::{RawString, StringDecoding, StringEncoding, StringForm, UolString};
+    use std::io::Cursor;
+
+    #[test]
+    fn decode_strings() {
+        let mut writer = WzWriter::unencrypted(0, 0, Cursor::new(Vec::new()));
+        for s in ["", "info", "ÃŸnail", &"x".repeat(200)] {
+            s.encode(&mut writer).expect("error encoding");
+        }
+        // Bytes that are not valid UTF-8 are replaced
+        writer.write_byte(-2i8 as u8).expect("error writing length");
+        writer
+            .write_utf8_bytes(&[b'a', 0xe9])
+            .expect("error writing string");
+        let data = writer.into_inner().into_inner();
+
+        let mut reader = WzReader::unencrypted(0, 0, Cursor::new(data));
+        for expected in ["", "info", "ÃŸnail", &"x".repeat(200), "a\u{fffd}"] {
+            assert_eq!(
+                String::decode(&mut reader).expect("error decoding"),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn string_forms() {
+        // "info" as UTF-16, "Wp" with a long length and "ÃŸnail" in the one byte form, as some
+        // images store them
+        let form = |encoding, long_length| StringForm {
+            encoding,
+            long_length,
+        };
+        let strings = [
+            ("info", form(StringEncoding::Unicode, false)),
+            ("Wp", form(StringEncoding::Utf8, true)),
+            ("ok", StringForm::default()),
+            ("ÃŸnail", form(StringEncoding::Utf8, false)),
+        ];
+        let mut writer = WzWriter::unencrypted(0, 0, Cursor::new(Vec::new()));
+        for (s, form) in strings {
+            let mut s = UolString::from(s);
+            s.set_form(form);
+            s.encode(&mut writer).expect("error encoding");
+        }
+        let data = writer.into_inner().into_inner();
+        assert_eq!(data.len(), (2 + 8) + (2 + 4 + 2) + (2 + 2) + (2 + 8));
+        assert_eq!(&data[..2], [0x00, 0x04]);
+        assert_eq!(&data[10..16], [0x00, 0x80, 0x02, 0x00, 0x00, 0x00]);
+        assert_eq!(&data[18..20], [0x00, 0xfe]);
+        assert_eq!(&data[22..24], [0x00, 0xf8]);
+        // Without a form, non-ASCII text is UTF-16
+        assert_eq!(UolString::from("ÃŸnail").size_hint(), 2 + 6 * 2);
+
+        // Decoded strings record how they were stored, even when it is the normal form
+        let mut reader = WzReader::unencrypted(0, 0, Cursor::new(data.clone()));
+        let decoded = (0..4)
+            .map(|_| UolString::decode(&mut reader).expect("error decoding"))
+            .collect::<Vec<UolString>>();
+        assert_eq!(decoded, strings.map(|(s, _)| UolString::from(s)));
+        assert_eq!(
+            decoded.iter().map(|s| s.form()).collect::<Vec<_>>(),
+            [
+                form(StringEncoding::Unicode, false),
+                form(StringEncoding::Utf8, true),
+                form(StringEncoding::Utf8, false),
+                form(StringEncoding::Utf8, false),
+            ]
+        );
+
+        let mut writer = WzWriter::unencrypted(0, 0, Cursor::new(Vec::new()));
+        for s in &decoded {
+            s.encode(&mut writer).expect("error encoding");
+        }
+        assert_eq!(
+            decoded.iter().map(|s| s.size_hint()).sum::<u32>(),
+            data.len() as u32
+        );
+        assert_eq!(writer.into_inner().into_inner(), data);
+    }
+
+    #[test]
+    fn invalid_strings() {
+        // UOL strings are prefixed with 0 when they are stored inline
+        let mut writer = WzWriter::unencrypted(0, 0, Cursor::new(Vec::new()));
+        writer
+            .write_all(&[0, -2i8 as u8])
+            .expect("error writing length");
+        writer
+            .write_utf8_bytes(&[b'a', 0xe9])
+            .expect("error writing string");
+        writer.write_all(&[0, 2]).expect("error writing length");
+        writer
+            .write_unicode_bytes(&[0xd800, b'b' as u16])
+            .expect("error writing string");
+        let data = writer.into_inner().into_inner();
+
+        // Lossy strings keep what was stored and encode back to it
+        let mut reader = WzReader::unencrypted(0, 0, Cursor::new(data.clone()));
+        let utf8 = UolString::decode(&mut reader).expect("error decoding");
+        assert_eq!(utf8, "a\u{fffd}");
+        assert_eq!(utf8.raw_bytes(), Some(&RawString::Utf8(vec![b'a', 0xe9])));
+        let unicode = UolString::decode(&mut reader).expect("error decoding");
+        assert_eq!(unicode, "\u{fffd}b");
+        assert_eq!(
+            unicode.raw_bytes(),
+            Some(&RawString::Unicode(vec![0xd800, b'b' as u16]))
+        );
+        let mut writer = WzWriter::unencrypted(0, 0, Cursor::new(Vec::new()));
+        utf8.encode(&mut writer).expect("error encoding");
+        unicode.encode(&mut writer).expect("error encoding");
+        assert_eq!(utf8.size_hint() + unicode.size_hint(), data.len() as u32);
+        assert_eq!(writer.into_inner().into_inner(), data);
+
+        // Modified strings are encoded from their text
+        let mut modified = utf8.clone();
+        modified.make_ascii_uppercase();
+        assert_eq!(modified.raw_bytes(), None);
+
+        // Strict decoding rejects them
+        let mut reader = WzReader::unencrypted(0, 0, Cursor::new(data));
+        reader.set_string_decoding(StringDecoding::Strict);
+        let error = reader.read_uol_string().expect_err("expected an error");
+        assert!(error.to_string().contains("UTF-8"), "{}", error);
+        let error = reader.read_uol_string().expect_err("expected an error");
+        assert!(error.to_string().contains("Unicode"), "{}", error);
+    }
+}
@@ -2,9 +2,11 @@
 
 use crate::error::Result;
 use crate::io::{xml::writer::ToXml, Decode, Encode, SizeHint, WzRead, WzWrite};
+use crate::types::string::{string_size_hint, RawString, StringForm};
 use crate::types::{macros, VerboseDebug};
 use std::{
-    io,
+    cmp::Ordering,
+    fmt, io,
     ops::{Deref, DerefMut},
 };
 
@@ -14,21 +16,74 @@ use std::{
 /// useful for compressing data. It is not entirely known when they decide to use a reference
 /// instead of re-encoding it. I arbitrarily set this threshold to when the encoded size of the
 /// string is >5 since that seems to match the behavior I've witnessed during decoding.
-#[derive(Clone, Debug, PartialOrd, PartialEq, Ord, Eq)]
-pub struct UolString(String);
+///
+/// The [`StringForm`] the string was decoded with is kept so it is encoded back to the same bytes,
+/// as are the stored contents of strings that could not be decoded. Both are ignored when
+/// comparing strings.
+#[derive(Clone)]
+pub struct UolString(String, StringForm, Option<RawString>);
 
 macros::impl_debug!(UolString);
 
+impl fmt::Debug for UolString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("UolString").field(&self.0).finish()
+    }
+}
+
 impl UolString {
     /// Consumes the UolString and returns the inner String
     pub fn into_string(self) -> String {
         self.0
     }
+
+    /// Returns the form the string is encoded in
+    pub fn form(&self) -> StringForm {
+        self.1
+    }
+
+    /// Sets the form the string is encoded in
+    pub fn set_form(&mut self, form: StringForm) {
+        self.1 = form;
+    }
+
+    /// Returns the stored contents of a string that was not valid UTF-8 or UTF-16 and decoded
+    /// lossily. The string is encoded from them until it is modified.
+    pub fn raw_bytes(&self) -> Option<&RawString> {
+        self.2.as_ref()
+    }
+
+    pub(crate) fn decoded(string: String, form: StringForm, raw: Option<RawString>) -> Self {
+        Self(string, form, raw)
+    }
 }
 
 impl From<String> for UolString {
     fn from(other: String) -> Self {
-        Self(other)
+        Self(other, StringForm::default(), None)
+    }
+}
+
+impl PartialEq for UolString {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl Eq for UolString {}
+
+impl PartialOrd for UolString {
+    #[inline]
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for UolString {
+    #[inline]
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.cmp(&other.0)
     }
 }
 
@@ -41,6 +96,7 @@ impl Deref for UolString {
 
 impl DerefMut for UolString {
     fn deref_mut(&mut self) -> &mut Self::Target {
+        self.2 = None;
         self.0.as_mut()
     }
 }
@@ -59,7 +115,7 @@ impl AsMut<str> for UolString {
 
 impl From<&str> for UolString {
     fn from(other: &str) -> Self {
-        Self(String::from(other))
+        Self(String::from(other), StringForm::default(), None)
     }
 }
 
@@ -82,7 +138,7 @@ impl Decode for UolString {
     where
         R: WzRead + ?Sized,
     {
-        Ok(Self(reader.read_uol_string()?))
+        reader.read_uol_string_exact()
     }
 }
 
@@ -91,14 +147,14 @@ impl Encode for UolString {
     where
         W: WzWrite + ?Sized,
     {
-        writer.write_uol_string(&self.0)
+        writer.write_uol_string_exact(self)
     }
 }
 
 impl SizeHint for UolString {
     #[inline]
     fn size_hint(&self) -> u32 {
-        1 + self.0.size_hint()
+        1 + string_size_hint(&self.0, self.1, self.2.as_ref())
     }
 }
 
@@ -207,7 +263,7 @@ impl Encode for UolObject {
         W: WzWrite + ?Sized,
     {
         0u8.encode(writer)?;
-        writer.write_uol_string(&self.uri)
+        self.uri.encode(writer)
     }
 }
 
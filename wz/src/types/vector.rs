@@ -1,14 +1,20 @@
 //! Parsed Vector type
 
-use crate::error::Result;
+use crate::error::{ImageError, Result};
 use crate::io::{xml::writer::ToXml, Decode, Encode, SizeHint, WzRead, WzWrite};
-use crate::types::{macros, VerboseDebug, WzInt};
-use std::io;
+use crate::map::Map;
+use crate::types::{macros, Property, VerboseDebug, WzInt};
+use std::{
+    io,
+    ops::{Add, Neg, Sub},
+    path::Path,
+};
 
 /// Vector property found in WZ images.
 ///
 /// This is just a `(x, y)` coordinate. Typically used in Canvas objects.
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Vector {
     pub x: WzInt,
     pub y: WzInt,
@@ -20,6 +26,71 @@ impl Vector {
     pub fn new(x: WzInt, y: WzInt) -> Self {
         Self { x, y }
     }
+
+    /// Multiplies both coordinates by `factor`
+    pub fn scale(self, factor: i32) -> Self {
+        Self::from((*self.x * factor, *self.y * factor))
+    }
+}
+
+impl Add for Vector {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self::from((*self.x + *other.x, *self.y + *other.y))
+    }
+}
+
+impl Sub for Vector {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Self::from((*self.x - *other.x, *self.y - *other.y))
+    }
+}
+
+impl Neg for Vector {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Self::from((-*self.x, -*self.y))
+    }
+}
+
+impl From<(i32, i32)> for Vector {
+    fn from((x, y): (i32, i32)) -> Self {
+        Self::new(WzInt::from(x), WzInt::from(y))
+    }
+}
+
+impl From<Vector> for (i32, i32) {
+    fn from(other: Vector) -> Self {
+        (*other.x, *other.y)
+    }
+}
+
+impl Map<Property> {
+    /// Returns the vectors of the [`Property::Convex`] at `path` in order, e.g. the points of a
+    /// foothold or hitbox. Errors when the node is not a convex or holds anything but vectors.
+    pub fn convex<S>(&self, path: S) -> Result<Vec<Vector>>
+    where
+        S: AsRef<Path>,
+    {
+        let cursor = self.cursor_at(path.as_ref())?;
+        if !matches!(cursor.get(), Property::Convex) {
+            return Err(ImageError::Path(cursor.pwd()).into());
+        }
+        let mut vectors = cursor
+            .list()
+            .zip(cursor.children())
+            .map(|(name, property)| match (name.parse::<usize>(), property) {
+                (Ok(index), Property::Vector(v)) => Ok((index, *v)),
+                _ => Err(ImageError::Property(format!("{}/{}", cursor.pwd(), name))),
+            })
+            .collect::<std::result::Result<Vec<(usize, Vector)>, ImageError>>()?;
+        vectors.sort_by_key(|(index, _)| *index);
+        Ok(vectors.into_iter().map(|(_, v)| v).collect())
+    }
 }
 
 impl Decode for Vector {
@@ -63,3 +134,54 @@ impl ToXml for Vector {
         ]
     }
 }
+
+#[cfg(test)]
+mod tests {
+
+    use crate::map::Map;
+    use crate::types::{Property, Vector, WzInt};
+
+    #[test]
+    fn vector_math() {
+        let a = Vector::from((3, -4));
+        let b = Vector::new(WzInt::from(-1), WzInt::from(10));
+        assert_eq!(a + b, Vector::from((2, 6)));
+        assert_eq!(a - b, Vector::from((4, -14)));
+        assert_eq!(-a, Vector::from((-3, 4)));
+        assert_eq!(a.scale(-2), Vector::from((-6, 8)));
+        assert_eq!(<(i32, i32)>::from(b), (-1, 10));
+    }
+
+    #[test]
+    fn convex_vectors() {
+        let mut map = Map::new(String::from("test.img"), Property::ImgDir);
+        let mut cursor = map.cursor_mut();
+        cursor
+            .create(String::from("foothold"), Property::Convex)
+            .unwrap()
+            .move_to("foothold")
+            .unwrap();
+        // Created out of order on purpose
+        for i in [1, 0, 10, 2] {
+            cursor
+                .create(i.to_string(), Property::Vector(Vector::from((i, -i))))
+                .unwrap();
+        }
+        let vectors = map
+            .convex("test.img/foothold")
+            .expect("error reading convex");
+        let expected: Vec<Vector> = [0, 1, 2, 10]
+            .into_iter()
+            .map(|i| Vector::from((i, -i)))
+            .collect();
+        assert_eq!(vectors, expected);
+
+        map.cursor_mut_at("test.img/foothold")
+            .unwrap()
+            .create(String::from("name"), Property::Int(WzInt::from(1)))
+            .unwrap();
+        assert!(map.convex("test.img/foothold").is_err());
+        assert!(map.convex("test.img").is_err());
+        assert!(map.convex("test.img/missing").is_err());
+    }
+}
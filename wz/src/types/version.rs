@@ -0,0 +1,121 @@
+//! WZ Version Hash
+
+use crate::error::Result;
+use crate::io::{Decode, Encode, SizeHint, WzRead, WzWrite};
+use crypto::checksum;
+use std::{collections::HashMap, sync::OnceLock};
+
+/// Candidate versions of every version hash, computed on first use
+static VERSIONS: OnceLock<HashMap<u16, Vec<(u16, u32)>>> = OnceLock::new();
+
+/// Encrypted version stored in the header of 32-bit archives. It is not really encrypted since it
+/// is irreversible. More like a checksum or non-cryptographic hash of the version number.
+///
+/// Hashes only ever use the lower byte, which is how the headers of 64-bit archives, which have no
+/// hash, are told apart.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct VersionHash(u16);
+
+impl VersionHash {
+    /// Hashes `version`
+    pub fn new(version: u16) -> Self {
+        Self(checksum(&version.to_string()).0)
+    }
+
+    /// Returns the version checksum of `version` if it hashes to this
+    pub fn checksum(&self, version: u16) -> Option<u32> {
+        let (hash, version_checksum) = checksum(&version.to_string());
+        (hash == self.0).then_some(version_checksum)
+    }
+
+    /// Returns `true` if `value` can be a version hash
+    pub fn is_valid(value: u16) -> bool {
+        value <= 0xFF
+    }
+
+    /// Returns every version with this hash along with its checksum, lowest version first. Many
+    /// versions share a hash, so the candidates have to be checked against the archive contents
+    /// to find the right one.
+    pub fn possible_versions(&self) -> &'static [(u16, u32)] {
+        VERSIONS
+            .get_or_init(|| {
+                let mut versions: HashMap<u16, Vec<(u16, u32)>> = HashMap::new();
+                for version in 1..=u16::MAX {
+                    let (hash, version_checksum) = checksum(&version.to_string());
+                    versions
+                        .entry(hash)
+                        .or_default()
+                        .push((version, version_checksum));
+                }
+                versions
+            })
+            .get(&self.0)
+            .map_or(&[], |versions| versions.as_slice())
+    }
+}
+
+impl From<u16> for VersionHash {
+    fn from(value: u16) -> Self {
+        Self(value)
+    }
+}
+
+impl From<VersionHash> for u16 {
+    fn from(hash: VersionHash) -> Self {
+        hash.0
+    }
+}
+
+impl PartialEq<u16> for VersionHash {
+    fn eq(&self, other: &u16) -> bool {
+        self.0 == *other
+    }
+}
+
+impl Decode for VersionHash {
+    fn decode<R>(reader: &mut R) -> Result<Self>
+    where
+        R: WzRead + ?Sized,
+    {
+        Ok(Self(u16::decode(reader)?))
+    }
+}
+
+impl Encode for VersionHash {
+    fn encode<W>(&self, writer: &mut W) -> Result<()>
+    where
+        W: WzWrite + ?Sized,
+    {
+        self.0.encode(writer)
+    }
+}
+
+impl SizeHint for VersionHash {
+    #[inline]
+    fn size_hint(&self) -> u32 {
+        2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::types::VersionHash;
+    use crypto::checksum;
+
+    #[test]
+    fn version_hashes() {
+        let hash = VersionHash::new(83);
+        assert_eq!(hash, 0xac);
+        assert_eq!(hash.checksum(83), Some(checksum("83").1));
+        assert_eq!(hash.checksum(84), None);
+        assert!(hash
+            .possible_versions()
+            .iter()
+            .all(|(version, _)| VersionHash::new(*version) == hash));
+        assert!(
+            (1..=u16::MAX).all(|version| VersionHash::is_valid(VersionHash::new(version).into()))
+        );
+        assert!(!VersionHash::is_valid(0x100));
+    }
+}
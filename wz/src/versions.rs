@@ -0,0 +1,167 @@
+//! Known client versions
+//!
+//! Each [`Client`] bundles what is needed to read the archives of a client release: the region's
+//! IV, the version and the usual header size.
+//!
+//! Example:
+//!
+//! ```no_run
+//! use wz::{archive, versions};
+//!
+//! let client = versions::GMS_V83;
+//! let reader = archive::Reader::open_as_version("Base.wz", client.version, client.key_stream())
+//!     .unwrap();
+//! ```
+
+use crate::error::{Error, PackageError, Result};
+use crate::types::WzHeader;
+use crypto::{KeyStream, GMS_IV, KMS_IV, TRIMMED_KEY};
+use std::{fmt, str::FromStr};
+
+/// GMS v62
+pub const GMS_V62: Client = Client::new(Region::Gms, 62);
+
+/// GMS v83
+pub const GMS_V83: Client = Client::new(Region::Gms, 83);
+
+/// GMS v95
+pub const GMS_V95: Client = Client::new(Region::Gms, 95);
+
+/// Every known client
+pub const KNOWN: &[Client] = &[GMS_V62, GMS_V83, GMS_V95];
+
+/// Size of the header written by the official tools
+const HEADER_SIZE: i32 = 60;
+
+/// Region a client was released in. Each region encrypts its strings with its own IV.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    Gms,
+    Kms,
+}
+
+impl Region {
+    /// Returns the IV strings are encrypted with
+    pub fn iv(&self) -> [u8; 4] {
+        match self {
+            Self::Gms => GMS_IV,
+            Self::Kms => KMS_IV,
+        }
+    }
+
+    /// Returns the lowercase name, e.g. `gms`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Gms => "gms",
+            Self::Kms => "kms",
+        }
+    }
+}
+
+/// Client release
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Client {
+    /// Region of the client
+    pub region: Region,
+
+    /// Version the archives are hashed with
+    pub version: u16,
+
+    /// Typical size of the archive headers, which is also their absolute position
+    pub header_size: i32,
+
+    /// Whether the client is 64-bit. The archives of 64-bit clients have no version hash and
+    /// cannot be read.
+    pub x64: bool,
+}
+
+impl Client {
+    /// Creates a 32-bit client with the usual header size
+    pub const fn new(region: Region, version: u16) -> Self {
+        Self {
+            region,
+            version,
+            header_size: HEADER_SIZE,
+            x64: false,
+        }
+    }
+
+    /// Returns the IV of the client's region
+    pub fn iv(&self) -> [u8; 4] {
+        self.region.iv()
+    }
+
+    /// Returns the key stream the client's archives are encrypted with
+    pub fn key_stream(&self) -> KeyStream {
+        KeyStream::new(&TRIMMED_KEY, &self.iv())
+    }
+
+    /// Returns the header archives of this client are written with
+    pub fn header(&self) -> WzHeader {
+        WzHeader::new(self.version)
+    }
+}
+
+impl FromStr for Client {
+    type Err = Error;
+
+    /// Parses names like `gms83`. Known clients are returned as is and unknown versions are
+    /// assumed to be 32-bit.
+    fn from_str(s: &str) -> Result<Self> {
+        let name = s.to_ascii_lowercase();
+        let unknown = || PackageError::Client(String::from(s));
+        let region = [Region::Gms, Region::Kms]
+            .into_iter()
+            .find(|region| name.starts_with(region.name()))
+            .ok_or_else(unknown)?;
+        let version = name[region.name().len()..]
+            .trim_start_matches('v')
+            .parse()
+            .map_err(|_| unknown())?;
+        Ok(KNOWN
+            .iter()
+            .find(|client| client.region == region && client.version == version)
+            .copied()
+            .unwrap_or_else(|| Self::new(region, version)))
+    }
+}
+
+impl fmt::Display for Client {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.region.name(), self.version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::archive;
+    use crate::versions::{self, Client, Region};
+
+    #[test]
+    fn parse_clients() {
+        assert_eq!("gms83".parse::<Client>().ok(), Some(versions::GMS_V83));
+        assert_eq!("GMSv95".parse::<Client>().ok(), Some(versions::GMS_V95));
+        assert_eq!(
+            "kms1".parse::<Client>().ok(),
+            Some(Client::new(Region::Kms, 1))
+        );
+        assert!("ems83".parse::<Client>().is_err());
+        assert!("gms".parse::<Client>().is_err());
+        assert_eq!(versions::GMS_V83.to_string(), "gms83");
+
+        let client = versions::GMS_V83;
+        let mut reader = archive::Reader::open_as_version(
+            "testdata/v83-base.wz",
+            client.version,
+            client.key_stream(),
+        )
+        .expect("error opening archive");
+        assert_eq!(
+            reader.header().absolute_position,
+            client.header().absolute_position
+        );
+        assert_eq!(client.header().absolute_position, client.header_size);
+        reader.map("Base").expect("error mapping archive");
+    }
+}
@@ -0,0 +1,556 @@
+//! Virtual filesystem over a directory of WZ archives
+
+use crate::archive::{self, reader::Node as ArchiveNode};
+use crate::error::{ImageError, PackageError, Result};
+use crate::image::{self, Cache, Metrics};
+use crate::io::{WzImageReader, WzRead, WzReader};
+use crate::map::Map;
+use crate::types::Property;
+use crypto::Decryptor;
+use std::{
+    collections::HashMap,
+    fs,
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, Receiver},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+pub mod links;
+pub mod text;
+
+/// Bytes of parsed images kept by default
+pub const DEFAULT_CACHE_BUDGET: usize = 256 * 1024 * 1024;
+
+/// Contents at a [`Vfs`] path
+#[derive(Debug, Clone)]
+pub enum Node {
+    /// Archive or package. Holds the names of its children.
+    Directory(Vec<String>),
+
+    /// Parsed image
+    Image(Arc<Map<Property>>),
+
+    /// Property within an image
+    Property(Property),
+}
+
+/// Metadata of a [`Vfs`] path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stat {
+    /// Archive or package with `entries` children
+    Directory { entries: usize },
+
+    /// Image stored within an archive
+    Image { size: i32, checksum: i32 },
+
+    /// Property within an image
+    Property,
+}
+
+/// Reader of an archive opened by [`Vfs::scan_parallel`]
+pub type ArchiveReader<D> = archive::Reader<WzReader<BufReader<File>, D>>;
+
+/// Single archive of the directory
+#[derive(Debug)]
+struct Archive<D>
+where
+    D: Decryptor,
+{
+    map: Map<ArchiveNode>,
+    reader: WzReader<BufReader<File>, D>,
+}
+
+/// Read-only view of every WZ archive in a directory as a single tree.
+///
+/// Paths start with the archive name without its extension and continue through packages and
+/// images down to properties, e.g. `String/Mob.img/100100/name`. Parsed images are kept in an
+/// [`image::Cache`]. Lookups are case-sensitive unless [`Vfs::set_case_insensitive`] is enabled.
+///
+/// Example:
+///
+/// ```no_run
+/// use crypto::{KeyStream, GMS_IV, TRIMMED_KEY};
+/// use wz::{types::Property, vfs::{Node, Vfs}};
+///
+/// let mut vfs = Vfs::open("Data", KeyStream::new(&TRIMMED_KEY, &GMS_IV)).unwrap();
+/// if let Node::Property(Property::String(name)) = vfs.read("String/Mob.img/100100/name").unwrap() {
+///     println!("{}", name.as_ref());
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Vfs<D>
+where
+    D: Decryptor,
+{
+    archives: Vec<(String, Archive<D>)>,
+    cache: Cache,
+
+    /// Lowercase path of every archive, package and image to its stored path. Only built for
+    /// case-insensitive lookups.
+    lowercase: Option<HashMap<String, String>>,
+}
+
+impl<D> Vfs<D>
+where
+    D: Decryptor + Clone,
+{
+    /// Opens every `.wz` file in `dir`. The version of each archive is brute forced.
+    pub fn open<S>(dir: S, decryptor: D) -> Result<Self>
+    where
+        S: AsRef<Path>,
+    {
+        Self::from_archives(archive_paths(dir)?, decryptor)
+    }
+
+    /// Opens and maps every `.wz` file in `dir` on up to `threads` threads, then calls `closure`
+    /// with the name, reader and map of each archive. The results are sent as soon as they are
+    /// ready, in no particular order, along with the archive name. An archive that fails to open,
+    /// map or scan only fails its own result. The receiver is closed once every archive is done.
+    ///
+    /// Example:
+    ///
+    /// ```no_run
+    /// use crypto::{KeyStream, GMS_IV, TRIMMED_KEY};
+    /// use wz::vfs::Vfs;
+    ///
+    /// let results = Vfs::scan_parallel("Data", KeyStream::new(&TRIMMED_KEY, &GMS_IV), 4, |_, _, map| {
+    ///     Ok(map.cursor().child_count())
+    /// })
+    /// .unwrap();
+    /// for (name, result) in results {
+    ///     match result {
+    ///         Ok(count) => println!("{}: {} entries", name, count),
+    ///         Err(e) => println!("{}: {}", name, e),
+    ///     }
+    /// }
+    /// ```
+    pub fn scan_parallel<S, F, T>(
+        dir: S,
+        decryptor: D,
+        threads: usize,
+        closure: F,
+    ) -> Result<Receiver<(String, Result<T>)>>
+    where
+        S: AsRef<Path>,
+        D: Send + 'static,
+        F: Fn(&str, &mut ArchiveReader<D>, &Map<ArchiveNode>) -> Result<T> + Send + Sync + 'static,
+        T: Send + 'static,
+    {
+        let paths = archive_paths(dir)?;
+        let threads = threads.clamp(1, paths.len().max(1));
+        let queue = Arc::new(Mutex::new(paths.into_iter()));
+        let closure = Arc::new(closure);
+        let (sender, receiver) = mpsc::channel();
+        for _ in 0..threads {
+            let (queue, closure, sender) = (queue.clone(), closure.clone(), sender.clone());
+            let decryptor = decryptor.clone();
+            thread::spawn(move || loop {
+                let next = queue.lock().expect("queue lock poisoned").next();
+                let path = match next {
+                    Some(path) => path,
+                    None => return,
+                };
+                let name = path
+                    .file_stem()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                let result =
+                    archive::Reader::open(&path, decryptor.clone()).and_then(|mut reader| {
+                        let map = reader.map(&name)?;
+                        closure(&name, &mut reader, &map)
+                    });
+                // Nobody is listening anymore
+                if sender.send((name, result)).is_err() {
+                    return;
+                }
+            });
+        }
+        Ok(receiver)
+    }
+
+    /// Opens the listed archives. Each archive is named after its file without the extension.
+    pub fn from_archives<I, S>(paths: I, decryptor: D) -> Result<Self>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<Path>,
+    {
+        let mut archives = Vec::new();
+        for path in paths {
+            let path = path.as_ref();
+            let name = path
+                .file_stem()
+                .and_then(|name| name.to_str())
+                .ok_or_else(|| PackageError::Path(path.to_string_lossy().into()))?
+                .to_string();
+            let mut reader = archive::Reader::open(path, decryptor.clone())?;
+            let map = reader.map(&name)?;
+            archives.push((
+                name,
+                Archive {
+                    map,
+                    reader: reader.into_inner(),
+                },
+            ));
+        }
+        Ok(Self {
+            archives,
+            cache: Cache::new(DEFAULT_CACHE_BUDGET),
+            lowercase: None,
+        })
+    }
+}
+
+impl<D> Vfs<D>
+where
+    D: Decryptor,
+{
+    /// Returns the names of the opened archives
+    pub fn archives(&self) -> impl Iterator<Item = &str> {
+        self.archives.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Sets the bytes of parsed images to keep. Zero disables caching.
+    pub fn set_cache_budget(&mut self, budget: usize) {
+        self.cache.set_budget(budget);
+    }
+
+    /// Returns the image cache counters
+    pub fn cache_metrics(&self) -> Metrics {
+        self.cache.metrics()
+    }
+
+    /// Makes lookups ignore case, e.g. `string/mob.img/100100/NAME` finds
+    /// `String/Mob.img/100100/name`. If names only differ in case, the first one stored wins.
+    ///
+    /// Archive contents are indexed up front. Properties are matched while resolving the path.
+    pub fn set_case_insensitive(&mut self, enabled: bool) {
+        if !enabled {
+            self.lowercase = None;
+            return;
+        }
+        let mut lowercase = HashMap::new();
+        for (_, archive) in &self.archives {
+            archive
+                .map
+                .walk::<()>(|cursor| {
+                    let path = cursor.pwd();
+                    lowercase.entry(path.to_lowercase()).or_insert(path);
+                    Ok(())
+                })
+                .expect("walk should not fail");
+        }
+        self.lowercase = Some(lowercase);
+    }
+
+    /// Returns the contents at `path`
+    pub fn read(&mut self, path: &str) -> Result<Node> {
+        let (archive, package_path, rest) = self.locate(path)?;
+        let map = &self.archives[archive].1.map;
+        match (map.get(&package_path)?, rest) {
+            (ArchiveNode::Package, _) => {
+                let cursor = map.cursor_at(&package_path)?;
+                let names = cursor.list().map(String::from).collect();
+                Ok(Node::Directory(names))
+            }
+            (ArchiveNode::Image { .. }, None) => {
+                Ok(Node::Image(self.image_at(archive, &package_path)?))
+            }
+            (ArchiveNode::Image { .. }, Some(rest)) => {
+                let image = self.image_at(archive, &package_path)?;
+                let property = image.get(self.property_path(&image, &rest)?)?;
+                Ok(Node::Property(property.clone()))
+            }
+        }
+    }
+
+    /// Returns the image at `path`
+    pub fn open_image(&mut self, path: &str) -> Result<Arc<Map<Property>>> {
+        match self.read(path)? {
+            Node::Image(image) => Ok(image),
+            _ => Err(ImageError::Path(String::from(path)).into()),
+        }
+    }
+
+    /// Returns the metadata of `path`. Only paths within an image require parsing it.
+    pub fn stat(&mut self, path: &str) -> Result<Stat> {
+        let (archive, package_path, rest) = self.locate(path)?;
+        let map = &self.archives[archive].1.map;
+        match (map.get(&package_path)?, rest) {
+            (ArchiveNode::Package, _) => Ok(Stat::Directory {
+                entries: map.cursor_at(&package_path)?.list().count(),
+            }),
+            (ArchiveNode::Image { size, checksum, .. }, None) => Ok(Stat::Image {
+                size: **size,
+                checksum: **checksum,
+            }),
+            (ArchiveNode::Image { .. }, Some(rest)) => {
+                let image = self.image_at(archive, &package_path)?;
+                image.get(self.property_path(&image, &rest)?)?;
+                Ok(Stat::Property)
+            }
+        }
+    }
+
+    /// Splits `path` into the archive index, the path within the archive map and the path within
+    /// the image, if any
+    fn locate(&self, path: &str) -> Result<(usize, String, Option<String>)> {
+        let path = &self.stored_path(path);
+        let mut components = path.split('/').filter(|c| !c.is_empty());
+        let name = components
+            .next()
+            .ok_or_else(|| PackageError::Path(String::from(path)))?;
+        let index = self
+            .archives
+            .iter()
+            .position(|(archive, _)| archive == name)
+            .ok_or_else(|| PackageError::Path(String::from(path)))?;
+        let map = &self.archives[index].1.map;
+        let mut package_path = String::from(name);
+        for component in components.by_ref() {
+            package_path.push('/');
+            package_path.push_str(component);
+            if let ArchiveNode::Image { .. } = map.get(&package_path)? {
+                break;
+            }
+        }
+        let rest = components.collect::<Vec<&str>>();
+        let rest = match rest.is_empty() {
+            true => None,
+            false => Some(rest.join("/")),
+        };
+        Ok((index, package_path, rest))
+    }
+
+    /// Replaces the longest prefix of `path` found in the lowercase index with its stored casing
+    fn stored_path(&self, path: &str) -> String {
+        let lowercase = match &self.lowercase {
+            Some(lowercase) => lowercase,
+            None => return String::from(path),
+        };
+        let components = path
+            .split('/')
+            .filter(|c| !c.is_empty())
+            .collect::<Vec<&str>>();
+        let mut key = String::new();
+        let mut matched = (0, String::new());
+        for (i, component) in components.iter().enumerate() {
+            if i > 0 {
+                key.push('/');
+            }
+            key.push_str(&component.to_lowercase());
+            match lowercase.get(&key) {
+                Some(stored) => matched = (i + 1, stored.clone()),
+                None => break,
+            }
+        }
+        let (count, mut stored) = matched;
+        for component in &components[count..] {
+            if !stored.is_empty() {
+                stored.push('/');
+            }
+            stored.push_str(component);
+        }
+        stored
+    }
+
+    /// Returns the full path of `rest` within `image`. Each name is matched ignoring case when
+    /// lookups are case-insensitive, preferring an exact match.
+    fn property_path(&self, image: &Map<Property>, rest: &str) -> Result<String> {
+        let mut path = String::from(image.name());
+        for component in rest.split('/').filter(|c| !c.is_empty()) {
+            let name = match self.lowercase {
+                Some(_) => {
+                    let cursor = image.cursor_at(&path)?;
+                    let lowercase = component.to_lowercase();
+                    cursor
+                        .list()
+                        .find(|name| *name == component)
+                        .or_else(|| cursor.list().find(|name| name.to_lowercase() == lowercase))
+                        .map(String::from)
+                        .unwrap_or_else(|| String::from(component))
+                }
+                None => String::from(component),
+            };
+            path.push('/');
+            path.push_str(&name);
+        }
+        Ok(path)
+    }
+
+    /// Returns the image at `path` and the full path of the property within it. A path to the
+    /// image itself returns the name of the image.
+    fn property_at(&mut self, path: &str) -> Result<(Arc<Map<Property>>, String)> {
+        let (archive, package_path, rest) = self.locate(path)?;
+        let image = self.image_at(archive, &package_path)?;
+        let path = match rest {
+            Some(rest) => self.property_path(&image, &rest)?,
+            None => String::from(image.name()),
+        };
+        Ok((image, path))
+    }
+
+    /// Returns the parsed image, reading it on a cache miss
+    fn image_at(&mut self, archive: usize, image_path: &str) -> Result<Arc<Map<Property>>> {
+        let (name, Archive { map, reader }) = &mut self.archives[archive];
+        self.cache
+            .image(name, image_path, || parse_image(map, reader, image_path))
+    }
+}
+
+/// Returns the sorted paths of the `.wz` files in `dir`
+fn archive_paths<S>(dir: S) -> Result<Vec<PathBuf>>
+where
+    S: AsRef<Path>,
+{
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().is_some_and(|ext| ext == "wz") {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// Parses the image at `image_path` of an archive
+fn parse_image<D>(
+    map: &Map<ArchiveNode>,
+    reader: &mut WzReader<BufReader<File>, D>,
+    image_path: &str,
+) -> Result<Map<Property>>
+where
+    D: Decryptor,
+{
+    let cursor = map.cursor_at(image_path)?;
+    let offset = match cursor.get() {
+        node if node.is_empty_image() => {
+            return Ok(Map::new(String::from(cursor.name()), Property::ImgDir))
+        }
+        ArchiveNode::Image { offset, .. } => *offset,
+        ArchiveNode::Package => return Err(ImageError::Path(String::from(image_path)).into()),
+    };
+    let mut image_reader = WzImageReader::with_offset(reader, offset);
+    image_reader.seek_to_start()?;
+    image::Reader::new(image_reader).map(cursor.name())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::archive::reader::Node as ArchiveNode;
+    use crate::types::Property;
+    use crate::vfs::{Node, Stat, Vfs};
+    use crypto::{KeyStream, GMS_IV, TRIMMED_KEY};
+    use std::{env, fs, sync::Arc};
+
+    #[test]
+    fn scan_in_parallel() {
+        let dir = env::temp_dir().join("wz-vfs-scan-test");
+        fs::create_dir_all(&dir).expect("error creating directory");
+        fs::copy("testdata/v83-base.wz", dir.join("Base.wz")).expect("error copying");
+        fs::copy("testdata/v83-string.wz", dir.join("String.wz")).expect("error copying");
+        fs::write(dir.join("Broken.wz"), b"not an archive").expect("error writing");
+
+        let key = KeyStream::new(&TRIMMED_KEY, &GMS_IV);
+        let receiver = Vfs::scan_parallel(&dir, key, 2, |name, reader, map| {
+            assert_eq!(map.name(), name);
+            reader.resolve_version()?;
+            let mut images = 0;
+            map.walk::<()>(|cursor| {
+                if let ArchiveNode::Image { .. } = cursor.get() {
+                    images += 1;
+                }
+                Ok(())
+            })
+            .expect("walk should not fail");
+            Ok(images)
+        })
+        .expect("error scanning directory");
+        let mut results = receiver.into_iter().collect::<Vec<_>>();
+        results.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let names = results
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["Base", "Broken", "String"]);
+        assert!(*results[0].1.as_ref().expect("error scanning Base") > 0);
+        assert!(results[1].1.is_err());
+        assert!(*results[2].1.as_ref().expect("error scanning String") > 0);
+        fs::remove_dir_all(&dir).expect("error removing directory");
+    }
+
+    #[test]
+    fn v83_vfs() {
+        let dir = env::temp_dir().join("wz-vfs-test");
+        fs::create_dir_all(&dir).expect("error creating directory");
+        fs::copy("testdata/v83-base.wz", dir.join("Base.wz")).expect("error copying");
+        fs::copy("testdata/v83-string.wz", dir.join("String.wz")).expect("error copying");
+
+        let mut vfs = Vfs::open(&dir, KeyStream::new(&TRIMMED_KEY, &GMS_IV))
+            .expect("error opening directory");
+        assert_eq!(vfs.archives().collect::<Vec<&str>>(), ["Base", "String"]);
+        match vfs.read("Base").expect("error reading Base") {
+            Node::Directory(names) => assert!(names.iter().any(|name| name == "String")),
+            n => panic!("expected a directory, found {:?}", n),
+        }
+        match vfs.stat("String/Mob.img").expect("error reading Mob.img") {
+            Stat::Image { size, .. } => assert!(size > 0),
+            s => panic!("expected an image, found {:?}", s),
+        }
+        match vfs
+            .read("String/Mob.img/100100/name")
+            .expect("error reading name")
+        {
+            Node::Property(Property::String(_)) => {}
+            n => panic!("expected a string, found {:?}", n),
+        }
+        assert_eq!(
+            vfs.stat("String/Mob.img/100100")
+                .expect("error reading 100100"),
+            Stat::Property
+        );
+
+        // The parsed image is reused
+        let first = vfs
+            .open_image("String/Mob.img")
+            .expect("error reading image");
+        let second = vfs
+            .open_image("String/Mob.img")
+            .expect("error reading image");
+        assert!(Arc::ptr_eq(&first, &second));
+        assert!(vfs.cache_metrics().hits > 0);
+        assert!(vfs.read("String/Missing.img").is_err());
+        assert!(vfs.read("Missing/Mob.img").is_err());
+        assert!(vfs.open_image("String").is_err());
+
+        // Case-insensitive lookups find the stored names and share the cached image
+        assert!(vfs.read("string/mob.img/100100/NAME").is_err());
+        vfs.set_case_insensitive(true);
+        match vfs
+            .read("string/mob.IMG/100100/NAME")
+            .expect("error reading name")
+        {
+            Node::Property(Property::String(_)) => {}
+            n => panic!("expected a string, found {:?}", n),
+        }
+        let third = vfs
+            .open_image("STRING/MOB.IMG")
+            .expect("error reading image");
+        assert!(Arc::ptr_eq(&first, &third));
+        assert_eq!(
+            vfs.stat("string/mob.img/100100")
+                .expect("error reading 100100"),
+            Stat::Property
+        );
+        assert!(vfs.read("string/missing.img").is_err());
+        vfs.set_case_insensitive(false);
+        assert!(vfs.read("string/mob.img").is_err());
+        fs::remove_dir_all(&dir).expect("error removing directory");
+    }
+}
@@ -0,0 +1,470 @@
+//! References between the properties of a [`Vfs`]
+
+use crate::archive::reader::Node as ArchiveNode;
+use crate::error::{Error, ImageError, Result};
+use crate::map::{Bookmark, Cursor, Map};
+use crate::types::Property;
+use crate::vfs::{parse_image, Vfs};
+use crypto::Decryptor;
+use std::collections::HashSet;
+
+/// Number of times [`inline`] inlines the links brought in by the targets it inlined
+pub const MAX_DEPTH: usize = 8;
+
+/// How a property refers to another
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LinkKind {
+    /// [`Property::Uol`] with a path relative to its parent
+    Uol,
+
+    /// `_outlink` string of a canvas with the full path of another canvas, e.g.
+    /// `Mob/_Canvas/8800000.img/stand/0`
+    Outlink,
+
+    /// `_inlink` string of a canvas with the path of another canvas of the same image, e.g.
+    /// `stand/0`
+    Inlink,
+}
+
+/// Reference from one property to another. Both paths are [`Vfs`] paths.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Link {
+    /// How the source refers to the target
+    pub kind: LinkKind,
+
+    /// Path of the UOL, or of the canvas holding the `_outlink` or `_inlink`
+    pub source: String,
+
+    /// Path the source refers to
+    pub target: String,
+}
+
+/// Returns the links of `image`, stored at `image_path` of a [`Vfs`]. The targets are resolved
+/// but not checked.
+pub fn links(image_path: &str, image: &Map<Property>) -> Vec<Link> {
+    collect(image_path, image)
+        .into_iter()
+        .map(|(_, link)| link)
+        .collect()
+}
+
+/// Links of every image under a [`Vfs`] path
+///
+/// Example:
+///
+/// ```no_run
+/// use crypto::{KeyStream, GMS_IV, TRIMMED_KEY};
+/// use wz::vfs::{links::Graph, Vfs};
+///
+/// let mut vfs = Vfs::open("Data", KeyStream::new(&TRIMMED_KEY, &GMS_IV)).unwrap();
+/// let graph = Graph::build(&mut vfs, "Mob").unwrap();
+/// for link in graph.dangling() {
+///     println!("{} -> {}", link.source, link.target);
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Graph {
+    links: Vec<Link>,
+    dangling: HashSet<usize>,
+}
+
+impl Graph {
+    /// Collects the links of every image under `path` and checks that their targets exist.
+    /// Targets may lie anywhere in `vfs`.
+    pub fn build<D>(vfs: &mut Vfs<D>, path: &str) -> Result<Self>
+    where
+        D: Decryptor,
+    {
+        let mut graph = Self::default();
+        for image_path in images(vfs, path)? {
+            let image = vfs.open_image(&image_path)?;
+            for link in links(&image_path, &image) {
+                if !exists(vfs, &link.target)? {
+                    graph.dangling.insert(graph.links.len());
+                }
+                graph.links.push(link);
+            }
+        }
+        Ok(graph)
+    }
+
+    /// Returns every link, in image order
+    pub fn links(&self) -> &[Link] {
+        &self.links
+    }
+
+    /// Returns the links whose target does not exist
+    pub fn dangling(&self) -> impl Iterator<Item = &Link> {
+        self.links
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| self.dangling.contains(i))
+            .map(|(_, link)| link)
+    }
+
+    /// Returns the links to `target`
+    pub fn referrers<'a>(&'a self, target: &'a str) -> impl Iterator<Item = &'a Link> {
+        self.links.iter().filter(move |link| link.target == target)
+    }
+}
+
+/// Parses the image at `image_path` and replaces its links with copies of their targets, so the
+/// image no longer depends on any other. UOLs are replaced by their target and everything under
+/// it. Canvases with an `_outlink` or `_inlink` take the pixels of their target and lose the link.
+///
+/// Links brought in by the copied targets are inlined as well, up to [`MAX_DEPTH`] times. Links
+/// whose target does not exist, or whose target is not a canvas for `_outlink` and `_inlink`, are
+/// kept. Returns the image with the inlined links.
+pub fn inline<D>(vfs: &mut Vfs<D>, image_path: &str) -> Result<(Map<Property>, Vec<Link>)>
+where
+    D: Decryptor,
+{
+    let (archive, package_path, _) = vfs.locate(image_path)?;
+    let (_, archive) = &mut vfs.archives[archive];
+    let mut image = parse_image(&archive.map, &mut archive.reader, &package_path)?;
+
+    let mut inlined = Vec::new();
+    let mut kept = HashSet::new();
+    for _ in 0..MAX_DEPTH {
+        let pending = collect(image_path, &image)
+            .into_iter()
+            .filter(|(_, link)| !kept.contains(link))
+            .collect::<Vec<(Bookmark, Link)>>();
+        if pending.is_empty() {
+            break;
+        }
+        for (bookmark, link) in pending {
+            let target = match exists(vfs, &link.target)? {
+                true => Some(vfs.property_at(&link.target)?),
+                false => None,
+            };
+            let replaced = match target {
+                Some((target_image, target_path)) => {
+                    let target = target_image.cursor_at(&target_path)?;
+                    replace(&mut image, bookmark, &link, &target)?
+                }
+                None => false,
+            };
+            match replaced {
+                true => inlined.push(link),
+                false => {
+                    kept.insert(link);
+                }
+            }
+        }
+    }
+    Ok((image, inlined))
+}
+
+/// Returns the full path of every image under `path`
+fn images<D>(vfs: &Vfs<D>, path: &str) -> Result<Vec<String>>
+where
+    D: Decryptor,
+{
+    let (archive, package_path, rest) = vfs.locate(path)?;
+    if rest.is_some() {
+        return Err(ImageError::Path(String::from(path)).into());
+    }
+    let mut images = Vec::new();
+    vfs.archives[archive]
+        .1
+        .map
+        .cursor_at(&package_path)?
+        .walk::<Error>(|cursor| {
+            if let ArchiveNode::Image { .. } = cursor.get() {
+                images.push(cursor.pwd());
+            }
+            Ok(())
+        })?;
+    Ok(images)
+}
+
+/// Returns if `path` exists. Only paths that cannot be found count as missing, other errors are
+/// returned.
+fn exists<D>(vfs: &mut Vfs<D>, path: &str) -> Result<bool>
+where
+    D: Decryptor,
+{
+    match vfs.stat(path) {
+        Ok(_) => Ok(true),
+        Err(Error::Map(_) | Error::Package(_)) => Ok(false),
+        Err(Error::Image(ImageError::Path(_))) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Returns the links of `image` with the bookmark of their source
+fn collect(image_path: &str, image: &Map<Property>) -> Vec<(Bookmark, Link)> {
+    // Paths within the image start with its name, which is already in the image path
+    let parent = match image_path.rsplit_once('/') {
+        Some((parent, _)) => format!("{}/", parent),
+        None => String::new(),
+    };
+    let mut links = Vec::new();
+    let _ = image.walk::<()>(|cursor| {
+        let source = format!("{}{}", parent, cursor.pwd());
+        let found = match cursor.get() {
+            Property::Uol(uol) => {
+                let base = source.rsplit_once('/').map_or("", |(base, _)| base);
+                Some((LinkKind::Uol, resolve(base, uol)))
+            }
+            Property::Canvas(_) => canvas_link(&cursor, image_path),
+            _ => None,
+        };
+        if let Some((kind, target)) = found {
+            links.push((
+                cursor.bookmark(),
+                Link {
+                    kind,
+                    source,
+                    target,
+                },
+            ));
+        }
+        Ok(())
+    });
+    links
+}
+
+/// Returns the `_outlink` or `_inlink` of the canvas at `cursor`
+fn canvas_link(cursor: &Cursor<Property>, image_path: &str) -> Option<(LinkKind, String)> {
+    cursor
+        .list()
+        .zip(cursor.children())
+        .find_map(|(name, property)| match (name, property) {
+            ("_outlink", Property::String(target)) => {
+                Some((LinkKind::Outlink, resolve("", target)))
+            }
+            ("_inlink", Property::String(target)) => {
+                Some((LinkKind::Inlink, resolve(image_path, target)))
+            }
+            _ => None,
+        })
+}
+
+/// Applies the relative `path` to `base`, both separated by `/`
+fn resolve(base: &str, path: &str) -> String {
+    let mut segments = base
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<&str>>();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    segments.join("/")
+}
+
+/// Replaces the source of `link` in `image` with `target`. Returns `false` if the target cannot
+/// replace it.
+fn replace(
+    image: &mut Map<Property>,
+    bookmark: Bookmark,
+    link: &Link,
+    target: &Cursor<Property>,
+) -> Result<bool> {
+    let mut cursor = image.cursor_mut_at_bookmark(bookmark)?;
+    match (link.kind, target.get()) {
+        (LinkKind::Uol, property) => {
+            let name = String::from(cursor.name());
+            let index = cursor.index();
+            let mut segments = cursor
+                .parent()?
+                .delete(&name)?
+                .create_at(index, name.clone(), property.clone())?
+                .move_to(&name)?
+                .segments()
+                .into_iter()
+                .map(String::from)
+                .collect::<Vec<String>>();
+            let depth = segments.len();
+            target.walk::<Error>(|descendant| {
+                // The target itself was copied above. Its descendants follow their parents.
+                let relative = descendant.segments();
+                let relative = &relative[target.segments().len()..];
+                if let Some((name, parents)) = relative.split_last() {
+                    segments.truncate(depth);
+                    segments.extend(parents.iter().map(|s| String::from(*s)));
+                    let parent = segments.iter().map(String::as_str).collect::<Vec<&str>>();
+                    image
+                        .cursor_mut_at_segments(&parent)?
+                        .create(String::from(*name), descendant.get().clone())?;
+                }
+                Ok(())
+            })?;
+            Ok(true)
+        }
+        (LinkKind::Outlink | LinkKind::Inlink, Property::Canvas(canvas)) => {
+            let canvas = canvas.clone();
+            *cursor.get_mut() = Property::Canvas(canvas);
+            cursor.delete(match link.kind {
+                LinkKind::Outlink => "_outlink",
+                _ => "_inlink",
+            })?;
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::archive::{writer::EncodedImage, Writer};
+    use crate::io::{DummyDecryptor, DummyEncryptor};
+    use crate::map::Map;
+    use crate::types::{Canvas, CanvasFormat, Property, UolObject, UolString, WzHeader, WzInt};
+    use crate::vfs::{
+        links::{self, Graph, Link, LinkKind},
+        Vfs,
+    };
+    use std::{env, fs};
+
+    fn canvas(width: i32) -> Property {
+        Property::Canvas(Canvas::new(
+            WzInt::from(width),
+            WzInt::from(1),
+            CanvasFormat::Bgra8888,
+            vec![0; 4],
+        ))
+    }
+
+    /// `Mob/a.img` links to itself, to `Mob/b.img` and to a missing canvas
+    fn mob() -> (Map<Property>, Map<Property>) {
+        let mut a = Map::new(String::from("a.img"), Property::ImgDir);
+        let mut cursor = a.cursor_mut();
+        cursor
+            .create(String::from("stand"), Property::ImgDir)
+            .unwrap()
+            .move_to("stand")
+            .unwrap()
+            .create(String::from("0"), canvas(1))
+            .unwrap()
+            .create(String::from("1"), Property::Uol(UolObject::from("0")))
+            .unwrap()
+            .create(String::from("2"), canvas(0))
+            .unwrap()
+            .create(String::from("3"), canvas(0))
+            .unwrap()
+            .create(String::from("4"), Property::Uol(UolObject::from("../move")))
+            .unwrap()
+            .create(String::from("5"), canvas(0))
+            .unwrap();
+        cursor
+            .move_to("0")
+            .unwrap()
+            .create(String::from("delay"), Property::Int(WzInt::from(100)))
+            .unwrap();
+        cursor
+            .parent()
+            .unwrap()
+            .move_to("2")
+            .unwrap()
+            .create(
+                String::from("_inlink"),
+                Property::String(UolString::from("stand/0")),
+            )
+            .unwrap();
+        cursor
+            .parent()
+            .unwrap()
+            .move_to("3")
+            .unwrap()
+            .create(
+                String::from("_outlink"),
+                Property::String(UolString::from("Mob/b.img/move/0")),
+            )
+            .unwrap();
+        cursor
+            .parent()
+            .unwrap()
+            .move_to("5")
+            .unwrap()
+            .create(
+                String::from("_outlink"),
+                Property::String(UolString::from("Mob/b.img/missing")),
+            )
+            .unwrap();
+
+        let mut b = Map::new(String::from("b.img"), Property::ImgDir);
+        b.cursor_mut()
+            .create(String::from("move"), Property::ImgDir)
+            .unwrap()
+            .move_to("move")
+            .unwrap()
+            .create(String::from("0"), canvas(2))
+            .unwrap();
+        (a, b)
+    }
+
+    #[test]
+    fn link_graph() {
+        let dir = env::temp_dir().join("wz-links-test");
+        fs::create_dir_all(&dir).expect("error creating directory");
+        let (a, b) = mob();
+        let mut writer = Writer::new("Mob");
+        writer
+            .add_image("Mob/a.img", EncodedImage::new(a, DummyEncryptor))
+            .expect("error adding image");
+        writer
+            .add_image("Mob/b.img", EncodedImage::new(b, DummyEncryptor))
+            .expect("error adding image");
+        writer
+            .save(dir.join("Mob.wz"), 83, WzHeader::new(83), DummyEncryptor)
+            .expect("error saving");
+        let mut vfs = Vfs::open(&dir, DummyDecryptor).expect("error opening directory");
+
+        let graph = Graph::build(&mut vfs, "Mob").expect("error building graph");
+        let link = |kind, source: &str, target: &str| Link {
+            kind,
+            source: String::from(source),
+            target: String::from(target),
+        };
+        assert_eq!(
+            graph.links(),
+            [
+                link(LinkKind::Uol, "Mob/a.img/stand/1", "Mob/a.img/stand/0"),
+                link(LinkKind::Inlink, "Mob/a.img/stand/2", "Mob/a.img/stand/0"),
+                link(LinkKind::Outlink, "Mob/a.img/stand/3", "Mob/b.img/move/0"),
+                link(LinkKind::Uol, "Mob/a.img/stand/4", "Mob/a.img/move"),
+                link(LinkKind::Outlink, "Mob/a.img/stand/5", "Mob/b.img/missing"),
+            ]
+        );
+        assert_eq!(
+            graph
+                .dangling()
+                .map(|link| link.source.as_str())
+                .collect::<Vec<&str>>(),
+            ["Mob/a.img/stand/4", "Mob/a.img/stand/5"]
+        );
+        assert_eq!(graph.referrers("Mob/a.img/stand/0").count(), 2);
+
+        let (image, inlined) = links::inline(&mut vfs, "Mob/a.img").expect("error inlining");
+        assert_eq!(inlined.len(), 3);
+        match image.get("a.img/stand/1") {
+            Ok(Property::Canvas(canvas)) => assert_eq!(*canvas.width(), 1),
+            p => panic!("expected a canvas, found {:?}", p),
+        }
+        assert!(image.get("a.img/stand/1/delay").is_ok());
+        match image.get("a.img/stand/3") {
+            Ok(Property::Canvas(canvas)) => assert_eq!(*canvas.width(), 2),
+            p => panic!("expected a canvas, found {:?}", p),
+        }
+        assert!(image.get("a.img/stand/2/_inlink").is_err());
+        assert!(image.get("a.img/stand/3/_outlink").is_err());
+        assert!(image.get("a.img/stand/5/_outlink").is_ok());
+        assert_eq!(
+            links::links("Mob/a.img", &image)
+                .into_iter()
+                .map(|link| link.source)
+                .collect::<Vec<String>>(),
+            ["Mob/a.img/stand/4", "Mob/a.img/stand/5"]
+        );
+        fs::remove_dir_all(&dir).expect("error removing directory");
+    }
+}
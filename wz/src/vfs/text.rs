@@ -0,0 +1,243 @@
+//! Full-text index of the strings of a [`Vfs`]
+
+use crate::archive::index::{read_string, read_u32, write_string};
+use crate::archive::reader::Node as ArchiveNode;
+use crate::error::{Error, PackageError, Result};
+use crate::image::Scanner;
+use crate::io::{WzImageReader, WzRead};
+use crate::types::{Property, WzOffset};
+use crate::vfs::Vfs;
+use crypto::Decryptor;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+const MAGIC: &[u8; 4] = b"WZTX";
+const FORMAT_VERSION: u16 = 1;
+
+/// Inverted index of every [`Property::String`] in a [`Vfs`]
+///
+/// Strings are split into words at every character that is not alphanumeric and the words are
+/// lowercased. The index only holds the paths of the strings, so it must be rebuilt once the
+/// archives change.
+///
+/// Example:
+///
+/// ```no_run
+/// use crypto::{KeyStream, GMS_IV, TRIMMED_KEY};
+/// use wz::vfs::{text::TextIndex, Vfs};
+///
+/// let mut vfs = Vfs::open("Data", KeyStream::new(&TRIMMED_KEY, &GMS_IV)).unwrap();
+/// let index = TextIndex::build(&mut vfs).unwrap();
+/// index.save("Data.wzti").unwrap();
+/// for (archive, path) in index.search("orange mushroom") {
+///     println!("{}: {}", archive, path);
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TextIndex {
+    /// Full path of every indexed string
+    paths: Vec<String>,
+
+    /// Every word to the sorted indices of the paths holding it
+    words: BTreeMap<String, Vec<u32>>,
+}
+
+impl TextIndex {
+    /// Indexes the strings of every image in `vfs`. Images are scanned without being mapped.
+    pub fn build<D>(vfs: &mut Vfs<D>) -> Result<Self>
+    where
+        D: Decryptor,
+    {
+        let mut index = Self::default();
+        for (_, archive) in &mut vfs.archives {
+            let mut images: Vec<(String, WzOffset)> = Vec::new();
+            archive.map.walk::<Error>(|cursor| {
+                // Empty images hold no strings
+                match cursor.get() {
+                    node if node.is_empty_image() => {}
+                    ArchiveNode::Image { offset, .. } => images.push((cursor.pwd(), *offset)),
+                    ArchiveNode::Package => {}
+                }
+                Ok(())
+            })?;
+            for (image_path, offset) in images {
+                let mut image_reader = WzImageReader::with_offset(&mut archive.reader, offset);
+                image_reader.seek_to_start()?;
+                Scanner::new(image_reader).scan(&image_path, |path, property| {
+                    if let Property::String(s) = property {
+                        index.insert(path, s);
+                    }
+                    Ok(())
+                })?;
+            }
+        }
+        Ok(index)
+    }
+
+    /// Indexes the string `value` at the [`Vfs`] path `path`
+    pub fn insert(&mut self, path: &str, value: &str) {
+        let id = self.paths.len() as u32;
+        let mut added = false;
+        for word in words(value).collect::<BTreeSet<String>>() {
+            self.words.entry(word).or_default().push(id);
+            added = true;
+        }
+        if added {
+            self.paths.push(String::from(path));
+        }
+    }
+
+    /// Returns the archive and the path within it of every string holding all the words of
+    /// `query`, in index order. The last word also matches the words it starts, so `orange mush`
+    /// finds `Orange Mushroom`.
+    pub fn search(&self, query: &str) -> Vec<(String, String)> {
+        let query = words(query).collect::<Vec<String>>();
+        let mut found: Option<BTreeSet<u32>> = None;
+        for (i, word) in query.iter().enumerate() {
+            let ids = match i + 1 == query.len() {
+                true => self
+                    .words
+                    .range(word.clone()..)
+                    .take_while(|(w, _)| w.starts_with(word.as_str()))
+                    .flat_map(|(_, ids)| ids.iter().copied())
+                    .collect::<BTreeSet<u32>>(),
+                false => self
+                    .words
+                    .get(word)
+                    .map(|ids| ids.iter().copied().collect())
+                    .unwrap_or_default(),
+            };
+            found = Some(match found {
+                Some(found) => found.intersection(&ids).copied().collect(),
+                None => ids,
+            });
+        }
+        found
+            .unwrap_or_default()
+            .into_iter()
+            .map(|id| {
+                let path = &self.paths[id as usize];
+                let (archive, path) = path.split_once('/').unwrap_or((path, ""));
+                (String::from(archive), String::from(path))
+            })
+            .collect()
+    }
+
+    /// Returns the number of indexed strings
+    pub fn len(&self) -> usize {
+        self.paths.len()
+    }
+
+    /// Returns if no string is indexed
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+
+    /// Loads the index saved at `path`
+    pub fn load<S>(path: S) -> Result<Self>
+    where
+        S: AsRef<Path>,
+    {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        let mut version = [0u8; 2];
+        reader.read_exact(&mut version)?;
+        if &magic != MAGIC || u16::from_le_bytes(version) != FORMAT_VERSION {
+            return Err(PackageError::Header.into());
+        }
+        let mut index = Self::default();
+        for _ in 0..read_u32(&mut reader)? {
+            index.paths.push(read_string(&mut reader)?);
+        }
+        for _ in 0..read_u32(&mut reader)? {
+            let word = read_string(&mut reader)?;
+            let count = read_u32(&mut reader)?;
+            let mut ids = Vec::new();
+            for _ in 0..count {
+                match read_u32(&mut reader)? {
+                    id if (id as usize) < index.paths.len() => ids.push(id),
+                    _ => return Err(PackageError::Header.into()),
+                }
+            }
+            index.words.insert(word, ids);
+        }
+        Ok(index)
+    }
+
+    /// Saves the index to `path`
+    pub fn save<S>(&self, path: S) -> Result<()>
+    where
+        S: AsRef<Path>,
+    {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        writer.write_all(&(self.paths.len() as u32).to_le_bytes())?;
+        for path in &self.paths {
+            write_string(&mut writer, path)?;
+        }
+        writer.write_all(&(self.words.len() as u32).to_le_bytes())?;
+        for (word, ids) in &self.words {
+            write_string(&mut writer, word)?;
+            writer.write_all(&(ids.len() as u32).to_le_bytes())?;
+            for id in ids {
+                writer.write_all(&id.to_le_bytes())?;
+            }
+        }
+        Ok(writer.flush()?)
+    }
+}
+
+/// Splits `text` into lowercase words
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::types::Property;
+    use crate::vfs::{text::TextIndex, Node, Vfs};
+    use crypto::{KeyStream, GMS_IV, TRIMMED_KEY};
+    use std::{env, fs};
+
+    #[test]
+    fn v83_text_index() {
+        let dir = env::temp_dir().join("wz-text-index-test");
+        fs::create_dir_all(&dir).expect("error creating directory");
+        fs::copy("testdata/v83-base.wz", dir.join("Base.wz")).expect("error copying");
+        fs::copy("testdata/v83-string.wz", dir.join("String.wz")).expect("error copying");
+        let mut vfs = Vfs::open(&dir, KeyStream::new(&TRIMMED_KEY, &GMS_IV))
+            .expect("error opening directory");
+
+        let index = TextIndex::build(&mut vfs).expect("error building index");
+        assert!(!index.is_empty());
+        let found = index.search("Orange MUSH");
+        assert!(!found.is_empty());
+        for (archive, path) in &found {
+            assert_eq!(archive, "String");
+            match vfs.read(&format!("{}/{}", archive, path)) {
+                Ok(Node::Property(Property::String(s))) => {
+                    let s = s.to_lowercase();
+                    assert!(s.contains("orange") && s.contains("mush"), "{}", s);
+                }
+                n => panic!("expected a string, found {:?}", n),
+            }
+        }
+        assert!(index.search("orange mush").len() >= index.search("orange mushroom").len());
+        assert!(index.search("orange xyzzy").is_empty());
+        assert!(index.search("").is_empty());
+
+        let path = dir.join("strings.wzti");
+        index.save(&path).expect("error saving index");
+        assert_eq!(TextIndex::load(&path).expect("error loading index"), index);
+        fs::remove_dir_all(&dir).expect("error removing directory");
+    }
+}
@@ -0,0 +1,241 @@
+//! Extracts the bundled archives, rebuilds them and compares the result with the originals.
+//!
+//! Stored images are copied verbatim, so their bytes, sizes and checksums must survive and a
+//! rebuild of the rebuild must match it byte for byte. Images that are parsed and encoded again
+//! may legitimately differ in their bytes, so those are compared by encoding both sides the same
+//! way. Both are done with and without encryption.
+//!
+//! Run with `cargo test -p wz --test roundtrip`.
+
+use crypto::{Decryptor, Encryptor, KeyStream, GMS_IV, TRIMMED_KEY};
+use std::io::{Cursor, Seek, Write};
+use wz::{
+    archive::{
+        reader::Node,
+        writer::{EncodedImage, ImageRef},
+        Reader, Writer,
+    },
+    error::{Error, Result},
+    image,
+    io::{DummyDecryptor, DummyEncryptor, WzImageWriter, WzRead, WzWrite, WzWriter},
+    map::Map,
+    types::{Property, WzInt},
+};
+
+/// Path of an archive entry, with the checksum and stored bytes if it is an image
+type Entry = (String, Option<(WzInt, Vec<u8>)>);
+
+/// Image copied out of an archive as it was stored
+struct Stored {
+    data: Vec<u8>,
+    checksum: WzInt,
+}
+
+impl ImageRef for Stored {
+    fn size(&self) -> Result<WzInt> {
+        Ok(WzInt::from(self.data.len() as i32))
+    }
+
+    fn checksum(&self) -> Result<WzInt> {
+        Ok(self.checksum)
+    }
+
+    fn write<W, E>(&self, writer: &mut WzWriter<W, E>) -> Result<()>
+    where
+        W: Write + Seek,
+        E: Encryptor,
+    {
+        writer.write_all(&self.data)
+    }
+}
+
+fn key() -> KeyStream {
+    KeyStream::new(&TRIMMED_KEY, &GMS_IV)
+}
+
+/// Returns the path and node of every entry in `map` but the root, in archive order
+fn entries(map: &Map<Node>) -> Vec<(String, Node)> {
+    let mut entries = Vec::new();
+    map.walk::<Error>(|cursor| {
+        let path = cursor.pwd();
+        if path.contains('/') {
+            entries.push((path, *cursor.get()));
+        }
+        Ok(())
+    })
+    .expect("error walking");
+    entries
+}
+
+/// Encodes `image` without encryption so images read with different keys can be compared
+fn canonical(image: Map<Property>) -> Vec<u8> {
+    let mut inner = WzWriter::unencrypted(0, 0, Cursor::new(Vec::new()));
+    image::Writer::from_map(image)
+        .write_to(&mut WzImageWriter::new(&mut inner))
+        .expect("error encoding image");
+    inner.into_inner().into_inner()
+}
+
+/// Maps every image of the archive in `bytes` and encodes it with [`canonical`]
+fn canonical_images<D, F>(bytes: Vec<u8>, name: &str, decryptor: F) -> Vec<(String, Vec<u8>)>
+where
+    D: Decryptor,
+    F: Fn() -> D,
+{
+    let mut reader = Reader::from_bytes(bytes, decryptor()).expect("error opening archive");
+    let map = reader.map(name).expect("error mapping archive");
+    entries(&map)
+        .into_iter()
+        .filter(|(_, node)| matches!(node, Node::Image { .. }))
+        .map(|(path, _)| {
+            let image = reader
+                .map_image(&map, &path, |_| decryptor())
+                .expect("error mapping image");
+            (path, canonical(image))
+        })
+        .collect()
+}
+
+/// Returns every entry of the archive in `bytes` with the stored bytes of the images, and the
+/// archive rebuilt from them with `encryptor`
+fn copy_stored<D, E>(
+    bytes: Vec<u8>,
+    name: &str,
+    decryptor: D,
+    encryptor: E,
+) -> (Vec<Entry>, Vec<u8>)
+where
+    D: Decryptor,
+    E: Encryptor,
+{
+    let mut reader = Reader::from_bytes(bytes, decryptor).expect("error opening archive");
+    let version = reader.resolve_version().expect("error resolving version");
+    let header = reader.header().clone();
+    let map = reader.map(name).expect("error mapping archive");
+    let mut inner = reader.into_inner();
+
+    let mut writer = Writer::new(name);
+    let mut stored = Vec::new();
+    for (path, node) in entries(&map) {
+        match node {
+            Node::Package => {
+                writer.add_package(&path).expect("error adding package");
+                stored.push((path, None));
+            }
+            Node::Image {
+                offset,
+                size,
+                checksum,
+            } => {
+                let mut data = Vec::new();
+                inner
+                    .copy_to(&mut data, offset, size)
+                    .expect("error copying image");
+                let image = Stored {
+                    data: data.clone(),
+                    checksum,
+                };
+                writer.add_image(&path, image).expect("error adding image");
+                stored.push((path, Some((checksum, data))));
+            }
+        }
+    }
+    let rebuilt = writer
+        .save_to_vec(version, header, encryptor)
+        .expect("error saving");
+    (stored, rebuilt)
+}
+
+/// Copies the stored images of the archive at `path` into a new archive. The package tables may
+/// be laid out differently than in the original, so the original is compared entry by entry and
+/// the rebuild must reproduce itself byte for byte.
+fn rebuild_stored<D, F, E, G>(path: &str, name: &str, decryptor: F, encryptor: G)
+where
+    D: Decryptor,
+    F: Fn() -> D,
+    E: Encryptor,
+    G: Fn() -> E,
+{
+    let original = std::fs::read(path).expect("error reading archive");
+    let (expected, rebuilt) = copy_stored(original.clone(), name, decryptor(), encryptor());
+    assert!(!expected.is_empty());
+    assert_eq!(rebuilt.len(), original.len(), "{} changed size", path);
+
+    let (stored, again) = copy_stored(rebuilt.clone(), name, decryptor(), encryptor());
+    assert!(stored == expected, "{} lost or changed entries", path);
+    assert!(again == rebuilt, "{} does not rebuild identically", path);
+}
+
+/// Parses every image of the archive at `path`, encodes it again with `encryptor` and checks the
+/// rebuilt archive, read back with `reread`, holds the same images
+fn rebuild_encoded<D, F, E, R, G>(path: &str, name: &str, decryptor: F, encryptor: E, reread: G)
+where
+    D: Decryptor,
+    F: Fn() -> D,
+    E: Encryptor + Clone + Send + Sync,
+    R: Decryptor,
+    G: Fn() -> R,
+{
+    let original = std::fs::read(path).expect("error reading archive");
+    let mut reader =
+        Reader::from_bytes(original.clone(), decryptor()).expect("error opening archive");
+    let version = reader.resolve_version().expect("error resolving version");
+    let header = reader.header().clone();
+    let map = reader.map(name).expect("error mapping archive");
+
+    let mut writer = Writer::new(name);
+    for (path, node) in entries(&map) {
+        match node {
+            Node::Package => writer.add_package(&path),
+            Node::Image { .. } => {
+                let image = reader
+                    .map_image(&map, &path, |_| decryptor())
+                    .expect("error mapping image");
+                writer.add_image(&path, EncodedImage::new(image, encryptor.clone()))
+            }
+        }
+        .expect("error adding entry");
+    }
+    writer.prepare(1).expect("error preparing images");
+    let rebuilt = writer
+        .save_to_vec(version, header, encryptor.clone())
+        .expect("error saving");
+
+    let expected = canonical_images(original, name, decryptor);
+    assert!(!expected.is_empty());
+    assert!(canonical_images(rebuilt, name, reread) == expected);
+}
+
+#[test]
+fn v83_stored() {
+    rebuild_stored("testdata/v83-base.wz", "Base", key, key);
+    rebuild_stored("testdata/v83-string.wz", "String", key, key);
+}
+
+#[test]
+fn v172_stored() {
+    let (decryptor, encryptor) = (|| DummyDecryptor, || DummyEncryptor);
+    rebuild_stored("testdata/v172-base.wz", "Base", decryptor, encryptor);
+    rebuild_stored("testdata/v172-string.wz", "String", decryptor, encryptor);
+}
+
+#[test]
+fn v83_encoded() {
+    rebuild_encoded("testdata/v83-base.wz", "Base", key, key(), key);
+    rebuild_encoded("testdata/v83-base.wz", "Base", key, DummyEncryptor, || {
+        DummyDecryptor
+    });
+}
+
+#[test]
+fn v172_encoded() {
+    let dummy = || DummyDecryptor;
+    rebuild_encoded(
+        "testdata/v172-base.wz",
+        "Base",
+        dummy,
+        DummyEncryptor,
+        dummy,
+    );
+    rebuild_encoded("testdata/v172-base.wz", "Base", dummy, key(), key);
+}